@@ -4,12 +4,12 @@
 use std::collections::HashSet;
 use std::ffi::OsString;
 
-use anyhow::Result;
+use anyhow::{Context as _, Result};
 use clap::{ArgAction::SetTrue, Args as _, FromArgMatches as _, Parser};
 
 use crate::config::Source as ConfigSource;
 use crate::util::{dirwalk, path};
-use crate::{CopyJobSpec, FileSpec, config::Manager, util::AddressFamily};
+use crate::{CopyJobSpec, FileSpec, PreserveSelection, config::Manager, util::AddressFamily};
 
 const META_JOBSPEC: &str = "command-line (user@host)";
 
@@ -23,6 +23,9 @@ pub(crate) enum MainMode {
     HelpBuffers,
     ShowConfigFiles,
     ListFeatures,
+    Agent,
+    AgentList,
+    AgentClose,
     // remember to add any new mode to the default_value_ifs set in CliArgs::Mode
 }
 
@@ -73,6 +76,9 @@ pub(crate) struct CliArgs {
         ("help_buffers", "true", "help-buffers"),
         ("config_files", "true", "show-config-files"),
         ("list_features", "true", "list-features"),
+        ("agent", "true", "agent"),
+        ("agent_list", "true", "agent-list"),
+        ("agent_close", "true", "agent-close"),
     ], default_value="client")]
     pub(crate) mode_: MainMode,
 
@@ -114,6 +120,53 @@ pub(crate) struct CliArgs {
     #[arg(long, help_heading("Debug"), exclusive(true), display_order(100))]
     pub list_features: bool,
 
+    /// Reports on one or more remote files or directories, e.g. `qcp --stat myserver:some/file`, then exits.
+    ///
+    /// For each path, outputs whether it is a file or a directory, its size, its permissions
+    /// and when it was last modified. Nothing is transferred.
+    ///
+    /// All the paths must be on the same remote host.
+    #[arg(long, conflicts_with_all(["agent", "agent_close"]), display_order(0))]
+    pub stat: bool,
+
+    /// Runs a connection agent for the remote host, e.g. `qcp --agent myserver:`
+    ///
+    /// The agent sets up a connection to the host, then keeps it open for later qcp invocations to use,
+    /// saving the time it takes to set up ssh and QUIC each time.
+    /// Any later qcp transfer to the same host (and user) uses the agent automatically, unless
+    /// `--no-agent` is given.
+    ///
+    /// The agent runs in the foreground; you will usually want to run it in the background.
+    /// It exits when no transfers have been in progress for `agent_timeout` seconds,
+    /// or when closed with `--agent-close`.
+    ///
+    /// This option is not available on Windows.
+    #[arg(
+        long,
+        help_heading("Connection agent"),
+        conflicts_with_all(["agent_list", "agent_close"]),
+        display_order(100)
+    )]
+    pub agent: bool,
+
+    /// Lists the running connection agents, then exits.
+    ///
+    /// This option cannot be used with any other option.
+    #[arg(
+        long,
+        help_heading("Connection agent"),
+        exclusive(true),
+        display_order(100)
+    )]
+    pub agent_list: bool,
+
+    /// Closes the connection agent for the remote host, e.g. `qcp --agent-close myserver:`, then exits.
+    ///
+    /// If no host is given, closes all running agents.
+    /// An agent finishes any transfers in progress before it exits.
+    #[arg(long, help_heading("Connection agent"), display_order(100))]
+    pub agent_close: bool,
+
     // CLIENT-SIDE NON-CONFIGURABLE OPTIONS ================================================
     // (including positional arguments!)
     #[command(flatten)]
@@ -127,6 +180,12 @@ pub(crate) struct CliArgs {
     #[arg(long, value_name("FILE"), display_order(0))]
     pub log_file: Option<String>,
 
+    /// Writes a list of the files transferred, with their SHA-256 checksums, to a file.
+    ///
+    /// The output is in the format used by `sha256sum`, and describes the local copy of each file.
+    #[arg(long, value_name("FILE"), display_order(0))]
+    pub checksum_manifest: Option<String>,
+
     /// Forces use of IPv4
     ///
     /// This is a convenience alias for `--address-family inet`
@@ -256,11 +315,18 @@ impl CliArgs {
         let mut jobs = Vec::with_capacity(sources.len());
 
         if self.client_params.recurse && destination_is_remote {
+            // Hard links are tracked across all the sources
+            let mut hard_links = self
+                .client_params
+                .hard_links
+                .then(dirwalk::HardLinkTracker::default);
             for source in sources {
                 success &= dirwalk::recurse_local_source(
                     &source,
                     &destination,
                     self.client_params.preserve,
+                    self.client_params.links,
+                    hard_links.as_mut(),
                     &mut jobs,
                 )?;
             }
@@ -289,6 +355,32 @@ impl CliArgs {
         Ok((success, jobs))
     }
 
+    /// Converts the paths given with `--stat` into job specs, one per path.
+    ///
+    /// Each job's source is the remote path; the destination is unused.
+    pub(crate) fn stat_jobspecs(&self) -> anyhow::Result<Vec<CopyJobSpec>> {
+        anyhow::ensure!(!self.paths.is_empty(), "a remote path is required");
+        let mut hosts = HashSet::new();
+        for path in &self.paths {
+            let host = path
+                .hostname()
+                .with_context(|| format!("{path} is not a remote path"))?;
+            let _ = hosts.insert(host);
+        }
+        anyhow::ensure!(hosts.len() == 1, "Only one remote host is supported");
+        self.paths
+            .iter()
+            .map(|path| {
+                CopyJobSpec::try_new(
+                    path.clone(),
+                    FileSpec::default(),
+                    PreserveSelection::default(),
+                    false,
+                )
+            })
+            .collect()
+    }
+
     /// A best-effort attempt to extract a single remote host string from the parameters.
     ///
     /// # Returns
@@ -319,8 +411,9 @@ impl CliArgs {
                 }
             }
         }
+        // With --stat, every path is remote
         anyhow::ensure!(
-            !(remote_in_sources && remote_in_destination),
+            self.stat || !(remote_in_sources && remote_in_destination),
             "Only one remote side is supported"
         );
         Ok(host)
@@ -574,6 +667,24 @@ mod test {
         assert!(res.is_ok());
     }
 
+    #[test]
+    fn stat_jobspecs() {
+        let args = CliArgs::custom_parse(["qcp", "--stat", "host:a", "user@host:b"]).unwrap();
+        assert_eq!(args.remote_host_lossy().unwrap(), Some("host"));
+        let jobs = args.stat_jobspecs().unwrap();
+        assert_eq!(jobs.len(), 2);
+        assert_eq!(jobs[1].source.to_string(), "user@host:b");
+        assert_eq!(jobs[1].destination.user_at_host, None);
+
+        let args = CliArgs::custom_parse(["qcp", "--stat", "host:a", "b"]).unwrap();
+        let e = args.stat_jobspecs().unwrap_err();
+        assert_eq!(e.to_string(), "b is not a remote path");
+        let args = CliArgs::custom_parse(["qcp", "--stat", "host:a", "other:b"]).unwrap();
+        let _ = args.stat_jobspecs().unwrap_err();
+        let args = CliArgs::custom_parse(["qcp", "--stat"]).unwrap();
+        let _ = args.stat_jobspecs().unwrap_err();
+    }
+
     #[test]
     fn recurse_jobspecs() {
         let args = &["qcp", "-r", "no-such-dir/", "desthost:otherdir"];
@@ -121,6 +121,9 @@ async fn handle_mode(args: Box<CliArgs>, config_manager: Manager) -> Result<bool
         MainMode::Server => run_server().await,
         MainMode::Client => run_client(config_manager, args).await,
         MainMode::ListFeatures => Ok(list_features()),
+        MainMode::Agent => run_agent(config_manager, args).await,
+        MainMode::AgentList => agent_list().await,
+        MainMode::AgentClose => agent_close(&config_manager, &args).await,
     }
 }
 
@@ -211,6 +214,77 @@ async fn run_client(config_manager: Manager, args: Box<CliArgs>) -> Result<bool>
     crate::client_main(config_manager, progress, args).await
 }
 
+#[cfg(unix)]
+async fn run_agent(config_manager: Manager, args: Box<CliArgs>) -> Result<bool> {
+    let progress =
+        MultiProgress::with_draw_target(ProgressDrawTarget::stderr_with_hz(MAX_UPDATE_FPS));
+    {
+        // As for run_client, validate what we have against the system default
+        let mut temp_mgr = config_manager.clone();
+        temp_mgr.apply_system_default();
+        temp_mgr.validate_configuration()?;
+    }
+    crate::client::agent_main(config_manager, progress, args).await
+}
+
+#[cfg(unix)]
+async fn agent_list() -> Result<bool> {
+    let agents = crate::client::agent::list().await?;
+    if agents.is_empty() {
+        println!("No connection agents are running");
+    }
+    for agent in agents {
+        println!(
+            "{}: {} transfer(s) in progress",
+            agent.host, agent.active_streams
+        );
+    }
+    Ok(true)
+}
+
+#[cfg(unix)]
+async fn agent_close(config_manager: &Manager, args: &CliArgs) -> Result<bool> {
+    use crate::client::agent::{self, AgentClient};
+
+    let keys = if let Some(host) = args.remote_host_lossy()? {
+        let user = config_manager
+            .get::<crate::config::Configuration_Optional>()?
+            .remote_user
+            .unwrap_or_default();
+        vec![agent::host_key(&user, host)]
+    } else {
+        let keys: Vec<_> = agent::list().await?.into_iter().map(|a| a.host).collect();
+        if keys.is_empty() {
+            println!("No connection agents are running");
+        }
+        keys
+    };
+    let mut success = true;
+    for key in keys {
+        if let Some(agent) = AgentClient::find(&key).await {
+            agent.close().await?;
+            println!("Closed connection agent for {key}");
+        } else {
+            eprintln!("No connection agent is running for {key}");
+            success = false;
+        }
+    }
+    Ok(success)
+}
+
+#[cfg(not(unix))]
+async fn run_agent(_: Manager, _: Box<CliArgs>) -> Result<bool> {
+    anyhow::bail!("connection agents are not supported on this platform")
+}
+#[cfg(not(unix))]
+async fn agent_list() -> Result<bool> {
+    anyhow::bail!("connection agents are not supported on this platform")
+}
+#[cfg(not(unix))]
+async fn agent_close(_: &Manager, _: &CliArgs) -> Result<bool> {
+    anyhow::bail!("connection agents are not supported on this platform")
+}
+
 #[cfg(test)]
 #[cfg_attr(coverage_nightly, coverage(off))]
 mod tests {
@@ -0,0 +1,583 @@
+//! Persistent connection agent
+// (c) 2025 Ross Younger
+//!
+//! A connection agent holds an established connection to a remote host open, so that later qcp
+//! processes can use it without having to set up ssh and QUIC again.
+//!
+//! Each agent listens on a Unix socket in the user's runtime directory (or, if there isn't one, a private
+//! directory in their home directory), named after the remote host.
+//! Clients only use a socket in a directory private to the user, and only if the agent is run by the same user.
+//! Every request to the agent is made on a fresh connection to the socket, beginning with an [`AgentRequest`]
+//! to which the agent sends an [`AgentResponse`].
+//! After the agent has acknowledged an [`AgentRequest::OpenStream`], the socket connection carries the
+//! data of a single QUIC stream, in each direction, until both sides have finished.
+
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{Context as _, Result};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::AsyncWriteExt as _,
+    net::{
+        UnixListener, UnixStream,
+        unix::{OwnedReadHalf, OwnedWriteHalf},
+    },
+    task::JoinSet,
+    time::timeout,
+};
+use tracing::{debug, info, trace, warn};
+
+use crate::protocol::{
+    common::{ProtocolMessage, ReceivingStream, SendReceivePair, SendingStream},
+    control::ServerMessageV2,
+};
+
+impl SendingStream for OwnedWriteHalf {}
+impl ReceivingStream for OwnedReadHalf {}
+
+/// How long we allow for an agent to respond to a request, or a client to send one
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long the agent pauses after failing to accept a connection, before trying again
+const ACCEPT_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// A request to a connection agent
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum AgentRequest {
+    /// Describe the connection
+    Session,
+    /// Open a stream on the connection, and relay it over this socket connection
+    OpenStream,
+    /// Finish any streams in progress, then close the connection and exit
+    Close,
+}
+impl ProtocolMessage for AgentRequest {}
+
+/// A connection agent's response to an [`AgentRequest`]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub(crate) enum AgentResponse {
+    /// Response to [`AgentRequest::Session`]
+    Session(AgentSession),
+    /// The request succeeded
+    Ok,
+    /// The request failed
+    Failure(String),
+}
+impl ProtocolMessage for AgentResponse {}
+
+/// What a client needs to know to use a connection held by an agent
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub(crate) struct AgentSession {
+    /// The remote `[user@]host` the agent is connected to
+    pub(crate) host: String,
+    /// The compatibility level selected for the connection
+    pub(crate) compatibility: u16,
+    /// The server message, which holds the negotiated configuration
+    pub(crate) server_message: ServerMessageV2,
+    /// The number of streams currently in progress
+    pub(crate) active_streams: u32,
+}
+
+/// Computes the key by which an agent is known: `user@host`, or `host` if no user was specified
+pub(crate) fn host_key(user: &str, host: &str) -> String {
+    if user.is_empty() {
+        host.to_string()
+    } else {
+        format!("{user}@{host}")
+    }
+}
+
+/// The directory holding agent sockets.
+///
+/// We never use a shared directory such as `/tmp`, where another user could set up a socket for us to find.
+fn socket_dir() -> Result<PathBuf> {
+    if let Some(dir) = dirs::runtime_dir() {
+        return Ok(dir.join("qcp"));
+    }
+    dirs::home_dir()
+        .map(|home| home.join(".qcp").join("agents"))
+        .context("could not find a runtime or home directory for connection agent sockets")
+}
+
+/// The filename of the socket for a given host key.
+/// We use a hash, as host names can be longer than a Unix socket path allows.
+fn socket_name(key: &str) -> String {
+    let digest = ring::digest::digest(&ring::digest::SHA256, key.as_bytes());
+    format!("agent-{}.sock", hex::encode(&digest.as_ref()[..8]))
+}
+
+/// Creates the socket directory, if necessary, and checks that it is private to us
+fn ensure_socket_dir(dir: &Path) -> Result<()> {
+    use std::os::unix::fs::DirBuilderExt as _;
+    std::fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(dir)
+        .with_context(|| format!("creating agent socket directory {}", dir.display()))?;
+    check_socket_dir(dir)
+}
+
+/// Checks that the socket directory is private to us, so that no other user can have put a socket in it
+#[allow(clippy::verbose_bit_mask)] // a mode mask is clearer than trailing_zeros()
+fn check_socket_dir(dir: &Path) -> Result<()> {
+    use std::os::unix::fs::MetadataExt as _;
+    let meta = std::fs::symlink_metadata(dir)?;
+    anyhow::ensure!(
+        meta.is_dir()
+            && meta.uid() == rustix::process::getuid().as_raw()
+            && meta.mode() & 0o077 == 0,
+        "agent socket directory {} must be owned by you and not accessible to others",
+        dir.display()
+    );
+    Ok(())
+}
+
+// =================================================================================
+// CLIENT
+
+/// Connects to an agent's socket, checking that the agent belongs to us
+async fn connect(path: &Path) -> std::io::Result<UnixStream> {
+    let stream = UnixStream::connect(path).await?;
+    let peer = stream.peer_cred()?.uid();
+    if peer != rustix::process::getuid().as_raw() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::PermissionDenied,
+            format!("connection agent is run by another user (uid {peer})"),
+        ));
+    }
+    Ok(stream)
+}
+
+/// A connection to a running agent
+#[derive(Debug, Clone)]
+pub(crate) struct AgentClient {
+    path: PathBuf,
+}
+
+impl AgentClient {
+    /// Finds the running agent for the given host key, if there is one.
+    ///
+    /// If the agent's socket exists but nothing is listening on it, the socket is removed.
+    pub(crate) async fn find(key: &str) -> Option<Self> {
+        let dir = socket_dir().ok()?;
+        if !dir.exists() {
+            return None;
+        }
+        if let Err(e) = check_socket_dir(&dir) {
+            warn!("Not using connection agents: {e:#}");
+            return None;
+        }
+        Self::at(dir.join(socket_name(key))).await
+    }
+
+    /// Finds the running agent listening on a given socket, if there is one.
+    async fn at(path: PathBuf) -> Option<Self> {
+        match connect(&path).await {
+            Ok(_) => Some(Self { path }),
+            Err(e) if e.kind() == std::io::ErrorKind::ConnectionRefused => {
+                debug!("removing stale agent socket {}", path.display());
+                let _ = std::fs::remove_file(&path);
+                None
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied => {
+                warn!("Not using {}: {e}", path.display());
+                None
+            }
+            Err(_) => None,
+        }
+    }
+
+    /// Sends a request, returning the socket connection and the agent's response
+    async fn request(&self, request: AgentRequest) -> Result<(UnixStream, AgentResponse)> {
+        let mut stream = connect(&self.path)
+            .await
+            .context("connecting to connection agent")?;
+        request.to_writer_async_framed(&mut stream).await?;
+        stream.flush().await?;
+        let response = AgentResponse::from_reader_async_framed(&mut stream);
+        // Opening a stream may have to wait for other transfers to finish
+        let response = if request == AgentRequest::OpenStream {
+            response.await
+        } else {
+            timeout(REQUEST_TIMEOUT, response)
+                .await
+                .context("timed out waiting for connection agent")?
+        }
+        .context("communicating with connection agent")?;
+        if let AgentResponse::Failure(f) = response {
+            anyhow::bail!("connection agent failed: {f}");
+        }
+        Ok((stream, response))
+    }
+
+    /// Retrieves the description of the agent's connection
+    pub(crate) async fn session(&self) -> Result<AgentSession> {
+        match self.request(AgentRequest::Session).await? {
+            (_, AgentResponse::Session(s)) => Ok(s),
+            (_, r) => anyhow::bail!("unexpected response from connection agent: {r:?}"),
+        }
+    }
+
+    /// Opens a stream on the agent's connection
+    pub(crate) async fn open_stream(
+        &self,
+    ) -> Result<SendReceivePair<OwnedWriteHalf, OwnedReadHalf>> {
+        let (stream, _) = self.request(AgentRequest::OpenStream).await?;
+        let (recv, send) = stream.into_split();
+        Ok(SendReceivePair { send, recv })
+    }
+
+    /// Asks the agent to exit
+    pub(crate) async fn close(&self) -> Result<()> {
+        let _ = self.request(AgentRequest::Close).await?;
+        Ok(())
+    }
+}
+
+/// Lists the running agents
+pub(crate) async fn list() -> Result<Vec<AgentSession>> {
+    let dir = socket_dir()?;
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+    check_socket_dir(&dir)?;
+    let entries = std::fs::read_dir(&dir)?;
+    let mut result = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().is_none_or(|e| e != "sock") {
+            continue;
+        }
+        if let Some(agent) = AgentClient::at(path).await {
+            match agent.session().await {
+                Ok(session) => result.push(session),
+                Err(e) => warn!("{}: {e:#}", agent.path.display()),
+            }
+        }
+    }
+    result.sort_by(|a, b| a.host.cmp(&b.host));
+    Ok(result)
+}
+
+// =================================================================================
+// AGENT
+
+/// Creates the listening socket for the agent for a given host key
+pub(crate) fn listen(key: &str) -> Result<(UnixListener, PathBuf)> {
+    let dir = socket_dir()?;
+    ensure_socket_dir(&dir)?;
+    let path = dir.join(socket_name(key));
+    Ok((listen_at(&path, key)?, path))
+}
+
+fn listen_at(path: &Path, key: &str) -> Result<UnixListener> {
+    if path.exists() {
+        anyhow::ensure!(
+            std::os::unix::net::UnixStream::connect(path).is_err(),
+            "a connection agent for {key} is already running"
+        );
+        std::fs::remove_file(path)?;
+    }
+    UnixListener::bind(path).with_context(|| format!("binding agent socket {}", path.display()))
+}
+
+/// Serves requests from clients, until closed or until idle for the given time.
+///
+/// Each client's request is read in its own task, so a slow client cannot hold up the others.
+/// `open_stream` opens a new stream on the connection.
+/// This may have to wait for other streams to finish, so each is opened in its own task.
+pub(crate) async fn serve<S, R, F, Fut>(
+    listener: UnixListener,
+    mut session: AgentSession,
+    open_stream: F,
+    idle_timeout: Duration,
+) -> Result<()>
+where
+    S: SendingStream + 'static,
+    R: ReceivingStream + 'static,
+    F: Fn() -> Fut,
+    Fut: Future<Output = Result<SendReceivePair<S, R>>> + Send + 'static,
+{
+    let mut requests = JoinSet::new();
+    let mut streams = JoinSet::new();
+    loop {
+        // The idle timer restarts whenever anything happens
+        let (mut socket, request) = tokio::select! {
+            accepted = listener.accept() => {
+                match accepted {
+                    Ok((socket, _)) => {
+                        let _ = requests.spawn(read_request(socket));
+                    }
+                    Err(e) => {
+                        // This may be transient (for example, running out of file descriptors)
+                        warn!("failed to accept agent connection: {e}");
+                        tokio::time::sleep(ACCEPT_RETRY_DELAY).await;
+                    }
+                }
+                continue;
+            }
+            Some(result) = requests.join_next(), if !requests.is_empty() => {
+                let Ok(Some(request)) = result else {
+                    continue;
+                };
+                request
+            }
+            Some(result) = streams.join_next(), if !streams.is_empty() => {
+                if let Ok(Err(e)) = result {
+                    warn!("relayed stream failed: {e:#}");
+                }
+                continue;
+            }
+            () = tokio::time::sleep(idle_timeout), if streams.is_empty() && requests.is_empty() => {
+                info!("No transfers for {}s; exiting", idle_timeout.as_secs());
+                break;
+            }
+        };
+        trace!("agent request: {request:?}");
+        let response = match request {
+            AgentRequest::Session => {
+                session.active_streams = u32::try_from(streams.len()).unwrap_or(u32::MAX);
+                AgentResponse::Session(session.clone())
+            }
+            AgentRequest::OpenStream => {
+                let opening = open_stream();
+                let _ = streams.spawn(async move {
+                    match opening.await {
+                        Ok(pair) => {
+                            respond(&mut socket, AgentResponse::Ok).await;
+                            relay(socket, pair).await
+                        }
+                        Err(e) => {
+                            respond(&mut socket, AgentResponse::Failure(format!("{e:#}"))).await;
+                            Err(e)
+                        }
+                    }
+                });
+                continue;
+            }
+            AgentRequest::Close => {
+                respond(&mut socket, AgentResponse::Ok).await;
+                info!("Closing on request");
+                break;
+            }
+        };
+        respond(&mut socket, response).await;
+    }
+    if !streams.is_empty() {
+        info!("Waiting for {} transfer(s) to finish", streams.len());
+    }
+    while streams.join_next().await.is_some() {}
+    Ok(())
+}
+
+/// Reads a client's request from a new socket connection.
+///
+/// Returns `None` if the client did not send a valid request in time.
+async fn read_request(mut socket: UnixStream) -> Option<(UnixStream, AgentRequest)> {
+    match timeout(
+        REQUEST_TIMEOUT,
+        AgentRequest::from_reader_async_framed(&mut socket),
+    )
+    .await
+    {
+        Ok(Ok(request)) => Some((socket, request)),
+        Ok(Err(e)) => {
+            debug!("bad request to agent: {e:#}");
+            None
+        }
+        Err(_) => {
+            debug!("timed out reading request to agent");
+            None
+        }
+    }
+}
+
+async fn respond(socket: &mut UnixStream, response: AgentResponse) {
+    let result = async {
+        response.to_writer_async_framed(socket).await?;
+        socket.flush().await?;
+        anyhow::Ok(())
+    }
+    .await;
+    if let Err(e) = result {
+        debug!("failed to send agent response: {e:#}");
+    }
+}
+
+/// Copies data between a client's socket connection and a stream, in both directions
+async fn relay<S: SendingStream, R: ReceivingStream>(
+    socket: UnixStream,
+    stream: SendReceivePair<S, R>,
+) -> Result<()> {
+    let (mut socket_recv, mut socket_send) = socket.into_split();
+    let SendReceivePair { mut send, mut recv } = stream;
+    let outbound = async {
+        let _ = tokio::io::copy(&mut socket_recv, &mut send).await?;
+        send.shutdown().await
+    };
+    let inbound = async {
+        let _ = tokio::io::copy(&mut recv, &mut socket_send).await?;
+        socket_send.shutdown().await
+    };
+    let (outbound, inbound) = tokio::join!(outbound, inbound);
+    outbound?;
+    inbound?;
+    Ok(())
+}
+
+#[cfg(test)]
+#[cfg_attr(coverage_nightly, coverage(off))]
+mod test {
+    use std::time::Duration;
+
+    use littertray::LitterTray;
+    use pretty_assertions::assert_eq;
+    use tokio::{
+        io::{AsyncReadExt as _, AsyncWriteExt as _},
+        net::UnixStream,
+    };
+
+    use super::{
+        AgentClient, AgentSession, check_socket_dir, ensure_socket_dir, host_key, listen_at, serve,
+        socket_name,
+    };
+    use crate::protocol::common::SendReceivePair;
+
+    #[test]
+    fn keys_and_names() {
+        assert_eq!(host_key("", "myhost"), "myhost");
+        assert_eq!(host_key("me", "myhost"), "me@myhost");
+        let name = socket_name("me@myhost");
+        assert!(name.starts_with("agent-"));
+        assert_eq!(
+            std::path::Path::new(&name).extension(),
+            Some("sock".as_ref())
+        );
+        assert_eq!(name, socket_name("me@myhost"));
+        assert_ne!(name, socket_name("myhost"));
+    }
+
+    #[test]
+    fn socket_dir_must_be_private() {
+        use std::os::unix::fs::PermissionsExt as _;
+        LitterTray::try_with(|tray| {
+            let dir = tray.directory().join("agents");
+            ensure_socket_dir(&dir)?;
+            check_socket_dir(&dir)?;
+            std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o755))?;
+            assert!(check_socket_dir(&dir).is_err());
+            assert!(ensure_socket_dir(&dir).is_err());
+            // A symlink to a private directory is not good enough either
+            std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o700))?;
+            std::os::unix::fs::symlink(&dir, "link")?;
+            assert!(check_socket_dir(&tray.directory().join("link")).is_err());
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    /// Opens a "stream" which echoes back whatever is sent to it
+    async fn echo_stream() -> anyhow::Result<
+        SendReceivePair<tokio::net::unix::OwnedWriteHalf, tokio::net::unix::OwnedReadHalf>,
+    > {
+        let (ours, theirs) = UnixStream::pair()?;
+        std::mem::drop(tokio::spawn(async move {
+            let (mut r, mut w) = theirs.into_split();
+            let _ = tokio::io::copy(&mut r, &mut w).await;
+            let _ = w.shutdown().await;
+        }));
+        let (recv, send) = ours.into_split();
+        Ok(SendReceivePair { send, recv })
+    }
+
+    #[tokio::test]
+    async fn session_stream_close() {
+        LitterTray::try_with_async(async |tray| {
+            let path = tray.directory().join("test.sock");
+            let listener = listen_at(&path, "me@myhost")?;
+            let session = AgentSession {
+                host: "me@myhost".into(),
+                compatibility: 5,
+                ..Default::default()
+            };
+            let agent = tokio::spawn(serve(
+                listener,
+                session,
+                echo_stream,
+                Duration::from_secs(60),
+            ));
+
+            let client = AgentClient::at(path.clone()).await.unwrap();
+            let s = client.session().await?;
+            assert_eq!(s.host, "me@myhost");
+            assert_eq!(s.compatibility, 5);
+
+            let mut pair = client.open_stream().await?;
+            pair.send.write_all(b"hello").await?;
+            pair.send.shutdown().await?;
+            let mut buf = Vec::new();
+            let _ = pair.recv.read_to_end(&mut buf).await?;
+            assert_eq!(buf, b"hello");
+
+            // A second agent for the same host is refused
+            let e = listen_at(&path, "me@myhost").unwrap_err();
+            assert!(e.to_string().contains("already running"));
+
+            client.close().await?;
+            agent.await??;
+            Ok(())
+        })
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn silent_client_does_not_block_others() {
+        LitterTray::try_with_async(async |tray| {
+            let path = tray.directory().join("test.sock");
+            let listener = listen_at(&path, "myhost")?;
+            let agent = tokio::spawn(serve(
+                listener,
+                AgentSession::default(),
+                echo_stream,
+                Duration::from_secs(60),
+            ));
+
+            // This client connects but never sends a request
+            let _silent = tokio::net::UnixStream::connect(&path).await?;
+            let client = AgentClient::at(path.clone()).await.unwrap();
+            let _ = tokio::time::timeout(Duration::from_secs(1), client.session()).await??;
+
+            client.close().await?;
+            agent.await??;
+            Ok(())
+        })
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn idle_timeout_and_stale_socket() {
+        LitterTray::try_with_async(async |tray| {
+            let path = tray.directory().join("test.sock");
+            let listener = listen_at(&path, "myhost")?;
+            serve(
+                listener,
+                AgentSession::default(),
+                echo_stream,
+                Duration::from_millis(10),
+            )
+            .await?;
+            // The agent has gone, leaving its socket behind
+            assert!(path.exists());
+            assert!(AgentClient::at(path.clone()).await.is_none());
+            assert!(!path.exists());
+            Ok(())
+        })
+        .await
+        .unwrap();
+    }
+}
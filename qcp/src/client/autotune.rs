@@ -0,0 +1,397 @@
+//! Measurement of the network link (`--autotune`)
+// (c) 2025 Ross Younger
+//!
+//! Autotuning replaces the configured `rx`, `tx` and `rtt` with values measured after connecting.
+//! Unless `--autotune-reduce` is given, it only raises them: a measurement which would make the windows
+//! smaller than configured is ignored.
+//!
+//! * While negotiating, the client asks for a high bandwidth ([`probe_config`]), so that the window
+//!   sizes agreed with the server do not limit the probe. The server's own configuration may still impose
+//!   lower limits.
+//! * The round-trip time is taken from the QUIC path statistics, after the handshake.
+//! * The capacity of the link in each direction is measured by a timed burst of data (the `Probe` command).
+//!   This lasts for a number of round trips, so that slow start is only a small part of it.
+//! * The client's window sizes are then set from the measured values, before any files are transferred.
+//!   The server's window sizes are not changed, but they do not limit the client's.
+//! * With several connections, the probe runs over the first. The measured values apply to each of them.
+
+use std::time::Duration;
+
+use anyhow::Result;
+use engineering_repr::EngineeringRepr as _;
+use human_repr::HumanDuration as _;
+use quinn::{Connection, VarInt};
+use tracing::{debug, info, warn};
+
+use crate::{
+    config::{Configuration, Source, structure::MINIMUM_BANDWIDTH},
+    protocol::{
+        common::SendReceivePair,
+        compat::Feature,
+        control::{Compatibility, CongestionController},
+    },
+    session::probe::probe,
+};
+
+/// Bandwidth we request while negotiating, in bytes per second (10 Gbit/s)
+const PROBE_BANDWIDTH: u64 = 1_250_000_000;
+
+/// How many round trips to probe each direction of the link for
+const PROBE_RTTS: u32 = 20;
+
+/// The shortest time to probe each direction of the link for
+pub(super) const MIN_PROBE_DURATION: Duration = Duration::from_secs(1);
+
+/// The longest time to probe each direction of the link for
+const MAX_PROBE_DURATION: Duration = Duration::from_secs(10);
+
+/// Returns a configuration layer to apply before negotiating, which raises `rx` and `tx`
+/// so that the negotiated window sizes do not limit the probe.
+pub(super) fn probe_config() -> Source {
+    let mut source = Source::new("--autotune");
+    source.add("rx", PROBE_BANDWIDTH.into());
+    source.add("tx", PROBE_BANDWIDTH.into());
+    source
+}
+
+/// Measures the link and sets up the connections to suit.
+///
+/// On return, `config` holds the measured `rx`, `tx` and `rtt`; unless `allow_reduce` is set,
+/// they are no lower than `configured`.
+/// The probe runs for [`PROBE_RTTS`] round trips, within `min_duration` and [`MAX_PROBE_DURATION`].
+///
+/// If the link cannot be measured, the bandwidth reverts to `configured` (the client's configuration
+/// before [`probe_config`] was applied), limited by what was negotiated with the server.
+pub(super) async fn autotune(
+    connections: &[Connection],
+    config: &mut Configuration,
+    configured: &Configuration,
+    compat: Compatibility,
+    min_duration: Duration,
+    allow_reduce: bool,
+) -> Result<()> {
+    // The server may have imposed it
+    ensure_not_fixed_rate(config.congestion)?;
+    if !compat.supports(Feature::PROBE) {
+        warn!("--autotune requested, but remote does not support this option");
+        revert(config, configured);
+        return apply(connections, config);
+    }
+    let Some(connection) = connections.first() else {
+        return Ok(());
+    };
+
+    // Measure RTT before the probe, while the link is quiet
+    let rtt = connection.rtt();
+    let duration = probe_duration(rtt, min_duration);
+    debug!("Probing for {}", duration.human_duration());
+    let rates = match probe(SendReceivePair::from(connection.open_bi().await?), duration).await {
+        Ok(rates) => rates,
+        Err(e) => {
+            warn!("Link probe failed, using configured bandwidth: {e}");
+            revert(config, configured);
+            return apply(connections, config);
+        }
+    };
+    debug!("Probe results: {rates:?}, rtt {rtt:?}");
+
+    let negotiated = (config.rx(), config.tx());
+    config.rx = choose_bandwidth("inbound", rates.to_client, negotiated.0, configured.rx());
+    config.tx = choose_bandwidth("outbound", rates.to_server, negotiated.1, configured.tx());
+    config.rtt = u16::try_from(rtt.as_micros().div_ceil(1000).max(1)).unwrap_or(u16::MAX);
+
+    info!(
+        "Measured link: rx {rx}B/s, tx {tx}B/s, rtt {rtt}",
+        rx = config.rx.to_eng(3),
+        tx = config.tx.to_eng(3),
+        rtt = rtt.human_duration(),
+    );
+    info!(
+        "To use these values without --autotune, add them to the Host block for this host in your qcp configuration:\n    Rx {rx}\n    Tx {tx}\n    Rtt {rtt}",
+        rx = config.rx.to_eng(3),
+        tx = config.tx.to_eng(3),
+        rtt = config.rtt,
+    );
+    if !allow_reduce && raise_to_configured(config, configured, negotiated) {
+        info!(
+            "Keeping the configured values where they are higher than measured (use --autotune-reduce to lower them)"
+        );
+    }
+    apply(connections, config)
+}
+
+/// How long to probe each direction of the link for, given its round-trip time
+fn probe_duration(rtt: Duration, min_duration: Duration) -> Duration {
+    (rtt * PROBE_RTTS).clamp(min_duration, MAX_PROBE_DURATION.max(min_duration))
+}
+
+/// Raises `rx`, `tx` and `rtt` to their `configured` values, where those are higher,
+/// so the windows are no smaller than they would have been without autotuning.
+/// The `negotiated` bandwidths (`rx`, `tx`) still apply.
+///
+/// Returns whether anything changed.
+fn raise_to_configured(
+    config: &mut Configuration,
+    configured: &Configuration,
+    negotiated: (u64, u64),
+) -> bool {
+    let before = (config.rx, config.tx, config.rtt);
+    config.rx = config.rx.max(configured.rx().min(negotiated.0));
+    config.tx = config.tx.max(configured.tx().min(negotiated.1));
+    config.rtt = config.rtt.max(configured.rtt);
+    before != (config.rx, config.tx, config.rtt)
+}
+
+/// The `FixedRate` congestion controller sends at the configured rate, and never backs off.
+/// It would flood the link while probing, and could not take up the measured rate afterwards.
+pub(super) fn ensure_not_fixed_rate(congestion: CongestionController) -> Result<()> {
+    anyhow::ensure!(
+        congestion != CongestionController::FixedRate,
+        "--autotune cannot be used with the FixedRate congestion controller; set rx and tx instead"
+    );
+    Ok(())
+}
+
+/// Chooses the bandwidth to use in one direction, given the `measured` rate.
+///
+/// A measurement of zero means no data got through, which tells us nothing about the link;
+/// in that case we fall back to the `configured` bandwidth.
+/// Either way, negotiation may have imposed a lower ceiling.
+fn choose_bandwidth(direction: &str, measured: u64, negotiated: u64, configured: u64) -> u64 {
+    if measured == 0 {
+        warn!("Link probe could not measure the {direction} bandwidth; using configured bandwidth");
+        return negotiated.min(configured);
+    }
+    measured.min(negotiated).max(MINIMUM_BANDWIDTH)
+}
+
+/// Restores the configured bandwidth, limited by what was negotiated
+fn revert(config: &mut Configuration, configured: &Configuration) {
+    config.rx = config.rx().min(configured.rx());
+    config.tx = config.tx().min(configured.tx());
+}
+
+/// Sets the connections' windows to suit `config`
+fn apply(connections: &[Connection], config: &Configuration) -> Result<()> {
+    let receive_window = VarInt::try_from(config.recv_window())?;
+    for connection in connections {
+        connection.set_receive_window(receive_window);
+        connection.set_send_window(config.send_window());
+    }
+    debug!("Windows now: {}", config.format_transport_config());
+    Ok(())
+}
+
+#[cfg(test)]
+#[cfg_attr(coverage_nightly, coverage(off))]
+mod test {
+    use std::net::{Ipv4Addr, SocketAddrV4};
+    use std::time::Duration;
+
+    use figment::Provider as _;
+    use pretty_assertions::assert_eq;
+    use tokio::time::timeout;
+
+    use super::{
+        MAX_PROBE_DURATION, PROBE_BANDWIDTH, autotune, choose_bandwidth, probe_config,
+        probe_duration, raise_to_configured,
+    };
+    use crate::{
+        Configuration,
+        config::structure::MINIMUM_BANDWIDTH,
+        protocol::{
+            common::{ProtocolMessage as _, SendReceivePair},
+            control::{Compatibility, CongestionController, ConnectionType},
+            session::Command,
+        },
+        transport::ThroughputMode,
+        util::Credentials,
+    };
+
+    #[test]
+    fn bandwidth_choice() {
+        // The measurement, limited by negotiation
+        assert_eq!(choose_bandwidth("inbound", 5_000, 10_000, 1_000), 5_000);
+        assert_eq!(choose_bandwidth("inbound", 50_000, 10_000, 1_000), 10_000);
+        assert_eq!(
+            choose_bandwidth("inbound", 1, 10_000, 1_000),
+            MINIMUM_BANDWIDTH
+        );
+        // Nothing measured: fall back to the configuration
+        assert_eq!(choose_bandwidth("inbound", 0, 10_000, 1_000), 1_000);
+        assert_eq!(choose_bandwidth("inbound", 0, 500, 1_000), 500);
+    }
+
+    #[test]
+    fn probe_scales_with_rtt() {
+        let min = Duration::from_secs(1);
+        assert_eq!(probe_duration(Duration::from_millis(10), min), min);
+        assert_eq!(
+            probe_duration(Duration::from_millis(300), min),
+            Duration::from_secs(6)
+        );
+        assert_eq!(
+            probe_duration(Duration::from_secs(2), min),
+            MAX_PROBE_DURATION
+        );
+    }
+
+    #[test]
+    fn measurements_only_raise_by_default() {
+        let mut configured = Configuration::system_default().clone();
+        configured.rx = 1_000_000;
+        configured.tx = 0;
+        configured.rtt = 300;
+        let mut config = configured.clone();
+        // Measured: a faster inbound link, a slower outbound link, and a shorter round trip
+        config.rx = 5_000_000;
+        config.tx = 500_000;
+        config.rtt = 20;
+        assert!(raise_to_configured(
+            &mut config,
+            &configured,
+            (10_000_000, 10_000_000)
+        ));
+        assert_eq!(
+            (config.rx, config.tx, config.rtt),
+            (5_000_000, 1_000_000, 300)
+        );
+        // Negotiation still sets a ceiling
+        config.tx = 500_000;
+        assert!(raise_to_configured(
+            &mut config,
+            &configured,
+            (10_000_000, 800_000)
+        ));
+        assert_eq!(config.tx, 800_000);
+        // Nothing to change
+        assert!(!raise_to_configured(
+            &mut config,
+            &configured,
+            (10_000_000, 800_000)
+        ));
+    }
+
+    #[test]
+    fn probe_config_raises_bandwidth() {
+        let data = probe_config().data().unwrap();
+        let dict = data.values().next().unwrap();
+        assert_eq!(dict["rx"].to_u128(), Some(u128::from(PROBE_BANDWIDTH)));
+        assert_eq!(dict["tx"].to_u128(), Some(u128::from(PROBE_BANDWIDTH)));
+    }
+
+    /// Runs autotune over a loopback connection
+    async fn autotune_loopback(compat: Compatibility) -> Configuration {
+        let config = Configuration::system_default();
+        let server_creds = Credentials::generate().unwrap();
+        let client_creds = Credentials::generate().unwrap();
+        let server_cert = server_creds.to_tagged_data(compat, None).unwrap();
+        let client_cert = client_creds.to_tagged_data(compat, None).unwrap();
+
+        let (server_endpoint, _) = crate::control::create_endpoint(
+            &server_creds,
+            &client_cert,
+            ConnectionType::Ipv4,
+            config,
+            ThroughputMode::Both,
+            true,
+            compat,
+        )
+        .unwrap();
+        let server_addr: std::net::SocketAddr = SocketAddrV4::new(
+            Ipv4Addr::LOCALHOST,
+            server_endpoint.local_addr().unwrap().port(),
+        )
+        .into();
+
+        let server_task = tokio::spawn(async move {
+            let connection = server_endpoint.accept().await.unwrap().await.unwrap();
+            if let Ok(bi) = connection.accept_bi().await {
+                let mut stream = SendReceivePair::from(bi);
+                let cmd = Command::from_reader_async_framed(&mut stream.recv)
+                    .await
+                    .unwrap();
+                let (mut handler, _) =
+                    crate::session::factory::command_handler(stream, cmd, compat, config);
+                handler.handle().await.unwrap();
+            }
+            let _ = connection.closed().await;
+            server_endpoint.wait_idle().await;
+        });
+
+        let (client_endpoint, _) = crate::control::create_endpoint(
+            &client_creds,
+            &server_cert,
+            ConnectionType::Ipv4,
+            config,
+            ThroughputMode::Both,
+            false,
+            compat,
+        )
+        .unwrap();
+        let connection = client_endpoint
+            .connect(server_addr, &server_creds.hostname)
+            .unwrap()
+            .await
+            .unwrap();
+
+        let mut negotiated = config.clone();
+        let mut configured = config.clone();
+        configured.rx = 1_000_000;
+        configured.tx = 0;
+        autotune(
+            std::slice::from_ref(&connection),
+            &mut negotiated,
+            &configured,
+            compat,
+            Duration::from_millis(100),
+            true,
+        )
+        .await
+        .unwrap();
+
+        connection.close(0u32.into(), b"test");
+        client_endpoint.close(0u32.into(), b"test");
+        let _ = timeout(Duration::from_secs(5), client_endpoint.wait_idle()).await;
+        let _ = timeout(Duration::from_secs(5), server_task).await;
+        negotiated
+    }
+
+    #[cfg_attr(target_os = "macos", ignore)]
+    #[cfg_attr(target_os = "windows", ignore = "fails under Wine in CI")]
+    #[tokio::test]
+    async fn measures_link() {
+        let config = autotune_loopback(Compatibility::Level(5)).await;
+        assert!(config.rx >= MINIMUM_BANDWIDTH);
+        assert!(config.tx >= MINIMUM_BANDWIDTH);
+        assert!(config.rx <= Configuration::system_default().rx);
+        assert!(config.rtt >= 1);
+    }
+
+    #[tokio::test]
+    async fn rejects_fixed_rate() {
+        let mut config = Configuration::system_default().clone();
+        config.congestion = CongestionController::FixedRate;
+        let e = autotune(
+            &[],
+            &mut config.clone(),
+            &config,
+            Compatibility::Level(5),
+            Duration::from_millis(100),
+            false,
+        )
+        .await
+        .unwrap_err();
+        assert!(e.to_string().contains("FixedRate"), "{e}");
+    }
+
+    #[cfg_attr(target_os = "macos", ignore)]
+    #[cfg_attr(target_os = "windows", ignore = "fails under Wine in CI")]
+    #[tokio::test]
+    async fn reverts_if_unsupported() {
+        let config = autotune_loopback(Compatibility::Level(4)).await;
+        assert_eq!(config.rx, 1_000_000);
+        assert_eq!(config.tx, 1_000_000);
+        assert_eq!(config.rtt, Configuration::system_default().rtt);
+    }
+}
@@ -1,8 +1,15 @@
 //! Job specifications for the client
 // (c) 2024 Ross Younger
 
-use std::{ffi::OsStr, path::Path, str::FromStr};
+use std::{
+    ffi::OsStr,
+    ops::Range,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::SystemTime,
+};
 
+use crate::PreserveSelection;
 use crate::os::{self, AbstractPlatform as _};
 use crate::protocol::control::Direction;
 
@@ -17,7 +24,7 @@ fn username_of(user_at_host: &str) -> Option<&str> {
 }
 
 /// A file source or destination specified by the user
-#[derive(Debug, Default, Clone, PartialEq, Eq)]
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash)]
 pub struct FileSpec {
     /// The remote `[user@]host` for the file. This may be a hostname or an IP address.
     /// It may also be a _hostname alias_ that matches a Host section in the user's ssh config file.
@@ -118,15 +125,15 @@ impl std::fmt::Display for FileSpec {
 }
 
 /// Details of a file copy job.
-#[derive(Debug, Default, Clone, PartialEq)]
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash)]
 pub struct CopyJobSpec {
     pub(crate) source: FileSpec,
     pub(crate) destination: FileSpec,
     /// The `[user@]host` part of whichever of the source or destination contained one.
     /// (There can be only one.)
     pub(crate) user_at_host: String,
-    /// If set, we want to preserve times and permissions as far as possible.
-    pub(crate) preserve: bool,
+    /// The file attributes we want to preserve, as far as possible.
+    pub(crate) preserve: PreserveSelection,
     /// Is this entry for a directory?
     ///
     /// **This flag does not provide any information regarding recursion.** That is up to the caller to determine from context.
@@ -134,6 +141,24 @@ pub struct CopyJobSpec {
     /// If present, Unix-style mode bits to apply to the target.
     /// (This currently only applies to directories.)
     pub(crate) mode: Option<u32>,
+    /// If present, the access time to apply to the target.
+    /// (This currently only applies to directories.)
+    pub(crate) atime: Option<SystemTime>,
+    /// If present, the modification time to apply to the target.
+    /// (This currently only applies to directories.)
+    pub(crate) mtime: Option<SystemTime>,
+    /// Extended attributes (names and values) to apply to the target.
+    /// (This currently only applies to directories.)
+    pub(crate) xattrs: Vec<(String, Vec<u8>)>,
+    /// If present, only this byte range of the file is to be transferred.
+    ///
+    /// This is used to stripe a large file across several streams.
+    pub(crate) range: Option<Range<u64>>,
+    /// If present, this entry is a symbolic link with this target, to be recreated as such at the destination.
+    pub(crate) link_target: Option<String>,
+    /// If present, this entry is a hard link to the file with this destination path.
+    /// That file is transferred first; this entry is then recreated as a link to it.
+    pub(crate) hard_link_target: Option<String>,
 }
 
 impl CopyJobSpec {
@@ -141,7 +166,7 @@ impl CopyJobSpec {
     pub(crate) fn try_new(
         source: FileSpec,
         destination: FileSpec,
-        preserve: bool,
+        preserve: PreserveSelection,
         directory: bool,
     ) -> anyhow::Result<Self> {
         if !(source.user_at_host.is_none() ^ destination.user_at_host.is_none()) {
@@ -159,6 +184,12 @@ impl CopyJobSpec {
             preserve,
             directory,
             mode: None,
+            atime: None,
+            mtime: None,
+            xattrs: Vec::new(),
+            range: None,
+            link_target: None,
+            hard_link_target: None,
         })
     }
 
@@ -171,7 +202,7 @@ impl CopyJobSpec {
     ) -> anyhow::Result<Self> {
         let source = FileSpec::from_str(source)?;
         let destination = FileSpec::from_str(destination)?;
-        Self::try_new(source, destination, preserve, directory)
+        Self::try_new(source, destination, preserve.into(), directory)
     }
 
     /// The hostname portion of whichever of the arguments contained one.
@@ -200,6 +231,20 @@ impl CopyJobSpec {
         let p = Path::new(s);
         p.file_name().unwrap_or_default()
     }
+
+    /// The path to the local copy of the file: the destination for a GET, the source for a PUT.
+    ///
+    /// If a GET destination is a directory, the source filename is appended.
+    pub(crate) fn local_path(&self) -> PathBuf {
+        if self.source.user_at_host.is_none() {
+            return PathBuf::from(&self.source.filename);
+        }
+        let mut path = PathBuf::from(&self.destination.filename);
+        if path.is_dir() {
+            path.push(self.display_filename());
+        }
+        path
+    }
 }
 
 #[cfg(test)]
@@ -296,6 +341,16 @@ mod test {
         assert_eq!(js.display_filename(), "file1");
     }
 
+    #[test]
+    fn local_path() {
+        let js = CopyJobSpec::from_parts("somedir/file1", "server:file2", false, false).unwrap();
+        assert_eq!(js.local_path(), std::path::Path::new("somedir/file1"));
+        let js = CopyJobSpec::from_parts("server:somedir/file1", "file2", false, false).unwrap();
+        assert_eq!(js.local_path(), std::path::Path::new("file2"));
+        let js = CopyJobSpec::from_parts("server:somedir/file1", ".", false, false).unwrap();
+        assert_eq!(js.local_path(), std::path::Path::new("./file1"));
+    }
+
     #[test]
     #[cfg(windows)]
     fn windows_local_paths() {
@@ -2,35 +2,45 @@
 // (c) 2024 Ross Younger
 
 use crate::{
-    FileSpec,
+    FileSpec, LinkMode, PreserveSelection,
     cli::{CliArgs, styles::use_colours},
     client::progress::SPINNER_TEMPLATE,
     config::{Configuration, Configuration_Optional, Manager},
     control::{ControlChannel, create, create_endpoint},
+    os::{AbstractPlatform as _, Platform},
     protocol::{
         FindTag, TaggedData,
         common::{ReceivingStream, SendReceivePair, SendingStream},
         compat::Feature,
         control::{ClosedownReportV1, Compatibility, CredentialsType, Direction, ServerMessageV2},
-        session::MetadataAttr,
+        session::{ListEntry, MetadataAttr},
     },
     session::{self, CommandStats, RequestResult, factory::TransferPhase},
     util::{
         self, Credentials, lookup_host_by_family,
         path::add_pathsep_if_needed,
         process::ProcessWrapper,
-        stats::format_rate,
+        rate_limit::RateLimiter,
+        stats::{format_rate, merge_connection_stats},
         time::{Stopwatch, StopwatchChain},
+        xattr::XattrSelection,
     },
 };
 
 use anyhow::{Context, Result};
 use async_trait::async_trait;
+use futures_util::{FutureExt as _, StreamExt as _, future::join_all, stream::FuturesUnordered};
+use human_repr::HumanCount as _;
 use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
-use quinn::{Connection as QuinnConnection, Endpoint};
+use quinn::{Connection as QuinnConnection, ConnectionStats, Endpoint};
 use std::{
+    collections::{HashMap, HashSet},
     net::{IpAddr, SocketAddr, SocketAddrV4, SocketAddrV6},
     path::MAIN_SEPARATOR,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
 };
 use tokio::{
     self,
@@ -39,11 +49,21 @@ use tokio::{
 };
 use tracing::{Instrument as _, debug, error, info, trace, trace_span, warn};
 
+#[cfg(unix)]
+use super::agent;
+use super::autotune;
 use super::job::CopyJobSpec;
+use super::manifest::ChecksumManifest;
+use super::migration::Migrator;
 
 /// a shared definition string used in a couple of places
 const SHOW_TIME: &str = "file transfer";
 
+/// How long to wait before the first reconnection attempt (see `--retry-connection`)
+const RECONNECT_DELAY_INITIAL: Duration = Duration::from_secs(1);
+/// The longest we wait between reconnection attempts
+const RECONNECT_DELAY_MAX: Duration = Duration::from_secs(30);
+
 /// Main client mode event loop
 ///
 /// # Return value
@@ -55,7 +75,33 @@ pub(crate) async fn client_main(
     display: MultiProgress,
     args: Box<crate::cli::CliArgs>,
 ) -> anyhow::Result<bool> {
-    Client::new(manager, display, args)?.run().await
+    let mut client = Client::new(manager, display, args)?;
+    // On interrupt, we drop the in-flight transfers, which cleans up any temporary files they were writing.
+    tokio::select! {
+        result = client.run() => result,
+        _ = tokio::signal::ctrl_c() => {
+            anyhow::bail!("Interrupted");
+        }
+    }
+}
+
+/// Connection agent mode: sets up a connection, then holds it open for other qcp processes to use.
+///
+/// # Return value
+/// `true` if the agent exited normally.
+#[cfg(unix)]
+pub(crate) async fn agent_main(
+    manager: Manager,
+    display: MultiProgress,
+    args: Box<crate::cli::CliArgs>,
+) -> anyhow::Result<bool> {
+    let mut client = Client::new(manager, display, args)?;
+    tokio::select! {
+        result = client.run_agent() => result,
+        _ = tokio::signal::ctrl_c() => {
+            anyhow::bail!("Interrupted");
+        }
+    }
 }
 
 struct Client {
@@ -68,41 +114,172 @@ struct Client {
     /// Before control channel negotiation, this is `None`.
     /// After negotiation, this holds the agreed configuration and may be assumed to be `Some`.
     negotiated: Option<Negotiated>,
+    /// Files transferred, if we were asked to write a checksum manifest
+    manifest: Option<ChecksumManifest>,
+    /// Jobs which have completed, so need not be run again if we have to reconnect
+    completed: Mutex<HashSet<CopyJobSpec>>,
+    /// In a recursive GET, the jobs found by listing the remote.
+    /// If we have to reconnect, we carry on with these rather than listing again.
+    listed_jobs: Mutex<Option<Vec<CopyJobSpec>>>,
+    /// Sizes of the source files we have looked up so far, by source filename
+    file_sizes: Mutex<HashMap<String, u64>>,
 }
 
 /// Items negotiated between client and server
 struct Negotiated {
     config: Configuration,
     compat: Compatibility,
+    /// Limits the rate of file data we send; shared by all the session's connections and streams
+    rate_limiter: Option<Arc<RateLimiter>>,
+}
+
+impl Negotiated {
+    fn new(config: Configuration, compat: Compatibility) -> Self {
+        let rate_limiter = RateLimiter::shared(config.limit_rate_tx);
+        Self {
+            config,
+            compat,
+            rate_limiter,
+        }
+    }
 }
 
 #[derive(Debug, PartialEq)]
 struct PrepResult {
     remote_address: IpAddr,
+    /// The remote host, as given by the user (i.e. before resolving any ssh aliases)
+    remote_host: String,
+    direction: Direction,
     job_specs: Vec<CopyJobSpec>,
     full_success: bool,
 }
 
 impl PrepResult {
-    fn primary_job(&self) -> &CopyJobSpec {
-        self.job_specs
-            .first()
-            .expect("prep should always produce at least one job")
-    }
-
     fn remote_host(&self) -> &str {
-        self.primary_job().remote_host()
+        &self.remote_host
     }
 
     fn direction(&self) -> Direction {
-        self.primary_job().direction()
+        self.direction
     }
 
     fn preserve(&self) -> bool {
-        self.primary_job().preserve
+        self.job_specs.first().is_some_and(|job| job.preserve.any())
     }
 }
 
+/// The combined results of the connection attempts made by a run
+#[derive(Default)]
+struct Outcome {
+    success: bool,
+    stats: CommandStats,
+    remote_stats: ClosedownReportV1,
+    /// One entry per QUIC connection, across all attempts
+    connection_stats: Vec<ConnectionStats>,
+}
+
+/// How a connection attempt ended
+enum Attempt {
+    /// All the jobs have been run (successfully or not)
+    Complete,
+    /// Dry run mode: we stopped after the control channel
+    DryRun,
+    /// The connection was lost, or could not be established
+    Lost(anyhow::Error),
+}
+
+/// Lists the transport options given on the command line which cannot apply to a connection held by an agent.
+///
+/// `agent` is the configuration of the agent's connection.
+#[cfg(unix)]
+fn ignored_transport_options(
+    cli: &Configuration_Optional,
+    agent: &Configuration,
+) -> Vec<&'static str> {
+    let mut ignored = Vec::new();
+    // These were negotiated, so we know what the agent's connection uses
+    macro_rules! differs {
+        ($($field:ident),*) => {$(
+            if cli.$field.is_some_and(|v| v != agent.$field) {
+                ignored.push(stringify!($field));
+            }
+        )*};
+    }
+    // These belong to the local end of the connection, which the agent set up
+    macro_rules! given {
+        ($($field:ident),*) => {$(
+            if cli.$field.is_some() {
+                ignored.push(stringify!($field));
+            }
+        )*};
+    }
+    differs!(rx, rtt, congestion, initial_congestion_window, connections);
+    // tx defaults to rx
+    if cli.tx.is_some_and(|v| v != agent.tx()) {
+        ignored.push("tx");
+    }
+    given!(
+        port,
+        udp_buffer,
+        packet_threshold,
+        time_threshold,
+        initial_mtu,
+        min_mtu,
+        max_mtu
+    );
+    ignored
+}
+
+/// Formats the result of a Stat request, for output
+fn format_stat(path: &FileSpec, entry: &ListEntry) -> String {
+    use std::fmt::Write as _;
+    let mut out = format!(
+        "{path}: {}",
+        if entry.directory { "directory" } else { "file" }
+    );
+    if !entry.directory {
+        let _ = write!(out, ", {} bytes", entry.size.0);
+    }
+    if let Some(mode) = entry.attributes.find_tag(MetadataAttr::ModeBits) {
+        let _ = write!(out, ", mode {:04o}", mode.coerce_unsigned());
+    }
+    if let Some(mtime) = entry
+        .attributes
+        .find_tag(MetadataAttr::ModificationTime)
+        .and_then(|t| i64::try_from(t.coerce_unsigned()).ok())
+        .and_then(|t| chrono::DateTime::from_timestamp(t, 0))
+    {
+        let _ = write!(
+            out,
+            ", modified {}",
+            mtime
+                .with_timezone(&chrono::Local)
+                .format("%Y-%m-%d %H:%M:%S")
+        );
+    }
+    out
+}
+
+/// How long to wait before reconnecting, given the number of reconnections so far
+fn reconnect_delay(reconnects: u16) -> Duration {
+    RECONNECT_DELAY_INITIAL
+        .saturating_mul(1 << reconnects.min(8))
+        .min(RECONNECT_DELAY_MAX)
+}
+
+/// Decides what to do about an error while setting up a connection.
+///
+/// Transport failures and lost connections might be helped by trying again;
+/// anything else (for example, the remote refusing our request) is fatal.
+fn setup_failure(e: anyhow::Error) -> anyhow::Result<Attempt> {
+    let lost = e.chain().any(|cause| {
+        cause.is::<std::io::Error>()
+            || cause.is::<tokio::time::error::Elapsed>()
+            || cause.is::<quinn::ConnectionError>()
+    });
+    if lost { Ok(Attempt::Lost(e)) } else { Err(e) }
+}
+
 type ControlChannelType = ControlChannel<ChildStdin, ChildStdout>;
 
 #[async_trait]
@@ -125,10 +302,50 @@ impl BiStreamOpener for QuinnConnection {
     }
 }
 
+/// A set of QUIC connections to the same server, which share out the streams between them
+struct ConnectionSet<C> {
+    connections: Vec<C>,
+    next: AtomicUsize,
+}
+
+impl<C> ConnectionSet<C> {
+    fn new(connections: Vec<C>) -> Self {
+        assert!(
+            !connections.is_empty(),
+            "at least one connection is required"
+        );
+        Self {
+            connections,
+            next: AtomicUsize::new(0),
+        }
+    }
+}
+
+#[async_trait]
+impl<C: BiStreamOpener + Send + Sync> BiStreamOpener for ConnectionSet<C> {
+    type Send = C::Send;
+    type Recv = C::Recv;
+
+    /// Opens a stream on the next connection in turn.
+    /// If that connection has no free streams right now, but another does, uses that one instead.
+    async fn open_bi_stream(&self) -> Result<SendReceivePair<Self::Send, Self::Recv>> {
+        let n = self.connections.len();
+        let first = self.next.fetch_add(1, Ordering::Relaxed) % n;
+        for i in 0..n {
+            let conn = &self.connections[(first + i) % n];
+            if let Some(Ok(stream)) = conn.open_bi_stream().now_or_never() {
+                return Ok(stream);
+            }
+        }
+        self.connections[first].open_bi_stream().await
+    }
+}
+
 struct QcpConnection {
     ssh_client: ProcessWrapper,
     control: ControlChannelType,
-    endpoint: Option<Endpoint>,
+    /// One endpoint per QUIC connection
+    endpoints: Vec<Endpoint>,
     server_message: ServerMessageV2,
 }
 
@@ -140,7 +357,7 @@ impl TryFrom<ProcessWrapper> for QcpConnection {
             ssh_client: client,
             control,
             server_message: ServerMessageV2::default(),
-            endpoint: None,
+            endpoints: Vec::new(),
         })
     }
 }
@@ -162,8 +379,12 @@ impl Client {
             credentials: Credentials::generate()?,
             timers: StopwatchChain::default(),
             spinner,
+            manifest: args.checksum_manifest.as_deref().map(ChecksumManifest::new),
             args,
             negotiated: None,
+            completed: Mutex::default(),
+            listed_jobs: Mutex::default(),
+            file_sizes: Mutex::default(),
         })
     }
 
@@ -173,21 +394,10 @@ impl Client {
     /// `true` if the requested operation succeeded.
     ///
     // Caution: As we are using ProgressBar, anything to be printed to console should use progress.println() !
+    #[allow(clippy::too_many_lines)]
     pub(crate) async fn run(&mut self) -> anyhow::Result<bool> {
         self.timers.next("Setup");
-        let working_config = self
-            .manager
-            .get::<Configuration_Optional>()
-            .unwrap_or_default();
-
-        util::setup_tracing(
-            util::trace_level(&self.args.client_params),
-            util::ConsoleTraceType::Indicatif(self.display.clone()),
-            self.args.log_file.as_ref(),
-            working_config.time_format.unwrap_or_default(),
-            use_colours(),
-        )?; // to provoke error: set RUST_LOG=.
-
+        let working_config = self.setup()?;
         let default_config = Configuration::system_default();
 
         let prep_result = {
@@ -195,11 +405,97 @@ impl Client {
             self.prep(&working_config, default_config)?
         };
 
+        #[cfg(unix)]
+        if let Some(agent) = self.find_agent(&working_config, &prep_result).await {
+            return self.run_with_agent(&agent, &prep_result).await;
+        }
+
+        // Autotune mode measures the link, so the window sizes must not limit it
+        let configured = if self.args.client_params.autotune {
+            let configured = self.manager.get::<Configuration>()?;
+            autotune::ensure_not_fixed_rate(configured.congestion)?;
+            self.manager.merge_provider(autotune::probe_config());
+            Some(configured)
+        } else {
+            None
+        };
+
+        let retries = self.args.client_params.retry_connection;
+        let mut outcome = Outcome::default();
+        let mut reconnects = 0u16;
+        loop {
+            match self
+                .connect_and_transfer(
+                    &working_config,
+                    &prep_result,
+                    configured.as_ref(),
+                    &mut outcome,
+                )
+                .await?
+            {
+                Attempt::Complete => break,
+                Attempt::DryRun => return Ok(prep_result.full_success),
+                Attempt::Lost(e) if reconnects < retries => {
+                    let delay = reconnect_delay(reconnects);
+                    reconnects += 1;
+                    warn!(
+                        "Connection lost: {e:#}; reconnecting in {delay:?} (attempt {reconnects} of {retries})"
+                    );
+                    self.spinner.set_message("Waiting to reconnect");
+                    self.timers.next("reconnect");
+                    tokio::time::sleep(delay).await;
+                }
+                Attempt::Lost(e) => return Err(e),
+            }
+        }
+
+        if let Some(manifest) = &self.manifest {
+            manifest.write()?;
+        }
+
+        // Post-transfer chatter -----------
+        if !self.args.client_params.quiet && !self.args.stat {
+            if reconnects > 0 {
+                info!("Reconnected {reconnects} time(s) after losing the connection");
+            }
+            let transport_time = self.timers.total(SHOW_TIME);
+            crate::util::stats::process_statistics(
+                &merge_connection_stats(&outcome.connection_stats),
+                outcome.stats,
+                transport_time,
+                &outcome.remote_stats,
+                &self.negotiated.as_ref().unwrap().config,
+                self.args.client_params.statistics,
+                prep_result.direction(),
+            );
+        }
+
+        if self.args.client_params.profile {
+            info!("Elapsed time by phase:\n{}", self.timers);
+        }
+        self.display.clear()?;
+        Ok(outcome.success & prep_result.full_success)
+    }
+
+    /// Connects to the remote and runs whichever jobs have not yet completed, adding the results to `outcome`.
+    ///
+    /// If the connection is lost along the way, returns [`Attempt::Lost`] so the caller may try again.
+    /// Err(...) is reserved for errors which would not be helped by reconnecting.
+    async fn connect_and_transfer(
+        &mut self,
+        working_config: &Configuration_Optional,
+        prep_result: &PrepResult,
+        configured: Option<&Configuration>,
+        outcome: &mut Outcome,
+    ) -> anyhow::Result<Attempt> {
         // Control channel ---------------
-        let (config, mut qcp_conn) = self
-            .establish_control_channel(&working_config, &prep_result)
+        let (mut config, mut qcp_conn) = match self
+            .establish_control_channel(working_config, prep_result)
             .await
-            .context("while establishing control channel")?;
+        {
+            Ok(result) => result,
+            Err(e) => return setup_failure(e.context("while establishing control channel")),
+        };
 
         // Dry run mode ends here! -------
         if self.args.client_params.dry_run {
@@ -208,51 +504,177 @@ impl Client {
                 "Negotiated network configuration: {}",
                 config.format_transport_config()
             );
-            return Ok(prep_result.full_success);
+            return Ok(Attempt::DryRun);
         }
 
         // Data channel ------------------
 
-        let connection = self
-            .establish_data_channel(&prep_result, &config, &mut qcp_conn)
+        let connections = match self
+            .establish_data_channel(prep_result, &config, &mut qcp_conn)
+            .await
+        {
+            Ok(connections) => connections,
+            Err(e) => return setup_failure(e),
+        };
+
+        if let Some(configured) = configured {
+            self.spinner.set_message("Measuring network link");
+            self.timers.next("autotune");
+            autotune::autotune(
+                &connections,
+                &mut config,
+                configured,
+                qcp_conn.control.selected_compat,
+                autotune::MIN_PROBE_DURATION,
+                self.args.client_params.autotune_reduce,
+            )
             .await?;
+        }
+
+        let migrators: Vec<_> = qcp_conn
+            .endpoints
+            .iter()
+            .zip(&connections)
+            .map(|(ep, conn)| Migrator::spawn(ep.clone(), conn.clone(), &config))
+            .collect();
+        let connection_set = ConnectionSet::new(connections.clone());
 
         // Show time! ---------------------
 
-        let direction = prep_result.direction();
         self.spinner.set_message("Transferring data");
         self.timers.next(SHOW_TIME);
-        self.negotiated = Some(Negotiated {
-            config,
-            compat: qcp_conn.control.selected_compat,
-        });
-        let (overall_success, aggregate_stats) = self
+        self.negotiated = Some(Negotiated::new(config, qcp_conn.control.selected_compat));
+        let result = self
             .process_job_requests(
                 &prep_result.job_specs,
-                || connection.open_bi_stream(),
+                || connection_set.open_bi_stream(),
                 |stream_pair, job, filename_width, pass| {
                     self.run_request(stream_pair, job, filename_width, pass)
                 },
             )
-            .await?;
+            .await;
+        let migrations = migrators.into_iter().map(Migrator::stop).sum();
+        outcome
+            .connection_stats
+            .extend(connections.iter().map(QuinnConnection::stats));
+
+        // A failed job may have been the result of losing the connection, which we can recover from
+        if let Some(reason) = connections.iter().find_map(QuinnConnection::close_reason) {
+            if let Ok((_, stats)) = &result {
+                outcome.stats.accumulate(stats);
+            }
+            for ep in &qcp_conn.endpoints {
+                ep.close(0u32.into(), "connection lost".as_bytes());
+            }
+            // Dropping the connection tidies up the ssh process
+            return Ok(Attempt::Lost(reason.into()));
+        }
+        let (success, stats) = result?;
+        outcome.success = success;
+        outcome.stats.accumulate(&stats);
 
         // Closedown ----------------------
-        let remote_stats = self.closedown(qcp_conn).await?;
+        outcome.remote_stats = self.closedown(qcp_conn, migrations).await?;
+        Ok(Attempt::Complete)
+    }
 
-        // Post-transfer chatter -----------
-        if !self.args.client_params.quiet {
-            let transport_time = self.timers.find(SHOW_TIME).and_then(Stopwatch::elapsed);
-            crate::util::stats::process_statistics(
-                &connection.stats(),
-                aggregate_stats,
-                transport_time,
-                &remote_stats,
-                &self.negotiated.as_ref().unwrap().config,
-                self.args.client_params.statistics,
-                direction,
+    /// Reads the working configuration and sets up tracing
+    fn setup(&self) -> anyhow::Result<Configuration_Optional> {
+        let working_config = self
+            .manager
+            .get::<Configuration_Optional>()
+            .unwrap_or_default();
+
+        util::setup_tracing(
+            util::trace_level(&self.args.client_params),
+            util::ConsoleTraceType::Indicatif(self.display.clone()),
+            self.args.log_file.as_ref(),
+            working_config.time_format.unwrap_or_default(),
+            use_colours(),
+        )?; // to provoke error: set RUST_LOG=.
+        Ok(working_config)
+    }
+
+    /// Looks for a connection agent we can use for this transfer
+    #[cfg(unix)]
+    async fn find_agent(
+        &self,
+        working_config: &Configuration_Optional,
+        prep_result: &PrepResult,
+    ) -> Option<agent::AgentClient> {
+        let params = &self.args.client_params;
+        // These modes need a connection of their own
+        if params.no_agent || params.dry_run || params.remote_config || params.autotune {
+            return None;
+        }
+        let key = agent::host_key(
+            working_config.remote_user.as_deref().unwrap_or_default(),
+            prep_result.remote_host(),
+        );
+        agent::AgentClient::find(&key).await
+    }
+
+    /// Runs the transfer on a connection held by an agent
+    #[cfg(unix)]
+    async fn run_with_agent(
+        &mut self,
+        agent: &agent::AgentClient,
+        prep_result: &PrepResult,
+    ) -> anyhow::Result<bool> {
+        self.spinner.set_message("Contacting connection agent");
+        self.timers.next("connection agent");
+        let session = agent
+            .session()
+            .await
+            .context("while contacting connection agent")?;
+        debug!("Using connection agent for {}", session.host);
+
+        // The agent's server message holds the negotiated configuration, just as if we had connected ourselves
+        self.manager.merge_provider(&session.server_message);
+        self.manager.apply_system_default();
+        let compat = Compatibility::from(session.compatibility);
+        let mut config = self
+            .manager
+            .get::<Configuration>()
+            .context("assembling final client configuration from connection agent")?;
+        self.check_negotiated(prep_result, &mut config, compat);
+        let ignored = ignored_transport_options(&self.args.config, &config);
+        if !ignored.is_empty() {
+            warn!(
+                "The connection agent has already set up the connection, so these options have no effect: {} (use --no-agent to apply them)",
+                ignored.join(", ")
             );
         }
 
+        self.spinner.set_message("Transferring data");
+        self.timers.next(SHOW_TIME);
+        self.negotiated = Some(Negotiated::new(config, compat));
+        let (overall_success, aggregate_stats) = self
+            .process_job_requests(
+                &prep_result.job_specs,
+                || agent.open_stream(),
+                |stream_pair, job, filename_width, pass| {
+                    self.run_request(stream_pair, job, filename_width, pass)
+                },
+            )
+            .await?;
+        self.timers.stop();
+
+        if let Some(manifest) = &self.manifest {
+            manifest.write()?;
+        }
+        // The connection statistics belong to the agent, so we can only report on the files.
+        if !self.args.client_params.quiet && aggregate_stats.payload_bytes != 0 {
+            let transport_time = self.timers.find(SHOW_TIME).and_then(Stopwatch::elapsed);
+            info!(
+                "Transferred {}",
+                format_rate(
+                    aggregate_stats.payload_bytes,
+                    transport_time,
+                    aggregate_stats.peak_transfer_rate,
+                )
+            );
+        }
         if self.args.client_params.profile {
             info!("Elapsed time by phase:\n{}", self.timers);
         }
@@ -260,6 +682,99 @@ impl Client {
         Ok(overall_success & prep_result.full_success)
     }
 
+    /// Connection agent main loop
+    #[cfg(unix)]
+    async fn run_agent(&mut self) -> anyhow::Result<bool> {
+        self.timers.next("Setup");
+        let working_config = self.setup()?;
+        let prep_result = self.prep_agent(&working_config, Configuration::system_default())?;
+        let key = agent::host_key(
+            working_config.remote_user.as_deref().unwrap_or_default(),
+            prep_result.remote_host(),
+        );
+        // Claim the socket first, so a duplicate agent fails early
+        let (listener, socket) = agent::listen(&key)?;
+        let result = self
+            .serve_agent(listener, &key, &working_config, &prep_result)
+            .await;
+        let _ = std::fs::remove_file(&socket);
+        result
+    }
+
+    #[cfg(unix)]
+    fn prep_agent(
+        &mut self,
+        working_config: &Configuration_Optional,
+        default_config: &Configuration,
+    ) -> anyhow::Result<PrepResult> {
+        self.spinner.set_message("Preparing");
+        self.spinner.enable_steady_tick(Duration::from_millis(150));
+
+        let remote_host = self
+            .args
+            .remote_host_lossy()?
+            .context("a remote host is required, e.g. `qcp --agent myserver:`")?
+            .to_string();
+        let remote_address = Self::resolve_remote(working_config, default_config, &remote_host)?;
+        Ok(PrepResult {
+            remote_address,
+            remote_host,
+            // We do not know which way future transfers will go
+            direction: Direction::Both,
+            job_specs: Vec::new(),
+            full_success: true,
+        })
+    }
+
+    #[cfg(unix)]
+    async fn serve_agent(
+        &mut self,
+        listener: tokio::net::UnixListener,
+        key: &str,
+        working_config: &Configuration_Optional,
+        prep_result: &PrepResult,
+    ) -> anyhow::Result<bool> {
+        let (config, mut qcp_conn) = self
+            .establish_control_channel(working_config, prep_result)
+            .await
+            .context("while establishing control channel")?;
+        let connections = self
+            .establish_data_channel(prep_result, &config, &mut qcp_conn)
+            .await?;
+        let migrators: Vec<_> = qcp_conn
+            .endpoints
+            .iter()
+            .zip(&connections)
+            .map(|(ep, conn)| Migrator::spawn(ep.clone(), conn.clone(), &config))
+            .collect();
+        let connection_set = Arc::new(ConnectionSet::new(connections.clone()));
+
+        let session = agent::AgentSession {
+            host: key.to_string(),
+            compatibility: qcp_conn.control.selected_compat.into(),
+            server_message: qcp_conn.server_message.clone(),
+            active_streams: 0,
+        };
+        let idle_timeout = config.agent_timeout_duration();
+        self.negotiated = Some(Negotiated::new(config, qcp_conn.control.selected_compat));
+        self.spinner.finish_and_clear();
+        self.timers.next("serving");
+        info!("Connection agent for {key} is ready");
+
+        tokio::select! {
+            result = agent::serve(listener, session, || {
+                let connection_set = connection_set.clone();
+                async move { connection_set.open_bi_stream().await }
+            }, idle_timeout) => result?,
+            e = connections[0].closed() => anyhow::bail!("connection to {key} was lost: {e}"),
+        }
+
+        let migrations = migrators.into_iter().map(Migrator::stop).sum();
+        let _ = self.closedown(qcp_conn, migrations).await?;
+        self.display.clear()?;
+        Ok(true)
+    }
+
     pub(crate) fn prep(
         &mut self,
         working_config: &Configuration_Optional,
@@ -272,12 +787,32 @@ impl Client {
         self.spinner.set_message("Preparing");
         self.spinner.enable_steady_tick(Duration::from_millis(150));
 
-        let (full_success, job_specs) = self.args.jobspecs()?;
-        let remote_ssh_hostname = job_specs
+        let (full_success, job_specs) = if self.args.stat {
+            (true, self.args.stat_jobspecs()?)
+        } else {
+            self.args.jobspecs()?
+        };
+        let primary_job = job_specs
             .first()
-            .expect("at least one job spec is required")
-            .remote_host();
+            .expect("at least one job spec is required");
+        let remote_host = primary_job.remote_host().to_string();
+        let direction = primary_job.direction();
+        let remote_address = Self::resolve_remote(working_config, default_config, &remote_host)?;
+        Ok(PrepResult {
+            remote_address,
+            remote_host,
+            direction,
+            job_specs,
+            full_success,
+        })
+    }
 
+    /// Looks up the address of the remote host
+    fn resolve_remote(
+        working_config: &Configuration_Optional,
+        default_config: &Configuration,
+        remote_ssh_hostname: &str,
+    ) -> anyhow::Result<IpAddr> {
         let ssh_config_files = super::ssh::SshConfigFiles::new(
             working_config
                 .ssh_config
@@ -291,17 +826,12 @@ impl Client {
 
         // If the user didn't specify the address family: we do the DNS lookup, figure it out and tell ssh to use that.
         // (Otherwise if we resolved a v4 and ssh a v6 - as might happen with round-robin DNS - that could be surprising.)
-        let remote_address = lookup_host_by_family(
+        lookup_host_by_family(
             &remote_dns_name,
             working_config
                 .address_family
                 .unwrap_or(default_config.address_family),
-        )?;
-        Ok(PrepResult {
-            remote_address,
-            job_specs,
-            full_success,
-        })
+        )
     }
 
     async fn establish_control_channel(
@@ -334,16 +864,51 @@ impl Client {
             )
             .await?;
 
-        let config = self
+        let mut config = self
             .manager
             .get::<Configuration>()
             .context("assembling final client configuration from server message")?;
+        self.check_negotiated(prep_result, &mut config, qcp_conn.control.selected_compat);
+        Ok((config, qcp_conn))
+    }
 
-        // Are any warnings necessary?
-        if prep_result.preserve() && !qcp_conn.control.selected_compat.supports(Feature::PRESERVE) {
+    /// Issues any necessary warnings about the negotiated configuration, and adjusts it for what the remote supports
+    fn check_negotiated(
+        &self,
+        prep_result: &PrepResult,
+        config: &mut Configuration,
+        compat: Compatibility,
+    ) {
+        if prep_result.preserve() && !compat.supports(Feature::PRESERVE) {
             warn!("--preserve requested, but remote does not support this option");
         }
-        Ok((config, qcp_conn))
+        if self.args.client_params.resume && !compat.supports(Feature::RESUME) {
+            warn!("--resume requested, but remote does not support this option");
+        }
+        if !self.args.client_params.links.follows() && !compat.supports(Feature::SYMLINKS) {
+            warn!(
+                "--links requested, but remote does not support this option; links to files will be followed, others skipped"
+            );
+        }
+        if self.args.client_params.hard_links && !compat.supports(Feature::HARD_LINKS) {
+            warn!("--hard-links requested, but remote does not support this option");
+        }
+        if (self.args.client_params.xattrs || self.args.client_params.acls)
+            && !compat.supports(Feature::EXTENDED_ATTRIBUTES)
+        {
+            warn!("--xattrs or --acls requested, but remote does not support this option");
+        }
+        if config.limit_rate_rx > 0 && !compat.supports(Feature::RATE_LIMIT) {
+            warn!("--limit-rate-rx requested, but remote does not support this option");
+        }
+        if config.connections > 1 && !compat.supports(Feature::MULTIPLE_CONNECTIONS) {
+            debug!("Remote does not support multiple connections; using one");
+            config.connections = 1;
+        }
+        if config.parallel_streams > 1 && !compat.supports(Feature::PARALLEL_STREAMS) {
+            debug!("Remote does not support parallel streams; using one at a time");
+            config.parallel_streams = 1;
+        }
     }
 
     async fn establish_data_channel(
@@ -351,30 +916,40 @@ impl Client {
         prep_result: &PrepResult,
         config: &Configuration,
         qcp_conn: &mut QcpConnection,
-    ) -> anyhow::Result<QuinnConnection> {
-        let message1 = &qcp_conn.server_message;
-        let server_address_port = match prep_result.remote_address {
-            std::net::IpAddr::V4(ip) => SocketAddrV4::new(ip, message1.port).into(),
-            std::net::IpAddr::V6(ip) => SocketAddrV6::new(ip, message1.port, 0, 0).into(),
-        };
+    ) -> anyhow::Result<Vec<QuinnConnection>> {
+        self.spinner.enable_steady_tick(Duration::from_millis(150));
+        self.spinner.set_message("Establishing data channel");
+        self.timers.next("data channel setup");
 
-        let endpoint = self.create_quic_endpoint(
-            prep_result,
-            config,
-            &message1.credentials,
-            server_address_port,
-            qcp_conn.control.selected_compat,
-        )?;
+        let message1 = &qcp_conn.server_message;
+        let mut connecting = Vec::new();
+        // Each connection has its own endpoint, so its own local port
+        for port in message1.ports() {
+            let server_address_port = match prep_result.remote_address {
+                std::net::IpAddr::V4(ip) => SocketAddrV4::new(ip, port).into(),
+                std::net::IpAddr::V6(ip) => SocketAddrV6::new(ip, port, 0, 0).into(),
+            };
+            let endpoint = self.create_quic_endpoint(
+                prep_result,
+                config,
+                &message1.credentials,
+                server_address_port,
+                qcp_conn.control.selected_compat,
+            )?;
+            debug!("Opening QUIC connection to {server_address_port:?}");
+            connecting.push(endpoint.connect(server_address_port, &message1.common_name)?);
+            qcp_conn.endpoints.push(endpoint);
+        }
 
-        debug!("Opening QUIC connection to {server_address_port:?}");
-        let connection = timeout(
-            config.timeout_duration(),
-            endpoint.connect(server_address_port, &message1.common_name)?,
-        )
-        .await
-        .context("UDP connection to QUIC endpoint timed out")??;
-        qcp_conn.endpoint = Some(endpoint);
-        Ok(connection)
+        let connections = timeout(config.timeout_duration(), join_all(connecting))
+            .await
+            .context("UDP connection to QUIC endpoint timed out")?
+            .into_iter()
+            .collect::<Result<Vec<_>, _>>()?;
+        if connections.len() > 1 {
+            debug!("Opened {} QUIC connections", connections.len());
+        }
+        Ok(connections)
     }
 
     fn create_quic_endpoint(
@@ -385,9 +960,6 @@ impl Client {
         server_address_port: SocketAddr,
         compat: Compatibility,
     ) -> anyhow::Result<Endpoint> {
-        self.spinner.enable_steady_tick(Duration::from_millis(150));
-        self.spinner.set_message("Establishing data channel");
-        self.timers.next("data channel setup");
         let (endpoint, _) = create_endpoint(
             &self.credentials,
             peer_credentials,
@@ -404,23 +976,49 @@ impl Client {
     async fn closedown(
         &mut self,
         mut conn: QcpConnection, // ctrl_result is consumed
+        migrations: u64,
     ) -> anyhow::Result<ClosedownReportV1> {
         let config = &self.negotiated.as_ref().unwrap().config;
         self.timers.next("shutdown");
         self.spinner.set_message("Shutting down");
         // Forcibly (but gracefully) tear down QUIC. All the requests have completed or errored.
-        let endpoint = conn.endpoint.take();
-        if let Some(ref ep) = endpoint {
+        let endpoints = std::mem::take(&mut conn.endpoints);
+        for ep in &endpoints {
             trace!("Closing QUIC endpoint");
             ep.close(0u32.into(), "finished".as_bytes());
         }
-        let remote_stats = conn.control.read_closedown_report().await?;
+        let report = timeout(
+            config.timeout_duration(),
+            conn.control.read_closedown_report(),
+        )
+        .await;
+        let remote_stats = match report {
+            Ok(Ok(report)) => report,
+            // The control channel cannot follow a migrated connection, so it may have been lost along the way.
+            // The transfer itself has completed, so this is not fatal.
+            Ok(Err(e)) if migrations > 0 => {
+                warn!(
+                    "Control channel lost after the connection migrated; remote statistics are not available ({e})"
+                );
+                ClosedownReportV1::default()
+            }
+            Ok(Err(e)) => return Err(e),
+            Err(_) => {
+                warn!(
+                    "Timed out waiting for the remote to report; remote statistics are not available"
+                );
+                ClosedownReportV1::default()
+            }
+        };
 
         let control_fut = conn.ssh_client.close();
-        if let Some(ep) = endpoint {
-            let _ = timeout(config.timeout_duration(), ep.wait_idle())
-                .await
-                .inspect_err(|_| warn!("QUIC shutdown timed out")); // otherwise ignore errors
+        if !endpoints.is_empty() {
+            let _ = timeout(
+                config.timeout_duration(),
+                join_all(endpoints.iter().map(Endpoint::wait_idle)),
+            )
+            .await
+            .inspect_err(|_| warn!("QUIC shutdown timed out")); // otherwise ignore errors
         }
         trace!("QUIC closed; waiting for control channel");
         let _ = timeout(config.timeout_duration(), control_fut)
@@ -452,8 +1050,8 @@ impl Client {
             "logic error: run_request called before negotiation completed"
         );
         match pass {
-            TransferPhase::Pre => {
-                self.manage_pre_transfer_request(stream_pair, &copy_spec)
+            TransferPhase::Pre | TransferPhase::Stat | TransferPhase::FreeSpace => {
+                self.manage_query_request(stream_pair, &copy_spec, pass)
                     .await
             }
             TransferPhase::Transfer => {
@@ -467,10 +1065,12 @@ impl Client {
         }
     }
 
-    async fn manage_pre_transfer_request<S, R>(
+    /// Asks the remote about files (List, Stat or FreeSpace), without transferring anything
+    async fn manage_query_request<S, R>(
         &self,
         stream_pair: SendReceivePair<S, R>,
         copy_spec: &CopyJobSpec,
+        pass: TransferPhase,
     ) -> Result<RequestResult>
     where
         S: SendingStream + 'static,
@@ -478,14 +1078,15 @@ impl Client {
     {
         let negotiated = self.negotiated.as_ref().unwrap(); // checked in run_request
         assert!(
-            copy_spec.source.user_at_host.is_some(),
-            "logic error: manage_pre_transfer_request called for local source"
+            copy_spec.source.user_at_host.is_some()
+                || matches!(pass, TransferPhase::Stat | TransferPhase::FreeSpace),
+            "logic error: manage_query_request called to list a local source"
         );
 
         let (mut cmd, _span_info) = session::factory::client_sender(
             stream_pair,
             copy_spec,
-            TransferPhase::Pre,
+            pass,
             negotiated.compat,
             &self.args.client_params,
             self.ui(0),
@@ -517,6 +1118,7 @@ impl Client {
             self.ui(filename_width),
             &negotiated.config,
         );
+        cmd.set_rate_limiter(negotiated.rate_limiter.clone());
         let span = trace_span!(
             "transfer",
             name = span_info.name,
@@ -564,7 +1166,9 @@ impl Client {
         let negotiated = self.negotiated.as_ref().unwrap(); // checked in run_request
         let destination_is_remote = copy_spec.destination.user_at_host.is_some();
 
-        if destination_is_remote && copy_spec.preserve && copy_spec.directory {
+        let xattrs = XattrSelection::from_params(&self.args.client_params).any();
+
+        if destination_is_remote && (copy_spec.preserve.any() || xattrs) && copy_spec.directory {
             let (mut cmd, _span_info) = session::factory::client_sender(
                 stream_pair,
                 copy_spec,
@@ -576,6 +1180,25 @@ impl Client {
             );
             return cmd.send(copy_spec, self.args.client_params).await;
         }
+        if !destination_is_remote {
+            // Extended attributes and times first, in case the new permissions prevent us from opening the directory
+            let path = std::path::Path::new(&copy_spec.destination.filename);
+            for warning in util::xattr::apply_to_directory(path, copy_spec.xattrs.clone()).await {
+                warn!("{}: {warning}", copy_spec.destination.filename);
+            }
+        }
+        if !destination_is_remote && (copy_spec.atime.is_some() || copy_spec.mtime.is_some()) {
+            let mut times = std::fs::FileTimes::new();
+            if let Some(t) = copy_spec.atime {
+                times = times.set_accessed(t);
+            }
+            if let Some(t) = copy_spec.mtime {
+                times = times.set_modified(t);
+            }
+            let path = std::path::PathBuf::from(&copy_spec.destination.filename);
+            tokio::task::spawn_blocking(move || Platform::set_directory_times(&path, times))
+                .await??;
+        }
         if !destination_is_remote && let Some(mode) = copy_spec.mode {
             let perms = tokio::fs::metadata(&copy_spec.destination.filename)
                 .await
@@ -603,12 +1226,12 @@ impl Client {
     async fn process_job_requests<S, R, OpenStream, JobRunner>(
         &self,
         jobs_in: &[CopyJobSpec],
-        mut open_stream: OpenStream,
-        mut run_job: JobRunner,
+        open_stream: OpenStream,
+        run_job: JobRunner,
     ) -> anyhow::Result<(bool, CommandStats)>
     where
-        OpenStream: AsyncFnMut() -> anyhow::Result<SendReceivePair<S, R>>,
-        JobRunner: AsyncFnMut(
+        OpenStream: AsyncFn() -> anyhow::Result<SendReceivePair<S, R>>,
+        JobRunner: AsyncFn(
             SendReceivePair<S, R>,
             CopyJobSpec,
             usize,
@@ -622,25 +1245,297 @@ impl Client {
             .is_some_and(|j| j.destination.user_at_host.is_some());
         let recurse: bool = self.args.client_params.recurse;
 
-        if !destination_is_remote && recurse {
-            self.process_recursive_get(jobs_in, async move || open_stream().await, &mut run_job)
+        if self.args.stat {
+            self.process_stat_requests(jobs_in, &open_stream, &run_job)
+                .await
+        } else if !destination_is_remote && recurse {
+            self.process_recursive_get(jobs_in, open_stream, run_job)
+                .await
+        } else {
+            self.check_free_space(jobs_in, &open_stream, &run_job)
+                .await?;
+            self.process_file_transfers(jobs_in, &open_stream, &run_job)
+                .await
+        }
+    }
+
+    /// Checks that the files to be transferred will fit at the destination.
+    ///
+    /// This is a pre-flight check for non-recursive GET, and for PUT; recursive GET
+    /// learns the file sizes from the remote listing, so checks for itself.
+    async fn check_free_space<S, R, OpenStream, JobRunner>(
+        &self,
+        jobs: &[CopyJobSpec],
+        open_stream: &OpenStream,
+        run_job: &JobRunner,
+    ) -> anyhow::Result<()>
+    where
+        OpenStream: AsyncFn() -> anyhow::Result<SendReceivePair<S, R>>,
+        JobRunner: AsyncFn(
+            SendReceivePair<S, R>,
+            CopyJobSpec,
+            usize,
+            TransferPhase,
+        ) -> Result<RequestResult>,
+        S: SendingStream + 'static,
+        R: ReceivingStream + 'static,
+    {
+        let Some(first) = jobs.first() else {
+            return Ok(());
+        };
+        if self.args.client_params.no_space_check {
+            return Ok(());
+        }
+        let files = jobs.iter().filter(|j| {
+            !j.directory
+                && j.link_target.is_none()
+                && j.hard_link_target.is_none()
+                && !self.completed.lock().unwrap().contains(j)
+        });
+        if first.source.user_at_host.is_some()
+            && !self
+                .negotiated
+                .as_ref()
+                .is_some_and(|n| n.compat.supports(Feature::STAT))
+        {
+            debug!("Remote cannot report file sizes; skipping free space check");
+            return Ok(());
+        }
+
+        // Errors finding out sizes aren't fatal here; the transfer proper will report them.
+        let mut sizes = futures_util::stream::iter(files)
+            .map(|job| self.source_file_size(job, open_stream, run_job))
+            .buffer_unordered(self.parallel_streams());
+        let mut required = 0;
+        while let Some(size) = sizes.next().await {
+            required += size?.unwrap_or(0);
+        }
+        self.ensure_free_space(required, first, open_stream, run_job)
+            .await
+    }
+
+    /// Finds out how big the source file of a job is, remembering the answer for later.
+    ///
+    /// Returns `Ok(None)` if the size could not be determined; the transfer proper will report any problem.
+    /// Err(...) is reserved for fatal errors.
+    async fn source_file_size<S, R, OpenStream, JobRunner>(
+        &self,
+        job: &CopyJobSpec,
+        open_stream: &OpenStream,
+        run_job: &JobRunner,
+    ) -> anyhow::Result<Option<u64>>
+    where
+        OpenStream: AsyncFn() -> anyhow::Result<SendReceivePair<S, R>>,
+        JobRunner: AsyncFn(
+            SendReceivePair<S, R>,
+            CopyJobSpec,
+            usize,
+            TransferPhase,
+        ) -> Result<RequestResult>,
+        S: SendingStream + 'static,
+        R: ReceivingStream + 'static,
+    {
+        if let Some(size) = self.file_sizes.lock().unwrap().get(&job.source.filename) {
+            return Ok(Some(*size));
+        }
+        let size = if job.source.user_at_host.is_some() {
+            let stream_pair = open_stream().await?;
+            let supports_stat = self
+                .negotiated
+                .as_ref()
+                .is_some_and(|n| n.compat.supports(Feature::STAT));
+            let phase = if supports_stat {
+                TransferPhase::Stat
+            } else {
+                TransferPhase::Pre
+            };
+            run_job(stream_pair, job.clone(), 0, phase)
                 .await
+                .inspect_err(|e| debug!("Could not determine remote file size: {e}"))
+                .ok()
+                .and_then(|r| r.list)
+                .and_then(|list| match list.entries.as_slice() {
+                    [entry] if !entry.directory => Some(entry.size.0),
+                    _ => None,
+                })
         } else {
-            self.process_file_transfers(jobs_in, async move || open_stream().await, &mut run_job)
+            tokio::fs::metadata(&job.source.filename)
                 .await
+                .ok()
+                .map(|m| m.len())
+        };
+        if let Some(size) = size {
+            let _ = self
+                .file_sizes
+                .lock()
+                .unwrap()
+                .insert(job.source.filename.clone(), size);
         }
+        Ok(size)
+    }
+
+    /// Fails if `required` bytes will not fit on the filesystem holding the destination of `job`.
+    /// With `--space-warn-only`, warns instead.
+    ///
+    /// If the free space cannot be determined, we carry on regardless.
+    async fn ensure_free_space<S, R, OpenStream, JobRunner>(
+        &self,
+        required: u64,
+        job: &CopyJobSpec,
+        open_stream: &OpenStream,
+        run_job: &JobRunner,
+    ) -> anyhow::Result<()>
+    where
+        OpenStream: AsyncFn() -> anyhow::Result<SendReceivePair<S, R>>,
+        JobRunner: AsyncFn(
+            SendReceivePair<S, R>,
+            CopyJobSpec,
+            usize,
+            TransferPhase,
+        ) -> Result<RequestResult>,
+        S: SendingStream + 'static,
+        R: ReceivingStream + 'static,
+    {
+        if required == 0 || self.args.client_params.no_space_check {
+            return Ok(());
+        }
+        self.spinner
+            .set_message("Checking free space at destination");
+        let space = if job.destination.user_at_host.is_some() {
+            if !self
+                .negotiated
+                .as_ref()
+                .is_some_and(|n| n.compat.supports(Feature::FREE_SPACE))
+            {
+                debug!("Remote cannot report free space; skipping free space check");
+                return Ok(());
+            }
+            run_job(
+                open_stream().await?,
+                job.clone(),
+                0,
+                TransferPhase::FreeSpace,
+            )
+            .await
+            .map(|r| r.space)
+        } else {
+            session::free_space::disk_space(&job.destination.filename).map_err(anyhow::Error::from)
+        };
+        let space = match space {
+            Ok(Some(space)) => space,
+            Ok(None) => {
+                debug!("Free space at destination is not known; skipping free space check");
+                return Ok(());
+            }
+            Err(e) => {
+                warn!("Could not check free space at destination: {e:#}");
+                return Ok(());
+            }
+        };
+        trace!("{required} bytes to transfer; {space:?}");
+        if required <= space.available {
+            return Ok(());
+        }
+        let need = required.human_count_bytes();
+        let avail = space.available.human_count_bytes();
+        if self.args.client_params.space_warn_only {
+            warn!(
+                "There may not be enough space at the destination: {need} to transfer, but only {avail} available"
+            );
+            return Ok(());
+        }
+        anyhow::bail!(
+            "Not enough space at the destination: {need} to transfer, but only {avail} available (use --no-space-check to try anyway)"
+        );
     }
 
+    /// Reports on each of the remote paths (`--stat`).
+    ///
     /// This function should generally log errors and return Ok(status, stats). Err(...) is reserved for fatal errors.
+    async fn process_stat_requests<S, R, OpenStream, JobRunner>(
+        &self,
+        jobs: &[CopyJobSpec],
+        open_stream: &OpenStream,
+        run_job: &JobRunner,
+    ) -> anyhow::Result<(bool, CommandStats)>
+    where
+        OpenStream: AsyncFn() -> anyhow::Result<SendReceivePair<S, R>>,
+        JobRunner: AsyncFn(
+            SendReceivePair<S, R>,
+            CopyJobSpec,
+            usize,
+            TransferPhase,
+        ) -> Result<RequestResult>,
+        S: SendingStream + 'static,
+        R: ReceivingStream + 'static,
+    {
+        let mut success = true;
+        for job in jobs {
+            let stream_pair = open_stream().await?;
+            match run_job(stream_pair, job.clone(), 0, TransferPhase::Stat).await {
+                Ok(RequestResult {
+                    list: Some(list), ..
+                }) if list.entries.len() == 1 => {
+                    let line = format_stat(&job.source, &list.entries[0]);
+                    self.display.suspend(|| println!("{line}"));
+                }
+                Ok(_) => anyhow::bail!("logic error: stat request did not return any data"),
+                Err(e) => {
+                    error!("{}: {e}", job.source);
+                    success = false;
+                }
+            }
+        }
+        Ok((success, CommandStats::default()))
+    }
+
+    /// Adds a completed file transfer to the checksum manifest, if we are keeping one.
+    ///
+    /// `digest` is the digest of the whole file, if the transfer computed it.
+    /// Returns false if that failed.
+    async fn record_in_manifest(&self, job: &CopyJobSpec, digest: Option<&[u8]>) -> bool {
+        let Some(manifest) = &self.manifest else {
+            return true;
+        };
+        let buffer_size = self
+            .negotiated
+            .as_ref()
+            .map_or(crate::util::io::DEFAULT_COPY_BUFFER_SIZE, |n| {
+                n.config.io_buffer_size
+            });
+        if let Err(e) = manifest.record(job, digest, buffer_size).await {
+            error!(
+                "Could not add {} to checksum manifest: {e:#}",
+                job.display_filename().display()
+            );
+            return false;
+        }
+        true
+    }
+
+    /// Notes that a job has completed, so it need not be run again after reconnecting
+    fn mark_completed(&self, job: &CopyJobSpec) {
+        let _ = self.completed.lock().unwrap().insert(job.clone());
+    }
+
+    /// The maximum number of file transfers to run at once, across all the connections
+    fn parallel_streams(&self) -> usize {
+        self.negotiated.as_ref().map_or(1, |n| {
+            usize::from(n.config.parallel_streams.max(1)) * usize::from(n.config.connections.max(1))
+        })
+    }
+
+    /// This function should generally log errors and return Ok(status, stats). Err(...) is reserved for fatal errors.
+    #[allow(clippy::too_many_lines)]
     async fn process_file_transfers<S, R, OpenStream, JobRunner>(
         &self,
         jobs: &[CopyJobSpec],
-        mut open_stream: OpenStream,
-        mut run_job: JobRunner,
+        open_stream: &OpenStream,
+        run_job: &JobRunner,
     ) -> anyhow::Result<(bool, CommandStats)>
     where
-        OpenStream: AsyncFnMut() -> anyhow::Result<SendReceivePair<S, R>>,
-        JobRunner: AsyncFnMut(
+        OpenStream: AsyncFn() -> anyhow::Result<SendReceivePair<S, R>>,
+        JobRunner: AsyncFn(
             SendReceivePair<S, R>,
             CopyJobSpec,
             usize,
@@ -657,85 +1552,226 @@ impl Client {
             .is_some_and(|j| j.destination.user_at_host.is_some());
 
         // FILE TRANSFER PHASE
-        // Send/receive files and create directories.
+        // Create directories, then send/receive files.
         // The list of job specs must be in the appropriate order i.e. create a directory before attempting to put any files into it.
+        // Directories are created one at a time, in list order, before any files are transferred.
+        // Files may then be transferred several at once, as they don't depend on each other.
 
         let filename_width = longest_filename(jobs);
         let n_jobs = jobs.len();
-        let n_files = jobs.iter().filter(|j| !j.directory).count();
-        let mut files_done = 0;
-        for job in jobs {
-            if n_files > 1 {
-                self.spinner.set_message(format!(
-                    "Transferring data (file {} of {n_files})",
-                    files_done + 1,
-                ));
-            }
-            if !destination_is_remote && job.directory {
-                // Local directory creation is trivial
-                debug!("Creating local directory {}", job.destination.filename);
-                let meta = tokio::fs::metadata(&job.destination.filename).await;
-                if let Ok(m) = meta {
-                    if m.is_file() {
-                        error!(
-                            "Cannot create local directory {}: a file already exists there",
-                            job.destination.filename
-                        );
+        // After reconnecting, we only need to run the jobs that did not complete last time
+        let (directories, files): (Vec<_>, Vec<_>) = jobs
+            .iter()
+            .filter(|j| !self.completed.lock().unwrap().contains(j))
+            .partition(|j| j.directory);
+        let (links, files): (Vec<_>, Vec<_>) =
+            files.into_iter().partition(|j| j.link_target.is_some());
+        let (hard_links, files): (Vec<_>, Vec<_>) = files
+            .into_iter()
+            .partition(|j| j.hard_link_target.is_some());
+        let n_files = files.len();
+
+        for job in directories {
+            if destination_is_remote {
+                debug!("Processing job {:?}", job);
+                let stream_pair = open_stream().await?;
+                let result = run_job(
+                    stream_pair,
+                    job.clone(),
+                    filename_width,
+                    TransferPhase::Transfer,
+                )
+                .await;
+                match result {
+                    Ok(result) => {
+                        aggregate_stats.accumulate(&result.stats);
+                        self.mark_completed(job);
+                    }
+                    Err(e) => {
+                        log_job_error(&e);
                         overall_success = false;
                         break;
                     }
-                    // directory already exists, that's fine
-                    continue;
                 }
-                if let Err(e) = tokio::fs::create_dir_all(&job.destination.filename).await
-                    && e.kind() != std::io::ErrorKind::AlreadyExists
-                {
+                continue;
+            }
+            // Local directory creation is trivial
+            debug!("Creating local directory {}", job.destination.filename);
+            let meta = tokio::fs::metadata(&job.destination.filename).await;
+            if let Ok(m) = meta {
+                if m.is_file() {
                     error!(
-                        "Failed to create local directory {}: {e}",
+                        "Cannot create local directory {}: a file already exists there",
                         job.destination.filename
                     );
                     overall_success = false;
                     break;
                 }
+                // directory already exists, that's fine
                 continue;
             }
+            if let Err(e) = tokio::fs::create_dir_all(&job.destination.filename).await
+                && e.kind() != std::io::ErrorKind::AlreadyExists
+            {
+                error!(
+                    "Failed to create local directory {}: {e}",
+                    job.destination.filename
+                );
+                overall_success = false;
+                break;
+            }
+            self.mark_completed(job);
+        }
+
+        // Run up to `parallel_streams` file transfers at once.
+        // If one fails, we start no more but allow those in flight to finish.
+        let parallel = self.parallel_streams();
+        let mut planned = Vec::with_capacity(n_files);
+        for job in files {
+            planned.push((
+                job,
+                self.plan_file_transfer(job, n_files, open_stream, run_job)
+                    .await?,
+            ));
+        }
+        let mut pending = planned.iter();
+        let mut in_flight = FuturesUnordered::new();
+        let mut files_started = 0;
+        loop {
+            while overall_success
+                && in_flight.len() < parallel
+                && let Some((job, parts)) = pending.next()
+            {
+                files_started += 1;
+                if n_files > 1 {
+                    self.spinner.set_message(format!(
+                        "Transferring data (file {files_started} of {n_files})",
+                    ));
+                }
+                for part in parts {
+                    debug!("Processing job {part:?}");
+                }
+                in_flight.push(async move {
+                    let result =
+                        transfer_file_parts(parts, open_stream, run_job, filename_width).await;
+                    (*job, parts, result)
+                });
+            }
+            let Some((job, parts, result)) = in_flight.next().await else {
+                break;
+            };
+            // An outer error (failure to open a stream) is fatal.
+            match result? {
+                Ok(result) => {
+                    aggregate_stats.accumulate(&result.stats);
+                    self.mark_completed(job);
+                    // A striped transfer's digests each cover only part of the file
+                    let digest = result.digest.as_deref().filter(|_| parts.len() == 1);
+                    overall_success &= self.record_in_manifest(&parts[0], digest).await;
+                }
+                Err(e) => {
+                    log_job_error(&e);
+                    overall_success = false;
+                }
+            }
+        }
+
+        // Hard links are created one at a time, once the files they link to have been transferred.
+        for job in hard_links {
+            if !overall_success {
+                break;
+            }
             debug!("Processing job {:?}", job);
-            let stream_pair = open_stream().await?;
-            let result = run_job(
-                stream_pair,
-                job.clone(),
-                filename_width,
-                TransferPhase::Transfer,
-            )
-            .await;
+            let result = if destination_is_remote {
+                let stream_pair = open_stream().await?;
+                run_job(
+                    stream_pair,
+                    job.clone(),
+                    filename_width,
+                    TransferPhase::Transfer,
+                )
+                .await
+                .map(|_| ())
+            } else {
+                let target = job.hard_link_target.as_deref().unwrap_or_default();
+                session::hardlink::create_hard_link(target, &job.destination.filename)
+                    .await
+                    .with_context(|| {
+                        format!(
+                            "Failed to create local hard link {}",
+                            job.destination.filename
+                        )
+                    })
+            };
             match result {
-                Ok(result) => {
-                    aggregate_stats.payload_bytes += result.stats.payload_bytes;
-                    aggregate_stats.peak_transfer_rate = aggregate_stats
-                        .peak_transfer_rate
-                        .max(result.stats.peak_transfer_rate);
+                Ok(()) => self.mark_completed(job),
+                Err(e) => {
+                    log_job_error(&e);
+                    overall_success = false;
                 }
+            }
+        }
+
+        // Symbolic links are created one at a time, after everything else has been transferred,
+        // so that no file is written through a link that came with the tree.
+        let remote_symlinks = self
+            .negotiated
+            .as_ref()
+            .is_some_and(|n| n.compat.supports(Feature::SYMLINKS));
+        for job in links {
+            if !overall_success {
+                break;
+            }
+            debug!("Processing job {:?}", job);
+            if destination_is_remote
+                && !remote_symlinks
+                && !std::path::Path::new(&job.source.filename).is_file()
+            {
+                // We can only fall back to sending what the link points to if it is a file
+                warn!(
+                    "Skipping symbolic link {}: remote does not support symbolic links",
+                    job.source.filename
+                );
+                self.mark_completed(job);
+                continue;
+            }
+            let result = if destination_is_remote {
+                let stream_pair = open_stream().await?;
+                run_job(
+                    stream_pair,
+                    job.clone(),
+                    filename_width,
+                    TransferPhase::Transfer,
+                )
+                .await
+                .map(|_| ())
+            } else {
+                let target = job.link_target.as_deref().unwrap_or_default();
+                session::symlink::create_symlink(target, &job.destination.filename)
+                    .await
+                    .with_context(|| {
+                        format!(
+                            "Failed to create local symbolic link {}",
+                            job.destination.filename
+                        )
+                    })
+            };
+            match result {
+                Ok(()) => self.mark_completed(job),
                 Err(e) => {
-                    if let Some(src) = e.source() {
-                        // Some error conditions come with an anyhow Context.
-                        // We want to output one tidy line, so glue them together.
-                        error!("{e}: {src}");
-                    } else {
-                        error!("{e}");
-                    }
+                    log_job_error(&e);
                     overall_success = false;
-                    break;
                 }
             }
-            files_done += 1;
         }
 
         // POST-TRANSFER: Apply preserve logic (permission bits) to any directories created.
         // We do this in _reverse order_ in case the changed permissions prevent us from being able to traverse a directory we recently created.
         if n_jobs > 1 {
             let mut message_set = false;
+            let xattrs = XattrSelection::from_params(&self.args.client_params).any();
             for job in jobs.iter().rev() {
-                if job.directory && job.preserve {
+                if job.directory && (job.preserve.any() || xattrs) {
                     let stream_pair = open_stream().await?;
                     if !message_set {
                         self.spinner
@@ -744,13 +1780,7 @@ impl Client {
                     }
                     let result = run_job(stream_pair, job.clone(), 0, TransferPhase::Post).await;
                     if let Err(e) = result {
-                        if let Some(src) = e.source() {
-                            // Some error conditions come with an anyhow Context.
-                            // We want to output one tidy line, so glue them together.
-                            error!("{e}: {src}");
-                        } else {
-                            error!("{e}");
-                        }
+                        log_job_error(&e);
                         overall_success = false;
                     }
                 }
@@ -760,17 +1790,59 @@ impl Client {
         Ok((overall_success, aggregate_stats))
     }
 
+    /// Decides how to transfer a file: returns the parts to transfer (see [`stripe_job`]).
+    ///
+    /// When there are fewer files than streams, and the remote supports it, large files are
+    /// striped across the spare streams.
+    async fn plan_file_transfer<S, R, OpenStream, JobRunner>(
+        &self,
+        job: &CopyJobSpec,
+        n_files: usize,
+        open_stream: &OpenStream,
+        run_job: &JobRunner,
+    ) -> anyhow::Result<Vec<CopyJobSpec>>
+    where
+        OpenStream: AsyncFn() -> anyhow::Result<SendReceivePair<S, R>>,
+        JobRunner: AsyncFn(
+            SendReceivePair<S, R>,
+            CopyJobSpec,
+            usize,
+            TransferPhase,
+        ) -> Result<RequestResult>,
+        S: SendingStream + 'static,
+        R: ReceivingStream + 'static,
+    {
+        let streams_per_file = self.parallel_streams() / n_files.max(1);
+        let compat = self
+            .negotiated
+            .as_ref()
+            .map(|n| n.compat)
+            .unwrap_or_default();
+        let ranged = compat.supports(Feature::RANGED_TRANSFER);
+        // A resumed transfer continues from wherever the destination left off, so is not striped.
+        if !ranged || streams_per_file < 2 || self.args.client_params.resume {
+            return Ok(vec![job.clone()]);
+        }
+
+        // We need to know how big the file is. Errors here aren't fatal; the transfer proper will report them.
+        let size = self.source_file_size(job, open_stream, run_job).await?;
+        Ok(size.map_or_else(
+            || vec![job.clone()],
+            |size| stripe_job(job, size, streams_per_file),
+        ))
+    }
+
     /// This function should generally log errors and return Ok(status, stats). Err(...) is reserved for fatal errors.
     #[allow(clippy::too_many_lines)]
     async fn process_recursive_get<S, R, OpenStream, JobRunner>(
         &self,
         jobs_in: &[CopyJobSpec],
-        mut open_stream: OpenStream,
-        mut run_job: JobRunner,
+        open_stream: OpenStream,
+        run_job: JobRunner,
     ) -> anyhow::Result<(bool, CommandStats)>
     where
-        OpenStream: AsyncFnMut() -> anyhow::Result<SendReceivePair<S, R>>,
-        JobRunner: AsyncFnMut(
+        OpenStream: AsyncFn() -> anyhow::Result<SendReceivePair<S, R>>,
+        JobRunner: AsyncFn(
             SendReceivePair<S, R>,
             CopyJobSpec,
             usize,
@@ -788,6 +1860,14 @@ impl Client {
             "Operation not supported by remote"
         );
 
+        let listed = self.listed_jobs.lock().unwrap().clone();
+        if let Some(jobs) = listed {
+            // We have reconnected. The local destination may have been created last time, so must not be examined again.
+            return self
+                .process_file_transfers(&jobs, &open_stream, &run_job)
+                .await;
+        }
+
         // If this is a recursive GET, check the local destination directory to fail fast.
         // We try very hard to match scp behaviour here:
         // - with one source:
@@ -825,11 +1905,33 @@ impl Client {
             Some(original_dest_dir.clone())
         };
 
+        // If the remote supports it, check that every source exists before we start, and find out what it is.
+        let mut first_source_is_dir = None;
+        if self
+            .negotiated
+            .as_ref()
+            .is_some_and(|n| n.compat.supports(Feature::STAT))
+        {
+            self.spinner.set_message("Checking remote sources");
+            for job in jobs_in {
+                let stream_pair = open_stream().await?;
+                let result = run_job(stream_pair, job.clone(), 0, TransferPhase::Stat)
+                    .await
+                    .inspect_err(|_| warn!("No files were transferred"))
+                    .with_context(|| format!("while checking remote source {}", job.source))?;
+                let Some(entry) = result.list.and_then(|mut list| list.entries.pop()) else {
+                    anyhow::bail!("logic error: stat request did not return any data");
+                };
+                let _ = first_source_is_dir.get_or_insert(entry.directory);
+            }
+        }
+
         // PRE-TRANSFER:
         // If this is a recursive GET, ask the remote to enumerate the files.
         self.spinner
             .set_message("Asking remote for list of files to transfer");
         let mut new_jobs = Vec::new();
+        let mut required = 0;
         for job in jobs_in {
             let stream_pair = open_stream().await?;
             let result = run_job(stream_pair, job.clone(), 0, TransferPhase::Pre)
@@ -840,13 +1942,46 @@ impl Client {
                     "logic error: pre-transfer request did not return List response data"
                 );
             };
+            // Where each file listed was sent, for any hard links to it
+            let mut destinations = HashMap::new();
             for item in contents.entries {
+                // The remote only reports hard links if we asked it to
+                let hard_link_target = item
+                    .hard_link_target()
+                    .and_then(|t| destinations.get(t))
+                    .cloned();
+                if !item.directory && hard_link_target.is_none() {
+                    required += item.size.0;
+                    let _ = self
+                        .file_sizes
+                        .lock()
+                        .unwrap()
+                        .insert(item.name.clone(), item.size.0);
+                }
                 let mut destfile = job.destination.filename.clone();
                 let leaf = item
                     .name
                     .strip_prefix(&job.source.filename)
                     .unwrap_or(&item.name)
                     .trim_start_matches(MAIN_SEPARATOR);
+                // The remote only reports links as such if we asked it not to follow them
+                let link_target = item.symlink_target().map(str::to_string);
+                if let Some(target) = &link_target {
+                    match self.args.client_params.links {
+                        LinkMode::Skip => {
+                            debug!("Skipping symbolic link {}", item.name);
+                            continue;
+                        }
+                        LinkMode::Safe if !util::path::symlink_stays_within(leaf, target) => {
+                            warn!(
+                                "Skipping symbolic link {} -> {target}, which points outside the tree",
+                                item.name
+                            );
+                            continue;
+                        }
+                        _ => (),
+                    }
+                }
                 trace!("dest {destfile}");
                 if single_source_mkdir_mode.is_none() {
                     // In normal mode, we need to add the remote directory name as well.
@@ -868,6 +2003,9 @@ impl Client {
                     "source path {name}; leaf {leaf:?}; final dest {destfile}",
                     name = item.name
                 );
+                if !item.directory && link_target.is_none() && hard_link_target.is_none() {
+                    let _ = destinations.insert(item.name.clone(), destfile.clone());
+                }
                 #[allow(clippy::cast_possible_truncation)]
                 new_jobs.push(CopyJobSpec {
                     user_at_host: job.user_at_host.clone(),
@@ -884,16 +2022,28 @@ impl Client {
                     mode: item
                         .attributes
                         .find_tag(MetadataAttr::ModeBits)
+                        .filter(|_| job.preserve.mode)
                         .map(|i| i.coerce_unsigned() as u32),
+                    atime: MetadataAttr::find_atime(&item.attributes)
+                        .filter(|_| job.preserve.times),
+                    mtime: MetadataAttr::find_mtime(&item.attributes)
+                        .filter(|_| job.preserve.times),
+                    xattrs: util::xattr::collect(&item.attributes),
+                    range: None,
+                    link_target,
+                    hard_link_target,
                 });
             }
         }
 
+        self.ensure_free_space(required, &jobs_in[0], &open_stream, &run_job)
+            .await?;
+
         // Now, if required, handle single-source mkdir mode.
         if let Some(dir_to_create) = single_source_mkdir_mode
             && !new_jobs.is_empty()
         {
-            if new_jobs[0].directory {
+            if first_source_is_dir.unwrap_or(new_jobs[0].directory) {
                 debug!("single source mode; item is a directory; creating it");
                 tokio::fs::create_dir_all(&dir_to_create)
                     .await
@@ -906,12 +2056,107 @@ impl Client {
         }
 
         let new_jobs = new_jobs;
+        *self.listed_jobs.lock().unwrap() = Some(new_jobs.clone());
 
-        self.process_file_transfers(&new_jobs, async move || open_stream().await, &mut run_job)
+        self.process_file_transfers(&new_jobs, &open_stream, &run_job)
             .await
     }
 }
 
+/// Smallest part of a file worth sending on a stream of its own
+const MIN_STRIPE_SIZE: u64 = 64 * 1024 * 1024;
+/// Size of the closing part of a striped file
+const CLOSING_STRIPE_SIZE: u64 = 1024 * 1024;
+
+/// Splits a file transfer job into byte ranges, so it can be transferred on up to `streams` streams at once.
+///
+/// The last part returned is the closing part, which must be sent after all the others have
+/// completed, because the receiver applies the file's metadata when it arrives.
+/// The other parts do not request metadata preservation.
+///
+/// Small files are not worth splitting; they are returned as a single part.
+fn stripe_job(job: &CopyJobSpec, size: u64, streams: usize) -> Vec<CopyJobSpec> {
+    if streams < 2 || size < 2 * MIN_STRIPE_SIZE {
+        return vec![job.clone()];
+    }
+    let n_parts = (size / MIN_STRIPE_SIZE).min(streams as u64);
+    let part_size = (size - CLOSING_STRIPE_SIZE).div_ceil(n_parts);
+    let mut parts: Vec<_> = (0..n_parts)
+        .map(|i| {
+            let start = CLOSING_STRIPE_SIZE + i * part_size;
+            CopyJobSpec {
+                preserve: PreserveSelection::default(),
+                range: Some(start..(start + part_size).min(size)),
+                ..job.clone()
+            }
+        })
+        .collect();
+    parts.push(CopyJobSpec {
+        range: Some(0..CLOSING_STRIPE_SIZE),
+        ..job.clone()
+    });
+    parts
+}
+
+/// Transfers the parts of a file (see [`stripe_job`]).
+///
+/// All but the last part run at once; the last part runs once they have all succeeded.
+/// The returned stats cover all the parts.
+///
+/// As with the other job processing functions, the outer Result is reserved for fatal errors.
+async fn transfer_file_parts<S, R, OpenStream, JobRunner>(
+    parts: &[CopyJobSpec],
+    open_stream: &OpenStream,
+    run_job: &JobRunner,
+    filename_width: usize,
+) -> anyhow::Result<Result<RequestResult>>
+where
+    OpenStream: AsyncFn() -> anyhow::Result<SendReceivePair<S, R>>,
+    JobRunner:
+        AsyncFn(SendReceivePair<S, R>, CopyJobSpec, usize, TransferPhase) -> Result<RequestResult>,
+    S: SendingStream + 'static,
+    R: ReceivingStream + 'static,
+{
+    let (closing, body) = parts
+        .split_last()
+        .expect("logic error: a file transfer needs at least one part");
+    let run_part = async |part: &CopyJobSpec| {
+        let stream_pair = open_stream().await?;
+        anyhow::Ok(
+            run_job(
+                stream_pair,
+                part.clone(),
+                filename_width,
+                TransferPhase::Transfer,
+            )
+            .await,
+        )
+    };
+
+    let mut stats = CommandStats::default();
+    for result in join_all(body.iter().map(run_part)).await {
+        match result? {
+            Ok(result) => stats.accumulate(&result.stats),
+            Err(e) => return Ok(Err(e)),
+        }
+    }
+    Ok(run_part(closing).await?.map(|mut result| {
+        result.stats.accumulate(&stats);
+        result
+    }))
+}
+
+/// Logs a failed job as one tidy line
+fn log_job_error(e: &anyhow::Error) {
+    if let Some(src) = e.source() {
+        // Some error conditions come with an anyhow Context.
+        // We want to output one tidy line, so glue them together.
+        error!("{e}: {src}");
+    } else {
+        error!("{e}");
+    }
+}
+
 fn longest_filename(jobs: &[CopyJobSpec]) -> usize {
     let mut result = 0;
     for j in jobs {
@@ -949,7 +2194,7 @@ mod test {
 
     use crate::session::CommandStats;
     use crate::{
-        Configuration, CopyJobSpec, FileSpec, Parameters,
+        Configuration, CopyJobSpec, FileSpec, Parameters, PreserveSelection,
         client::main_loop::Client,
         config::{Configuration_Optional, Manager},
         protocol::{common::ProtocolMessage as _, test_helpers::new_test_plumbing},
@@ -987,10 +2232,10 @@ mod test {
 
         let mut client =
             Client::new(Manager::without_default(None), MultiProgress::new(), args).unwrap();
-        client.negotiated = Some(Negotiated {
-            config: Configuration::system_default().clone(),
-            compat: crate::protocol::control::Compatibility::Level(compat_level),
-        });
+        client.negotiated = Some(Negotiated::new(
+            Configuration::system_default().clone(),
+            crate::protocol::control::Compatibility::Level(compat_level),
+        ));
         client
     }
     const REMOTE_FILE: &str = "8.8.8.8:file";
@@ -1057,9 +2302,9 @@ mod test {
 
         let ssh_client = create_fake(&buf);
         let mut qcp_conn = QcpConnection::try_from(ssh_client).unwrap();
-        qcp_conn.endpoint = Some(endpoint);
+        qcp_conn.endpoints.push(endpoint);
 
-        let report = uut.closedown(qcp_conn).await.unwrap();
+        let report = uut.closedown(qcp_conn, 0).await.unwrap();
         assert_eq!(report, ClosedownReportV1::default());
         eprintln!("Closedown report: {report:?}");
     }
@@ -1309,6 +2554,7 @@ mod test {
             RequestResult::new(
                 CommandStats {
                     payload_bytes: 10,
+                    wire_bytes: 10,
                     peak_transfer_rate: 100,
                 },
                 None,
@@ -1316,6 +2562,7 @@ mod test {
             RequestResult::new(
                 CommandStats {
                     payload_bytes: 5,
+                    wire_bytes: 5,
                     peak_transfer_rate: 200,
                 },
                 None,
@@ -1360,6 +2607,7 @@ mod test {
             Ok(RequestResult::new(
                 CommandStats {
                     payload_bytes: 10,
+                    wire_bytes: 10,
                     peak_transfer_rate: 100,
                 },
                 None,
@@ -1369,6 +2617,7 @@ mod test {
             Ok(RequestResult::new(
                 CommandStats {
                     payload_bytes: 999,
+                    wire_bytes: 999,
                     peak_transfer_rate: 999,
                 },
                 None,
@@ -1399,6 +2648,656 @@ mod test {
         assert_eq!(stats.peak_transfer_rate, 100);
     }
 
+    #[tokio::test]
+    async fn process_job_requests_skips_completed_jobs() {
+        let jobs = vec![
+            CopyJobSpec::from_parts("dir", "host:dir", false, true).unwrap(),
+            CopyJobSpec::from_parts("file1", "host:dir", false, false).unwrap(),
+            CopyJobSpec::from_parts("file2", "host:dir", false, false).unwrap(),
+            CopyJobSpec::from_parts("file3", "host:dir", false, false).unwrap(),
+        ];
+        let client = make_uut(|_, _| (), "src", "dest", 1);
+        let run = async |fail: &str| {
+            let ran = Mutex::new(Vec::new());
+            let (success, _) = client
+                .process_job_requests(
+                    &jobs,
+                    async || Ok::<_, anyhow::Error>(new_test_plumbing().0),
+                    async |_stream_pair, job, _filename_width, _pass| {
+                        ran.lock().unwrap().push(job.source.filename.clone());
+                        anyhow::ensure!(job.source.filename != fail, "this one failed");
+                        Ok(RequestResult::new(CommandStats::default(), None))
+                    },
+                )
+                .await
+                .unwrap();
+            (success, ran.into_inner().unwrap())
+        };
+
+        // As if the connection were lost during file2
+        let (success, ran) = run("file2").await;
+        assert!(!success);
+        assert_eq!(ran, ["dir", "file1", "file2"]);
+        // After reconnecting, we pick up where we left off
+        let (success, ran) = run("").await;
+        assert!(success);
+        assert_eq!(ran, ["file2", "file3"]);
+    }
+
+    #[tokio::test]
+    async fn process_job_requests_creates_remote_links() {
+        let mut link = CopyJobSpec::from_parts("lnk", "host:dir/lnk", false, false).unwrap();
+        link.link_target = Some("file".to_string());
+        let jobs = vec![
+            CopyJobSpec::from_parts("file", "host:dir/file", false, false).unwrap(),
+            link,
+            CopyJobSpec::from_parts("dir", "host:dir", false, true).unwrap(),
+        ];
+        let client = make_uut(|_, _| (), "src", "dest", 5);
+        let ran = Mutex::new(Vec::new());
+        let (success, _) = client
+            .process_job_requests(
+                &jobs,
+                async || Ok::<_, anyhow::Error>(new_test_plumbing().0),
+                async |_stream_pair, job, _filename_width, pass| {
+                    if let TransferPhase::Transfer = pass {
+                        ran.lock().unwrap().push(job.source.filename.clone());
+                    }
+                    Ok(RequestResult::new(CommandStats::default(), None))
+                },
+            )
+            .await
+            .unwrap();
+        assert!(success);
+        // Directories first, then files, then links
+        assert_eq!(ran.into_inner().unwrap(), ["dir", "file", "lnk"]);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn process_job_requests_old_remote_skips_links_to_directories() {
+        LitterTray::try_with_async(async |tray| {
+            let _ = tray.create_text("file", "contents")?;
+            let _ = tray.make_dir("dir")?;
+            let _ = tray.make_symlink("file", "file_link")?;
+            let _ = tray.make_symlink("dir", "dir_link")?;
+            let mut jobs = Vec::new();
+            for name in ["file_link", "dir_link"] {
+                let mut link =
+                    CopyJobSpec::from_parts(name, &format!("host:{name}"), false, false)?;
+                link.link_target = Some(name.trim_end_matches("_link").to_string());
+                jobs.push(link);
+            }
+            let client = make_uut(|_, _| (), "src", "dest", 4);
+            let ran = Mutex::new(Vec::new());
+            let (success, _) = client
+                .process_job_requests(
+                    &jobs,
+                    async || Ok::<_, anyhow::Error>(new_test_plumbing().0),
+                    async |_stream_pair, job, _filename_width, _pass| {
+                        ran.lock().unwrap().push(job.source.filename.clone());
+                        Ok(RequestResult::new(CommandStats::default(), None))
+                    },
+                )
+                .await?;
+            assert!(success);
+            // The link to a file is sent as a file; the link to a directory is left out
+            assert_eq!(ran.into_inner().unwrap(), ["file_link"]);
+            Ok(())
+        })
+        .await
+        .unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn process_job_requests_creates_local_links() {
+        let mut link = CopyJobSpec::from_parts("host:lnk", "lnk", false, false).unwrap();
+        link.link_target = Some("../target".to_string());
+        let client = make_uut(|_, _| (), "src", "dest", 5);
+        LitterTray::try_with_async(async |_| {
+            let (success, _) = client
+                .process_job_requests(
+                    &[link],
+                    async || Ok::<_, anyhow::Error>(new_test_plumbing().0),
+                    async |_, _, _, pass| {
+                        anyhow::bail!("unexpected remote request in phase {pass:?}")
+                    },
+                )
+                .await?;
+            assert!(success);
+            assert_eq!(std::fs::read_link("lnk")?.to_str(), Some("../target"));
+            Ok(())
+        })
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn process_job_requests_recursive_get_hard_links() {
+        use crate::protocol::{
+            DataTag as _,
+            session::{ListData, ListEntry, MetadataAttr},
+        };
+        let entry = |name: &str, directory, attributes| {
+            ListEntry::new(name.to_string(), directory, serde_bare::Uint(5), attributes)
+        };
+        let jobs = vec![CopyJobSpec::from_parts("host:d", "out", false, false).unwrap()];
+        let client = make_uut(
+            |_, p| {
+                p.recurse = true;
+                p.hard_links = true;
+            },
+            "src",
+            "dest",
+            5,
+        );
+        let transferred = Mutex::new(Vec::new());
+        LitterTray::try_with_async(async |_| {
+            let (success, _) = client
+                .process_job_requests(
+                    &jobs,
+                    async || Ok::<_, anyhow::Error>(new_test_plumbing().0),
+                    async |_stream_pair, job, _filename_width, pass| {
+                        let list = match pass {
+                            TransferPhase::Stat => vec![entry("d", true, vec![])],
+                            TransferPhase::Pre => vec![
+                                entry("d", true, vec![]),
+                                entry("d/f", false, vec![]),
+                                entry(
+                                    "d/g",
+                                    false,
+                                    vec![MetadataAttr::HardLinkTarget.with_str("d/f")],
+                                ),
+                            ],
+                            _ => {
+                                std::fs::write(&job.destination.filename, "hello")?;
+                                transferred.lock().unwrap().push(job.source.filename);
+                                return Ok(RequestResult::default());
+                            }
+                        };
+                        Ok(RequestResult::new(
+                            CommandStats::default(),
+                            Some(ListData::new(list, false)),
+                        ))
+                    },
+                )
+                .await?;
+            assert!(success);
+            // The file was transferred once, and linked to under its other name
+            assert_eq!(*transferred.lock().unwrap(), ["d/f"]);
+            std::fs::write("out/f", "changed")?;
+            assert_eq!(std::fs::read_to_string("out/g")?, "changed");
+            Ok(())
+        })
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn process_job_requests_recursive_get_directory_times() {
+        use crate::protocol::session::{ListData, ListEntry, MetadataAttr};
+        use crate::util::time::SystemTimeExt as _;
+        use std::time::SystemTime;
+        let when = SystemTime::from_unix(1_000_000_000);
+        let jobs = vec![CopyJobSpec::from_parts("host:d", "out", true, false).unwrap()];
+        let client = make_uut(|_, p| p.recurse = true, "src", "dest", 5);
+        LitterTray::try_with_async(async |_| {
+            let (success, _) = client
+                .process_job_requests(
+                    &jobs,
+                    async || Ok::<_, anyhow::Error>(new_test_plumbing().0),
+                    async |stream_pair, job, _filename_width, pass| {
+                        let dir = ListEntry::new(
+                            "d".to_string(),
+                            true,
+                            serde_bare::Uint(0),
+                            vec![MetadataAttr::new_mtime(when)],
+                        );
+                        let file =
+                            ListEntry::new("d/f".to_string(), false, serde_bare::Uint(5), vec![]);
+                        let list = match pass {
+                            TransferPhase::Stat => vec![dir],
+                            TransferPhase::Pre => vec![dir, file],
+                            TransferPhase::Transfer => {
+                                std::fs::write(&job.destination.filename, "hello")?;
+                                return Ok(RequestResult::default());
+                            }
+                            TransferPhase::FreeSpace => {
+                                anyhow::bail!(
+                                    "unexpected free space request for a local destination"
+                                )
+                            }
+                            TransferPhase::Post => {
+                                // Directory times are applied locally
+                                return client
+                                    .manage_post_transfer_request(stream_pair, &job)
+                                    .await;
+                            }
+                        };
+                        Ok(RequestResult::new(
+                            CommandStats::default(),
+                            Some(ListData::new(list, false)),
+                        ))
+                    },
+                )
+                .await?;
+            assert!(success);
+            assert_eq!(std::fs::read_to_string("out/f")?, "hello");
+            // Writing the file did not disturb the directory's time
+            assert_eq!(std::fs::metadata("out")?.modified()?, when);
+            Ok(())
+        })
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn process_job_requests_stat() {
+        use crate::protocol::session::{ListData, ListEntry};
+        let jobs = vec![
+            CopyJobSpec::try_new(
+                remote_file_spec(),
+                FileSpec::default(),
+                PreserveSelection::default(),
+                false,
+            )
+            .unwrap(),
+            CopyJobSpec::from_parts("host:missing", "", false, false).unwrap(),
+        ];
+        let mut client = make_uut(|_, _| (), "src", "dest", 5);
+        client.args.stat = true;
+        let passes = Mutex::new(Vec::new());
+        let (success, _) = client
+            .process_job_requests(
+                &jobs,
+                async || Ok::<_, anyhow::Error>(new_test_plumbing().0),
+                async |_stream_pair, job, _filename_width, pass| {
+                    passes.lock().unwrap().push(pass);
+                    anyhow::ensure!(job.source.filename != "missing", "FileNotFound");
+                    Ok(RequestResult::new(
+                        CommandStats::default(),
+                        Some(ListData::new(
+                            vec![ListEntry::new(
+                                job.source.filename,
+                                false,
+                                serde_bare::Uint(1),
+                                vec![],
+                            )],
+                            false,
+                        )),
+                    ))
+                },
+            )
+            .await
+            .unwrap();
+        // The second path failed, but it did not stop us
+        assert!(!success);
+        let passes = passes.into_inner().unwrap();
+        assert_eq!(passes.len(), 2);
+        assert!(passes.iter().all(|p| matches!(p, TransferPhase::Stat)));
+    }
+
+    #[test]
+    fn format_stat() {
+        use crate::protocol::DataTag as _;
+        use crate::protocol::session::{ListEntry, MetadataAttr};
+        let entry = ListEntry::new(
+            "file".into(),
+            false,
+            serde_bare::Uint(1234),
+            vec![
+                MetadataAttr::new_mode(0o644),
+                MetadataAttr::ModificationTime.with_unsigned(86_400u64 * 365),
+            ],
+        );
+        let s = super::format_stat(&remote_file_spec(), &entry);
+        assert!(
+            s.starts_with("8.8.8.8:file: file, 1234 bytes, mode 0644, modified 19"),
+            "{s}"
+        );
+
+        let entry = ListEntry::new("dir".into(), true, serde_bare::Uint(0), vec![]);
+        assert_eq!(
+            super::format_stat(&remote_file_spec(), &entry),
+            "8.8.8.8:file: directory"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn agent_ignores_transport_options() {
+        use super::ignored_transport_options;
+        let agent = Configuration::system_default();
+        let mut cli = Configuration_Optional::default();
+        assert!(ignored_transport_options(&cli, agent).is_empty());
+        // Options that match the agent's connection are fine
+        cli.rx = Some(agent.rx);
+        cli.congestion = Some(agent.congestion);
+        assert!(ignored_transport_options(&cli, agent).is_empty());
+        cli.rx = Some(agent.rx + 1);
+        cli.udp_buffer = Some(agent.udp_buffer);
+        assert_eq!(ignored_transport_options(&cli, agent), ["rx", "udp_buffer"]);
+    }
+
+    #[test]
+    fn setup_failure_retries_only_lost_connections() {
+        use super::{Attempt, setup_failure};
+        let eof = anyhow::Error::from(std::io::Error::from(std::io::ErrorKind::UnexpectedEof))
+            .context("receiving server message")
+            .context("while establishing control channel");
+        assert!(matches!(setup_failure(eof), Ok(Attempt::Lost(_))));
+
+        // A negotiation failure is not retried
+        let refused = anyhow::anyhow!("server sent failure message: bad config")
+            .context("while establishing control channel");
+        let e = setup_failure(refused).err().unwrap();
+        assert!(format!("{e:#}").contains("server sent failure message"));
+    }
+
+    #[test]
+    fn reconnect_delay_backs_off() {
+        use super::reconnect_delay;
+        assert_eq!(reconnect_delay(0), Duration::from_secs(1));
+        assert_eq!(reconnect_delay(1), Duration::from_secs(2));
+        assert_eq!(reconnect_delay(3), Duration::from_secs(8));
+        assert_eq!(reconnect_delay(100), Duration::from_secs(30));
+    }
+
+    #[tokio::test]
+    async fn process_job_requests_runs_files_in_parallel() {
+        let jobs = vec![
+            CopyJobSpec::from_parts("file1", "host:dir1/", false, false).unwrap(),
+            CopyJobSpec::from_parts("dir1", "host:dir1", false, true).unwrap(),
+            CopyJobSpec::from_parts("file2", "host:dir1/", false, false).unwrap(),
+            CopyJobSpec::from_parts("file3", "host:dir1/", false, false).unwrap(),
+            CopyJobSpec::from_parts("dir2", "host:dir2", false, true).unwrap(),
+            CopyJobSpec::from_parts("file4", "host:dir2/", false, false).unwrap(),
+            CopyJobSpec::from_parts("file5", "host:dir2/", false, false).unwrap(),
+        ];
+
+        let active = AtomicUsize::new(0);
+        let peak = AtomicUsize::new(0);
+        let order = Mutex::new(Vec::new());
+
+        let mut client = make_uut(|_, _| (), "src", "dest", 1);
+        client.negotiated.as_mut().unwrap().config.parallel_streams = 3;
+        let (success, stats) = client
+            .process_job_requests(
+                &jobs,
+                async || Ok::<_, anyhow::Error>(new_test_plumbing().0),
+                async |stream_pair, job, _filename_width, _pass| {
+                    drop(stream_pair);
+                    order.lock().unwrap().push(job.source.filename.clone());
+                    let now = active.fetch_add(1, Ordering::SeqCst) + 1;
+                    let _ = peak.fetch_max(now, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(20)).await;
+                    let _ = active.fetch_sub(1, Ordering::SeqCst);
+                    Ok(RequestResult::new(
+                        CommandStats {
+                            payload_bytes: 1,
+                            wire_bytes: 1,
+                            peak_transfer_rate: 0,
+                        },
+                        None,
+                    ))
+                },
+            )
+            .await
+            .unwrap();
+
+        assert!(success);
+        assert_eq!(stats.payload_bytes, 7);
+        assert_eq!(peak.load(Ordering::SeqCst), 3);
+        // Directories are created before any files, in their original order
+        let order = order.into_inner().unwrap();
+        assert_eq!(order[0..2], ["dir1", "dir2"]);
+        assert_eq!(order.len(), 7);
+    }
+
+    #[tokio::test]
+    async fn process_job_requests_parallel_stops_after_failure() {
+        let jobs = (1..=6)
+            .map(|i| CopyJobSpec::from_parts(&format!("file{i}"), "host:dir", false, false))
+            .collect::<anyhow::Result<Vec<_>>>()
+            .unwrap();
+
+        let handle_calls = AtomicUsize::new(0);
+        let mut client = make_uut(|_, _| (), "src", "dest", 1);
+        client.negotiated.as_mut().unwrap().config.parallel_streams = 2;
+        let (success, stats) = client
+            .process_job_requests(
+                &jobs,
+                async || Ok::<_, anyhow::Error>(new_test_plumbing().0),
+                async |_stream_pair, job, _filename_width, _pass| {
+                    let _ = handle_calls.fetch_add(1, Ordering::SeqCst);
+                    if job.source.filename == "file2" {
+                        anyhow::bail!("this one failed");
+                    }
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                    Ok(RequestResult::new(
+                        CommandStats {
+                            payload_bytes: 1,
+                            wire_bytes: 1,
+                            peak_transfer_rate: 0,
+                        },
+                        None,
+                    ))
+                },
+            )
+            .await
+            .unwrap();
+
+        assert!(!success);
+        // file1 and file2 start together; file2 fails at once, so no more are started.
+        assert_eq!(handle_calls.load(Ordering::SeqCst), 2);
+        assert_eq!(stats.payload_bytes, 1);
+    }
+
+    #[test]
+    fn stripe_job() {
+        use super::{CLOSING_STRIPE_SIZE, MIN_STRIPE_SIZE, stripe_job};
+        let job = CopyJobSpec::from_parts("host:file", "file", true, false).unwrap();
+
+        // Small files, or a lack of streams, are not worth striping
+        assert_eq!(
+            stripe_job(&job, 2 * MIN_STRIPE_SIZE - 1, 8),
+            std::slice::from_ref(&job)
+        );
+        assert_eq!(
+            stripe_job(&job, 100 * MIN_STRIPE_SIZE, 1),
+            std::slice::from_ref(&job)
+        );
+
+        let size = 10 * MIN_STRIPE_SIZE + 1;
+        let parts = stripe_job(&job, size, 4);
+        assert_eq!(parts.len(), 5);
+        // The closing part comes last, and alone carries the preserve flag
+        let (closing, body) = parts.split_last().unwrap();
+        assert_eq!(closing.range, Some(0..CLOSING_STRIPE_SIZE));
+        assert!(closing.preserve.any());
+        assert!(body.iter().all(|p| !p.preserve.any()));
+        // The parts cover the whole file, without gaps or overlaps
+        let mut ranges: Vec<_> = parts.iter().map(|p| p.range.clone().unwrap()).collect();
+        ranges.sort_by_key(|r| r.start);
+        assert_eq!(ranges.first().unwrap().start, 0);
+        assert_eq!(ranges.last().unwrap().end, size);
+        assert!(ranges.windows(2).all(|w| w[0].end == w[1].start));
+
+        // No more parts than the file is worth
+        assert_eq!(stripe_job(&job, 3 * MIN_STRIPE_SIZE, 8).len(), 4);
+    }
+
+    #[tokio::test]
+    async fn process_job_requests_stripes_large_file() {
+        use crate::protocol::session::{ListData, ListEntry};
+        const SIZE: u64 = 1 << 30;
+
+        let jobs = vec![CopyJobSpec::from_parts("host:bigfile", "bigfile", true, false).unwrap()];
+        let transfers = Mutex::new(Vec::new());
+        let mut client = make_uut(|_, p| p.no_space_check = true, "src", "dest", 5);
+        client.negotiated.as_mut().unwrap().config.parallel_streams = 4;
+        let (success, stats) = client
+            .process_job_requests(
+                &jobs,
+                async || Ok::<_, anyhow::Error>(new_test_plumbing().0),
+                async |_stream_pair, job, _filename_width, pass| {
+                    let list = if let TransferPhase::Stat = pass {
+                        Some(ListData::new(
+                            vec![ListEntry::new(
+                                job.source.filename.clone(),
+                                false,
+                                serde_bare::Uint(SIZE),
+                                vec![],
+                            )],
+                            false,
+                        ))
+                    } else {
+                        transfers.lock().unwrap().push(job.clone());
+                        None
+                    };
+                    let range = job.range.unwrap_or_default();
+                    Ok(RequestResult::new(
+                        CommandStats {
+                            payload_bytes: range.end - range.start,
+                            wire_bytes: range.end - range.start,
+                            peak_transfer_rate: 0,
+                        },
+                        list,
+                    ))
+                },
+            )
+            .await
+            .unwrap();
+
+        assert!(success);
+        assert_eq!(stats.payload_bytes, SIZE);
+        let transfers = transfers.into_inner().unwrap();
+        assert_eq!(transfers.len(), 5);
+        // The closing part, which carries the metadata, runs last
+        assert_eq!(transfers[4].range, Some(0..super::CLOSING_STRIPE_SIZE));
+        assert!(transfers[4].preserve.any());
+    }
+
+    #[tokio::test]
+    async fn process_job_requests_checks_free_space() {
+        use crate::protocol::session::{ListData, ListEntry};
+
+        let jobs =
+            vec![CopyJobSpec::from_parts("host:hugefile", "hugefile", false, false).unwrap()];
+        for (no_space_check, space_warn_only) in [(false, false), (true, false), (false, true)] {
+            let passes = Mutex::new(Vec::new());
+            let client = make_uut(
+                |_, p| {
+                    p.no_space_check = no_space_check;
+                    p.space_warn_only = space_warn_only;
+                },
+                "src",
+                "dest",
+                5,
+            );
+            let result = client
+                .process_job_requests(
+                    &jobs,
+                    async || Ok::<_, anyhow::Error>(new_test_plumbing().0),
+                    async |_stream_pair, job, _filename_width, pass| {
+                        passes.lock().unwrap().push(pass);
+                        let list = matches!(pass, TransferPhase::Stat).then(|| {
+                            ListData::new(
+                                vec![ListEntry::new(
+                                    job.source.filename.clone(),
+                                    false,
+                                    serde_bare::Uint(u64::MAX),
+                                    vec![],
+                                )],
+                                false,
+                            )
+                        });
+                        Ok(RequestResult::new(CommandStats::default(), list))
+                    },
+                )
+                .await;
+            let passes = passes.into_inner().unwrap();
+            if no_space_check {
+                assert!(result.unwrap().0);
+                assert!(matches!(passes[..], [TransferPhase::Transfer]));
+            } else if space_warn_only {
+                assert!(result.unwrap().0);
+                assert!(matches!(
+                    passes[..],
+                    [TransferPhase::Stat, TransferPhase::Transfer]
+                ));
+            } else {
+                let err = result.unwrap_err();
+                assert!(err.to_string().contains("--no-space-check"), "{err}");
+                assert!(matches!(passes[..], [TransferPhase::Stat]));
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn process_job_requests_stats_each_file_once() {
+        use crate::protocol::session::{ListData, ListEntry};
+
+        let jobs = vec![
+            CopyJobSpec::from_parts("host:file1", "file1", false, false).unwrap(),
+            CopyJobSpec::from_parts("host:file2", "file2", false, false).unwrap(),
+        ];
+        let stats = Mutex::new(Vec::new());
+        let mut client = make_uut(|_, _| (), "src", "dest", 5);
+        client.negotiated.as_mut().unwrap().config.parallel_streams = 4;
+        let (success, _) = client
+            .process_job_requests(
+                &jobs,
+                async || Ok::<_, anyhow::Error>(new_test_plumbing().0),
+                async |_stream_pair, job, _filename_width, pass| {
+                    let list = matches!(pass, TransferPhase::Stat).then(|| {
+                        stats.lock().unwrap().push(job.source.filename.clone());
+                        ListData::new(
+                            vec![ListEntry::new(
+                                job.source.filename.clone(),
+                                false,
+                                serde_bare::Uint(1 << 20),
+                                vec![],
+                            )],
+                            false,
+                        )
+                    });
+                    Ok(RequestResult::new(CommandStats::default(), list))
+                },
+            )
+            .await
+            .unwrap();
+        assert!(success);
+        // The sizes found for the free space check are reused when deciding whether to stripe
+        let mut stats = stats.into_inner().unwrap();
+        stats.sort();
+        assert_eq!(stats, ["file1", "file2"]);
+    }
+
+    #[tokio::test]
+    async fn process_job_requests_no_striping_without_support() {
+        let jobs = vec![CopyJobSpec::from_parts("host:bigfile", "bigfile", false, false).unwrap()];
+        let passes = Mutex::new(Vec::new());
+        let mut client = make_uut(|_, _| (), "src", "dest", 4);
+        client.negotiated.as_mut().unwrap().config.parallel_streams = 4;
+        let (success, _) = client
+            .process_job_requests(
+                &jobs,
+                async || Ok::<_, anyhow::Error>(new_test_plumbing().0),
+                async |_stream_pair, job, _filename_width, pass| {
+                    passes.lock().unwrap().push((pass, job.range));
+                    Ok(RequestResult::default())
+                },
+            )
+            .await
+            .unwrap();
+        assert!(success);
+        let passes = passes.into_inner().unwrap();
+        assert_eq!(passes.len(), 1);
+        assert!(matches!(passes[0], (TransferPhase::Transfer, None)));
+    }
+
     fn encode_get_success_response(data: &[u8]) -> Vec<u8> {
         let mut send_buf = Vec::new();
         crate::protocol::session::Response::V1(crate::protocol::session::ResponseV1 {
@@ -1461,6 +3360,49 @@ mod test {
         }
     }
 
+    /// A connection which counts the streams opened on it, and can be marked as having no free streams
+    #[derive(Default)]
+    struct FakeConnection {
+        busy: std::sync::atomic::AtomicBool,
+        opened: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl BiStreamOpener for FakeConnection {
+        type Send = tokio::io::WriteHalf<tokio::io::SimplexStream>;
+        type Recv = tokio::io::ReadHalf<tokio::io::SimplexStream>;
+
+        async fn open_bi_stream(
+            &self,
+        ) -> anyhow::Result<crate::protocol::common::SendReceivePair<Self::Send, Self::Recv>>
+        {
+            if self.busy.load(Ordering::SeqCst) {
+                std::future::pending::<()>().await;
+            }
+            let _ = self.opened.fetch_add(1, Ordering::SeqCst);
+            Ok(new_test_plumbing().0)
+        }
+    }
+
+    #[tokio::test]
+    async fn connection_set_shares_streams() {
+        use super::ConnectionSet;
+        let set = ConnectionSet::new(vec![FakeConnection::default(), FakeConnection::default()]);
+        for _ in 0..4 {
+            let _ = set.open_bi_stream().await.unwrap();
+        }
+        assert_eq!(set.connections[0].opened.load(Ordering::SeqCst), 2);
+        assert_eq!(set.connections[1].opened.load(Ordering::SeqCst), 2);
+
+        // A connection with no free streams is passed over
+        set.connections[0].busy.store(true, Ordering::SeqCst);
+        for _ in 0..4 {
+            let _ = set.open_bi_stream().await.unwrap();
+        }
+        assert_eq!(set.connections[0].opened.load(Ordering::SeqCst), 2);
+        assert_eq!(set.connections[1].opened.load(Ordering::SeqCst), 6);
+    }
+
     #[test]
     fn longest_filenames() {
         use super::longest_filename;
@@ -1497,6 +3439,7 @@ mod test {
                 // this is file1
                 CommandStats {
                     payload_bytes: 10,
+                    wire_bytes: 10,
                     peak_transfer_rate: 100,
                 },
                 None,
@@ -1536,7 +3479,7 @@ mod test {
         use littertray::LitterTray;
 
         let mut uut = make_uut(|_, _| (), "srcdir", "127.0.0.1:destdir", 4);
-        uut.args.client_params.preserve = true;
+        uut.args.client_params.preserve = PreserveSelection::STANDARD;
         uut.args.client_params.recurse = true;
         let working = Configuration_Optional::default();
         let r = LitterTray::try_with_async(async |tray| {
@@ -142,6 +142,7 @@ async fn run_plumbing(uut: &mut Client) -> anyhow::Result<(bool, crate::session:
                     p2,
                     Compatibility::Level(4),
                     Configuration::system_default(),
+                    None,
                 )
                 .await
                 {
@@ -217,7 +218,7 @@ async fn get_multi(
         // We need the tray path to be able to work with absolute paths.
         let mut uut = super::make_uut_multi(|_, _| (), &sources_strs, &dest, 4);
         uut.args.client_params.remote_debug = true;
-        uut.args.client_params.preserve = preserve_metadata;
+        uut.args.client_params.preserve = preserve_metadata.into();
         uut.display = MultiProgress::with_draw_target(ProgressDrawTarget::hidden());
         uut.spinner = ProgressBar::hidden();
         uut.args.client_params.recurse = true;
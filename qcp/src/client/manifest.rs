@@ -0,0 +1,129 @@
+//! Checksum manifest of transferred files (`--checksum-manifest`)
+// (c) 2025 Ross Younger
+
+use std::io::Write as _;
+use std::path::Path;
+use std::sync::Mutex;
+
+use anyhow::Context as _;
+use tokio::fs::File as TokioFile;
+
+use super::CopyJobSpec;
+
+/// Accumulates a list of the files transferred, in the format used by `sha256sum`.
+///
+/// The list covers the local copy of each file, so it can be checked with `sha256sum -c`.
+#[derive(Debug)]
+pub(crate) struct ChecksumManifest {
+    path: String,
+    entries: Mutex<Vec<String>>,
+}
+
+impl ChecksumManifest {
+    pub(crate) fn new(path: &str) -> Self {
+        Self {
+            path: path.to_string(),
+            entries: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Records a file that was successfully transferred.
+    ///
+    /// `digest` is the SHA-256 digest of the whole file, if the transfer computed it.
+    /// Otherwise (for example, if only part of the file was sent) we read the file to compute it.
+    pub(crate) async fn record(
+        &self,
+        job: &CopyJobSpec,
+        digest: Option<&[u8]>,
+        buffer_size: u64,
+    ) -> anyhow::Result<()> {
+        let path = job.local_path();
+        let hash = if let Some(digest) = digest {
+            digest.to_vec()
+        } else {
+            let mut file = TokioFile::open(&path)
+                .await
+                .with_context(|| format!("opening {}", path.display()))?;
+            crate::util::io::hash_prefix(&mut file, u64::MAX, buffer_size).await?
+        };
+        let line = manifest_line(&hex::encode(hash), &path);
+        self.entries
+            .lock()
+            .expect("manifest lock was poisoned")
+            .push(line);
+        Ok(())
+    }
+
+    /// Writes out the manifest
+    pub(crate) fn write(&self) -> anyhow::Result<()> {
+        let mut file = std::fs::File::create(&self.path)
+            .with_context(|| format!("creating checksum manifest {}", self.path))?;
+        for line in self
+            .entries
+            .lock()
+            .expect("manifest lock was poisoned")
+            .iter()
+        {
+            writeln!(file, "{line}")?;
+        }
+        file.sync_all()?;
+        Ok(())
+    }
+}
+
+/// Formats a manifest line. Like `sha256sum`, if the filename contains any awkward characters
+/// we escape them and prefix the line with a backslash.
+fn manifest_line(hash: &str, path: &Path) -> String {
+    let name = path.to_string_lossy();
+    if name.contains(['\\', '\n', '\r']) {
+        let name = name
+            .replace('\\', "\\\\")
+            .replace('\n', "\\n")
+            .replace('\r', "\\r");
+        format!("\\{hash}  {name}")
+    } else {
+        format!("{hash}  {name}")
+    }
+}
+
+#[cfg(test)]
+#[cfg_attr(coverage_nightly, coverage(off))]
+mod test {
+    use std::path::Path;
+
+    use littertray::LitterTray;
+    use pretty_assertions::assert_eq;
+
+    use super::{ChecksumManifest, manifest_line};
+    use crate::client::CopyJobSpec;
+
+    #[test]
+    fn escaping() {
+        assert_eq!(manifest_line("abc", Path::new("file")), "abc  file");
+        assert_eq!(
+            manifest_line("abc", Path::new("we\\ird\nname")),
+            "\\abc  we\\\\ird\\nname"
+        );
+    }
+
+    #[tokio::test]
+    async fn record_and_write() {
+        LitterTray::try_with_async(async |tray| {
+            let _ = tray.create_text("file1", "hello")?;
+            let manifest = ChecksumManifest::new("manifest");
+            let job = CopyJobSpec::from_parts("file1", "server:", false, false)?;
+            manifest.record(&job, None, 1024).await?;
+            // A digest from the transfer is used as it is, without reading the file
+            let job2 = CopyJobSpec::from_parts("file2", "server:", false, false)?;
+            manifest.record(&job2, Some(&[0xab, 0xcd]), 1024).await?;
+            manifest.write()?;
+            assert_eq!(
+                std::fs::read_to_string("manifest")?,
+                "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824  file1\nabcd  file2\n"
+            );
+            Ok(())
+        })
+        .await
+        .unwrap();
+    }
+}
@@ -26,16 +26,19 @@ pub(crate) struct InstaMeterRunner {
 }
 
 impl InstaMeterRunner {
+    /// Creates a meter. If `rate_limit` is non-zero, it is shown alongside the measured rate.
     pub(crate) fn new(
         source: &ProgressBar,
         destination: Option<ProgressBar>,
         max_throughput: u64,
+        rate_limit: u64,
     ) -> Self {
         Self {
             inner: Arc::new(Mutex::new(InstaMeterInner::new(
                 source,
                 destination.unwrap_or_else(ProgressBar::hidden),
                 max_throughput,
+                rate_limit,
             ))),
             task: None,
             stopper: None,
@@ -109,17 +112,28 @@ pub(crate) struct InstaMeterInner {
     source: ProgressBar,
     destination: ProgressBar,
     tick_calc: TickRateCalculator,
+    rate_limit: u64,
     pub(crate) peak: f64,
 }
 
 impl InstaMeterInner {
-    pub(crate) fn new(source: &ProgressBar, destination: ProgressBar, max_throughput: u64) -> Self {
+    pub(crate) fn new(
+        source: &ProgressBar,
+        destination: ProgressBar,
+        max_throughput: u64,
+        rate_limit: u64,
+    ) -> Self {
+        let max_throughput = match rate_limit {
+            0 => max_throughput,
+            limit => limit.min(max_throughput),
+        };
         #[allow(clippy::cast_precision_loss)]
         Self {
             previous_position: 0u64,
             source: source.clone(),
             destination,
             tick_calc: TickRateCalculator::new(max_throughput as f64),
+            rate_limit,
             peak: 0.,
         }
     }
@@ -132,7 +146,15 @@ impl InstaMeterInner {
         let elapsed = elapsed.as_secs_f64();
         let rate = progress / elapsed;
         self.previous_position = current;
-        let msg = format!("{} (last ~1s)", rate.human_throughput_bytes());
+        #[allow(clippy::cast_precision_loss)]
+        let msg = match self.rate_limit {
+            0 => format!("{} (last ~1s)", rate.human_throughput_bytes()),
+            limit => format!(
+                "{} (last ~1s; limit {})",
+                rate.human_throughput_bytes(),
+                (limit as f64).human_throughput_bytes()
+            ),
+        };
         self.peak = f64::max(self.peak, rate);
         self.destination.set_prefix(msg.clone());
         self.destination
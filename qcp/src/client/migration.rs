@@ -0,0 +1,342 @@
+//! Connection migration, for when the client's network changes
+// (c) 2025 Ross Younger
+//!
+//! QUIC connections are not tied to the client's address, so a transfer can survive the client
+//! roaming between networks. While the connection is open, we watch for:
+//!
+//! * a change to the local address we would use to reach the server (e.g. a new Wi-Fi network, or a VPN reconnecting);
+//! * the path failing: hearing nothing from the server for a while, despite the keepalives.
+//!
+//! In either case we move the connection onto a fresh socket with [`quinn::Endpoint::rebind`].
+//! The server validates the new path and carries on.
+//! If we still hear nothing, we try again after progressively longer waits, up to a limit;
+//! after that, the connection is left to time out and the client may reconnect (`--retry-connection`).
+//!
+//! The ssh control channel cannot move in the same way. It is idle during the transfer, so losing it
+//! does not stop the transfer, but the server's closedown report may then be lost.
+
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use anyhow::Result;
+use num_traits::ToPrimitive as _;
+use quinn::{Connection, Endpoint};
+use tokio::{task::JoinHandle, time::Instant};
+use tracing::{debug, info, warn};
+
+use crate::{
+    config::Configuration,
+    transport::{PATH_CHECK_INTERVAL, PROTOCOL_KEEPALIVE},
+    util::{
+        PortRange,
+        socket::{bind_range_for_family, local_address_for, set_udp_buffer_sizes},
+    },
+};
+
+/// If we hear nothing from the server for this long, we assume the path has failed.
+/// (The server sends keepalives more often than this.)
+const PATH_FAILURE_TIMEOUT: Duration = Duration::from_secs(2 * PROTOCOL_KEEPALIVE.as_secs());
+
+/// The most times in a row we move the connection because the path failed, without hearing from the server in between
+const MAX_PATH_FAILURE_MIGRATIONS: u32 = 4;
+
+/// Watches the network path of a connection, and migrates it when necessary
+pub(super) struct Migrator {
+    migrations: Arc<AtomicU64>,
+    task: JoinHandle<()>,
+}
+
+impl Migrator {
+    /// Starts watching the connection
+    pub(super) fn spawn(
+        endpoint: Endpoint,
+        connection: Connection,
+        config: &Configuration,
+    ) -> Self {
+        let migrations = Arc::new(AtomicU64::new(0));
+        let socket = SocketSettings {
+            port: config.port,
+            udp_buffer: config.udp_buffer.to_usize().unwrap_or(usize::MAX),
+        };
+        let task = tokio::spawn(watch(endpoint, connection, socket, migrations.clone()));
+        Self { migrations, task }
+    }
+
+    /// Stops watching, and returns the number of times the connection was migrated
+    pub(super) fn stop(self) -> u64 {
+        self.task.abort();
+        self.migrations.load(Ordering::Relaxed)
+    }
+}
+
+/// How to set up a new socket
+#[derive(Debug, Clone, Copy)]
+struct SocketSettings {
+    port: PortRange,
+    udp_buffer: usize,
+}
+
+/// State of the path to the server
+#[derive(Debug)]
+struct PathState {
+    local: Option<IpAddr>,
+    datagrams: u64,
+    last_heard: Instant,
+    /// Migrations since we last heard from the server, because the path had failed
+    failures: u32,
+}
+
+impl PathState {
+    fn new(local: Option<IpAddr>, datagrams: u64) -> Self {
+        Self {
+            local,
+            datagrams,
+            last_heard: Instant::now(),
+            failures: 0,
+        }
+    }
+
+    /// How long to wait for the server before deciding the path has failed.
+    /// This doubles with each migration that does not bring the path back.
+    fn failure_timeout(&self) -> Duration {
+        PATH_FAILURE_TIMEOUT.saturating_mul(1 << self.failures.min(MAX_PATH_FAILURE_MIGRATIONS))
+    }
+
+    /// Updates the state, and returns the reason to migrate the connection, if there is one.
+    ///
+    /// * `local` is the local address we would now use to reach the server (`None` if there is no route)
+    /// * `datagrams` is the number of datagrams received so far
+    fn update(&mut self, local: Option<IpAddr>, datagrams: u64) -> Option<String> {
+        let now = Instant::now();
+        if datagrams != self.datagrams {
+            self.datagrams = datagrams;
+            self.last_heard = now;
+            self.failures = 0;
+        }
+        // With no route to the server, there is nothing we can usefully do yet.
+        let local = local?;
+        let reason = if self.local != Some(local) {
+            Some(format!("Local address changed to {local}"))
+        } else if self.failures < MAX_PATH_FAILURE_MIGRATIONS
+            && now - self.last_heard >= self.failure_timeout()
+        {
+            self.failures += 1;
+            Some(format!(
+                "Nothing heard from the server for {}s",
+                (now - self.last_heard).as_secs()
+            ))
+        } else {
+            None
+        };
+        if reason.is_some() {
+            self.local = Some(local);
+            // Give the new path a chance
+            self.last_heard = now;
+        }
+        if self.failures == MAX_PATH_FAILURE_MIGRATIONS && reason.is_some() {
+            debug!("giving up on moving the connection until we hear from the server");
+        }
+        reason
+    }
+}
+
+async fn watch(
+    endpoint: Endpoint,
+    connection: Connection,
+    settings: SocketSettings,
+    migrations: Arc<AtomicU64>,
+) {
+    let server = connection.remote_address();
+    let mut state = PathState::new(
+        local_address_for(server),
+        connection.stats().udp_rx.datagrams,
+    );
+    let mut ticker = tokio::time::interval(PATH_CHECK_INTERVAL);
+    while connection.close_reason().is_none() {
+        let _ = ticker.tick().await;
+        let Some(reason) = state.update(
+            local_address_for(server),
+            connection.stats().udp_rx.datagrams,
+        ) else {
+            continue;
+        };
+        match rebind(&endpoint, server, settings) {
+            Ok(addr) => {
+                let _ = migrations.fetch_add(1, Ordering::Relaxed);
+                info!("{reason}; moved the connection to {addr}");
+            }
+            Err(e) => warn!("{reason}, but could not move the connection: {e}"),
+        }
+    }
+}
+
+/// Moves the endpoint onto a fresh socket
+fn rebind(endpoint: &Endpoint, server: SocketAddr, settings: SocketSettings) -> Result<SocketAddr> {
+    let mut socket = bind_range_for_family(server.into(), settings.port)?;
+    let _ = set_udp_buffer_sizes(
+        &mut socket,
+        Some(settings.udp_buffer),
+        Some(settings.udp_buffer),
+    )?;
+    let addr = socket.local_addr()?;
+    endpoint.rebind(socket)?;
+    debug!("endpoint rebound to {addr}");
+    Ok(addr)
+}
+
+#[cfg(test)]
+#[cfg_attr(coverage_nightly, coverage(off))]
+mod test {
+    use std::net::{IpAddr, Ipv4Addr, SocketAddrV4};
+    use std::time::Duration;
+
+    use pretty_assertions::assert_eq;
+    use tokio::time::timeout;
+
+    use super::{
+        MAX_PATH_FAILURE_MIGRATIONS, PATH_FAILURE_TIMEOUT, PathState, SocketSettings, rebind,
+    };
+    use crate::{
+        Configuration,
+        protocol::control::{Compatibility, ConnectionType},
+        transport::ThroughputMode,
+        util::Credentials,
+    };
+
+    const ADDR1: Option<IpAddr> = Some(IpAddr::V4(Ipv4Addr::new(192, 168, 1, 2)));
+    const ADDR2: Option<IpAddr> = Some(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)));
+
+    #[tokio::test(start_paused = true)]
+    async fn address_change() {
+        let mut uut = PathState::new(ADDR1, 0);
+        assert_eq!(uut.update(ADDR1, 1), None);
+        // Losing the route is not a reason to migrate...
+        assert_eq!(uut.update(None, 1), None);
+        // ... but coming back on a different address is
+        assert!(uut.update(ADDR2, 1).unwrap().contains("10.0.0.2"));
+        assert_eq!(uut.update(ADDR2, 1), None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn path_failure() {
+        let mut uut = PathState::new(ADDR1, 0);
+        tokio::time::advance(PATH_FAILURE_TIMEOUT / 2).await;
+        assert_eq!(uut.update(ADDR1, 5), None);
+        tokio::time::advance(PATH_FAILURE_TIMEOUT / 2).await;
+        assert_eq!(uut.update(ADDR1, 5), None);
+        tokio::time::advance(PATH_FAILURE_TIMEOUT / 2).await;
+        assert!(uut.update(ADDR1, 5).unwrap().contains("Nothing heard"));
+        // We give the new path a chance
+        assert_eq!(uut.update(ADDR1, 5), None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn path_failure_backs_off() {
+        let mut uut = PathState::new(ADDR1, 0);
+        let mut wait = PATH_FAILURE_TIMEOUT;
+        for _ in 0..MAX_PATH_FAILURE_MIGRATIONS {
+            tokio::time::advance(wait / 2).await;
+            assert_eq!(uut.update(ADDR1, 0), None);
+            tokio::time::advance(wait / 2).await;
+            assert!(uut.update(ADDR1, 0).unwrap().contains("Nothing heard"));
+            // Each attempt waits twice as long as the last
+            wait *= 2;
+        }
+        // Having reached the limit, we stop trying...
+        tokio::time::advance(wait * 4).await;
+        assert_eq!(uut.update(ADDR1, 0), None);
+        // ... until we hear from the server again
+        assert_eq!(uut.update(ADDR1, 1), None);
+        tokio::time::advance(PATH_FAILURE_TIMEOUT).await;
+        assert!(uut.update(ADDR1, 1).unwrap().contains("Nothing heard"));
+    }
+
+    #[cfg_attr(target_os = "macos", ignore)]
+    #[cfg_attr(target_os = "windows", ignore = "fails under Wine in CI")]
+    #[tokio::test]
+    async fn connection_survives_rebind() {
+        let compat = Compatibility::Level(5);
+        let config = Configuration::system_default();
+        let server_creds = Credentials::generate().unwrap();
+        let client_creds = Credentials::generate().unwrap();
+        let server_cert = server_creds.to_tagged_data(compat, None).unwrap();
+        let client_cert = client_creds.to_tagged_data(compat, None).unwrap();
+
+        let (server_endpoint, _) = crate::control::create_endpoint(
+            &server_creds,
+            &client_cert,
+            ConnectionType::Ipv4,
+            config,
+            ThroughputMode::Both,
+            true,
+            compat,
+        )
+        .unwrap();
+        let server_addr: std::net::SocketAddr = SocketAddrV4::new(
+            Ipv4Addr::LOCALHOST,
+            server_endpoint.local_addr().unwrap().port(),
+        )
+        .into();
+
+        // The server echoes one message on each stream, and reports the client's address
+        let server_task = tokio::spawn(async move {
+            let connection = server_endpoint.accept().await.unwrap().await.unwrap();
+            let mut addresses = vec![];
+            while let Ok((mut send, mut recv)) = connection.accept_bi().await {
+                let mut buf = [0u8; 5];
+                recv.read_exact(&mut buf).await.unwrap();
+                send.write_all(&buf).await.unwrap();
+                send.finish().unwrap();
+                addresses.push(connection.remote_address());
+            }
+            server_endpoint.wait_idle().await;
+            addresses
+        });
+
+        let (client_endpoint, _) = crate::control::create_endpoint(
+            &client_creds,
+            &server_cert,
+            ConnectionType::Ipv4,
+            config,
+            ThroughputMode::Both,
+            false,
+            compat,
+        )
+        .unwrap();
+        let connection = client_endpoint
+            .connect(server_addr, &server_creds.hostname)
+            .unwrap()
+            .await
+            .unwrap();
+
+        let echo = async || {
+            let (mut send, mut recv) = connection.open_bi().await.unwrap();
+            send.write_all(b"hello").await.unwrap();
+            send.finish().unwrap();
+            let buf = recv.read_to_end(5).await.unwrap();
+            assert_eq!(buf, b"hello");
+        };
+        echo().await;
+        let old_addr = client_endpoint.local_addr().unwrap();
+        let settings = SocketSettings {
+            port: config.port,
+            udp_buffer: 65536,
+        };
+        let new_addr = rebind(&client_endpoint, server_addr, settings).unwrap();
+        assert_ne!(old_addr.port(), new_addr.port());
+        timeout(Duration::from_secs(5), echo()).await.unwrap();
+
+        connection.close(0u32.into(), b"test");
+        client_endpoint.close(0u32.into(), b"test");
+        let _ = timeout(Duration::from_secs(5), client_endpoint.wait_idle()).await;
+        let addresses = timeout(Duration::from_secs(5), server_task)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(addresses.len(), 2);
+        assert_eq!(addresses[0].port(), old_addr.port());
+        assert_eq!(addresses[1].port(), new_addr.port());
+    }
+}
@@ -4,14 +4,21 @@ mod job;
 pub use job::CopyJobSpec;
 pub use job::FileSpec;
 
+#[cfg(unix)]
+pub(crate) mod agent;
+mod autotune;
 mod main_loop;
+mod manifest;
+mod migration;
+#[cfg(unix)]
+pub(crate) use main_loop::agent_main;
 #[allow(clippy::module_name_repetitions)]
 pub(crate) use main_loop::client_main;
 
 pub(crate) mod meter;
 
 mod options;
-pub use options::Parameters;
+pub use options::{LinkMode, Parameters, PreserveSelection};
 
 pub(crate) mod progress;
 pub(crate) use progress::MAX_UPDATE_FPS;
@@ -1,8 +1,171 @@
 //! Options specific to qcp client-mode
 // (c) 2024 Ross Younger
 
+use std::str::FromStr;
+
 use clap::Parser;
 
+use crate::protocol::{
+    TaggedData,
+    compat::Feature,
+    control::Compatibility,
+    session::{CommandParam, MetadataAttr},
+};
+
+/// How to treat symbolic links found while copying directories recursively (see `--links`)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum LinkMode {
+    /// Copy whatever the link points to
+    #[default]
+    Follow,
+    /// Recreate the link at the destination
+    Preserve,
+    /// Leave the link out
+    Skip,
+    /// Recreate the link, unless it points outside the tree being copied
+    Safe,
+}
+
+impl LinkMode {
+    /// Are links followed (rather than reported as links)?
+    #[must_use]
+    pub fn follows(self) -> bool {
+        self == LinkMode::Follow
+    }
+}
+
+/// Which file attributes to preserve (see `--preserve`)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct PreserveSelection {
+    /// File and directory permissions (mode bits)
+    pub mode: bool,
+    /// Access and modification times
+    pub times: bool,
+    /// File ownership, as far as the receiving side is privileged to set it
+    pub owner: bool,
+    /// Extended attributes; the same as `--xattrs`
+    pub xattrs: bool,
+    /// POSIX access control lists; the same as `--acls`
+    pub acls: bool,
+}
+
+impl PreserveSelection {
+    /// What plain `--preserve` selects: mode, times and ownership
+    pub const STANDARD: Self = Self {
+        mode: true,
+        times: true,
+        owner: true,
+        xattrs: false,
+        acls: false,
+    };
+
+    /// Is any of mode, times or ownership selected?
+    ///
+    /// Extended attributes and ACLs are handled separately (see `XattrSelection`).
+    #[must_use]
+    pub fn any(self) -> bool {
+        self.mode || self.times || self.owner
+    }
+
+    /// Should this item of file metadata be sent or applied?
+    ///
+    /// Metadata which is not subject to `--preserve` is always kept.
+    pub(crate) fn keeps(self, md: &TaggedData<MetadataAttr>) -> bool {
+        match md.tag() {
+            Some(MetadataAttr::ModeBits) => self.mode,
+            Some(
+                MetadataAttr::AccessTime
+                | MetadataAttr::ModificationTime
+                | MetadataAttr::AccessTimeNanos
+                | MetadataAttr::ModificationTimeNanos,
+            ) => self.times,
+            Some(
+                MetadataAttr::OwnerId
+                | MetadataAttr::GroupId
+                | MetadataAttr::OwnerName
+                | MetadataAttr::GroupName,
+            ) => self.owner,
+            _ => true,
+        }
+    }
+
+    /// Reads the selection from command options
+    pub(crate) fn from_options(options: &[TaggedData<CommandParam>]) -> Self {
+        let has = |param| options.iter().any(|o| o.tag() == Some(param));
+        if has(CommandParam::PreserveMetadata) {
+            return Self::STANDARD;
+        }
+        Self {
+            mode: has(CommandParam::PreserveMode),
+            times: has(CommandParam::PreserveTimes),
+            owner: has(CommandParam::PreserveOwnership),
+            ..Self::default()
+        }
+    }
+
+    /// Converts the selection to command options.
+    ///
+    /// If the remote cannot select attributes individually, it is asked for all of them;
+    /// the receiving side then applies only those selected.
+    pub(crate) fn to_options(self, compat: Compatibility) -> Vec<TaggedData<CommandParam>> {
+        let selected = Self {
+            xattrs: false,
+            acls: false,
+            ..self
+        };
+        if selected == Self::STANDARD
+            || (selected.any() && !compat.supports(Feature::PRESERVE_SELECTION))
+        {
+            return vec![CommandParam::PreserveMetadata.into()];
+        }
+        let mut options = Vec::new();
+        if self.mode {
+            options.push(CommandParam::PreserveMode.into());
+        }
+        if self.times {
+            options.push(CommandParam::PreserveTimes.into());
+        }
+        if self.owner {
+            options.push(CommandParam::PreserveOwnership.into());
+        }
+        options
+    }
+}
+
+impl From<bool> for PreserveSelection {
+    /// `true` selects what plain `--preserve` does; `false` selects nothing
+    fn from(preserve: bool) -> Self {
+        if preserve {
+            Self::STANDARD
+        } else {
+            Self::default()
+        }
+    }
+}
+
+impl FromStr for PreserveSelection {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut selection = Self::default();
+        for item in s.split(',').map(str::trim) {
+            match item {
+                "mode" => selection.mode = true,
+                "times" => selection.times = true,
+                "owner" => selection.owner = true,
+                "xattrs" => selection.xattrs = true,
+                "acls" => selection.acls = true,
+                "none" => (),
+                _ => anyhow::bail!(
+                    "unknown attribute {item:?} (expected mode, times, owner, xattrs or acls)"
+                ),
+            }
+        }
+        Ok(selection)
+    }
+}
+
 #[derive(Debug, Parser, Clone, Copy, Default)]
 #[allow(clippy::struct_excessive_bools)]
 /// Client-side options which may be provided on the command line, but are not persistent configuration options.
@@ -60,11 +223,49 @@ pub struct Parameters {
 
     /// Preserves file/directory permissions and file modification times as far as possible.
     ///
-    /// Directory modification times are not preserved. This is because they are OS-specific and not well defined.
-    #[arg(short, long, display_order(0))]
-    pub preserve: bool,
+    /// File ownership is preserved too, if the receiving side is running as root;
+    /// otherwise, only the group is preserved, and only if the receiving user belongs to it.
+    /// Users and groups are matched by name where possible (see `--numeric-ids`).
+    ///
+    /// When copying recursively, directory access and modification times are preserved too, if the remote supports this.
+    /// They are applied once everything within the directory has been written.
+    ///
+    /// To preserve only some of these, give a comma-separated list of `mode`, `times` and `owner`,
+    /// for example `--preserve=times`. Without `mode`, new files are created with the source
+    /// permissions less the receiver's umask, as if `--preserve` had not been given.
+    /// The list may also include `xattrs` and `acls`, which have the same meaning as `--xattrs` and `--acls`.
+    #[arg(
+        short,
+        long,
+        value_name = "LIST",
+        num_args(0..=1),
+        require_equals(true),
+        default_value = "none",
+        hide_default_value(true),
+        default_missing_value = "mode,times,owner",
+        display_order(0)
+    )]
+    pub preserve: PreserveSelection,
 
-    /// Copies entire directories recursively, following symbolic links.
+    /// When preserving file ownership, uses numeric user and group IDs rather than matching by name.
+    #[arg(long, requires("preserve"), display_order(0))]
+    pub numeric_ids: bool,
+
+    /// Preserves extended attributes (for example `user.*` attributes and SELinux labels) as far as possible.
+    ///
+    /// This applies to directories as well as files; a directory's attributes are applied once its contents have been transferred.
+    /// Attributes which cannot be applied at the destination are reported, but do not cause the transfer to fail.
+    #[arg(long, display_order(0))]
+    pub xattrs: bool,
+
+    /// Preserves POSIX access control lists as far as possible.
+    ///
+    /// Currently only supported on Linux. Directories keep their default ACLs as well as their access ACLs.
+    /// ACLs which cannot be applied are reported, but do not cause the transfer to fail.
+    #[arg(long, display_order(0))]
+    pub acls: bool,
+
+    /// Copies entire directories recursively, following symbolic links (unless `--links`).
     ///
     /// Behaviour is intended to match that of scp.
     ///
@@ -84,10 +285,110 @@ pub struct Parameters {
         long,
         display_order(0),
         long_help(
-            "Copies entire directories recursively, following symbolic links.\n\nBehaviour is intended to match scp."
+            "Copies entire directories recursively, following symbolic links (unless --links).\n\nBehaviour is intended to match scp."
         )
     )]
     pub recurse: bool,
+
+    /// Resumes interrupted transfers.
+    ///
+    /// If a destination file already exists, only the part of the source beyond its length is transferred,
+    /// provided that the existing data matches the source (this is checked by hashing it).
+    /// Otherwise, the whole file is transferred.
+    ///
+    /// Files are written in place (as with `--in-place`), so an interrupted transfer leaves behind
+    /// the data received so far, which a later `--resume` can continue from.
+    #[arg(long, display_order(0))]
+    pub resume: bool,
+
+    /// Writes received files directly to their destination.
+    ///
+    /// By default, files are received into a hidden temporary file in the destination directory,
+    /// which is renamed into place only when the transfer succeeds, and deleted if it fails.
+    /// Writing in place needs less disk space, but an interrupted transfer leaves a partial file behind.
+    ///
+    /// Transfers with `--resume`, and striped transfers, always write to the destination in place.
+    #[arg(long, display_order(0))]
+    pub in_place: bool,
+
+    /// Skips the check that there is enough free space at the destination.
+    ///
+    /// Before transferring, qcp adds up the sizes of the files to be sent and refuses to start
+    /// if they will not fit on the destination filesystem.
+    /// The check does not allow for existing files that would be overwritten, so this option may
+    /// be needed when replacing large files on a nearly full disk.
+    #[arg(long, display_order(0))]
+    pub no_space_check: bool,
+
+    /// Warns, instead of refusing to start, if there may not be enough free space at the destination.
+    ///
+    /// See `--no-space-check`.
+    #[arg(long, display_order(0), conflicts_with("no_space_check"))]
+    pub space_warn_only: bool,
+
+    /// Determines what happens to symbolic links found while copying directories recursively.
+    ///
+    /// * `follow` (the default): copies whatever the link points to, as scp does.
+    /// * `preserve`: recreates the link at the destination. This is what `--links` on its own means.
+    /// * `skip`: leaves links out altogether.
+    /// * `safe`: as `preserve`, but leaves out (with a warning) any link that points outside the
+    ///   tree being copied, whether by an absolute path or by climbing out with `..`.
+    ///
+    /// Links named on the command line are always followed.
+    #[arg(
+        long,
+        value_name = "MODE",
+        num_args(0..=1),
+        require_equals(true),
+        default_value = "follow",
+        default_missing_value = "preserve",
+        display_order(0)
+    )]
+    pub links: LinkMode,
+
+    /// Preserves hard links when copying directories recursively.
+    ///
+    /// Where several names in the tree refer to the same file, the file is transferred once;
+    /// the other names are recreated as hard links to it.
+    /// Files which are hard-linked to others outside the tree are copied as normal.
+    ///
+    /// Not supported when copying from Windows.
+    #[arg(short = 'H', long, requires("recurse"), display_order(0))]
+    pub hard_links: bool,
+
+    /// Measures the network link before transferring, instead of relying on the configured `rx`, `tx` and `rtt`.
+    ///
+    /// After connecting, qcp runs a short probe in each direction and uses the results to set
+    /// its window sizes for the transfer. The measured values are printed in configuration file
+    /// syntax, so they can be copied into a `Host` block.
+    ///
+    /// The probe lasts for 20 round trips in each direction (at least one second, at most ten),
+    /// and relies on the link being otherwise idle.
+    /// Measurements lower than the configured values are not used, unless `--autotune-reduce` is given.
+    /// Autotuning cannot be used with the `FixedRate` congestion controller.
+    #[arg(long, help_heading("Tuning"), display_order(1))]
+    pub autotune: bool,
+
+    /// Allows `--autotune` to set `rx`, `tx` and `rtt` lower than their configured values.
+    ///
+    /// This makes the window sizes smaller, which may slow the transfer down if the probe
+    /// under-measured the link.
+    #[arg(long, requires("autotune"), help_heading("Tuning"), display_order(1))]
+    pub autotune_reduce: bool,
+
+    /// If the connection to the remote is lost, reconnects up to this many times and carries on.
+    ///
+    /// Files that had been completely transferred are not sent again; a file that was cut off
+    /// part way through is restarted (or resumed, with `--resume`).
+    /// qcp waits for a short while before each attempt, backing off exponentially.
+    #[arg(long, value_name = "N", default_value_t = 0, display_order(0))]
+    pub retry_connection: u16,
+
+    /// Does not use a connection agent, even if one is running for the remote host.
+    ///
+    /// See `--agent`.
+    #[arg(long, help_heading("Connection agent"), display_order(100))]
+    pub no_agent: bool,
 }
 
 #[cfg(test)]
@@ -130,6 +431,173 @@ mod tests {
         assert!(params.remote_debug);
     }
 
+    #[test]
+    fn test_resume_option() {
+        let params = Parameters::parse_from(["test", "--resume"]);
+        assert!(params.resume);
+    }
+
+    #[test]
+    fn test_in_place_option() {
+        let params = Parameters::parse_from(["test", "--in-place"]);
+        assert!(params.in_place);
+        assert!(!Parameters::parse_from(["test"]).in_place);
+    }
+
+    #[test]
+    fn test_links_option() {
+        use super::LinkMode;
+        assert_eq!(Parameters::parse_from(["test"]).links, LinkMode::Follow);
+        assert_eq!(
+            Parameters::parse_from(["test", "--links"]).links,
+            LinkMode::Preserve
+        );
+        assert_eq!(
+            Parameters::parse_from(["test", "--links=safe"]).links,
+            LinkMode::Safe
+        );
+        // --links does not swallow the next argument
+        let args = CliArgs::parse_from(["test", "--links", "src", "dest"]);
+        assert_eq!(args.client_params.links, LinkMode::Preserve);
+        assert_eq!(args.paths.len(), 2);
+    }
+
+    #[test]
+    fn test_numeric_ids_option() {
+        assert!(Parameters::parse_from(["test", "-p", "--numeric-ids"]).numeric_ids);
+        assert!(Parameters::try_parse_from(["test", "--numeric-ids"]).is_err());
+    }
+
+    #[test]
+    fn test_xattrs_acls_options() {
+        let params = Parameters::parse_from(["test", "--xattrs"]);
+        assert!(params.xattrs && !params.acls);
+        let params = Parameters::parse_from(["test", "--acls"]);
+        assert!(!params.xattrs && params.acls);
+    }
+
+    #[test]
+    fn test_preserve_option() {
+        use super::PreserveSelection;
+        assert_eq!(
+            Parameters::parse_from(["test"]).preserve,
+            PreserveSelection::default()
+        );
+        assert_eq!(
+            Parameters::parse_from(["test", "-p"]).preserve,
+            PreserveSelection::STANDARD
+        );
+        assert_eq!(
+            Parameters::parse_from(["test", "--preserve=times"]).preserve,
+            PreserveSelection {
+                times: true,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            Parameters::parse_from(["test", "--preserve=mode,owner,xattrs"]).preserve,
+            PreserveSelection {
+                mode: true,
+                owner: true,
+                xattrs: true,
+                ..Default::default()
+            }
+        );
+        assert!(Parameters::try_parse_from(["test", "--preserve=colour"]).is_err());
+        // -p does not swallow the next argument
+        let args = CliArgs::parse_from(["test", "-p", "src", "dest"]);
+        assert_eq!(args.client_params.preserve, PreserveSelection::STANDARD);
+        assert_eq!(args.paths.len(), 2);
+        // --numeric-ids needs --preserve
+        assert!(Parameters::try_parse_from(["test", "--numeric-ids"]).is_err());
+        assert!(Parameters::try_parse_from(["test", "--preserve=owner", "--numeric-ids"]).is_ok());
+    }
+
+    #[test]
+    fn preserve_selection_options() {
+        use super::PreserveSelection;
+        use crate::protocol::{
+            DataTag as _,
+            control::Compatibility,
+            session::{CommandParam, MetadataAttr},
+        };
+        use crate::util::time::SystemTimeExt as _;
+        use std::time::SystemTime;
+
+        let times = PreserveSelection {
+            times: true,
+            ..Default::default()
+        };
+        let options = times.to_options(Compatibility::Level(5));
+        assert_eq!(options, vec![CommandParam::PreserveTimes.into()]);
+        assert_eq!(PreserveSelection::from_options(&options), times);
+        // Older peers are asked for everything
+        assert_eq!(
+            times.to_options(Compatibility::Level(4)),
+            vec![CommandParam::PreserveMetadata.into()]
+        );
+        assert_eq!(
+            PreserveSelection::from_options(&[CommandParam::PreserveMetadata.into()]),
+            PreserveSelection::STANDARD
+        );
+        assert!(
+            PreserveSelection::default()
+                .to_options(Compatibility::Level(5))
+                .is_empty()
+        );
+
+        assert!(times.keeps(&MetadataAttr::new_mtime(SystemTime::from_unix(1))));
+        assert!(!times.keeps(&MetadataAttr::new_mode(0o644)));
+        assert!(!times.keeps(&MetadataAttr::OwnerName.with_str("root")));
+        assert!(times.keeps(&MetadataAttr::Sha256Digest.with_bytes(vec![1, 2, 3])));
+    }
+
+    #[test]
+    fn test_hard_links_option() {
+        assert!(Parameters::parse_from(["test", "-rH"]).hard_links);
+        assert!(Parameters::parse_from(["test", "-r", "--hard-links"]).hard_links);
+        assert!(!Parameters::parse_from(["test", "-r"]).hard_links);
+        // Only meaningful when recursing
+        assert!(Parameters::try_parse_from(["test", "-H"]).is_err());
+    }
+
+    #[test]
+    fn test_no_space_check_option() {
+        assert!(Parameters::parse_from(["test", "--no-space-check"]).no_space_check);
+        assert!(!Parameters::parse_from(["test"]).no_space_check);
+        assert!(Parameters::parse_from(["test", "--space-warn-only"]).space_warn_only);
+        assert!(
+            Parameters::try_parse_from(["test", "--space-warn-only", "--no-space-check"]).is_err()
+        );
+    }
+
+    #[test]
+    fn test_autotune_option() {
+        let params = Parameters::parse_from(["test", "--autotune"]);
+        assert!(params.autotune);
+        assert!(!Parameters::parse_from(["test"]).autotune);
+        assert!(!params.autotune_reduce);
+        assert!(
+            Parameters::parse_from(["test", "--autotune", "--autotune-reduce"]).autotune_reduce
+        );
+        assert!(Parameters::try_parse_from(["test", "--autotune-reduce"]).is_err());
+    }
+
+    #[test]
+    fn test_no_agent_option() {
+        assert!(Parameters::parse_from(["test", "--no-agent"]).no_agent);
+        assert!(!Parameters::parse_from(["test"]).no_agent);
+    }
+
+    #[test]
+    fn test_retry_connection_option() {
+        assert_eq!(
+            Parameters::parse_from(["test", "--retry-connection", "3"]).retry_connection,
+            3
+        );
+        assert_eq!(Parameters::parse_from(["test"]).retry_connection, 0);
+    }
+
     #[test]
     fn test_profile_option() {
         let params = Parameters::parse_from(["test", "--profile"]);
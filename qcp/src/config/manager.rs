@@ -234,7 +234,9 @@ impl Manager {
     /// Performs additional validation checks on the fields present in the configuration, as far as possible.
     /// This is only useful when the [`Manager`] holds a [`Configuration`].
     pub fn validate_configuration(&self) -> Result<()> {
-        self.get::<Configuration>()?.try_validate()
+        let config = self.get::<Configuration>()?;
+        config.try_validate()?;
+        config.validate_mtus()
     }
 }
 
@@ -514,6 +516,26 @@ mod test {
         .unwrap();
     }
 
+    #[test]
+    fn validate_mtu_against_payload_size() {
+        let mut mgr = Manager::without_files(None);
+        mgr.merge_provider(Configuration_Optional {
+            max_mtu: Some(8972),
+            ..Default::default()
+        });
+        let err = mgr.validate_configuration().unwrap_err().to_string();
+        assert!(
+            err.contains("larger than the maximum UDP payload size"),
+            "{err}"
+        );
+
+        mgr.merge_provider(Configuration_Optional {
+            max_udp_payload_size: Some(8972),
+            ..Default::default()
+        });
+        mgr.validate_configuration().unwrap();
+    }
+
     #[test]
     fn parse_eng_quantity() {
         LitterTray::try_with(|tray| {
@@ -28,6 +28,17 @@ pub(crate) const MINIMUM_BANDWIDTH: u64 = 150;
 
 pub(crate) const MINIMUM_UDP_BUFFER: u64 = 1024; // ridiculously small, but you have to have a limit somewhere.
 
+/// Highest zstd compression level we accept. (zstd goes higher, but the "ultra" levels are very slow and use a lot of memory.)
+pub(crate) const MAXIMUM_COMPRESSION_LEVEL: u8 = 19;
+
+/// Most QUIC connections we will open to one host.
+/// Each connection has its own endpoint, so this also limits the number of UDP ports used.
+pub(crate) const MAXIMUM_CONNECTIONS: u16 = 16;
+
+/// Limits on `max_udp_payload_size`, which are set by the QUIC protocol (RFC 9000 s18.2)
+pub(crate) const MINIMUM_UDP_PAYLOAD: u16 = 1200;
+pub(crate) const MAXIMUM_UDP_PAYLOAD: u16 = 65527;
+
 /// The set of configurable options supported by qcp.
 ///
 /// **IMPORTANT:** The server and client configurations are combined at runtime.
@@ -146,6 +157,50 @@ This parameter is always interpreted as the _local_ bandwidth, whether operating
     )]
     pub tx: u64,
 
+    /// Caps the rate at which we receive file data FROM the remote system, in bytes per second.
+    /// [default: 0, no limit]
+    ///
+    /// Unlike `rx`, which describes the network, this is a hard limit:
+    /// the sender paces its data so it is not exceeded, leaving the rest of the link for other traffic.
+    /// Either side may impose a limit; if both do, the smaller is used.
+    ///
+    /// Specify as a number, or as an SI quantity (e.g. `10M`).
+    #[arg(
+        long,
+        value_name = "bytes",
+        value_parser (clap::builder::StringValueParser::new().try_map(|s| EngineeringQuantity::<u64>::from_str(&s)).map(|v| u64::from(v))),
+        help_heading("Tuning"),
+        display_order(0),
+    )]
+    #[serde(with = "EQHelper")]
+    #[deftly(
+        serde = "default, deserialize_with = \"EQHelper::deserialize_optional\"",
+        serialize_with = "EQHelper::to_string_figment"
+    )]
+    pub limit_rate_rx: u64,
+
+    /// Caps the rate at which we send file data TO the remote system, in bytes per second.
+    /// [default: 0, no limit]
+    ///
+    /// Unlike `tx`, which describes the network, this is a hard limit:
+    /// we pace our data so it is not exceeded, leaving the rest of the link for other traffic.
+    /// Either side may impose a limit; if both do, the smaller is used.
+    ///
+    /// Specify as a number, or as an SI quantity (e.g. `10M`).
+    #[arg(
+        long,
+        value_name = "bytes",
+        value_parser (clap::builder::StringValueParser::new().try_map(|s| EngineeringQuantity::<u64>::from_str(&s)).map(|v| u64::from(v))),
+        help_heading("Tuning"),
+        display_order(0),
+    )]
+    #[serde(with = "EQHelper")]
+    #[deftly(
+        serde = "default, deserialize_with = \"EQHelper::deserialize_optional\"",
+        serialize_with = "EQHelper::to_string_figment"
+    )]
+    pub limit_rate_tx: u64,
+
     /// The expected network Round Trip time to the target system, in milliseconds.
     /// [default: 300]
     #[arg(long, help_heading("Tuning"), value_name("ms"), display_order(1))]
@@ -234,6 +289,47 @@ This may be specified directly as a number, or as an SI quantity like `10k`."
     )]
     pub timeout: u16,
 
+    /// The maximum number of files to transfer at once, on each connection [default: 1]
+    ///
+    /// Each file is transferred on its own QUIC stream. When copying many files over a long-latency
+    /// link, running several at once hides the per-file setup time.
+    /// The receive window is shared between the streams, so this does not increase memory usage.
+    ///
+    /// The server may impose a lower limit. If both sides specify a value, the smaller is used.
+    #[arg(
+        long,
+        value_name("N"),
+        alias("streams"),
+        help_heading("Tuning"),
+        display_order(1)
+    )]
+    pub parallel_streams: u16,
+
+    /// The number of QUIC connections to open to the remote host [default: 1]
+    ///
+    /// Each connection has its own UDP port at each end. On fast links, several connections
+    /// can spread the load across CPU cores, and across the paths of an ECMP network.
+    /// Files are shared out between the connections; each connection carries up to
+    /// `parallel_streams` files at once.
+    ///
+    /// Each connection is set up for the full `rx` and `tx`, so memory use grows with the number of connections.
+    /// (The `FixedRate` congestion controller is the exception: it shares `tx` between the connections.)
+    /// The server's local port range must have room for all of them.
+    ///
+    /// The server may impose a lower limit. If both sides specify a value, the smaller is used.
+    #[arg(long, value_name("N"), help_heading("Tuning"), display_order(1))]
+    pub connections: u16,
+
+    /// Compresses file data with zstd at this level (1-19) [default: 0, off]
+    ///
+    /// This helps on slow links when the data compresses well (for example text, logs or CSV).
+    /// Files whose data does not shrink are detected early, and sent uncompressed.
+    /// Higher levels compress better, but are slower.
+    ///
+    /// Compression is requested by the client; the server's setting is not used.
+    #[arg(long, value_name("level"), help_heading("Tuning"), display_order(2))]
+    pub compression: u8,
+
     /// Size of the UDP kernel buffer in bytes.
     ///
     /// Specify as an integer or as an SI quantity, e.g. 4M.
@@ -333,6 +429,21 @@ This may be specified directly as a number, or as an SI quantity like `10k`."
     )]
     pub max_mtu: u16,
 
+    /// The largest UDP payload we will accept from the remote endpoint (default: 1472)
+    ///
+    /// This limits the size of the packets the remote endpoint may send to us, whatever MTU it discovers.
+    /// To make use of jumbo frames, set this as well as `max_mtu` and (optionally) `initial_mtu`;
+    /// for example, a 9000-byte MTU on IPv4 allows a payload of 8972 bytes.
+    ///
+    /// Both endpoints use the smaller of their two settings.
+    #[arg(
+        long,
+        help_heading("Advanced network tuning"),
+        display_order(0),
+        value_name = "bytes"
+    )]
+    pub max_udp_payload_size: u16,
+
     // CLIENT OPTIONS ==================================================================================
     /// Forces use of a particular IP version when connecting to the remote. [default: any]
     ///
@@ -460,6 +571,18 @@ For example, to pass `-i /dev/null` to ssh, specify: `-S -i -S /dev/null`"
     )]
     pub ssh_subsystem: bool,
 
+    /// How long a connection agent waits with no transfers in progress before it exits
+    /// [seconds; default 600]
+    ///
+    /// See `--agent`.
+    #[arg(
+        long,
+        value_name("seconds"),
+        help_heading("Connection"),
+        display_order(0)
+    )]
+    pub agent_timeout: u32,
+
     // OTHER PARAMETERS ================================================================================
     /// Colour mode for console output (default: auto)
     ///
@@ -553,18 +676,24 @@ static SYSTEM_DEFAULT_CONFIG: LazyLock<Configuration> = LazyLock::new(|| Configu
     // Transport
     rx: 12_500_000, // 100Mbit
     tx: 0,
+    limit_rate_rx: 0,
+    limit_rate_tx: 0,
     rtt: 300,
     congestion: CongestionController::Cubic,
     initial_congestion_window: 0,
     port: PortRange::default(),
     timeout: 5,
+    parallel_streams: 1,
+    connections: 1,
+    compression: 0,
     // https://fasterdata.es.net/host-tuning/linux/udp-tuning/ recommends 4M as good for most settings
     udp_buffer: 4_000_000,
-    packet_threshold: 3,     // default from Quinn
-    time_threshold: 9. / 8., // default from Quinn
-    initial_mtu: 1200,       // same as Quinn
-    min_mtu: 1200,           // same as Quinn
-    max_mtu: 1452,           // same as Quinn
+    packet_threshold: 3,        // default from Quinn
+    time_threshold: 9. / 8.,    // default from Quinn
+    initial_mtu: 1200,          // same as Quinn
+    min_mtu: 1200,              // same as Quinn
+    max_mtu: 1452,              // same as Quinn
+    max_udp_payload_size: 1472, // same as Quinn
 
     // Client
     address_family: AddressFamily::Any,
@@ -576,6 +705,7 @@ static SYSTEM_DEFAULT_CONFIG: LazyLock<Configuration> = LazyLock::new(|| Configu
     time_format: TimeFormat::Local,
     ssh_config: Vec::new(),
     ssh_subsystem: false,
+    agent_timeout: 600,
     color: ColourMode::Auto,
     io_buffer_size: crate::util::io::DEFAULT_COPY_BUFFER_SIZE,
 
@@ -621,6 +751,14 @@ impl Configuration {
         self.rtt_bandwidth_delay_product_rx()
     }
 
+    /// QUIC per-stream receive window.
+    ///
+    /// The connection receive window is shared between all the streams we may have open at once.
+    #[must_use]
+    pub fn stream_recv_window(&self) -> u64 {
+        self.recv_window() / u64::from(self.parallel_streams.max(1))
+    }
+
     /// QUIC send window
     #[must_use]
     pub fn send_window(&self) -> u64 {
@@ -634,6 +772,12 @@ impl Configuration {
         Duration::from_secs(self.timeout.into())
     }
 
+    /// Accessor for `agent_timeout`, as a Duration
+    #[must_use]
+    pub fn agent_timeout_duration(&self) -> Duration {
+        Duration::from_secs(self.agent_timeout.into())
+    }
+
     /// Formats the transport-related options for display
     #[must_use]
     pub fn format_transport_config(&self) -> String {
@@ -647,6 +791,7 @@ impl Configuration {
                 "rx {rx} ({rxbits}), tx {tx} ({txbits}), rtt {rtt}; ",
                 "congestion algorithm {congestion} with initial window {iwind}; ",
                 "send window {swnd}, receive window {rwnd}, ",
+                "parallel streams {streams}, ",
                 "UDP buffer size {udp}; ",
                 "packet_threshold {pkt_t}, time_threshold {tim_t}xRTT"
             ),
@@ -659,6 +804,7 @@ impl Configuration {
             iwind = iwind,
             swnd = self.send_window().human_count_bytes(),
             rwnd = self.recv_window().human_count_bytes(),
+            streams = self.parallel_streams,
             udp = self.udp_buffer.human_count_bytes(),
             pkt_t = self.packet_threshold,
             tim_t = self.time_threshold,
@@ -723,12 +869,38 @@ impl Configuration {
             val = tx.to_eng(0),
             min = MINIMUM_BANDWIDTH.to_eng(3),
         );
+        for (name, limit) in [
+            ("limit_rate_rx", self.limit_rate_rx),
+            ("limit_rate_tx", self.limit_rate_tx),
+        ] {
+            anyhow::ensure!(
+                limit == 0 || limit >= MINIMUM_BANDWIDTH,
+                "The rate limit ({INFO}{name} {val}{RESET}B) is too small; it must be at least {min}",
+                val = limit.to_eng(0),
+                min = MINIMUM_BANDWIDTH.to_eng(3),
+            );
+        }
         anyhow::ensure!(
             tx == 0 || tx.checked_mul(rtt.into()).is_some(),
             "The transmit bandwidth delay product calculation ({INFO}tx {val}{RESET}B x {INFO}rtt {rtt}{RESET}ms) overflowed",
             val = tx.to_eng(0),
         );
 
+        anyhow::ensure!(
+            self.parallel_streams > 0,
+            "The number of parallel streams ({INFO}parallel_streams{RESET}) cannot be zero"
+        );
+        anyhow::ensure!(
+            (1..=MAXIMUM_CONNECTIONS).contains(&self.connections),
+            "The number of connections ({INFO}connections {n}{RESET}) must be between 1 and {MAXIMUM_CONNECTIONS}",
+            n = self.connections,
+        );
+        anyhow::ensure!(
+            self.compression <= MAXIMUM_COMPRESSION_LEVEL,
+            "The compression level ({INFO}compression {level}{RESET}) is too high; it must be at most {MAXIMUM_COMPRESSION_LEVEL}",
+            level = self.compression,
+        );
+
         let udp = data.udp;
         anyhow::ensure!(
             udp >= MINIMUM_UDP_BUFFER,
@@ -750,6 +922,30 @@ impl Configuration {
             "Initial MTU ({mtu}) cannot be less than 1200",
             mtu = self.initial_mtu
         );
+        anyhow::ensure!(
+            (MINIMUM_UDP_PAYLOAD..=MAXIMUM_UDP_PAYLOAD).contains(&self.max_udp_payload_size),
+            "The maximum UDP payload size ({INFO}max_udp_payload_size {size}{RESET}) must be between {MINIMUM_UDP_PAYLOAD} and {MAXIMUM_UDP_PAYLOAD}",
+            size = self.max_udp_payload_size
+        );
+        Ok(())
+    }
+
+    /// Checks that the MTU settings are consistent with the maximum UDP payload size.
+    ///
+    /// This only applies to a local configuration. After negotiation, the agreed `max_udp_payload_size`
+    /// may be smaller than our `max_mtu`; Path MTU discovery takes care of that.
+    pub(crate) fn validate_mtus(&self) -> Result<()> {
+        #[allow(non_snake_case)] // look, it's a const
+        let INFO = info();
+        #[allow(non_snake_case)] // look, it's a const
+        let RESET = reset();
+        for (name, mtu) in [("max_mtu", self.max_mtu), ("initial_mtu", self.initial_mtu)] {
+            anyhow::ensure!(
+                mtu <= self.max_udp_payload_size,
+                "{INFO}{name} {mtu}{RESET} is larger than the maximum UDP payload size ({INFO}max_udp_payload_size {size}{RESET})",
+                size = self.max_udp_payload_size
+            );
+        }
         Ok(())
     }
 
@@ -803,6 +999,10 @@ mod test {
         cfg.initial_congestion_window = 1000;
         let s = cfg.format_transport_config();
         assert!(s.contains("congestion algorithm cubic with initial window 1kB"));
+        assert!(s.contains("parallel streams 1"));
+
+        cfg.parallel_streams = 4;
+        assert_eq!(cfg.stream_recv_window(), cfg.recv_window() / 4);
     }
 
     #[test]
@@ -838,6 +1038,31 @@ mod test {
             Some("overflowed"),
         );
         tc(|c| c.rtt = 0, "RTT cannot be zero", None);
+        tc(
+            |c| c.limit_rate_tx = 1,
+            "rate limit (limit_rate_tx 1B) is too small",
+            None,
+        );
+        tc(
+            |c| c.parallel_streams = 0,
+            "number of parallel streams (parallel_streams) cannot be zero",
+            None,
+        );
+        tc(
+            |c| c.connections = 0,
+            "number of connections (connections 0) must be between",
+            None,
+        );
+        tc(
+            |c| c.connections = 17,
+            "number of connections (connections 17) must be between",
+            None,
+        );
+        tc(
+            |c| c.compression = 20,
+            "compression level (compression 20) is too high",
+            None,
+        );
         tc(
             |c| c.udp_buffer = 0,
             "The UDP buffer size (0) is too small",
@@ -846,6 +1071,46 @@ mod test {
         tc(|c| c.min_mtu = 0, "Minimum MTU (0) cannot be ", None);
         tc(|c| c.max_mtu = 0, "Maximum MTU (0) cannot be ", None);
         tc(|c| c.initial_mtu = 0, "Initial MTU (0) cannot be ", None);
+        tc(
+            |c| c.max_udp_payload_size = 1000,
+            "maximum UDP payload size (max_udp_payload_size 1000) must be between",
+            None,
+        );
+        tc(
+            |c| c.max_udp_payload_size = 65535,
+            "maximum UDP payload size (max_udp_payload_size 65535) must be between",
+            None,
+        );
+    }
+
+    #[test]
+    fn validate_mtus() {
+        fn tc<T: Fn(&mut crate::Configuration)>(func: T, expected: &str) {
+            let mut cfg = SYSTEM_DEFAULT_CONFIG.clone();
+            func(&mut cfg);
+            let str = cfg.validate_mtus().unwrap_err().to_string();
+            assert_contains!(console::strip_ansi_codes(&str), expected);
+        }
+        assert!(SYSTEM_DEFAULT_CONFIG.validate_mtus().is_ok());
+
+        tc(
+            |c| c.max_mtu = 9000,
+            "max_mtu 9000 is larger than the maximum UDP payload size (max_udp_payload_size 1472)",
+        );
+        tc(
+            |c| {
+                c.max_udp_payload_size = 8972;
+                c.initial_mtu = 9000;
+            },
+            "initial_mtu 9000 is larger than the maximum UDP payload size (max_udp_payload_size 8972)",
+        );
+
+        let mut jumbo = SYSTEM_DEFAULT_CONFIG.clone();
+        jumbo.max_udp_payload_size = 8972;
+        jumbo.max_mtu = 8972;
+        jumbo.initial_mtu = 8972;
+        assert!(jumbo.try_validate().is_ok());
+        assert!(jumbo.validate_mtus().is_ok());
     }
 
     #[test]
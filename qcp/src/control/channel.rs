@@ -16,15 +16,16 @@ use tracing::{Instrument as _, debug, error, info, trace, warn};
 use crate::client::Parameters;
 use crate::config::{Configuration, Configuration_Optional, Manager};
 use crate::control::create_endpoint;
-use crate::protocol::FindTag as _;
 use crate::protocol::common::{ProtocolMessage, ReceivingStream, SendReceivePair, SendingStream};
 use crate::protocol::compat::Feature;
 use crate::protocol::control::{
     BANNER, ClientGreeting, ClientMessage, ClientMessage2Attributes, ClientMessageV2,
-    ClosedownReport, ClosedownReportV1, Compatibility, CongestionController, ConnectionType,
-    Direction, OLD_BANNER, OUR_COMPATIBILITY_LEVEL, OUR_COMPATIBILITY_NUMERIC, ServerFailure,
-    ServerGreeting, ServerMessage, ServerMessage2Attributes, ServerMessageV2,
+    ClosedownReport, ClosedownReportExtension, ClosedownReportV1, Compatibility,
+    CongestionController, ConnectionType, Direction, OLD_BANNER, OUR_COMPATIBILITY_LEVEL,
+    OUR_COMPATIBILITY_NUMERIC, ServerFailure, ServerGreeting, ServerMessage,
+    ServerMessage2Attributes, ServerMessageV2,
 };
+use crate::protocol::{DataTag as _, FindTag as _};
 use crate::transport::combine_bandwidth_configurations;
 use crate::util::{Credentials, TimeFormat, TracingSetupFn};
 
@@ -50,7 +51,11 @@ pub(crate) trait ControlChannelServerInterface<
 
     async fn run_server_inner(&mut self, manager: &mut Manager) -> anyhow::Result<ServerResult>;
 
-    async fn send_closedown_report(&mut self, stats: &ConnectionStats) -> Result<()>;
+    async fn send_closedown_report(
+        &mut self,
+        stats: &ConnectionStats,
+        migrations: u64,
+    ) -> Result<()>;
 
     fn compat(&self) -> Compatibility;
 }
@@ -79,8 +84,8 @@ pub(crate) fn stdio_channel() -> ControlChannel<Stdout, Stdin> {
 pub(crate) struct ServerResult {
     /// Final negotiated configuration
     pub(crate) config: Configuration,
-    /// The Quinn endpoint created during the control channel phase
-    pub(crate) endpoint: Endpoint,
+    /// The Quinn endpoints created during the control channel phase, one per connection
+    pub(crate) endpoints: Vec<Endpoint>,
 }
 
 impl<S: SendingStream, R: ReceivingStream> ControlChannel<S, R> {
@@ -178,6 +183,18 @@ impl<S: SendingStream, R: ReceivingStream> ControlChannel<S, R> {
                 "Remote host does not support NewReno"
             );
         }
+        if congestion == CongestionController::FixedRate {
+            anyhow::ensure!(
+                self.selected_compat.supports(Feature::FIXED_RATE),
+                "Remote host does not support FixedRate"
+            );
+        }
+        if congestion == CongestionController::Ledbat {
+            anyhow::ensure!(
+                self.selected_compat.supports(Feature::LEDBAT),
+                "Remote host does not support Ledbat"
+            );
+        }
 
         let tagged_creds =
             credentials.to_tagged_data(self.selected_compat, config.tls_auth_type)?;
@@ -358,7 +375,7 @@ impl<S: SendingStream, R: ReceivingStream> ControlChannel<S, R> {
 
     async fn server_send_message(
         &mut self,
-        port: u16,
+        ports: &[u16],
         credentials: &Credentials,
         config: &Configuration,
         warning: String,
@@ -369,7 +386,7 @@ impl<S: SendingStream, R: ReceivingStream> ControlChannel<S, R> {
         let message = ServerMessage::new(
             self.selected_compat,
             config,
-            port,
+            ports,
             tagged_creds,
             credentials.hostname.clone(),
             warning,
@@ -473,44 +490,60 @@ impl<S: SendingStream + 'static, R: ReceivingStream + 'static> ControlChannelSer
         );
         trace!("Direction of travel: {direction}");
 
-        let (endpoint, warning) = match create_endpoint(
-            &credentials,
-            &message2.credentials,
-            message2.connection_type,
-            &config,
-            direction.server_mode(),
-            true,
-            self.selected_compat,
-        ) {
-            Ok(t) => t,
-            Err(e) => {
-                self.send_error(ServerFailure::EndpointFailed(format!("{e}")))
-                    .await?;
-                anyhow::bail!("failed to create server endpoint: {e}");
-            }
+        // Each connection has its own endpoint, so its own UDP port
+        let connections = if self.selected_compat.supports(Feature::MULTIPLE_CONNECTIONS) {
+            config.connections.max(1)
+        } else {
+            1
         };
-        let local_addr = endpoint.local_addr()?;
-        debug!("Local endpoint address is {local_addr}");
+        let mut endpoints = Vec::with_capacity(connections.into());
+        let mut ports = Vec::with_capacity(connections.into());
+        let mut warning = None;
+        for _ in 0..connections {
+            let (endpoint, warn) = match create_endpoint(
+                &credentials,
+                &message2.credentials,
+                message2.connection_type,
+                &config,
+                direction.server_mode(),
+                true,
+                self.selected_compat,
+            ) {
+                Ok(t) => t,
+                Err(e) => {
+                    self.send_error(ServerFailure::EndpointFailed(format!("{e}")))
+                        .await?;
+                    anyhow::bail!("failed to create server endpoint: {e}");
+                }
+            };
+            let local_addr = endpoint.local_addr()?;
+            debug!("Local endpoint address is {local_addr}");
+            ports.push(local_addr.port());
+            endpoints.push(endpoint);
+            warning = warning.or(warn);
+        }
 
         // PHASE 3D: Send server message
-        self.server_send_message(
-            local_addr.port(),
-            &credentials,
-            &config,
-            warning.unwrap_or_default(),
-        )
-        .await?;
+        self.server_send_message(&ports, &credentials, &config, warning.unwrap_or_default())
+            .await?;
 
-        Ok(ServerResult { config, endpoint })
+        Ok(ServerResult { config, endpoints })
     }
 
-    async fn send_closedown_report(&mut self, stats: &ConnectionStats) -> Result<()> {
+    async fn send_closedown_report(
+        &mut self,
+        stats: &ConnectionStats,
+        migrations: u64,
+    ) -> Result<()> {
         // FUTURE: When later versions of ClosedownReport are created, check client compatibility and send the appropriate version.
-        self.send(
-            ClosedownReport::V1(ClosedownReportV1::from(stats)),
-            "closedown report",
-        )
-        .await?;
+        let mut report = ClosedownReportV1::from(stats);
+        if migrations > 0 {
+            report
+                .extension
+                .push(ClosedownReportExtension::Migrations.with_unsigned(migrations));
+        }
+        self.send(ClosedownReport::V1(report), "closedown report")
+            .await?;
         Ok(())
     }
 
@@ -527,13 +560,14 @@ mod test {
         config::{Configuration_Optional, Manager},
         control::{ControlChannel, ControlChannelServerInterface as _},
         protocol::{
+            DataTag as _,
             common::{
                 MessageHeader, ProtocolMessage as _, ReceivingStream, SendReceivePair,
                 SendingStream,
             },
             control::{
-                ClosedownReportV1, Compatibility, CongestionController, ConnectionType, OLD_BANNER,
-                ServerMessageV2,
+                ClosedownReportExtension, ClosedownReportV1, Compatibility, CongestionController,
+                ConnectionType, OLD_BANNER, ServerMessageV2,
             },
             test_helpers::new_test_plumbing,
         },
@@ -615,8 +649,11 @@ mod test {
         assert!(ser_res.is_ok());
 
         let stats = ConnectionStats::default();
-        let expected = ClosedownReportV1::from(&stats);
-        let _ = server.send_closedown_report(&stats).await;
+        let mut expected = ClosedownReportV1::from(&stats);
+        expected
+            .extension
+            .push(ClosedownReportExtension::Migrations.with_unsigned(2u64));
+        let _ = server.send_closedown_report(&stats, 2).await;
         let got = cli.client.read_closedown_report().await.unwrap();
         assert_eq!(expected, got);
     }
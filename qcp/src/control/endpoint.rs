@@ -89,11 +89,11 @@ pub fn create_endpoint(
         .inspect(|s| warn!("{s:?}"));
 
     trace!("create endpoint");
-    // SOMEDAY: allow user to specify max_udp_payload_size in endpoint config, to support jumbo frames
+    let mut endpoint_cfg = EndpointConfig::default();
+    let _ = endpoint_cfg.max_udp_payload_size(config.max_udp_payload_size)?;
     let runtime =
         quinn::default_runtime().ok_or_else(|| anyhow::anyhow!("no async runtime found"))?;
-    let mut endpoint =
-        quinn::Endpoint::new(EndpointConfig::default(), server_cfg, socket, runtime)?;
+    let mut endpoint = quinn::Endpoint::new(endpoint_cfg, server_cfg, socket, runtime)?;
     if let Some(c) = client_cfg {
         endpoint.set_default_client_config(c);
     }
@@ -112,7 +112,7 @@ pub use cli::cli as main;
 pub use cli::styles;
 pub(crate) mod client;
 pub(crate) use client::client_main;
-pub use client::{CopyJobSpec, FileSpec, Parameters};
+pub use client::{CopyJobSpec, FileSpec, LinkMode, Parameters, PreserveSelection};
 
 pub mod config;
 pub use config::structure::Configuration;
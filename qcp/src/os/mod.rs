@@ -6,7 +6,7 @@
 
 use std::{
     net::{Ipv4Addr, SocketAddrV4, UdpSocket},
-    path::PathBuf,
+    path::{Path, PathBuf},
     sync::Once,
 };
 
@@ -226,6 +226,49 @@ pub trait AbstractPlatform {
     fn override_path_is_local(_path: &str) -> bool {
         false
     }
+
+    /// Reports on the capacity of the filesystem holding `path`, which must exist.
+    ///
+    /// Returns `Ok(None)` if this information is not available on this platform.
+    fn disk_space(path: &Path) -> std::io::Result<Option<DiskSpace>>;
+
+    /// Reads the names and values of the extended attributes of the file at `path`,
+    /// following symbolic links.
+    ///
+    /// Attributes whose names are not valid UTF-8 are omitted.
+    /// Returns an empty list if extended attributes are not supported on this platform.
+    fn extended_attributes(path: &Path) -> std::io::Result<Vec<(String, Vec<u8>)>>;
+
+    /// Sets an extended attribute on an open file.
+    fn set_extended_attribute(
+        file: &std::fs::File,
+        name: &str,
+        value: &[u8],
+    ) -> std::io::Result<()>;
+
+    /// Finds the regions of the file at `path` which hold data, in ascending order.
+    /// Anything outside these regions is a hole.
+    ///
+    /// Returns `Ok(None)` if this information is not available on this platform.
+    /// Filesystems which do not support holes report the whole file as data.
+    fn data_extents(path: &Path) -> std::io::Result<Option<Vec<std::ops::Range<u64>>>>;
+
+    /// Deallocates a region of an open file, so that it reads as zeros, without changing the file's length.
+    ///
+    /// Returns an error of kind `Unsupported` if this is not possible on this platform.
+    fn punch_hole(file: &std::fs::File, range: std::ops::Range<u64>) -> std::io::Result<()>;
+
+    /// Sets the access and/or modification times of the directory at `path`.
+    fn set_directory_times(path: &Path, times: std::fs::FileTimes) -> std::io::Result<()>;
+}
+
+/// The capacity of a filesystem
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DiskSpace {
+    /// Total size of the filesystem, in bytes
+    pub total: u64,
+    /// Space available to the current user, in bytes
+    pub available: u64,
 }
 
 #[cfg(test)]
@@ -32,7 +32,7 @@ use crate::config::BASE_CONFIG_FILENAME;
 use human_repr::HumanCount as _;
 use rustix::process::{Uid, geteuid};
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 /// Unix platform implementation (Linux, OSX, BSD and others)
 #[allow(missing_copy_implementations, missing_debug_implementations)]
@@ -74,6 +74,136 @@ impl super::AbstractPlatform for UnixPlatform {
     fn help_buffers_mode(udp: u64) -> String {
         help_buffers_unix(udp)
     }
+
+    fn disk_space(path: &Path) -> std::io::Result<Option<super::DiskSpace>> {
+        let st = rustix::fs::statvfs(path)?;
+        Ok(Some(super::DiskSpace {
+            total: st.f_blocks.saturating_mul(st.f_frsize),
+            available: st.f_bavail.saturating_mul(st.f_frsize),
+        }))
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android", target_vendor = "apple"))]
+    fn extended_attributes(path: &Path) -> std::io::Result<Vec<(String, Vec<u8>)>> {
+        use rustix::fs::{getxattr, listxattr};
+
+        let names = read_xattr_buffer(|buf| listxattr(path, buf))?;
+        let mut result = Vec::new();
+        for name in names.split(|b| *b == 0).filter(|n| !n.is_empty()) {
+            let Ok(name) = std::str::from_utf8(name) else {
+                continue;
+            };
+            let value = read_xattr_buffer(|buf| getxattr(path, name, buf))?;
+            result.push((name.to_string(), value));
+        }
+        Ok(result)
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "android", target_vendor = "apple")))]
+    fn extended_attributes(_path: &Path) -> std::io::Result<Vec<(String, Vec<u8>)>> {
+        Ok(Vec::new())
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android", target_vendor = "apple"))]
+    fn set_extended_attribute(
+        file: &std::fs::File,
+        name: &str,
+        value: &[u8],
+    ) -> std::io::Result<()> {
+        rustix::fs::fsetxattr(file, name, value, rustix::fs::XattrFlags::empty())?;
+        Ok(())
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "android", target_vendor = "apple")))]
+    fn set_extended_attribute(
+        _file: &std::fs::File,
+        _name: &str,
+        _value: &[u8],
+    ) -> std::io::Result<()> {
+        Err(std::io::ErrorKind::Unsupported.into())
+    }
+
+    #[cfg(any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "freebsd",
+        target_vendor = "apple"
+    ))]
+    fn data_extents(path: &Path) -> std::io::Result<Option<Vec<std::ops::Range<u64>>>> {
+        use rustix::fs::{SeekFrom, seek};
+
+        let file = std::fs::File::open(path)?;
+        let len = file.metadata()?.len();
+        let mut extents = Vec::new();
+        let mut pos = 0;
+        while pos < len {
+            let start = match seek(&file, SeekFrom::Data(pos)) {
+                Ok(start) => start,
+                // There is no more data, only a hole at the end of the file
+                Err(rustix::io::Errno::NXIO) => break,
+                Err(e) => return Err(e.into()),
+            };
+            let end = seek(&file, SeekFrom::Hole(start))?.min(len);
+            if start >= end {
+                break;
+            }
+            extents.push(start..end);
+            pos = end;
+        }
+        Ok(Some(extents))
+    }
+
+    #[cfg(not(any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "freebsd",
+        target_vendor = "apple"
+    )))]
+    fn data_extents(_path: &Path) -> std::io::Result<Option<Vec<std::ops::Range<u64>>>> {
+        Ok(None)
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    fn punch_hole(file: &std::fs::File, range: std::ops::Range<u64>) -> std::io::Result<()> {
+        use rustix::fs::{FallocateFlags, fallocate};
+        fallocate(
+            file,
+            FallocateFlags::PUNCH_HOLE | FallocateFlags::KEEP_SIZE,
+            range.start,
+            range.end - range.start,
+        )?;
+        Ok(())
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    fn punch_hole(_file: &std::fs::File, _range: std::ops::Range<u64>) -> std::io::Result<()> {
+        Err(std::io::ErrorKind::Unsupported.into())
+    }
+
+    fn set_directory_times(path: &Path, times: std::fs::FileTimes) -> std::io::Result<()> {
+        // A directory may be opened read-only, which is enough to set its times
+        std::fs::File::open(path)?.set_times(times)
+    }
+}
+
+/// Calls an xattr syscall which fills a buffer, first asking it how big the buffer needs to be.
+#[cfg(any(target_os = "linux", target_os = "android", target_vendor = "apple"))]
+fn read_xattr_buffer(
+    f: impl Fn(&mut [u8]) -> rustix::io::Result<usize>,
+) -> std::io::Result<Vec<u8>> {
+    loop {
+        let size = f(&mut [])?;
+        let mut buf = vec![0u8; size];
+        match f(&mut buf) {
+            Ok(len) => {
+                buf.truncate(len);
+                return Ok(buf);
+            }
+            // It grew in the meantime; try again
+            Err(rustix::io::Errno::RANGE) => (),
+            Err(e) => return Err(e.into()),
+        }
+    }
 }
 
 #[cfg_attr(coverage_nightly, coverage(off))]
@@ -221,4 +351,80 @@ mod test {
         assert!(pv[1].to_string_lossy().contains(HOME_COMMON));
         assert!(pv[1].to_string_lossy().contains("/.qcp.conf"));
     }
+
+    #[test]
+    fn disk_space() {
+        let space = Platform::disk_space(std::path::Path::new("/"))
+            .unwrap()
+            .unwrap();
+        assert!(space.total > 0);
+        assert!(space.available <= space.total);
+        let _ = Platform::disk_space(std::path::Path::new("/nonexistent/xyzy")).unwrap_err();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn data_extents() {
+        use std::io::{Seek as _, SeekFrom, Write as _};
+        littertray::LitterTray::try_with(|_| {
+            const MB: u64 = 1 << 20;
+            let mut file = std::fs::File::create("f")?;
+            file.set_len(4 * MB)?;
+            let _ = file.seek(SeekFrom::Start(MB))?;
+            file.write_all(&[1u8; 4096])?;
+            file.sync_all()?;
+            let extents = Platform::data_extents(std::path::Path::new("f"))?.unwrap();
+            // The filesystem might not support holes, in which case it's all data
+            let dense = extents.len() == 1 && extents[0] == (0..4 * MB);
+            if !dense {
+                assert_eq!(extents.len(), 1);
+                assert!(extents[0].contains(&MB));
+                assert!(extents[0].end < 4 * MB);
+            }
+            drop(file);
+            let _ = std::fs::File::create("empty")?;
+            assert!(
+                Platform::data_extents(std::path::Path::new("empty"))?
+                    .unwrap()
+                    .is_empty()
+            );
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn extended_attributes() {
+        use std::fs::File;
+        littertray::LitterTray::try_with(|tray| {
+            let _ = tray.create_text("f", "hello")?;
+            let file = File::options().write(true).open("f")?;
+            if let Err(e) = Platform::set_extended_attribute(&file, "user.qcp.test", b"value") {
+                // Not every filesystem supports user attributes (tmpfs on older kernels, for example)
+                eprintln!("skipping test: {e}");
+                return Ok(());
+            }
+            let attrs = Platform::extended_attributes(std::path::Path::new("f"))?;
+            assert!(attrs.contains(&("user.qcp.test".to_string(), b"value".to_vec())));
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn set_directory_times() {
+        use std::time::{Duration, SystemTime};
+        littertray::LitterTray::try_with(|tray| {
+            let _ = tray.make_dir("d")?;
+            let when = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+            let times = std::fs::FileTimes::new()
+                .set_accessed(when)
+                .set_modified(when);
+            Platform::set_directory_times(std::path::Path::new("d"), times)?;
+            assert_eq!(std::fs::metadata("d")?.modified()?, when);
+            Ok(())
+        })
+        .unwrap();
+    }
 }
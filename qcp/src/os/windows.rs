@@ -45,7 +45,7 @@ use crate::cli::styles::{info, reset, success, warning};
 use crate::config::BASE_CONFIG_FILENAME;
 
 use human_repr::HumanCount as _;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 /// Windows platform implementation
 #[allow(missing_copy_implementations, missing_debug_implementations)]
@@ -118,6 +118,54 @@ impl super::AbstractPlatform for WindowsPlatform {
             && (b[2] == b'/' || b[2] == b'\\')
         // then we assume it's local.
     }
+
+    /// Not currently implemented on Windows.
+    fn disk_space(_path: &Path) -> std::io::Result<Option<super::DiskSpace>> {
+        Ok(None)
+    }
+
+    /// Extended attributes are not currently supported on Windows.
+    fn extended_attributes(_path: &Path) -> std::io::Result<Vec<(String, Vec<u8>)>> {
+        Ok(Vec::new())
+    }
+
+    /// Extended attributes are not currently supported on Windows.
+    fn set_extended_attribute(
+        _file: &std::fs::File,
+        _name: &str,
+        _value: &[u8],
+    ) -> std::io::Result<()> {
+        Err(std::io::ErrorKind::Unsupported.into())
+    }
+
+    /// Not currently implemented on Windows.
+    fn data_extents(_path: &Path) -> std::io::Result<Option<Vec<std::ops::Range<u64>>>> {
+        Ok(None)
+    }
+
+    /// Not currently implemented on Windows.
+    fn punch_hole(_file: &std::fs::File, _range: std::ops::Range<u64>) -> std::io::Result<()> {
+        Err(std::io::ErrorKind::Unsupported.into())
+    }
+
+    fn set_directory_times(path: &Path, times: std::fs::FileTimes) -> std::io::Result<()> {
+        cfg_if::cfg_if! {
+            if #[cfg(windows)] {
+                use std::os::windows::fs::OpenOptionsExt as _;
+                // Opening a directory requires FILE_FLAG_BACKUP_SEMANTICS;
+                // setting its times requires FILE_WRITE_ATTRIBUTES.
+                std::fs::OpenOptions::new()
+                    .access_mode(0x0000_0100)
+                    .custom_flags(0x0200_0000)
+                    .open(path)?
+                    .set_times(times)
+            } else {
+                // This layer is only built on other platforms for test coverage
+                let _ = (path, times);
+                Err(std::io::ErrorKind::Unsupported.into())
+            }
+        }
+    }
 }
 
 #[cfg_attr(coverage_nightly, coverage(off))]
@@ -61,6 +61,28 @@ def_enum!(
         GET2_PUT2 => Compatibility::Level(2) => "Get2 and Put2 commands with extensible options.\n`FileHeaderV2` and `FileTrailerV2` structures with extensible metadata.",
         CMSG_SMSG_2 => Compatibility::Level(3) => "Version 2 of `ClientMessage` and `ServerMessage` with extensible attributes.\n`CredentialsType` enum.",
         MKDIR_SETMETA_LS => Compatibility::Level(4) => "CreateDirectory, SetMetadata, ListFiles commands",
+        PARALLEL_STREAMS => Compatibility::Level(5) => "Negotiation of the number of concurrent streams (`parallel_streams`)",
+        RANGED_TRANSFER => Compatibility::Level(5) => "Get2 and Put2 may transfer a byte range of a file, so a single file can be striped across several streams",
+        RESUME => Compatibility::Level(5) => "Resumption of interrupted transfers (`--resume`)",
+        CHECKSUM => Compatibility::Level(5) => "End-to-end verification of file content by SHA-256 digest",
+        ATOMIC_WRITE => Compatibility::Level(5) => "Received files are written to a temporary file, then renamed into place (unless `--in-place` or `--resume`)",
+        COMPRESSION => Compatibility::Level(5) => "Optional zstd compression of file data (`compression`)",
+        RATE_LIMIT => Compatibility::Level(5) => "Negotiation of hard limits on the rate of file data (`limit_rate_rx`, `limit_rate_tx`)",
+        PROBE => Compatibility::Level(5) => "The Probe command, which measures the capacity of the link (`--autotune`)",
+        MAX_UDP_PAYLOAD => Compatibility::Level(5) => "Negotiation of the maximum UDP payload size (`max_udp_payload_size`), for jumbo frames",
+        MULTIPLE_CONNECTIONS => Compatibility::Level(5) => "Several QUIC connections between the same pair of endpoints, each on its own UDP port (`connections`)",
+        FIXED_RATE => Compatibility::Level(5) => "Support for the `FixedRate` congestion controller, for dedicated circuits",
+        LEDBAT => Compatibility::Level(5) => "Support for the `Ledbat` low-priority congestion controller",
+        STAT => Compatibility::Level(5) => "The Stat command, which reports on a single remote path (`--stat`)",
+        FREE_SPACE => Compatibility::Level(5) => "The FreeSpace command, which reports the capacity of the remote filesystem",
+        SYMLINKS => Compatibility::Level(5) => "Symbolic links may be listed and recreated as links (`--links`)",
+        OWNERSHIP => Compatibility::Level(5) => "Preservation of file ownership, by name or by number (`--numeric-ids`)",
+        EXTENDED_ATTRIBUTES => Compatibility::Level(5) => "Preservation of extended attributes and POSIX ACLs (`--xattrs`, `--acls`)",
+        SPARSE_FILES => Compatibility::Level(5) => "Sparse files are sent as their data extents, and recreated with holes",
+        HARD_LINKS => Compatibility::Level(5) => "Hard links may be listed and recreated as links (`--hard-links`)",
+        DIRECTORY_TIMES => Compatibility::Level(5) => "Directory access and modification times may be listed and set (`--preserve`)",
+        NANOSECOND_TIMES => Compatibility::Level(5) => "Access and modification times are preserved to the nanosecond",
+        PRESERVE_SELECTION => Compatibility::Level(5) => "File modes, times and ownership may be preserved separately (`--preserve=LIST`)",
     }
     // Note: When adding a new compatibility level, don't forget to update OUR_COMPATIBILITY_LEVEL.
 );
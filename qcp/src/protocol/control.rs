@@ -58,7 +58,7 @@ pub const BANNER: &str = "qcp-server-2\n";
 pub const OLD_BANNER: &str = "qcp-server-1\n";
 
 /// The protocol compatibility version implemented by this crate
-pub(crate) const OUR_COMPATIBILITY_NUMERIC: u16 = 4;
+pub(crate) const OUR_COMPATIBILITY_NUMERIC: u16 = 5;
 /// The protocol compatibility version implemented by this crate
 pub const OUR_COMPATIBILITY_LEVEL: Compatibility = Compatibility::Level(OUR_COMPATIBILITY_NUMERIC);
 
@@ -255,6 +255,25 @@ pub enum CongestionController {
     ///
     /// This option requires qcp protocol compatibility level V2.
     NewReno,
+    /// (Use with caution!) Sends at a constant rate, set by the negotiated bandwidth
+    /// (`tx` for data we send), and does not back off when packets are lost.
+    /// With several `connections`, the rate is shared equally between them.
+    ///
+    /// This cannot be combined with `--autotune`, which needs to probe the link.
+    ///
+    /// This is only suitable for dedicated circuits with guaranteed capacity.
+    /// On a shared network it will cause heavy loss, for you and for everybody else.
+    ///
+    /// This option requires qcp protocol compatibility level V5.
+    FixedRate,
+    /// A low-priority ("scavenger") algorithm after LEDBAT (RFC 6817).
+    /// It watches the queueing delay on the link and backs off as soon as it rises,
+    /// so that it yields to other traffic.
+    ///
+    /// If either side asks for this algorithm, it is used.
+    ///
+    /// This option requires qcp protocol compatibility level V5.
+    Ledbat,
 }
 
 impl SerializeEnumAsString for CongestionController {}
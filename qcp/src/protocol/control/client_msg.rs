@@ -207,6 +207,26 @@ impl ClientMessageV2 {
             self.attributes
                 .push(ClientMessage2Attributes::QuicTimeout.with_unsigned(t));
         }
+        if let Some(n) = our_config.parallel_streams {
+            self.attributes
+                .push(ClientMessage2Attributes::ParallelStreams.with_unsigned(n));
+        }
+        if let Some(limit) = our_config.limit_rate_tx {
+            self.attributes
+                .push(ClientMessage2Attributes::RateLimitToServer.with_unsigned(limit));
+        }
+        if let Some(limit) = our_config.limit_rate_rx {
+            self.attributes
+                .push(ClientMessage2Attributes::RateLimitToClient.with_unsigned(limit));
+        }
+        if let Some(size) = our_config.max_udp_payload_size {
+            self.attributes
+                .push(ClientMessage2Attributes::MaxUdpPayloadSize.with_unsigned(size));
+        }
+        if let Some(n) = our_config.connections {
+            self.attributes
+                .push(ClientMessage2Attributes::Connections.with_unsigned(n));
+        }
         // DirectionOfTravel is set up by set_direction()
     }
 }
@@ -352,6 +372,31 @@ pub enum ClientMessage2Attributes {
     /// Connection timeout for the QUIC endpoints, in seconds.
     /// Data is [`crate::protocol::Variant::Unsigned`].
     QuicTimeout,
+    /// The maximum number of streams the client would like to have open at once.
+    /// Data is [`crate::protocol::Variant::Unsigned`].
+    ///
+    /// This attribute was introduced with `VersionCompatibility` level 5.
+    ParallelStreams,
+    /// The hard limit on the rate of file data from client to server, in bytes per second (0 means no limit).
+    /// Data is [`crate::protocol::Variant::Unsigned`].
+    ///
+    /// This attribute was introduced with `VersionCompatibility` level 5.
+    RateLimitToServer,
+    /// The hard limit on the rate of file data from server to client, in bytes per second (0 means no limit).
+    /// Data is [`crate::protocol::Variant::Unsigned`].
+    ///
+    /// This attribute was introduced with `VersionCompatibility` level 5.
+    RateLimitToClient,
+    /// The largest UDP payload the client is prepared to accept, in bytes.
+    /// Data is [`crate::protocol::Variant::Unsigned`].
+    ///
+    /// This attribute was introduced with `VersionCompatibility` level 5.
+    MaxUdpPayloadSize,
+    /// The number of QUIC connections the client would like to open.
+    /// Data is [`crate::protocol::Variant::Unsigned`].
+    ///
+    /// This attribute was introduced with `VersionCompatibility` level 5.
+    Connections,
 }
 impl DataTag for ClientMessage2Attributes {
     fn debug_data(&self, data: &Variant) -> String {
@@ -527,12 +572,18 @@ mod test {
             }),
             remote_user: None,
             timeout: Some(432),
+            limit_rate_rx: None,
+            limit_rate_tx: None,
+            parallel_streams: None,
+            connections: None,
+            compression: None,
             // other client options are irrelevant to this test but we'll specify them anyway so we can rely on the compiler to catch any missing fields
             packet_threshold: None,
             time_threshold: None,
             initial_mtu: None,
             min_mtu: None,
             max_mtu: None,
+            max_udp_payload_size: None,
 
             address_family: None,
             ssh: None,
@@ -541,6 +592,7 @@ mod test {
             time_format: None,
             ssh_config: None,
             ssh_subsystem: None,
+            agent_timeout: None,
             color: None,
             tls_auth_type: None,
             aes256: None,
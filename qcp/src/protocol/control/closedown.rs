@@ -94,6 +94,11 @@ pub enum ClosedownReportExtension {
     Pmtu = 1,
     /// The Round Trip Time for the connection, as measured by the server, in microseconds
     Rtt = 2,
+    /// The number of times the server saw the client's address change during the connection
+    /// (connection migration). Not sent if zero.
+    ///
+    /// This attribute was introduced with `VersionCompatibility` level 5.
+    Migrations = 3,
 }
 impl DataTag for ClosedownReportExtension {}
 
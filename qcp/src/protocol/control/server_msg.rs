@@ -42,20 +42,25 @@ pub enum ServerMessage {
 impl ProtocolMessage for ServerMessage {}
 
 impl ServerMessage {
+    /// Creates a server message.
+    ///
+    /// `ports` lists the UDP ports the server has bound to, one per connection.
+    /// The first is the primary port; the others are sent as [`ServerMessage2Attributes::AdditionalPort`] attributes.
     pub(crate) fn new(
         compat: Compatibility,
         config: &Configuration,
-        port: u16,
+        ports: &[u16],
         credentials: TaggedData<CredentialsType>,
         common_name: String,
         warning: String,
     ) -> Self {
         assert!(credentials.data.is_bytes());
+        let (port, additional_ports) = ports.split_first().expect("at least one port is required");
         let bandwidth_to_server = Uint(config.rx());
         let bandwidth_to_client = Uint(config.tx());
         if compat.supports(Feature::CMSG_SMSG_2) {
             let mut msg = ServerMessageV2 {
-                port,
+                port: *port,
                 credentials,
                 common_name,
                 bandwidth_to_server,
@@ -63,12 +68,19 @@ impl ServerMessage {
                 rtt: config.rtt,
                 ..Default::default()
             };
-            msg.apply_config_attributes(config);
+            msg.apply_config_attributes(config, compat);
+            if compat.supports(Feature::MULTIPLE_CONNECTIONS) {
+                msg.attributes.extend(
+                    additional_ports
+                        .iter()
+                        .map(|p| ServerMessage2Attributes::AdditionalPort.with_unsigned(*p)),
+                );
+            }
             msg.into()
         } else {
             let cert_bytes = credentials.data.into_bytes().unwrap_or_default();
             ServerMessageV1 {
-                port,
+                port: *port,
                 cert: cert_bytes,
                 name: common_name,
                 bandwidth_to_server,
@@ -279,12 +291,48 @@ pub enum ServerMessage2Attributes {
     /// Connection timeout for the QUIC endpoints, in seconds.
     /// Data is [`crate::protocol::Variant::Unsigned`].
     QuicTimeout,
+
+    /// The maximum number of streams the client may have open at once.
+    /// Data is [`crate::protocol::Variant::Unsigned`].
+    ///
+    /// This attribute was introduced with `VersionCompatibility` level 5.
+    ParallelStreams,
+    /// The hard limit on the rate of file data from client to server, in bytes per second (0 means no limit).
+    /// Data is [`crate::protocol::Variant::Unsigned`].
+    ///
+    /// This attribute was introduced with `VersionCompatibility` level 5.
+    RateLimitToServer,
+    /// The hard limit on the rate of file data from server to client, in bytes per second (0 means no limit).
+    /// Data is [`crate::protocol::Variant::Unsigned`].
+    ///
+    /// This attribute was introduced with `VersionCompatibility` level 5.
+    RateLimitToClient,
+    /// The largest UDP payload either endpoint may send, in bytes.
+    /// Data is [`crate::protocol::Variant::Unsigned`].
+    ///
+    /// This attribute was introduced with `VersionCompatibility` level 5.
+    MaxUdpPayloadSize,
+    /// The number of QUIC connections the client should open.
+    /// Data is [`crate::protocol::Variant::Unsigned`].
+    ///
+    /// This attribute was introduced with `VersionCompatibility` level 5.
+    Connections,
+    /// A further UDP port the server has bound to, for an additional connection.
+    /// This attribute appears once for each connection after the first, in order.
+    /// Data is [`crate::protocol::Variant::Unsigned`].
+    ///
+    /// This attribute was introduced with `VersionCompatibility` level 5.
+    AdditionalPort,
 }
 
 impl DataTag for ServerMessage2Attributes {}
 
 impl ServerMessageV2 {
-    pub(crate) fn apply_config_attributes(&mut self, config: &Configuration) {
+    pub(crate) fn apply_config_attributes(
+        &mut self,
+        config: &Configuration,
+        compat: Compatibility,
+    ) {
         if config.congestion != CongestionController::default() {
             self.attributes.push(
                 ServerMessage2Attributes::CongestionController
@@ -300,8 +348,47 @@ impl ServerMessageV2 {
             self.attributes
                 .push(ServerMessage2Attributes::QuicTimeout.with_unsigned(config.timeout));
         }
+        if compat.supports(Feature::PARALLEL_STREAMS) {
+            // Always sent, so the client knows the outcome of negotiation
+            self.attributes.push(
+                ServerMessage2Attributes::ParallelStreams.with_unsigned(config.parallel_streams),
+            );
+        }
+        if compat.supports(Feature::RATE_LIMIT) {
+            // Always sent, so the client knows the outcome of negotiation.
+            // This is written from the server's point of view, i.e. our rx is the rate to the server.
+            self.attributes.push(
+                ServerMessage2Attributes::RateLimitToServer.with_unsigned(config.limit_rate_rx),
+            );
+            self.attributes.push(
+                ServerMessage2Attributes::RateLimitToClient.with_unsigned(config.limit_rate_tx),
+            );
+        }
+        if compat.supports(Feature::MAX_UDP_PAYLOAD) {
+            // Always sent, so the client knows the outcome of negotiation
+            self.attributes.push(
+                ServerMessage2Attributes::MaxUdpPayloadSize
+                    .with_unsigned(config.max_udp_payload_size),
+            );
+        }
+        if compat.supports(Feature::MULTIPLE_CONNECTIONS) {
+            // Always sent, so the client knows the outcome of negotiation
+            self.attributes
+                .push(ServerMessage2Attributes::Connections.with_unsigned(config.connections));
+        }
         // WarningMessage is set up when the message is created.
     }
+
+    /// Returns the UDP ports the server has bound to, one per connection, starting with the primary port
+    pub(crate) fn ports(&self) -> Vec<u16> {
+        std::iter::once(self.port)
+            .chain(self.attributes.iter().filter_map(|attr| {
+                (attr.tag() == Some(ServerMessage2Attributes::AdditionalPort))
+                    .then(|| u16::try_from(attr.data.coerce_unsigned()).ok())
+                    .flatten()
+            }))
+            .collect()
+    }
 }
 
 impl Provider for ServerMessageV2 {
@@ -334,8 +421,24 @@ impl Provider for ServerMessageV2 {
                     ServerMessage2Attributes::QuicTimeout => {
                         insert("timeout", data.coerce_unsigned().into());
                     }
+                    ServerMessage2Attributes::ParallelStreams => {
+                        insert("parallel_streams", data.coerce_unsigned().into());
+                    }
+                    ServerMessage2Attributes::RateLimitToServer => {
+                        insert("limit_rate_tx", data.coerce_unsigned().into());
+                    }
+                    ServerMessage2Attributes::RateLimitToClient => {
+                        insert("limit_rate_rx", data.coerce_unsigned().into());
+                    }
+                    ServerMessage2Attributes::MaxUdpPayloadSize => {
+                        insert("max_udp_payload_size", data.coerce_unsigned().into());
+                    }
+                    ServerMessage2Attributes::Connections => {
+                        insert("connections", data.coerce_unsigned().into());
+                    }
                     // attributes not forming part of the configuration:
                     ServerMessage2Attributes::WarningMessage
+                    | ServerMessage2Attributes::AdditionalPort
                     | ServerMessage2Attributes::Invalid => {}
                 }
             } else {
@@ -511,12 +614,22 @@ mod test {
             congestion: Some(CongestionController::Bbr),
             initial_congestion_window: Some(42),
             timeout: Some(88),
+            parallel_streams: Some(6),
+            max_udp_payload_size: Some(8972),
+            connections: Some(3),
             ..Default::default()
         };
         mgr.merge_provider(&cfg);
         let final_cfg = mgr.get::<Configuration>().unwrap();
         let mut msg = ServerMessageV2::default();
-        msg.apply_config_attributes(&final_cfg);
+        msg.apply_config_attributes(&final_cfg, Compatibility::Level(4));
+        assert!(
+            msg.attributes
+                .find_tag(ServerMessage2Attributes::ParallelStreams)
+                .is_none()
+        );
+        let mut msg = ServerMessageV2::default();
+        msg.apply_config_attributes(&final_cfg, Compatibility::Level(5));
 
         let attrs = &msg.attributes;
 
@@ -534,6 +647,52 @@ mod test {
             .find_tag(ServerMessage2Attributes::QuicTimeout)
             .unwrap();
         assert_eq!(tag.coerce_unsigned(), 88);
+
+        let tag = attrs
+            .find_tag(ServerMessage2Attributes::ParallelStreams)
+            .unwrap();
+        assert_eq!(tag.coerce_unsigned(), 6);
+
+        let tag = attrs
+            .find_tag(ServerMessage2Attributes::MaxUdpPayloadSize)
+            .unwrap();
+        assert_eq!(tag.coerce_unsigned(), 8972);
+
+        let tag = attrs
+            .find_tag(ServerMessage2Attributes::Connections)
+            .unwrap();
+        assert_eq!(tag.coerce_unsigned(), 3);
+    }
+
+    #[test]
+    fn server_message_ports() {
+        let cfg = Configuration::system_default();
+        let ports = [1000, 1001, 1003];
+        let ServerMessage::V2(msg) = ServerMessage::new(
+            Compatibility::Level(5),
+            cfg,
+            &ports,
+            dummy_credentials(),
+            "test".into(),
+            String::new(),
+        ) else {
+            panic!("expected V2 message");
+        };
+        assert_eq!(msg.port, 1000);
+        assert_eq!(msg.ports(), ports);
+
+        // Servers which do not support multiple connections only report the primary port
+        let ServerMessage::V2(msg) = ServerMessage::new(
+            Compatibility::Level(4),
+            cfg,
+            &ports,
+            dummy_credentials(),
+            "test".into(),
+            String::new(),
+        ) else {
+            panic!("expected V2 message");
+        };
+        assert_eq!(msg.ports(), [1000]);
     }
 
     #[test]
@@ -547,7 +706,13 @@ mod test {
                     .with_unsigned(CongestionController::Bbr as u64),
                 ServerMessage2Attributes::InitialCongestionWindow.with_unsigned(5544u32),
                 ServerMessage2Attributes::QuicTimeout.with_unsigned(55u32),
-                // these two are not part of the config:
+                ServerMessage2Attributes::ParallelStreams.with_unsigned(7u32),
+                ServerMessage2Attributes::RateLimitToServer.with_unsigned(100_000u32),
+                ServerMessage2Attributes::RateLimitToClient.with_unsigned(0u32),
+                ServerMessage2Attributes::MaxUdpPayloadSize.with_unsigned(8972u32),
+                ServerMessage2Attributes::Connections.with_unsigned(4u32),
+                ServerMessage2Attributes::AdditionalPort.with_unsigned(1234u32),
+                // these are not part of the config:
                 ServerMessage2Attributes::WarningMessage.with_str("hi"),
                 ServerMessage2Attributes::Invalid.into(),
             ],
@@ -563,6 +728,12 @@ mod test {
         assert_eq!(cfg.congestion, CongestionController::Bbr);
         assert_eq!(cfg.initial_congestion_window, 5544);
         assert_eq!(cfg.timeout, 55);
+        assert_eq!(cfg.parallel_streams, 7);
+        // From the client's point of view
+        assert_eq!(cfg.limit_rate_tx, 100_000);
+        assert_eq!(cfg.limit_rate_rx, 0);
+        assert_eq!(cfg.max_udp_payload_size, 8972);
+        assert_eq!(cfg.connections, 4);
     }
 
     #[test]
@@ -39,6 +39,58 @@
 //!
 //! If the server needs to abort the transfer mid-flow, it may send a Response explaining why, then close the stream.
 //!
+//! ### Resuming
+//!
+//! When the client asks to resume a transfer (`--resume`), the receiver describes the data it already holds
+//! (its length and a hash). The sender checks this against its own copy of the file and, if it matches,
+//! sends only the remainder as partial content. Otherwise the whole file is sent.
+//!
+//! * For Get, the client includes its description in the [Get2Args] options.
+//! * For Put, the server sends a [ResumeReport] after its [Response]; the client then sends a second [FileHeader].
+//!
+//! ### Probe
+//!
+//! Measures the capacity of the link in each direction (`--autotune`).
+//! * C ➡️ S: [ProbeArgs] _(within [Command])_
+//! * S ➡️ C: [Response]. If the status within was not OK, the command does not proceed.
+//! * C ➡️ S: filler data for the requested duration; then the client finishes its side of the stream.
+//! * S ➡️ C: [ProbeReport], describing how much data arrived and how quickly.
+//! * S ➡️ C: filler data for the requested duration; then the server finishes its side of the stream.
+//!
+//! ### Stat
+//!
+//! Reports on a single remote file or directory (`--stat`).
+//! * C ➡️ S: [StatArgs] _(within [Command])_
+//! * S ➡️ C: [Response]. If the status within was not OK, the command does not proceed.
+//! * S ➡️ C: [ListEntry], describing the path with its metadata.
+//!
+//! ### FreeSpace
+//!
+//! Reports the capacity of the remote filesystem that holds a path, so the client can check
+//! that a transfer will fit before starting it.
+//! * C ➡️ S: [FreeSpaceArgs] _(within [Command])_
+//! * S ➡️ C: [Response]. If the status within was not OK, the command does not proceed.
+//! * S ➡️ C: [FreeSpaceReport]
+//!
+//! ### CreateSymlink
+//!
+//! Creates a symbolic link on the remote (`--links`).
+//! * C ➡️ S: [CreateSymlinkArgs] _(within [Command])_
+//! * S ➡️ C: [Response]
+//!
+//! ### CreateHardLink
+//!
+//! Creates a hard link on the remote to a file already transferred (`--hard-links`).
+//! * C ➡️ S: [CreateHardLinkArgs] _(within [Command])_
+//! * S ➡️ C: [Response]
+//!
+//! ### Compression
+//!
+//! When the client asks for compression (see [CommandParam::Compression]), the file data between
+//! [FileHeader] and [FileTrailer] is sent as a sequence of compressed chunks.
+//! The [FileHeader] still describes the uncompressed size, and any digest in the [FileTrailer]
+//! is computed over the uncompressed data.
+//!
 //! # Wire encoding
 //!
 //! On the wire these are [BARE] messages.
@@ -65,6 +117,10 @@ mod response;
 pub use response::*;
 mod file_transfer;
 pub use file_transfer::*;
+mod probe;
+pub use probe::*;
+mod free_space;
+pub use free_space::*;
 
 /// Convenient includes for session protocol building blocks
 pub mod prelude {
@@ -1,8 +1,12 @@
 //! Session protocol command structure definitions
 // (c) 2025 Ross Younger
 
+use super::free_space::FreeSpaceArgs;
 use super::get_put::{Get2Args, GetArgs, Put2Args, PutArgs};
-use super::misc_fs::{CreateDirectoryArgs, ListArgs, SetMetadataArgs};
+use super::misc_fs::{
+    CreateDirectoryArgs, CreateHardLinkArgs, CreateSymlinkArgs, ListArgs, SetMetadataArgs, StatArgs,
+};
+use super::probe::ProbeArgs;
 use crate::protocol::prelude::*;
 #[allow(unused_imports, reason = "needed for docs")]
 use crate::protocol::session::Response;
@@ -11,7 +15,11 @@ use int_enum::IntEnum;
 use std::time::SystemTime;
 
 #[allow(unused_imports, reason = "needed for docs")]
-use super::file_transfer::{FileHeader, FileTrailer};
+use super::file_transfer::{FileHeader, FileTrailer, ResumeReport};
+#[allow(unused_imports, reason = "needed for docs")]
+use super::free_space::FreeSpaceReport;
+#[allow(unused_imports, reason = "needed for docs")]
+use super::probe::ProbeReport;
 
 /// A command from client to server.
 ///
@@ -65,6 +73,8 @@ pub enum Command {
     /// * Then close the stream.
     ///
     /// If the server needs to abort the transfer, it may send a Response explaining why, then close the stream.
+    ///
+    /// When resuming a transfer, there are additional steps; see [`ResumeReport`].
     Put2(Put2Args),
 
     /// Ensures that a directory on the remote exists, creating it if necessary.
@@ -88,6 +98,59 @@ pub enum Command {
     ///
     /// * Either side may close the stream early if it has a problem.
     List(ListArgs),
+
+    /// Measures the capacity of the link in each direction, by sending filler data for a while.
+    ///
+    /// This command was introduced with compatibility level 5.
+    ///
+    /// * Client ➡️ Server: `Probe` command
+    /// * S➡️C: [`Response`]
+    /// * C➡️S: filler data, for the requested duration. Then the client finishes its side of the stream.
+    /// * S➡️C: [`ProbeReport`]
+    /// * S➡️C: filler data, for the requested duration. Then the server finishes its side of the stream.
+    Probe(ProbeArgs),
+
+    /// Reports on a single file or directory on the remote, without listing its contents.
+    ///
+    /// This command was introduced with compatibility level 5.
+    ///
+    /// * Client ➡️ Server: `Stat` command
+    /// * S➡️C: [`Response`]
+    /// * S➡️C: [`ListEntry`](crate::protocol::session::ListEntry) (if Response was OK), with full metadata
+    /// * Then close the stream.
+    Stat(StatArgs),
+
+    /// Reports the capacity and free space of the filesystem holding a path on the remote.
+    ///
+    /// This command was introduced with compatibility level 5.
+    ///
+    /// * Client ➡️ Server: `FreeSpace` command
+    /// * S➡️C: [`Response`]
+    /// * S➡️C: [`FreeSpaceReport`] (if Response was OK)
+    /// * Then close the stream.
+    FreeSpace(FreeSpaceArgs),
+
+    /// Creates a symbolic link on the remote.
+    ///
+    /// Any existing file or link at the path is replaced. An existing directory is not.
+    ///
+    /// This command was introduced with compatibility level 5.
+    ///
+    /// * Client ➡️ Server: `CreateSymlink` command
+    /// * S➡️C: [`Response`]
+    /// * Then close the stream.
+    CreateSymlink(CreateSymlinkArgs),
+
+    /// Creates a hard link on the remote, to a file which already exists there.
+    ///
+    /// Any existing file or link at the path is replaced. An existing directory is not.
+    ///
+    /// This command was introduced with compatibility level 5.
+    ///
+    /// * Client ➡️ Server: `CreateHardLink` command
+    /// * S➡️C: [`Response`]
+    /// * Then close the stream.
+    CreateHardLink(CreateHardLinkArgs),
 }
 impl ProtocolMessage for Command {}
 
@@ -119,6 +182,10 @@ pub enum CommandParam {
     ///
     /// The associated [`Variant`] data is empty (ignored).
     ///
+    /// On [`Command::List`], this asks for directories to be listed with their
+    /// [`MetadataAttr::AccessTime`] and [`MetadataAttr::ModificationTime`]
+    /// (from compatibility level 5).
+    ///
     /// Introduced in qcp 0.5 with `VersionCompatibility=V2`.
     PreserveMetadata,
 
@@ -128,6 +195,170 @@ pub enum CommandParam {
     ///
     /// Introduced in qcp 0.8 with compatibility level 4
     Recurse,
+
+    /// Transfer only part of the file, starting at this byte offset.
+    ///
+    /// The associated [`Variant`] data is Unsigned.
+    ///
+    /// Valid on [`Command::Get2`] and [`Command::Put2`].
+    /// The [`FileHeader`] then describes the partial content that follows.
+    ///
+    /// Introduced in compatibility level 5.
+    RangeOffset,
+
+    /// Transfer at most this many bytes of the file.
+    /// If not specified, the transfer runs to the end of the file.
+    ///
+    /// The associated [`Variant`] data is Unsigned.
+    ///
+    /// Valid on [`Command::Get2`] and [`Command::Put2`], alongside [`CommandParam::RangeOffset`].
+    ///
+    /// Introduced in compatibility level 5.
+    RangeLength,
+
+    /// The receiver of a file already holds this many bytes of it, from an interrupted transfer.
+    ///
+    /// The associated [`Variant`] data is Unsigned.
+    ///
+    /// Valid on [`Command::Get2`], and in a [`ResumeReport`].
+    /// If the sender agrees that the receiver's data matches its own, it sends only the rest
+    /// of the file, as partial content.
+    /// Otherwise it sends the whole file.
+    ///
+    /// Introduced in compatibility level 5.
+    ResumeFrom,
+
+    /// The SHA-256 hash of the data described by [`CommandParam::ResumeFrom`].
+    ///
+    /// The associated [`Variant`] data is Bytes.
+    ///
+    /// This is optional; if it is not present, the sender trusts the receiver's data.
+    ///
+    /// Introduced in compatibility level 5.
+    ResumeHash,
+
+    /// Requests that the server report how much of the destination file it already holds,
+    /// so that an interrupted transfer may be resumed.
+    ///
+    /// The associated [`Variant`] data is empty (ignored).
+    ///
+    /// Valid on [`Command::Put2`]. See [`ResumeReport`] for the protocol flow.
+    ///
+    /// Introduced in compatibility level 5.
+    Resume,
+
+    /// Requests that the receiver write directly to the destination file.
+    ///
+    /// By default, the receiver writes to a temporary file in the same directory, which it renames
+    /// over the destination only once the transfer has succeeded.
+    /// Partial content (see [`CommandParam::RangeOffset`]) is always written in place.
+    ///
+    /// The associated [`Variant`] data is empty (ignored).
+    ///
+    /// Valid on [`Command::Put2`].
+    ///
+    /// Introduced in compatibility level 5.
+    InPlace,
+
+    /// The file data is sent as zstd-compressed chunks.
+    ///
+    /// The associated [`Variant`] data is Unsigned: the compression level the sender should use.
+    /// (The receiver does not need to know the level, but it is useful for logging.)
+    ///
+    /// Valid on [`Command::Get2`], where it asks the server to compress the data it sends,
+    /// and on [`Command::Put2`], where it indicates that the client is compressing the data it sends.
+    ///
+    /// The encoding is described in the `util::compression` module.
+    ///
+    /// Introduced in compatibility level 5.
+    Compression,
+
+    /// Symbolic links are reported as links, rather than followed.
+    ///
+    /// The associated [`Variant`] data is empty (ignored).
+    ///
+    /// Valid on [`Command::List`]. Each link found below the listed path is reported as a
+    /// [`ListEntry`](crate::protocol::session::ListEntry) carrying [`MetadataAttr::SymlinkTarget`].
+    /// A link named by the path itself is still followed.
+    ///
+    /// Introduced in compatibility level 5.
+    PreserveLinks,
+
+    /// The receiver should apply file ownership by numeric user and group ID,
+    /// ignoring any [`MetadataAttr::OwnerName`] and [`MetadataAttr::GroupName`].
+    ///
+    /// The associated [`Variant`] data is empty (ignored).
+    ///
+    /// Valid on [`Command::Put2`].
+    ///
+    /// Introduced in compatibility level 5.
+    NumericIds,
+
+    /// The sender should include the file's extended attributes in the [`FileTrailer`],
+    /// other than those which hold access control lists.
+    ///
+    /// The associated [`Variant`] data is empty (ignored).
+    ///
+    /// Valid on [`Command::Get2`]. See [`MetadataAttr::ExtendedAttribute`].
+    /// Also valid on [`Command::List`], where the attributes of directories are included
+    /// in their [`ListEntry`](crate::protocol::session::ListEntry).
+    ///
+    /// Introduced in compatibility level 5.
+    ExtendedAttributes,
+
+    /// The sender should include the file's POSIX access control lists in the [`FileTrailer`].
+    ///
+    /// The associated [`Variant`] data is empty (ignored).
+    ///
+    /// Valid on [`Command::Get2`]. ACLs are carried as [`MetadataAttr::ExtendedAttribute`],
+    /// in the form used by Linux (`system.posix_acl_access`, and `system.posix_acl_default`
+    /// for directories).
+    /// Also valid on [`Command::List`], as for [`CommandParam::ExtendedAttributes`].
+    ///
+    /// Introduced in compatibility level 5.
+    Acls,
+
+    /// Files which are hard links to a file listed earlier are reported as such.
+    ///
+    /// The associated [`Variant`] data is empty (ignored).
+    ///
+    /// Valid on [`Command::List`]. The second and subsequent names for the same file are each
+    /// reported as a [`ListEntry`](crate::protocol::session::ListEntry) carrying
+    /// [`MetadataAttr::HardLinkTarget`].
+    ///
+    /// Introduced in compatibility level 5.
+    HardLinks,
+
+    /// Preserve file permissions (mode bits).
+    ///
+    /// The associated [`Variant`] data is empty (ignored).
+    ///
+    /// Valid on [`Command::Get2`] and [`Command::Put2`]. Together with [`CommandParam::PreserveTimes`] and
+    /// [`CommandParam::PreserveOwnership`], this allows a subset of what
+    /// [`CommandParam::PreserveMetadata`] selects to be preserved.
+    /// The sender of the file only includes the selected attributes in the [`FileTrailer`],
+    /// and the receiver only applies those.
+    ///
+    /// Introduced in compatibility level 5.
+    PreserveMode,
+
+    /// Preserve file access and modification times.
+    ///
+    /// The associated [`Variant`] data is empty (ignored).
+    ///
+    /// Valid on [`Command::Get2`] and [`Command::Put2`]; see [`CommandParam::PreserveMode`].
+    ///
+    /// Introduced in compatibility level 5.
+    PreserveTimes,
+
+    /// Preserve file ownership.
+    ///
+    /// The associated [`Variant`] data is empty (ignored).
+    ///
+    /// Valid on [`Command::Get2`] and [`Command::Put2`]; see [`CommandParam::PreserveMode`].
+    ///
+    /// Introduced in compatibility level 5.
+    PreserveOwnership,
 }
 impl DataTag for CommandParam {}
 
@@ -180,6 +411,149 @@ pub enum MetadataAttr {
     ///
     /// Introduced in qcp 0.5 with `VersionCompatibility=V2`.
     ModificationTime,
+
+    /// The byte offset within the file at which the data that follows belongs.
+    ///
+    /// Variant data is Unsigned.
+    ///
+    /// Only valid in [`FileHeader`]. Its presence marks the payload as partial content.
+    ///
+    /// Introduced in compatibility level 5.
+    RangeOffset,
+
+    /// The total size of the file, of which the data that follows is a part.
+    ///
+    /// Variant data is Unsigned.
+    ///
+    /// Only valid in [`FileHeader`], alongside [`MetadataAttr::RangeOffset`].
+    ///
+    /// Introduced in compatibility level 5.
+    FileSize,
+
+    /// SHA-256 digest of the file data that was sent.
+    /// If the [`FileHeader`] described partial content, this covers only that part.
+    ///
+    /// Variant data is Bytes.
+    ///
+    /// Only valid in [`FileTrailer`]. The receiver hashes the data it wrote and reports
+    /// [`Status::ChecksumMismatch`](crate::protocol::session::Status::ChecksumMismatch) if they differ.
+    ///
+    /// Introduced in compatibility level 5.
+    Sha256Digest,
+
+    /// The target of a symbolic link, exactly as stored in the link.
+    ///
+    /// Variant data is String.
+    ///
+    /// Only valid in [`ListEntry`](crate::protocol::session::ListEntry), in response to
+    /// [`Command::List`] with [`CommandParam::PreserveLinks`]. Its presence marks the entry as a link.
+    ///
+    /// Introduced in compatibility level 5.
+    SymlinkTarget,
+
+    /// Numeric ID of the user that owns the file.
+    ///
+    /// Variant data is Unsigned.
+    ///
+    /// Valid in [`FileTrailer`], when preserving metadata. The receiver only applies ownership if it
+    /// has the privilege to do so; otherwise the file belongs to whoever wrote it.
+    /// If [`MetadataAttr::OwnerName`] is also present and names a user known to the receiver,
+    /// the name takes precedence (unless [`CommandParam::NumericIds`]).
+    ///
+    /// Introduced in compatibility level 5.
+    OwnerId,
+
+    /// Numeric ID of the group that owns the file.
+    ///
+    /// Variant data is Unsigned.
+    ///
+    /// As [`MetadataAttr::OwnerId`], but for the group.
+    ///
+    /// Introduced in compatibility level 5.
+    GroupId,
+
+    /// Name of the user that owns the file, if known to the sender.
+    ///
+    /// Variant data is String.
+    ///
+    /// See [`MetadataAttr::OwnerId`].
+    ///
+    /// Introduced in compatibility level 5.
+    OwnerName,
+
+    /// Name of the group that owns the file, if known to the sender.
+    ///
+    /// Variant data is String.
+    ///
+    /// See [`MetadataAttr::OwnerId`].
+    ///
+    /// Introduced in compatibility level 5.
+    GroupName,
+
+    /// An extended attribute of the file.
+    ///
+    /// Variant data is a List of two items: the attribute name (String) and its value (Bytes).
+    ///
+    /// Valid in [`FileTrailer`], when requested; also in [`Command::SetMetadata`] and in
+    /// directory entries of a [`Command::List`] response. A large value may be split across several
+    /// consecutive entries with the same name; the receiver concatenates them.
+    /// The receiver applies what it can, reporting any attributes it could not apply
+    /// without failing the transfer.
+    ///
+    /// Introduced in compatibility level 5.
+    ExtendedAttribute,
+
+    /// The number of further [`FileTrailer`]s which follow this one.
+    ///
+    /// Variant data is Unsigned.
+    ///
+    /// Only valid in [`FileTrailer`]. When the metadata for a file is too large for a single
+    /// trailer, it is split across several; the receiver merges their metadata.
+    ///
+    /// Introduced in compatibility level 5.
+    MoreTrailers,
+
+    /// A region of a sparse file which holds data.
+    ///
+    /// Variant data is a List of two Unsigned items: the byte offset within the file, and the length.
+    ///
+    /// Only valid in [`FileHeader`]. When present, only the data in these extents is sent,
+    /// in the order given; the rest of the region described by the header is a hole.
+    /// Extents must be in ascending order, must not overlap, and must lie within that region.
+    ///
+    /// Introduced in compatibility level 5.
+    DataExtent,
+
+    /// The name of an earlier entry in the same listing which is the same file as this one.
+    ///
+    /// Variant data is String.
+    ///
+    /// Only valid in [`ListEntry`](crate::protocol::session::ListEntry), in response to
+    /// [`Command::List`] with [`CommandParam::HardLinks`]. Its presence marks the entry as a
+    /// hard link, to be recreated as such rather than transferred again.
+    ///
+    /// Introduced in compatibility level 5.
+    HardLinkTarget,
+
+    /// The sub-second part of the access time, in nanoseconds (less than 10^9).
+    ///
+    /// Variant data is Unsigned.
+    ///
+    /// Valid wherever [`MetadataAttr::AccessTime`] is, alongside it; it is added to that time.
+    /// Receivers which do not understand it apply the whole seconds only.
+    ///
+    /// Introduced in compatibility level 5.
+    AccessTimeNanos,
+
+    /// The sub-second part of the modification time, in nanoseconds (less than 10^9).
+    ///
+    /// Variant data is Unsigned.
+    ///
+    /// Valid wherever [`MetadataAttr::ModificationTime`] is, alongside it; it is added to that time.
+    /// Receivers which do not understand it apply the whole seconds only.
+    ///
+    /// Introduced in compatibility level 5.
+    ModificationTimeNanos,
 }
 impl DataTag for MetadataAttr {
     fn debug_data(&self, data: &Variant) -> String {
@@ -209,6 +583,48 @@ impl MetadataAttr {
     pub fn new_mtime(t: SystemTime) -> TaggedData<MetadataAttr> {
         Self::ModificationTime.with_unsigned(t.to_unix())
     }
+    /// Convenience constructor for Metadata::AccessTimeNanos
+    #[must_use]
+    pub fn new_atime_nanos(t: SystemTime) -> TaggedData<MetadataAttr> {
+        Self::AccessTimeNanos.with_unsigned(t.unix_subsec_nanos())
+    }
+    /// Convenience constructor for Metadata::ModificationTimeNanos
+    #[must_use]
+    pub fn new_mtime_nanos(t: SystemTime) -> TaggedData<MetadataAttr> {
+        Self::ModificationTimeNanos.with_unsigned(t.unix_subsec_nanos())
+    }
+
+    /// Finds the access time in a set of metadata, to the nanosecond if available
+    #[must_use]
+    pub fn find_atime(metadata: &[TaggedData<MetadataAttr>]) -> Option<SystemTime> {
+        find_time(metadata, Self::AccessTime, Self::AccessTimeNanos)
+    }
+    /// Finds the modification time in a set of metadata, to the nanosecond if available
+    #[must_use]
+    pub fn find_mtime(metadata: &[TaggedData<MetadataAttr>]) -> Option<SystemTime> {
+        find_time(
+            metadata,
+            Self::ModificationTime,
+            Self::ModificationTimeNanos,
+        )
+    }
+}
+
+fn find_time(
+    metadata: &[TaggedData<MetadataAttr>],
+    secs: MetadataAttr,
+    nanos: MetadataAttr,
+) -> Option<SystemTime> {
+    let find = |tag| {
+        metadata
+            .iter()
+            .find(|md| md.tag() == Some(tag))
+            .and_then(|md| md.data.as_unsigned_ref().copied())
+    };
+    Some(SystemTime::from_unix_nanos(
+        find(secs)?,
+        find(nanos).unwrap_or_default(),
+    ))
 }
 
 #[cfg(test)]
@@ -228,6 +644,30 @@ mod test {
         assert_eq!(cmd, deser);
     }
 
+    #[test]
+    fn find_times() {
+        use super::MetadataAttr;
+        use crate::util::time::SystemTimeExt as _;
+        use std::time::SystemTime;
+
+        let t = SystemTime::from_unix_nanos(1_700_000_000, 123_456_789);
+        let full = vec![
+            MetadataAttr::new_atime(t),
+            MetadataAttr::new_mtime(t),
+            MetadataAttr::new_atime_nanos(t),
+            MetadataAttr::new_mtime_nanos(t),
+        ];
+        assert_eq!(MetadataAttr::find_atime(&full), Some(t));
+        assert_eq!(MetadataAttr::find_mtime(&full), Some(t));
+        // Without the nanoseconds, we have whole seconds
+        assert_eq!(
+            MetadataAttr::find_mtime(&full[..2]),
+            Some(SystemTime::from_unix(1_700_000_000))
+        );
+        // Nanoseconds alone are meaningless
+        assert_eq!(MetadataAttr::find_atime(&full[2..]), None);
+    }
+
     #[test]
     fn metadata_debug_render() {
         let t = MetadataAttr::ModeBits.with_unsigned(0o644u32);
@@ -1,9 +1,11 @@
 //! Session protocol command structure definitions
 // (c) 2025 Ross Younger
 
+use crate::PreserveSelection;
 use crate::protocol::session::prelude::*;
 use crate::util::FsMetadataExt as _;
 use std::fs::Metadata as FsMetadata;
+use std::ops::Range;
 use tracing::debug;
 
 #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
@@ -46,11 +48,73 @@ pub struct FileHeaderV2 {
     ///   is implicitly added. This can be fixed, if needed, by providing a Mode in the
     ///   [`FileTrailer`].
     ///
+    /// - RangeOffset and FileSize (compatibility level 5).
+    ///   These mark the data that follows as partial content: `size` bytes, which belong
+    ///   at `RangeOffset` within a file of `FileSize` bytes.
+    ///
+    /// - DataExtent (compatibility level 5).
+    ///   These mark the file as sparse. `size` still describes the whole region of the file
+    ///   (or of the partial content), but only the data within the extents is sent.
+    ///   See [`FileHeaderV2::payload_len`].
+    ///
     /// N.B. AccessTime and ModificationTime are not valid here. They can only be provided
     /// in the [`FileTrailer`].
     pub metadata: Vec<TaggedData<MetadataAttr>>,
 }
 
+impl FileHeaderV2 {
+    /// If this header describes partial content, returns the offset of the data
+    /// within the file and the total size of the file.
+    #[must_use]
+    pub fn range(&self) -> Option<(u64, u64)> {
+        let offset = self.metadata.find_tag(MetadataAttr::RangeOffset)?;
+        let total = self.metadata.find_tag(MetadataAttr::FileSize)?;
+        Some((offset.coerce_unsigned(), total.coerce_unsigned()))
+    }
+
+    /// If this header describes a sparse file, returns the extents of the file which hold data.
+    ///
+    /// # Errors
+    /// If the extents are malformed, out of order, overlap, or lie outside the region the header describes.
+    pub fn extents(&self) -> anyhow::Result<Option<Vec<Range<u64>>>> {
+        let mut result = None;
+        let start = self.range().map_or(0, |(offset, _)| offset);
+        let end = start.saturating_add(self.size.0);
+        let mut previous_end = start;
+        for md in &self.metadata {
+            if md.tag() != Some(MetadataAttr::DataExtent) {
+                continue;
+            }
+            let Some([Variant::Unsigned(offset), Variant::Unsigned(len)]) =
+                md.data.as_slice_variant()
+            else {
+                anyhow::bail!("malformed data extent");
+            };
+            let extent = offset.0..offset.0.saturating_add(len.0);
+            anyhow::ensure!(
+                extent.start >= previous_end && extent.end <= end,
+                "invalid data extent {extent:?}"
+            );
+            previous_end = extent.end;
+            result.get_or_insert_with(Vec::new).push(extent);
+        }
+        Ok(result)
+    }
+
+    /// The number of bytes of file data which follow this header.
+    ///
+    /// This is `size`, unless the header describes a sparse file.
+    #[must_use]
+    pub fn payload_len(&self) -> u64 {
+        self.extents()
+            .ok()
+            .flatten()
+            .map_or(self.size.0, |extents| {
+                extents.iter().map(|e| e.end - e.start).sum()
+            })
+    }
+}
+
 impl FileHeader {
     #[must_use]
     /// Convenience constructor
@@ -85,7 +149,49 @@ impl FileHeader {
             FileHeader::new_v1(meta.len(), protocol_filename)
         }
     }
+
+    /// Creates a header describing part of a file (see [`MetadataAttr::RangeOffset`]).
+    ///
+    /// This requires [`Feature::RANGED_TRANSFER`], so always produces a V2 header.
+    pub(crate) fn for_file_range(
+        meta: &FsMetadata,
+        protocol_filename: &str,
+        range: &Range<u64>,
+    ) -> Self {
+        let mut qcpmeta = meta.to_tagged_data(false);
+        qcpmeta.push(MetadataAttr::RangeOffset.with_unsigned(range.start));
+        qcpmeta.push(MetadataAttr::FileSize.with_unsigned(meta.len()));
+        debug!("Header metadata: {}", display_vec_td(&qcpmeta));
+        FileHeader::new_v2(range.end - range.start, protocol_filename, qcpmeta)
+    }
 }
+impl FileHeader {
+    /// Marks this header as describing a sparse file, whose data lies only in the given extents.
+    ///
+    /// This requires [`Feature::SPARSE_FILES`]; it has no effect on a V1 header.
+    #[must_use]
+    pub(crate) fn with_extents(mut self, extents: Option<&[Range<u64>]>) -> Self {
+        if let (FileHeader::V2(hdr), Some(extents)) = (&mut self, extents) {
+            if extents.is_empty() {
+                // It's all hole. An empty extent marks the header as sparse.
+                let start = hdr.range().map_or(0, |(offset, _)| offset);
+                hdr.metadata
+                    .push(MetadataAttr::DataExtent.with_variant(Variant::List(vec![
+                        Variant::Unsigned(Uint(start)),
+                        Variant::Unsigned(Uint(0)),
+                    ])));
+            }
+            hdr.metadata.extend(extents.iter().map(|e| {
+                MetadataAttr::DataExtent.with_variant(Variant::List(vec![
+                    Variant::Unsigned(Uint(e.start)),
+                    Variant::Unsigned(Uint(e.end - e.start)),
+                ]))
+            }));
+        }
+        self
+    }
+}
+
 impl From<FileHeaderV1> for FileHeaderV2 {
     fn from(other: FileHeaderV1) -> Self {
         Self {
@@ -128,8 +234,87 @@ pub struct FileTrailerV2 {
     /// - Mode
     /// - AccessTime. If unspecified, the time will be set by the receiving OS.
     /// - ModificationTime. If unspecified, the time will be set by the receiving OS.
+    /// - OwnerId, GroupId, OwnerName, GroupName. If unspecified, or if the receiver is not
+    ///   privileged to change them, the file belongs to whoever wrote it.
+    /// - ExtendedAttribute, when requested.
+    /// - MoreTrailers, if the metadata did not fit into a single trailer.
+    ///
+    /// When the transfer carried partial content, the receiver applies this metadata as soon as
+    /// that part has been written. A sender striping a file across several streams should therefore
+    /// only send metadata with the last part to complete.
     pub metadata: Vec<TaggedData<MetadataAttr>>,
 }
+impl FileTrailerV2 {
+    /// The most trailers a single file may send
+    pub(crate) const MAX_TRAILERS: u64 = 64;
+
+    /// Split this trailer into as many as are needed to respect the wire encoding limit.
+    ///
+    /// Only extended attributes are moved into the further trailers; the first trailer
+    /// carries all other metadata, and [`MetadataAttr::MoreTrailers`] if needed.
+    pub(crate) fn split_by_size(self, max_size: u32) -> anyhow::Result<Vec<Self>> {
+        use std::collections::VecDeque;
+
+        fn encoded_size<T: Serialize>(item: &T) -> anyhow::Result<usize> {
+            Ok(serde_bare::to_vec(item)?.len())
+        }
+        let max_size = usize::try_from(max_size)?;
+        if encoded_size(&FileTrailer::V2(self.clone()))? <= max_size {
+            return Ok(vec![self]);
+        }
+
+        let (input, base): (Vec<_>, Vec<_>) = self
+            .metadata
+            .into_iter()
+            .partition(|md| md.tag() == Some(MetadataAttr::ExtendedAttribute));
+        let mut input = VecDeque::from(input);
+        let mut result = vec![];
+        let mut working = FileTrailerV2 { metadata: base };
+        while !input.is_empty() {
+            // Allow 7 bytes for the array length to grow, and 16 for MoreTrailers
+            let mut current_size = encoded_size(&FileTrailer::V2(working.clone()))? + 7 + 16;
+            while let Some(front) = input.pop_front() {
+                let entry_size = encoded_size(&front)?;
+                if current_size + entry_size > max_size {
+                    anyhow::ensure!(
+                        !working.metadata.is_empty(),
+                        "metadata entry too large to send"
+                    );
+                    input.push_front(front);
+                    break;
+                }
+                current_size += entry_size;
+                working.metadata.push(front);
+            }
+            result.push(std::mem::take(&mut working));
+        }
+        if result.is_empty() {
+            result.push(working);
+        }
+        let more = result.len() as u64 - 1;
+        anyhow::ensure!(
+            more <= Self::MAX_TRAILERS,
+            "file metadata is too large to send"
+        );
+        if more > 0 {
+            result[0]
+                .metadata
+                .push(MetadataAttr::MoreTrailers.with_unsigned(more));
+        }
+        Ok(result)
+    }
+
+    /// Join multiple `FileTrailerV2` parts from the wire into a single trailer.
+    pub(crate) fn join(parts: Vec<Self>) -> Self {
+        let metadata = parts
+            .into_iter()
+            .flat_map(|p| p.metadata)
+            .filter(|md| md.tag() != Some(MetadataAttr::MoreTrailers))
+            .collect();
+        Self { metadata }
+    }
+}
+
 impl From<FileTrailer> for FileTrailerV2 {
     fn from(value: FileTrailer) -> Self {
         match value {
@@ -140,13 +325,37 @@ impl From<FileTrailer> for FileTrailerV2 {
 }
 
 impl FileTrailer {
-    pub(crate) fn for_file(compat: Compatibility, meta: &FsMetadata, preserve: bool) -> Self {
+    /// `digest` is the SHA-256 digest of the data sent, if computed. It is only sent if the peer supports it.
+    pub(crate) async fn for_file(
+        compat: Compatibility,
+        meta: &FsMetadata,
+        preserve: PreserveSelection,
+        digest: Option<Vec<u8>>,
+    ) -> Self {
         if compat.supports(Feature::GET2_PUT2) {
-            let metadata = if preserve {
+            let mut metadata = if preserve.any() {
                 meta.to_tagged_data(true)
             } else {
                 Vec::new()
             };
+            if preserve.times && compat.supports(Feature::NANOSECOND_TIMES) {
+                metadata.extend(meta.time_nanos());
+            }
+            if preserve.owner && compat.supports(Feature::OWNERSHIP) {
+                // Looking up user and group names may block
+                let meta = meta.clone();
+                metadata.extend(
+                    tokio::task::spawn_blocking(move || meta.ownership())
+                        .await
+                        .unwrap_or_default(),
+                );
+            }
+            metadata.retain(|md| preserve.keeps(md));
+            if let Some(digest) = digest
+                && compat.supports(Feature::CHECKSUM)
+            {
+                metadata.push(MetadataAttr::Sha256Digest.with_bytes(digest));
+            }
             debug!("Trailer metadata: {}", display_vec_td(&metadata));
             FileTrailer::V2(FileTrailerV2 { metadata })
         } else {
@@ -155,10 +364,49 @@ impl FileTrailer {
     }
 }
 
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+/// Resume Report packet. Describes the data that the receiver of a file already holds.
+///
+/// This is sent in response to a [`Command::Put2`] which carries [`CommandParam::Resume`]:
+/// * C➡️S: `Put2` command, [`FileHeader`] (describing the whole file)
+/// * S➡️C: [`Response`], then `ResumeReport`
+/// * C➡️S: a second [`FileHeader`], describing the data that actually follows.
+///   If the client agrees with the report, this is partial content (see [`MetadataAttr::RangeOffset`]);
+///   otherwise it is the whole file.
+/// * The transfer then continues as normal: file data, [`FileTrailer`], and so on.
+///
+/// This is an enum to provide for forward compatibility.
+pub enum ResumeReport {
+    /// This version was introduced in compatibility level 5.
+    V1(ResumeReportV1),
+}
+impl ProtocolMessage for ResumeReport {
+    const WIRE_ENCODING_LIMIT: u32 = 65_536;
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Default)]
+/// Version 1 of [`ResumeReport`]
+pub struct ResumeReportV1 {
+    /// Details of the data the server holds.
+    /// Valid keys are:
+    /// - [`CommandParam::ResumeFrom`]. If this is not present, the server holds no data.
+    /// - [`CommandParam::ResumeHash`]
+    pub options: Vec<TaggedData<CommandParam>>,
+}
+impl From<ResumeReport> for ResumeReportV1 {
+    fn from(value: ResumeReport) -> Self {
+        match value {
+            ResumeReport::V1(r) => r,
+        }
+    }
+}
+
 #[cfg(test)]
 #[cfg_attr(coverage_nightly, coverage(off))]
 mod test {
-    use super::{FileHeader, FileHeaderV1, FileTrailer, FileTrailerV2};
+    use super::{
+        FileHeader, FileHeaderV1, FileTrailer, FileTrailerV2, ResumeReport, ResumeReportV1,
+    };
     use crate::protocol::session::prelude::*;
 
     use pretty_assertions::assert_eq;
@@ -198,6 +446,21 @@ mod test {
         assert_eq!(wire, expected);
     }
 
+    #[test]
+    fn wire_marshalling_resume_report() {
+        let report = ResumeReport::V1(ResumeReportV1 {
+            options: vec![
+                CommandParam::ResumeFrom.with_unsigned(1000u64),
+                CommandParam::ResumeHash.with_bytes(vec![1, 2, 3]),
+            ],
+        });
+        let wire = report.to_vec().unwrap();
+        let expected = b"\x00\x02\x05\x03\xe8\x07\x06\x05\x03\x01\x02\x03".to_vec();
+        assert_eq!(wire, expected);
+        let deser = ResumeReport::from_slice(&wire).unwrap();
+        assert_eq!(report, deser);
+    }
+
     #[test]
     fn wire_marshalling_file_trailer_v1() {
         let trail = FileTrailer::V1;
@@ -228,4 +491,94 @@ mod test {
         trail.to_writer_framed(&mut buf).unwrap();
         eprintln!("{buf:?}");
     }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn trailer_carries_ownership() {
+        use std::os::unix::fs::MetadataExt as _;
+
+        let meta = std::fs::metadata(".").unwrap();
+        let FileTrailer::V2(trailer) =
+            FileTrailer::for_file(Compatibility::Level(5), &meta, true.into(), None).await
+        else {
+            panic!("expected V2 trailer");
+        };
+        let uid = trailer.metadata.find_tag(MetadataAttr::OwnerId).unwrap();
+        assert_eq!(uid.as_unsigned_ref(), Some(&u64::from(meta.uid())));
+        assert!(trailer.metadata.find_tag(MetadataAttr::GroupId).is_some());
+
+        // Not sent to older peers, nor without --preserve
+        for (level, preserve) in [(4, true), (5, false)] {
+            let FileTrailer::V2(trailer) =
+                FileTrailer::for_file(Compatibility::Level(level), &meta, preserve.into(), None)
+                    .await
+            else {
+                panic!("expected V2 trailer");
+            };
+            assert!(trailer.metadata.find_tag(MetadataAttr::OwnerId).is_none());
+        }
+    }
+
+    #[test]
+    fn split_and_join_trailer() {
+        use super::FileTrailer;
+        let xattr = |i: u8| {
+            MetadataAttr::ExtendedAttribute.with_variant(Variant::List(vec![
+                Variant::String(format!("user.{i}")),
+                Variant::Bytes(vec![i; 100]),
+            ]))
+        };
+        let mut metadata = vec![MetadataAttr::ModeBits.with_unsigned(0o644u32)];
+        metadata.extend((0..20).map(xattr));
+        let trailer = FileTrailerV2 { metadata };
+
+        // Small enough to go in one
+        let parts = trailer.clone().split_by_size(65536).unwrap();
+        assert_eq!(parts, vec![trailer.clone()]);
+
+        let parts = trailer.clone().split_by_size(512).unwrap();
+        assert!(parts.len() > 1);
+        for p in &parts {
+            assert!(
+                serde_bare::to_vec(&FileTrailer::V2(p.clone()))
+                    .unwrap()
+                    .len()
+                    <= 512
+            );
+        }
+        let more = parts[0]
+            .metadata
+            .find_tag(MetadataAttr::MoreTrailers)
+            .unwrap();
+        assert_eq!(more.as_unsigned_ref(), Some(&(parts.len() as u64 - 1)));
+        assert!(parts[0].metadata.find_tag(MetadataAttr::ModeBits).is_some());
+        assert_eq!(FileTrailerV2::join(parts), trailer);
+
+        // An entry that cannot fit at all
+        let _ = trailer.split_by_size(64).unwrap_err();
+    }
+
+    #[test]
+    #[allow(clippy::single_range_in_vec_init)]
+    fn header_extents() {
+        use super::FileHeaderV2;
+        let header = |extents: &[std::ops::Range<u64>]| {
+            FileHeaderV2::from(FileHeader::new_v2(100, "f", vec![]).with_extents(Some(extents)))
+        };
+        let hdr = header(&[10..20, 50..55]);
+        assert_eq!(hdr.extents().unwrap(), Some(vec![10..20, 50..55]));
+        assert_eq!(hdr.payload_len(), 15);
+        // A file which is all hole is still sparse
+        let hdr = header(&[]);
+        assert_eq!(hdr.extents().unwrap(), Some(vec![0..0]));
+        assert_eq!(hdr.payload_len(), 0);
+        assert_eq!(
+            FileHeaderV2::from(FileHeader::new_v2(100, "f", vec![])).payload_len(),
+            100
+        );
+        // Out of order, overlapping, or out of bounds
+        for bad in [&[50..55, 10..20][..], &[10..20, 15..25], &[90..101]] {
+            let _ = header(bad).extents().unwrap_err();
+        }
+    }
 }
@@ -0,0 +1,64 @@
+//! Filesystem capacity queries
+// (c) 2025 Ross Younger
+
+use crate::protocol::session::prelude::*;
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Default, Clone)]
+/// Arguments for the `FreeSpace` command
+///
+/// This was introduced in compatibility level 5.
+pub struct FreeSpaceArgs {
+    /// The path to query. It may be a relative or absolute path.
+    ///
+    /// It need not exist; if it does not, the server reports on the filesystem that would hold it.
+    pub path: String,
+
+    /// Extended options (not currently used; reserved for future expansion)
+    pub options: Vec<TaggedData<CommandParam>>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy)]
+/// The server's report on the filesystem holding the path in a `FreeSpace` command.
+///
+/// This is an enum to provide for forward compatibility.
+pub enum FreeSpaceReport {
+    /// This version was introduced in compatibility level 5.
+    V1(FreeSpaceReportV1),
+}
+impl ProtocolMessage for FreeSpaceReport {}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy, Default)]
+/// Version 1 of [`FreeSpaceReport`]
+pub struct FreeSpaceReportV1 {
+    /// Total capacity of the filesystem, in bytes
+    pub total: Uint,
+    /// Space available to the server process, in bytes
+    pub available: Uint,
+}
+impl From<FreeSpaceReport> for FreeSpaceReportV1 {
+    fn from(value: FreeSpaceReport) -> Self {
+        match value {
+            FreeSpaceReport::V1(r) => r,
+        }
+    }
+}
+
+#[cfg(test)]
+#[cfg_attr(coverage_nightly, coverage(off))]
+mod test {
+    use super::{FreeSpaceReport, FreeSpaceReportV1};
+    use crate::protocol::session::prelude::*;
+
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn wire_marshalling_free_space_report() {
+        let report = FreeSpaceReport::V1(FreeSpaceReportV1 {
+            total: Uint(1 << 40),
+            available: Uint(1234),
+        });
+        let wire = report.to_vec().unwrap();
+        let deser = FreeSpaceReport::from_slice(&wire).unwrap();
+        assert_eq!(report, deser);
+    }
+}
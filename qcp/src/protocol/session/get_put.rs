@@ -19,7 +19,8 @@ pub struct Get2Args {
 
     /// Extended options for the GET command
     ///
-    /// Supported options: [`CommandParam::PreserveMetadata`]
+    /// Supported options: [`CommandParam::PreserveMetadata`], or any of [`CommandParam::PreserveMode`],
+    /// [`CommandParam::PreserveTimes`] and [`CommandParam::PreserveOwnership`]
     pub options: Vec<TaggedData<CommandParam>>,
 }
 impl From<GetArgs> for Get2Args {
@@ -48,7 +49,8 @@ pub struct Put2Args {
 
     /// Extended options for the PUT command
     ///
-    /// Supported options: [`CommandParam::PreserveMetadata`]
+    /// Supported options: [`CommandParam::PreserveMetadata`], or any of [`CommandParam::PreserveMode`],
+    /// [`CommandParam::PreserveTimes`] and [`CommandParam::PreserveOwnership`]
     pub options: Vec<TaggedData<CommandParam>>,
 }
 impl From<PutArgs> for Put2Args {
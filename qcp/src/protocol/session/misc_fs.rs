@@ -26,7 +26,10 @@ pub struct SetMetadataArgs {
 
     /// The metadata to apply.
     ///
-    /// At present only permissions are supported.
+    /// At present only permissions are supported; and, from compatibility level 5,
+    /// access and modification times and extended attributes.
+    /// Extended attributes which cannot be applied are reported in the response message,
+    /// without failing the command.
     pub metadata: Vec<TaggedData<MetadataAttr>>,
 
     /// Extended options (not currently used; reserved for future expansion)
@@ -47,3 +50,46 @@ pub struct ListArgs {
     /// Supported options: [`CommandParam::Recurse`]
     pub options: Vec<TaggedData<CommandParam>>,
 }
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Default, Clone)]
+/// Arguments for the `Stat` command
+///
+/// This was introduced with compatibility level 5.
+pub struct StatArgs {
+    /// This is the path to query. It may be a relative or absolute path.
+    /// It may be a file or directory.
+    pub path: String,
+
+    /// Extended options (not currently used; reserved for future expansion)
+    pub options: Vec<TaggedData<CommandParam>>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Default, Clone)]
+/// Arguments for the `CreateSymlink` command
+///
+/// This was introduced with compatibility level 5.
+pub struct CreateSymlinkArgs {
+    /// The path of the link to create. It may be a relative or absolute path.
+    pub path: String,
+
+    /// What the link points to. This is stored in the link exactly as given.
+    pub target: String,
+
+    /// Extended options (not currently used; reserved for future expansion)
+    pub options: Vec<TaggedData<CommandParam>>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Default, Clone)]
+/// Arguments for the `CreateHardLink` command
+///
+/// This was introduced with compatibility level 5.
+pub struct CreateHardLinkArgs {
+    /// The path of the link to create. It may be a relative or absolute path.
+    pub path: String,
+
+    /// The existing file to link to. It may be a relative or absolute path.
+    pub target: String,
+
+    /// Extended options (not currently used; reserved for future expansion)
+    pub options: Vec<TaggedData<CommandParam>>,
+}
@@ -0,0 +1,64 @@
+//! Link probing
+// (c) 2025 Ross Younger
+
+use crate::protocol::session::prelude::*;
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Default, Clone)]
+/// Arguments for the `Probe` command
+///
+/// This was introduced in compatibility level 5.
+pub struct ProbeArgs {
+    /// How long each direction of the probe should run, in milliseconds.
+    ///
+    /// The server may impose a shorter limit.
+    pub duration_ms: Uint,
+
+    /// Extended options (not currently used; reserved for future expansion)
+    pub options: Vec<TaggedData<CommandParam>>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy)]
+/// The server's measurement of the client-to-server part of a `Probe`.
+///
+/// This is an enum to provide for forward compatibility.
+pub enum ProbeReport {
+    /// This version was introduced in compatibility level 5.
+    V1(ProbeReportV1),
+}
+impl ProtocolMessage for ProbeReport {}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy, Default)]
+/// Version 1 of [`ProbeReport`]
+pub struct ProbeReportV1 {
+    /// Number of bytes the server received
+    pub received: Uint,
+    /// Time from the arrival of the first byte to the end of the data, in microseconds
+    pub elapsed_us: Uint,
+}
+impl From<ProbeReport> for ProbeReportV1 {
+    fn from(value: ProbeReport) -> Self {
+        match value {
+            ProbeReport::V1(r) => r,
+        }
+    }
+}
+
+#[cfg(test)]
+#[cfg_attr(coverage_nightly, coverage(off))]
+mod test {
+    use super::{ProbeReport, ProbeReportV1};
+    use crate::protocol::session::prelude::*;
+
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn wire_marshalling_probe_report() {
+        let report = ProbeReport::V1(ProbeReportV1 {
+            received: Uint(1234),
+            elapsed_us: Uint(5678),
+        });
+        let wire = report.to_vec().unwrap();
+        let deser = ProbeReport::from_slice(&wire).unwrap();
+        assert_eq!(report, deser);
+    }
+}
@@ -111,10 +111,7 @@ impl ListData {
             // Add 7 bytes to allow for the encoded size of the array length to grow to 2^63, which is obviously more than we will ever need
             let mut current_size = working.encoded_size()? + 7;
 
-            loop {
-                let Some(front) = input.pop_front() else {
-                    break;
-                };
+            while let Some(front) = input.pop_front() {
                 let entry_size = front.encoded_size()?;
                 if current_size + entry_size > max_size {
                     // Oops! It's too big. Put it back and finish this output packet.
@@ -159,16 +156,43 @@ pub struct ListEntry {
     /// Additional metadata for the entry as required.
     ///
     /// Currently supported: [`MetadataAttr::ModeBits`] on directories.
+    /// In response to [`Command::Stat`], [`MetadataAttr::ModeBits`], [`MetadataAttr::AccessTime`]
+    /// and [`MetadataAttr::ModificationTime`] on files and directories alike.
+    /// [`MetadataAttr::SymlinkTarget`] on symbolic links, which are only reported as such on request.
+    /// [`MetadataAttr::HardLinkTarget`] on further names for a file already listed, likewise.
+    /// [`MetadataAttr::AccessTime`] and [`MetadataAttr::ModificationTime`] on directories, likewise.
     pub attributes: Vec<TaggedData<MetadataAttr>>,
 }
 
-/// This struct is not currently encoded on the wire directly, but it implements [`ProtocolMessage`]
-/// so its encoded size can be calculated conveniently.
+/// This struct is encoded on the wire directly in response to [`Command::Stat`].
+/// Within [`ListData`], its encoded size is used to split up large listings.
 impl ProtocolMessage for ListEntry {}
 
+impl ListEntry {
+    /// If this entry is a symbolic link, returns its target
+    #[must_use]
+    pub fn symlink_target(&self) -> Option<&str> {
+        self.attributes
+            .find_tag(MetadataAttr::SymlinkTarget)
+            .and_then(Variant::as_str)
+    }
+
+    /// If this entry is a hard link to an earlier entry, returns the name of that entry
+    #[must_use]
+    pub fn hard_link_target(&self) -> Option<&str> {
+        self.attributes
+            .find_tag(MetadataAttr::HardLinkTarget)
+            .and_then(Variant::as_str)
+    }
+}
+
 impl Display for ListEntry {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        if self.directory {
+        if let Some(target) = self.symlink_target() {
+            write!(f, "<LNK> {} -> {target}", self.name)
+        } else if let Some(target) = self.hard_link_target() {
+            write!(f, "<HLN> {} => {target}", self.name)
+        } else if self.directory {
             let mode = self.attributes.find_tag(MetadataAttr::ModeBits);
             if let Some(mode) = mode {
                 write!(f, "<DIR> {} mode={:o}", self.name, mode.coerce_unsigned())
@@ -188,10 +212,21 @@ impl From<walkdir::DirEntry> for ListEntry {
         if directory && let Ok(meta) = value.metadata() {
             attributes.push(MetadataAttr::new_mode(meta.mode()));
         }
+        // We only see links here if the walk is not following them
+        if value.file_type().is_symlink()
+            && let Ok(target) = std::fs::read_link(value.path())
+        {
+            attributes.push(MetadataAttr::SymlinkTarget.with_str(target.to_string_lossy()));
+        }
+        let size = if value.file_type().is_symlink() {
+            0
+        } else {
+            value.metadata().map_or(0, |m| m.len())
+        };
         Self {
             name: value.path().to_string_lossy().to_string(), // relative to root!
             directory,
-            size: Uint(value.metadata().map_or(0, |m| m.len())),
+            size: Uint(size),
             attributes,
         }
     }
@@ -231,6 +266,7 @@ pub enum Status {
     ItIsAFile = 8,
     UnknownError = 9,
     EncodingFailed = 10,
+    ChecksumMismatch = 11,
 }
 
 impl From<Status> for Uint {
@@ -392,6 +428,31 @@ mod test {
         assert_contains!(str, "bbb");
     }
 
+    #[test]
+    fn list_entry_symlink() {
+        let entry = ListEntry {
+            name: "lnk".to_string(),
+            directory: false,
+            size: Uint(0),
+            attributes: vec![MetadataAttr::SymlinkTarget.with_str("../target")],
+        };
+        assert_eq!(entry.symlink_target(), Some("../target"));
+        assert_eq!(entry.to_string(), "<LNK> lnk -> ../target");
+    }
+
+    #[test]
+    fn list_entry_hard_link() {
+        let entry = ListEntry {
+            name: "d/second".to_string(),
+            directory: false,
+            size: Uint(5),
+            attributes: vec![MetadataAttr::HardLinkTarget.with_str("d/first")],
+        };
+        assert_eq!(entry.hard_link_target(), Some("d/first"));
+        assert_eq!(entry.symlink_target(), None);
+        assert_eq!(entry.to_string(), "<HLN> d/second => d/first");
+    }
+
     #[test]
     fn list_split_join() {
         let mut entries = vec![];
@@ -8,11 +8,43 @@ use crate::{
         common::{ReceivingStream as QcpRS, SendReceivePair, SendingStream as QcpSS},
         control::Compatibility,
     },
+    transport::PATH_CHECK_INTERVAL,
+    util::rate_limit::RateLimiter,
 };
 
 use async_trait::async_trait;
 use quinn::ConnectionStats;
-use tracing::{debug, error, trace};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tracing::{debug, error, info, trace};
+
+/// What we know about a connection once it has finished
+#[derive(Debug, Default)]
+pub(super) struct ConnectionSummary {
+    pub(super) stats: ConnectionStats,
+    /// Number of times the client's address changed
+    pub(super) migrations: u64,
+}
+
+/// Tracks changes to the remote address of a connection
+struct MigrationCounter {
+    address: SocketAddr,
+    count: u64,
+}
+
+impl MigrationCounter {
+    fn new(address: SocketAddr) -> Self {
+        Self { address, count: 0 }
+    }
+
+    fn check(&mut self, address: SocketAddr) {
+        if address != self.address {
+            info!("client address changed from {} to {address}", self.address);
+            self.address = address;
+            self.count += 1;
+        }
+    }
+}
 
 #[async_trait]
 trait Connection<SS: QcpSS, RS: QcpRS> {
@@ -44,23 +76,33 @@ pub(super) async fn handle_incoming(
     i: quinn::Incoming,
     compat: Compatibility,
     config: &Configuration,
-) -> anyhow::Result<ConnectionStats> {
-    handle_inner(i.await?, compat, config).await
+    rate_limiter: Option<Arc<RateLimiter>>,
+) -> anyhow::Result<ConnectionSummary> {
+    handle_inner(i.await?, compat, config, rate_limiter).await
 }
 
 async fn handle_inner<SS: QcpSS + 'static, RS: QcpRS + 'static, C: Connection<SS, RS>>(
     connection: C,
     compat: Compatibility,
     config: &Configuration,
-) -> anyhow::Result<ConnectionStats> {
+    rate_limiter: Option<Arc<RateLimiter>>,
+) -> anyhow::Result<ConnectionSummary> {
     debug!(
         "accepted QUIC connection from {}",
         connection.remote_address()
     );
+    let mut migrations = MigrationCounter::new(connection.remote_address());
+    let mut path_check = tokio::time::interval(PATH_CHECK_INTERVAL);
 
     async {
         loop {
-            let stream = connection.accept_bi().await;
+            let stream = tokio::select! {
+                s = connection.accept_bi() => s,
+                _ = path_check.tick() => {
+                    migrations.check(connection.remote_address());
+                    continue;
+                }
+            };
             let sp = match stream {
                 Err(quinn::ConnectionError::ApplicationClosed { .. }) => {
                     // we're closing down
@@ -79,15 +121,20 @@ async fn handle_inner<SS: QcpSS + 'static, RS: QcpRS + 'static, C: Connection<SS
             };
             trace!("opened stream");
             let cfg = config.clone();
+            let rate_limiter = rate_limiter.clone();
             let _j = tokio::spawn(async move {
-                if let Err(e) = handle_stream(sp, compat, &cfg).await {
+                if let Err(e) = handle_stream(sp, compat, &cfg, rate_limiter).await {
                     error!("stream handler failed: {e}");
                 }
             });
         }
     }
     .await?;
-    Ok(connection.stats())
+    migrations.check(connection.remote_address());
+    Ok(ConnectionSummary {
+        stats: connection.stats(),
+        migrations: migrations.count,
+    })
 }
 
 #[cfg(test)]
@@ -98,7 +145,7 @@ mod tests {
     use crate::Configuration;
     use crate::{protocol::control::Compatibility, server::connection::handle_inner};
 
-    use super::Connection;
+    use super::{Connection, MigrationCounter};
 
     use assertables::assert_contains;
     use async_trait::async_trait;
@@ -154,9 +201,14 @@ mod tests {
     #[tokio::test]
     async fn timeout() {
         let mc = MockConnection::err(quinn::ConnectionError::TimedOut);
-        let e = handle_inner(mc, Compatibility::Level(1), Configuration::system_default())
-            .await
-            .unwrap_err();
+        let e = handle_inner(
+            mc,
+            Compatibility::Level(1),
+            Configuration::system_default(),
+            None,
+        )
+        .await
+        .unwrap_err();
         assert_contains!(e.to_string(), "timed out");
     }
     #[tokio::test]
@@ -166,10 +218,16 @@ mod tests {
             frame_type: None,
             reason: "no".into(),
         }));
-        let s = handle_inner(mc, Compatibility::Level(1), Configuration::system_default())
-            .await
-            .unwrap();
-        assert_eq!(s.path.sent_packets, 0);
+        let s = handle_inner(
+            mc,
+            Compatibility::Level(1),
+            Configuration::system_default(),
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(s.stats.path.sent_packets, 0);
+        assert_eq!(s.migrations, 0);
     }
 
     #[tokio::test]
@@ -178,9 +236,29 @@ mod tests {
             ok_count: 1.into(),
             ..Default::default()
         };
-        let s = handle_inner(mc, Compatibility::Level(1), Configuration::system_default())
-            .await
-            .unwrap();
-        assert_eq!(s.path.sent_packets, 0);
+        let s = handle_inner(
+            mc,
+            Compatibility::Level(1),
+            Configuration::system_default(),
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(s.stats.path.sent_packets, 0);
+        assert_eq!(s.migrations, 0);
+    }
+
+    #[test]
+    fn migration_counter() {
+        let addr1 = SocketAddrV4::new(Ipv4Addr::LOCALHOST, 8765).into();
+        let addr2 = SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 1), 4321).into();
+        let mut uut = MigrationCounter::new(addr1);
+        uut.check(addr1);
+        assert_eq!(uut.count, 0);
+        uut.check(addr2);
+        uut.check(addr2);
+        assert_eq!(uut.count, 1);
+        uut.check(addr1);
+        assert_eq!(uut.count, 2);
     }
 }
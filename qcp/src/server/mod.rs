@@ -5,14 +5,15 @@ use crate::cli::styles::use_colours;
 use crate::config::Manager;
 use crate::control::ControlChannelServerInterface;
 use crate::protocol::common::{ReceivingStream, SendingStream};
+use crate::util::rate_limit::RateLimiter;
 use crate::util::setup_tracing;
+use crate::util::stats::merge_connection_stats;
 
 use anyhow::Context as _;
 use human_repr::HumanDuration;
-use tokio::sync::oneshot;
 use tokio::task::JoinSet;
 use tokio::time::timeout;
-use tracing::{debug, error, info, trace, warn};
+use tracing::{debug, error, info, trace};
 
 mod connection;
 mod connection_info;
@@ -51,50 +52,59 @@ async fn server_main_inner<
         .run_server(remote_ip, manager, setup_tracing, use_colours(), None)
         .await?;
     let _span = tracing::error_span!("[Server]").entered();
-    let endpoint = result.endpoint;
+    let endpoints = result.endpoints;
+    let config = result.config;
     let compat = control.compat();
+    // The rate limit applies to the session as a whole, so all connections share it
+    let rate_limiter = RateLimiter::shared(config.limit_rate_tx);
 
     let mut tasks = JoinSet::new();
 
     // Main loop:
-    // Wait for a successful connection OR timeout OR for stdin to be closed (implicitly handled).
-    // We have tight control over what we expect (TLS peer certificate/name) so only need to handle one successful connection,
-    // but a timeout is useful to give the user a cue that UDP isn't getting there.
+    // On each endpoint, wait for a successful connection OR timeout OR for stdin to be closed (implicitly handled).
+    // We have tight control over what we expect (TLS peer certificate/name) so only need to handle one successful connection
+    // per endpoint, but a timeout is useful to give the user a cue that UDP isn't getting there.
     trace!("waiting for QUIC");
-    let (stats_tx, mut stats_rx) = oneshot::channel();
-    if let Some(conn) = timeout(result.config.timeout_duration(), endpoint.accept())
-        .await
-        .context("Timed out waiting for QUIC connection")?
-    {
-        let _ = tasks.spawn(async move {
-            let result = connection::handle_incoming(conn, compat, &result.config).await;
-            match result {
-                Err(e) => error!("inward stream failed: {reason}", reason = e.to_string()),
-                Ok(conn_stats) => {
-                    let _ = stats_tx.send(conn_stats).inspect_err(|_| {
-                        warn!("unable to pass connection stats; possible logic error");
-                    });
-                }
-            }
-            trace!("connection completed");
-        });
-    } else {
-        info!("Endpoint was unexpectedly closed");
+    for endpoint in &endpoints {
+        if let Some(conn) = timeout(config.timeout_duration(), endpoint.accept())
+            .await
+            .context("Timed out waiting for QUIC connection")?
+        {
+            let config = config.clone();
+            let rate_limiter = rate_limiter.clone();
+            let _ = tasks.spawn(async move {
+                let result = connection::handle_incoming(conn, compat, &config, rate_limiter).await;
+                trace!("connection completed");
+                result
+                    .inspect_err(|e| {
+                        error!("inward stream failed: {reason}", reason = e.to_string());
+                    })
+                    .ok()
+            });
+        } else {
+            info!("Endpoint was unexpectedly closed");
+        }
     }
 
     // Graceful closedown. Wait for all connections and streams to finish.
     trace!("waiting for completion");
-    let _ = tasks.join_all().await;
-    endpoint.close(1u8.into(), "finished".as_bytes());
-    endpoint.wait_idle().await;
-    let stats = stats_rx.try_recv().unwrap_or_default();
+    let summaries: Vec<_> = tasks.join_all().await.into_iter().flatten().collect();
+    for endpoint in &endpoints {
+        endpoint.close(1u8.into(), "finished".as_bytes());
+    }
+    for endpoint in &endpoints {
+        endpoint.wait_idle().await;
+    }
+    let stats: Vec<_> = summaries.iter().map(|s| s.stats).collect();
+    let stats = &merge_connection_stats(&stats);
+    let migrations = summaries.iter().map(|s| s.migrations).sum();
 
     debug!(
         "Remote stats: final mtu={pmtu}, rtt={rtt}",
         pmtu = stats.path.current_mtu,
         rtt = stats.path.rtt.human_duration()
     );
-    control.send_closedown_report(&stats).await?;
+    control.send_closedown_report(stats, migrations).await?;
     trace!("finished");
     Ok(())
 }
@@ -157,14 +167,14 @@ mod test {
 
                 Ok(ServerResult {
                     config: mgr.get::<Configuration>().unwrap(),
-                    endpoint,
+                    endpoints: vec![endpoint],
                 })
             });
         let _ = mock_control
             .expect_send_closedown_report()
-            .with(predicate::always())
+            .with(predicate::always(), predicate::eq(0))
             .times(1)
-            .returning(|_| Ok(()));
+            .returning(|_, _| Ok(()));
         let _ = mock_control
             .expect_compat()
             .times(1)
@@ -2,12 +2,14 @@
 // (c) 2024 Ross Younger
 
 use std::io::ErrorKind;
+use std::sync::Arc;
 
 use crate::protocol::common::{
     ProtocolMessage as _, ReceivingStream, SendReceivePair, SendingStream,
 };
 use crate::protocol::control::Compatibility;
 use crate::protocol::session::Command;
+use crate::util::rate_limit::RateLimiter;
 
 use tracing::{Instrument as _, trace, trace_span};
 
@@ -15,6 +17,7 @@ pub(crate) async fn handle_stream<W, R>(
     mut sp: SendReceivePair<W, R>,
     compat: Compatibility,
     config: &crate::config::Configuration,
+    rate_limiter: Option<Arc<RateLimiter>>,
 ) -> anyhow::Result<()>
 where
     R: ReceivingStream + 'static, // AsyncRead + Unpin + Send,
@@ -36,6 +39,7 @@ where
     };
 
     let (mut handler, span_info) = session::factory::command_handler(sp, packet, compat, config);
+    handler.set_rate_limiter(rate_limiter);
     let span = trace_span!(
         "handler",
         cmd = span_info.name,
@@ -79,6 +83,7 @@ mod tests {
             SendReceivePair::from((out_write, mock_recv)),
             Compatibility::Level(compat),
             Configuration::system_default(),
+            None,
         )
         .await
         .unwrap();
@@ -1,14 +1,26 @@
 //! Common functions within the session protocol
 // (c) 2024-5 Ross Younger
 
-use std::io::ErrorKind;
-use tokio::io::AsyncWriteExt;
+use std::io::{ErrorKind, SeekFrom};
+use std::ops::Range;
+use std::path::Path;
+use tokio::fs::File as TokioFile;
+use tokio::io::{AsyncRead, AsyncSeekExt as _, AsyncWrite, AsyncWriteExt};
+use tracing::debug;
 
+use crate::config::{Configuration, structure::MAXIMUM_COMPRESSION_LEVEL};
 use crate::protocol::{
-    common::ProtocolMessage as _,
-    session::{Response, ResponseV1, Status},
-    {TaggedData, Variant},
+    FindTag as _,
+    common::ProtocolMessage,
+    compat::Feature,
+    control::Compatibility,
+    session::{
+        CommandParam, FileTrailer, FileTrailerV2, MetadataAttr, Response, ResponseV1, Status,
+    },
+    {DataTag as _, TaggedData, Variant},
 };
+use crate::util::compression::{self, PayloadCounts};
+use crate::util::rate_limit::{Paced, RateLimiter};
 
 /// Sends a response message
 async fn send_response<W>(send: &mut W, status: Status, message: Option<&str>) -> anyhow::Result<()>
@@ -33,6 +45,14 @@ where
         .await
 }
 
+/// Helper function for sending an OK response which carries a message, for example a warning
+pub(super) async fn send_ok_with_message<W>(send: &mut W, message: &str) -> anyhow::Result<()>
+where
+    W: AsyncWriteExt + std::marker::Unpin + Send,
+{
+    send_response(send, Status::Ok, Some(message)).await
+}
+
 pub(crate) fn io_error_to_status(io: &std::io::Error) -> (Status, Option<String>) {
     match io.kind() {
         ErrorKind::NotFound => (Status::FileNotFound, None),
@@ -75,6 +95,279 @@ impl FindOption for Vec<TaggedData<crate::protocol::session::CommandParam>> {
     }
 }
 
+/// Determines the byte range requested by [`CommandParam::RangeOffset`] and [`CommandParam::RangeLength`], if any.
+///
+/// The range is clamped to the end of the file.
+pub(crate) fn requested_range(
+    options: &Vec<TaggedData<CommandParam>>,
+    file_len: u64,
+) -> Option<Range<u64>> {
+    let offset = options
+        .find_option(CommandParam::RangeOffset)?
+        .coerce_unsigned()
+        .min(file_len);
+    let length = options
+        .find_option(CommandParam::RangeLength)
+        .map_or(u64::MAX, Variant::coerce_unsigned);
+    Some(offset..offset.saturating_add(length).min(file_len))
+}
+
+/// Creates the command options which request a byte range of a file
+pub(crate) fn range_options(range: &Range<u64>) -> [TaggedData<CommandParam>; 2] {
+    [
+        CommandParam::RangeOffset.with_unsigned(range.start),
+        CommandParam::RangeLength.with_unsigned(range.end - range.start),
+    ]
+}
+
+/// Copies a file payload from `reader` to `writer`.
+///
+/// If the peer supports [`Feature::CHECKSUM`], also computes the SHA-256 digest of the data.
+/// Returns the number of bytes copied, and the digest (if computed).
+pub(crate) async fn copy_payload<R, W>(
+    reader: &mut R,
+    writer: &mut W,
+    buffer_size: u64,
+    compat: Compatibility,
+) -> std::io::Result<(u64, Option<Vec<u8>>)>
+where
+    R: AsyncRead + Unpin + ?Sized,
+    W: AsyncWrite + Unpin + ?Sized,
+{
+    if compat.supports(Feature::CHECKSUM) {
+        let (n, digest) = crate::util::io::copy_large_hashed(reader, writer, buffer_size).await?;
+        Ok((n, Some(digest)))
+    } else {
+        Ok((
+            crate::util::io::copy_large(reader, writer, buffer_size).await?,
+            None,
+        ))
+    }
+}
+
+/// Sends a file payload from `reader` to `writer`, compressing it at the given level if requested.
+///
+/// If the peer supports [`Feature::CHECKSUM`], also computes the SHA-256 digest of the (uncompressed) data.
+///
+/// If there is a `rate_limiter`, the data is paced according to it.
+pub(crate) async fn send_payload<R, W>(
+    reader: &mut R,
+    writer: &mut W,
+    buffer_size: u64,
+    compat: Compatibility,
+    compression: Option<i32>,
+    rate_limiter: Option<&RateLimiter>,
+) -> std::io::Result<(PayloadCounts, Option<Vec<u8>>)>
+where
+    R: AsyncRead + Unpin + ?Sized,
+    W: AsyncWrite + Unpin + ?Sized,
+{
+    if let Some(limiter) = rate_limiter {
+        let mut writer = Paced::new(writer, limiter);
+        send_payload_unpaced(reader, &mut writer, buffer_size, compat, compression).await
+    } else {
+        send_payload_unpaced(reader, writer, buffer_size, compat, compression).await
+    }
+}
+
+async fn send_payload_unpaced<R, W>(
+    reader: &mut R,
+    writer: &mut W,
+    buffer_size: u64,
+    compat: Compatibility,
+    compression: Option<i32>,
+) -> std::io::Result<(PayloadCounts, Option<Vec<u8>>)>
+where
+    R: AsyncRead + Unpin + ?Sized,
+    W: AsyncWrite + Unpin + ?Sized,
+{
+    let Some(level) = compression else {
+        let (n, digest) = copy_payload(reader, writer, buffer_size, compat).await?;
+        return Ok((
+            PayloadCounts {
+                logical: n,
+                wire: n,
+            },
+            digest,
+        ));
+    };
+    let mut context = compat
+        .supports(Feature::CHECKSUM)
+        .then(|| ring::digest::Context::new(&ring::digest::SHA256));
+    let counts = compression::send_compressed(reader, writer, level, context.as_mut()).await?;
+    Ok((counts, context.map(|c| c.finish().as_ref().to_vec())))
+}
+
+/// Receives a file payload of `len` bytes from `reader` to `writer`.
+///
+/// This is the counterpart of [`send_payload`]; `compressed` says whether the sender is compressing.
+/// If fewer than `len` bytes of uncompressed data arrive, returns the number that did.
+pub(crate) async fn receive_payload<R, W>(
+    reader: &mut R,
+    writer: &mut W,
+    len: u64,
+    buffer_size: u64,
+    compat: Compatibility,
+    compressed: bool,
+) -> std::io::Result<(PayloadCounts, Option<Vec<u8>>)>
+where
+    R: AsyncRead + Unpin + ?Sized,
+    W: AsyncWrite + Unpin + ?Sized,
+{
+    use tokio::io::AsyncReadExt as _;
+    if !compressed {
+        let (n, digest) = copy_payload(&mut reader.take(len), writer, buffer_size, compat).await?;
+        return Ok((
+            PayloadCounts {
+                logical: n,
+                wire: n,
+            },
+            digest,
+        ));
+    }
+    let mut context = compat
+        .supports(Feature::CHECKSUM)
+        .then(|| ring::digest::Context::new(&ring::digest::SHA256));
+    let counts = compression::receive_compressed(reader, writer, len, context.as_mut()).await?;
+    Ok((counts, context.map(|c| c.finish().as_ref().to_vec())))
+}
+
+/// Determines the compression level requested by [`CommandParam::Compression`], if any.
+pub(crate) fn requested_compression(options: &Vec<TaggedData<CommandParam>>) -> Option<i32> {
+    let level = options
+        .find_option(CommandParam::Compression)?
+        .coerce_unsigned()
+        .min(MAXIMUM_COMPRESSION_LEVEL.into());
+    Some(i32::try_from(level).unwrap_or_default())
+}
+
+/// Creates the command option which requests compression, if it is configured and the peer supports it
+pub(crate) fn compression_option(
+    config: &Configuration,
+    compat: Compatibility,
+) -> Option<TaggedData<CommandParam>> {
+    (config.compression > 0 && compat.supports(Feature::COMPRESSION))
+        .then(|| CommandParam::Compression.with_unsigned(config.compression))
+}
+
+/// Sends a file trailer, split across as many trailers as needed.
+///
+/// See [`MetadataAttr::MoreTrailers`].
+pub(crate) async fn send_trailer<W>(send: &mut W, trailer: FileTrailer) -> anyhow::Result<()>
+where
+    W: AsyncWriteExt + std::marker::Unpin + Send,
+{
+    let FileTrailer::V2(trailer) = trailer else {
+        return trailer.to_writer_async_framed(send).await;
+    };
+    for part in trailer.split_by_size(FileTrailer::WIRE_ENCODING_LIMIT)? {
+        FileTrailer::V2(part).to_writer_async_framed(send).await?;
+    }
+    Ok(())
+}
+
+/// Receives a file trailer, joining it up with any further trailers that follow it.
+pub(crate) async fn receive_trailer<R>(recv: &mut R) -> anyhow::Result<FileTrailerV2>
+where
+    R: AsyncRead + std::marker::Unpin + Send,
+{
+    let first = FileTrailerV2::from(FileTrailer::from_reader_async_framed(recv).await?);
+    let more = first
+        .metadata
+        .find_tag(MetadataAttr::MoreTrailers)
+        .map_or(0, Variant::coerce_unsigned);
+    if more == 0 {
+        return Ok(first);
+    }
+    anyhow::ensure!(
+        more <= FileTrailerV2::MAX_TRAILERS,
+        "peer sent too many file trailers ({more})"
+    );
+    let mut parts = vec![first];
+    for _ in 0..more {
+        parts.push(FileTrailer::from_reader_async_framed(recv).await?.into());
+    }
+    Ok(FileTrailerV2::join(parts))
+}
+
+/// Checks the digest in a file trailer (if there is one) against that of the data we received.
+///
+/// If either side did not compute a digest, there is nothing to check.
+pub(crate) fn verify_digest(trailer: &FileTrailerV2, ours: Option<&Vec<u8>>) -> Result<(), Status> {
+    let theirs = trailer
+        .metadata
+        .find_tag(MetadataAttr::Sha256Digest)
+        .and_then(Variant::as_bytes_ref);
+    match (theirs, ours) {
+        (Some(theirs), Some(ours)) if theirs != ours => {
+            debug!(
+                "digest mismatch: sent {}, received {}",
+                hex::encode(theirs),
+                hex::encode(ours)
+            );
+            Err(Status::ChecksumMismatch)
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Describes the data we already hold at `path`, for resuming a transfer.
+///
+/// The result is empty if there is no regular file at `path`.
+/// See [`CommandParam::ResumeFrom`].
+pub(crate) async fn resume_report(
+    path: &Path,
+    buffer_size: u64,
+) -> std::io::Result<Vec<TaggedData<CommandParam>>> {
+    let mut file = match TokioFile::open(path).await {
+        Ok(f) => f,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e),
+    };
+    let meta = file.metadata().await?;
+    if !meta.is_file() {
+        return Ok(vec![]);
+    }
+    let hash = crate::util::io::hash_prefix(&mut file, meta.len(), buffer_size).await?;
+    Ok(vec![
+        CommandParam::ResumeFrom.with_unsigned(meta.len()),
+        CommandParam::ResumeHash.with_bytes(hash),
+    ])
+}
+
+/// Determines the offset from which we can resume sending `file`, given the receiver's report of what it holds.
+///
+/// If the receiver's data does not match ours, returns 0.
+/// On return, the file is positioned at the returned offset.
+pub(crate) async fn resume_offset(
+    report: &Vec<TaggedData<CommandParam>>,
+    file: &mut TokioFile,
+    file_len: u64,
+    buffer_size: u64,
+) -> std::io::Result<u64> {
+    let held = report
+        .find_option(CommandParam::ResumeFrom)
+        .map_or(0, Variant::coerce_unsigned);
+    let mut offset = 0;
+    if held > 0 && held <= file_len {
+        if let Some(theirs) = report
+            .find_option(CommandParam::ResumeHash)
+            .and_then(Variant::as_bytes_ref)
+        {
+            let ours = crate::util::io::hash_prefix(file, held, buffer_size).await?;
+            if ours == *theirs {
+                offset = held;
+            } else {
+                debug!("resume: data held by receiver does not match");
+            }
+        } else {
+            offset = held;
+        }
+    }
+    let _ = file.seek(SeekFrom::Start(offset)).await?;
+    Ok(offset)
+}
+
 #[cfg(test)]
 #[cfg_attr(coverage_nightly, coverage(off))]
 mod tests {
@@ -135,6 +428,83 @@ mod tests {
         );
     }
 
+    #[test]
+    fn requested_range() {
+        use super::{range_options, requested_range};
+        use crate::protocol::{DataTag as _, session::CommandParam};
+
+        assert_eq!(requested_range(&vec![], 100), None);
+        assert_eq!(
+            requested_range(&range_options(&(10..20)).into(), 100),
+            Some(10..20)
+        );
+        // Clamped to the end of the file
+        assert_eq!(
+            requested_range(&range_options(&(90..120)).into(), 100),
+            Some(90..100)
+        );
+        assert_eq!(
+            requested_range(&range_options(&(120..130)).into(), 100),
+            Some(100..100)
+        );
+        // No length means to the end of the file
+        let opts = vec![CommandParam::RangeOffset.with_unsigned(40u64)];
+        assert_eq!(requested_range(&opts, 100), Some(40..100));
+    }
+
+    #[test]
+    fn verify_digest() {
+        use super::verify_digest;
+        use crate::protocol::{
+            DataTag as _,
+            session::{FileTrailerV2, MetadataAttr},
+        };
+
+        let ours = vec![1u8, 2, 3];
+        let trailer = |digest: &[u8]| FileTrailerV2 {
+            metadata: vec![MetadataAttr::Sha256Digest.with_bytes(digest)],
+        };
+        assert!(verify_digest(&trailer(&[1, 2, 3]), Some(&ours)).is_ok());
+        assert_eq!(
+            verify_digest(&trailer(&[1, 2, 4]), Some(&ours)),
+            Err(Status::ChecksumMismatch)
+        );
+        // Nothing to compare against
+        assert!(verify_digest(&trailer(&[1, 2, 4]), None).is_ok());
+        assert!(verify_digest(&FileTrailerV2::default(), Some(&ours)).is_ok());
+    }
+
+    #[tokio::test]
+    async fn resume() {
+        use super::{resume_offset, resume_report};
+        use littertray::LitterTray;
+        use tokio::io::AsyncReadExt as _;
+
+        LitterTray::try_with_async(async |tray| {
+            let _ = tray.create_text("source", "0123456789")?;
+            let _ = tray.create_text("good", "01234")?;
+            let _ = tray.create_text("bad", "01x34")?;
+            let _ = tray.create_text("long", "0123456789ab")?;
+            let _ = tray.make_dir("dir")?;
+
+            assert!(resume_report("nonexistent".as_ref(), 4).await?.is_empty());
+            assert!(resume_report("dir".as_ref(), 4).await?.is_empty());
+
+            for (dest, expected) in [("good", 5_usize), ("bad", 0), ("long", 0), ("dir", 0)] {
+                let report = resume_report(dest.as_ref(), 4).await?;
+                let mut file = tokio::fs::File::open("source").await?;
+                let offset = resume_offset(&report, &mut file, 10, 4).await?;
+                assert_eq!(offset, expected as u64, "{dest}");
+                let mut rest = String::new();
+                let _ = file.read_to_string(&mut rest).await?;
+                assert_eq!(rest, &"0123456789"[expected..]);
+            }
+            Ok(())
+        })
+        .await
+        .unwrap();
+    }
+
     #[test]
     fn unknown_error_status() {
         #[derive(thiserror::Error, Debug, derive_more::Display)]
@@ -2,6 +2,7 @@
 // (c) 2025 Ross Younger
 
 use crate::protocol::common::{ReceivingStream, SendReceivePair, SendingStream};
+use crate::protocol::compat::Feature;
 use crate::protocol::control::Compatibility;
 use crate::protocol::session::{
     Command, CommandParam, Get2Args, GetArgs, ListArgs, Put2Args, PutArgs,
@@ -13,8 +14,9 @@ use crate::session::handler::UI;
 
 use super::SessionCommandImpl;
 use super::handler::{
-    CreateDirectoryHandler, GetHandler, ListingHandler, PutHandler, SessionCommand,
-    SetMetadataHandler,
+    CreateDirectoryHandler, CreateHardLinkHandler, CreateSymlinkHandler, FreeSpaceHandler,
+    GetHandler, ListingHandler, ProbeHandler, PutHandler, SessionCommand, SetMetadataHandler,
+    StatHandler,
 };
 
 /// Span information for a command (used for tracing)
@@ -28,10 +30,14 @@ pub(crate) struct SpanInfo {
 pub(crate) enum TransferPhase {
     /// Pre-transfer phase: list directory contents (remote source only)
     Pre,
-    /// Transfer phase: GET, PUT, or CREATE_DIRECTORY
+    /// Transfer phase: GET, PUT, CREATE_DIRECTORY or CREATE_SYMLINK
     Transfer,
     /// Post-transfer phase: set metadata on remote destination (remote dest, preserve mode, directory only)
     Post,
+    /// Query the metadata of the remote path (source or destination, whichever is remote)
+    Stat,
+    /// Query the capacity of the filesystem holding the remote destination
+    FreeSpace,
 }
 
 /// Factory function to create the appropriate client-side command sender from a copy job spec.
@@ -69,6 +75,15 @@ pub(crate) fn client_sender<'a, S: SendingStream + 'static, R: ReceivingStream +
             if params.recurse {
                 options.push(CommandParam::Recurse.into());
             }
+            if !params.links.follows() && compat.supports(Feature::SYMLINKS) {
+                options.push(CommandParam::PreserveLinks.into());
+            }
+            if params.hard_links && compat.supports(Feature::HARD_LINKS) {
+                options.push(CommandParam::HardLinks.into());
+            }
+            if params.preserve.times && compat.supports(Feature::DIRECTORY_TIMES) {
+                options.push(CommandParam::PreserveMetadata.into());
+            }
             let args = Some(ListArgs {
                 path: path.clone(),
                 options,
@@ -80,10 +95,19 @@ pub(crate) fn client_sender<'a, S: SendingStream + 'static, R: ReceivingStream +
                 // Remote source: GET
                 let mut args = Get2Args::default();
                 args.filename.clone_from(&copy_spec.source.filename);
-                if copy_spec.preserve {
-                    args.options.push(CommandParam::PreserveMetadata.into());
+                args.options.extend(copy_spec.preserve.to_options(compat));
+                if let Some(range) = &copy_spec.range {
+                    args.options.extend(super::common::range_options(range));
                 }
                 xreturn!(GetHandler, "GETx", Some(args), src.clone())
+            } else if copy_spec.link_target.is_some() && compat.supports(Feature::SYMLINKS) {
+                // Local source, symbolic link: SYMLINK.
+                // If the remote can't do this, we fall back to sending what the link points to.
+                xreturn!(CreateSymlinkHandler, "SYMLINK", None, dest.clone())
+            } else if copy_spec.hard_link_target.is_some() && compat.supports(Feature::HARD_LINKS) {
+                // Local source, hard link to a file already sent: HARDLINK.
+                // If the remote can't do this, we fall back to sending the file again.
+                xreturn!(CreateHardLinkHandler, "HARDLINK", None, dest.clone())
             } else if copy_spec.directory {
                 // Local source, directory: MKDIR
                 xreturn!(CreateDirectoryHandler, "MKDIR", None, dest.clone())
@@ -96,6 +120,17 @@ pub(crate) fn client_sender<'a, S: SendingStream + 'static, R: ReceivingStream +
             // Post-transfer: set metadata on remote directory
             xreturn!(SetMetadataHandler, "SETMETA", None, dest.clone())
         }
+        TransferPhase::Stat => {
+            let path = if copy_spec.source.user_at_host.is_some() {
+                src.clone()
+            } else {
+                dest.clone()
+            };
+            xreturn!(StatHandler, "STAT", None, path)
+        }
+        TransferPhase::FreeSpace => {
+            xreturn!(FreeSpaceHandler, "FREESPACE", None, dest.clone())
+        }
     }
 }
 
@@ -157,6 +192,26 @@ pub(crate) fn command_handler<'a, S: SendingStream + 'static, R: ReceivingStream
             let path = args.path.clone();
             xreturn!(ListingHandler, "LS", Some(args), path)
         }
+        Command::Probe(args) => {
+            let duration = format!("{}ms", args.duration_ms.0);
+            xreturn!(ProbeHandler, "PROBE", Some(args), duration)
+        }
+        Command::Stat(args) => {
+            let path = args.path.clone();
+            xreturn!(StatHandler, "STAT", Some(args), path)
+        }
+        Command::FreeSpace(args) => {
+            let path = args.path.clone();
+            xreturn!(FreeSpaceHandler, "FREESPACE", Some(args), path)
+        }
+        Command::CreateSymlink(args) => {
+            let path = args.path.clone();
+            xreturn!(CreateSymlinkHandler, "SYMLINK", Some(args), path)
+        }
+        Command::CreateHardLink(args) => {
+            let path = args.path.clone();
+            xreturn!(CreateHardLinkHandler, "HARDLINK", Some(args), path)
+        }
     };
     (handler, span_info)
 }
@@ -0,0 +1,186 @@
+//! FreeSpace command
+// (c) 2025 Ross Younger
+
+use std::path::Path;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use serde_bare::Uint;
+use tokio::io::AsyncWriteExt as _;
+use tracing::trace;
+
+use crate::Parameters;
+use crate::os::{AbstractPlatform as _, DiskSpace, Platform};
+use crate::protocol::common::{ProtocolMessage as _, ReceivingStream, SendingStream};
+use crate::protocol::compat::Feature;
+use crate::protocol::session::{
+    Command, FreeSpaceArgs, FreeSpaceReport, FreeSpaceReportV1, Response, Status,
+};
+use crate::session::common::send_ok;
+use crate::session::handler::{CommandHandler, SessionCommandInner};
+use crate::session::{RequestResult, error_and_return};
+
+/// Reports on the capacity of the filesystem that holds `path`.
+///
+/// The path need not exist yet; if it does not, we report on its nearest existing ancestor.
+///
+/// Returns `Ok(None)` if this information is not available on this platform.
+pub(crate) fn disk_space(path: &str) -> std::io::Result<Option<DiskSpace>> {
+    let path = Path::new(path);
+    let existing = path
+        .ancestors()
+        .map(|p| {
+            if p.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                p
+            }
+        })
+        .find(|p| p.exists())
+        .unwrap_or(path);
+    Platform::disk_space(existing)
+}
+
+pub(crate) struct FreeSpaceHandler;
+
+#[async_trait]
+impl CommandHandler for FreeSpaceHandler {
+    type Args = FreeSpaceArgs;
+
+    async fn send_impl<'a, S: SendingStream, R: ReceivingStream>(
+        &mut self,
+        inner: &mut SessionCommandInner<'a, S, R>,
+        job: &crate::client::CopyJobSpec,
+        _params: Parameters,
+    ) -> Result<RequestResult> {
+        anyhow::ensure!(
+            inner.compat.supports(Feature::FREE_SPACE),
+            "Operation not supported by remote"
+        );
+        let stream = &mut inner.stream;
+        trace!("sending command");
+        let cmd = Command::FreeSpace(FreeSpaceArgs {
+            path: job.destination.filename.clone(),
+            options: vec![],
+        });
+        cmd.to_writer_async_framed(&mut stream.send).await?;
+        stream.send.flush().await?;
+
+        trace!("await response");
+        let response = Response::from_reader_async_framed(&mut stream.recv).await?;
+        if response.status() == Status::NotYetImplemented {
+            // The remote cannot tell
+            return Ok(RequestResult::default());
+        }
+        let _ = response.into_result()?;
+        let report = FreeSpaceReportV1::from(
+            FreeSpaceReport::from_reader_async_framed(&mut stream.recv).await?,
+        );
+        Ok(RequestResult {
+            space: Some(DiskSpace {
+                total: report.total.0,
+                available: report.available.0,
+            }),
+            ..Default::default()
+        })
+    }
+
+    async fn handle_impl<'a, S: SendingStream, R: ReceivingStream>(
+        &mut self,
+        inner: &mut SessionCommandInner<'a, S, R>,
+        args: &FreeSpaceArgs,
+    ) -> Result<()> {
+        let stream = &mut inner.stream;
+        let space = match disk_space(&args.path) {
+            Ok(Some(space)) => space,
+            Ok(None) => {
+                error_and_return!(stream, Status::NotYetImplemented);
+            }
+            Err(e) => {
+                error_and_return!(stream, e);
+            }
+        };
+        send_ok(&mut stream.send).await?;
+        FreeSpaceReport::V1(FreeSpaceReportV1 {
+            total: Uint(space.total),
+            available: Uint(space.available),
+        })
+        .to_writer_async_framed(&mut stream.send)
+        .await?;
+        stream.send.flush().await?;
+        trace!("complete");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+#[cfg_attr(coverage_nightly, coverage(off))]
+mod test {
+    use crate::{
+        Configuration, Parameters,
+        client::CopyJobSpec,
+        protocol::{
+            control::Compatibility,
+            session::Command,
+            test_helpers::{new_test_plumbing, read_from_stream},
+        },
+    };
+    use anyhow::{Result, bail};
+    use littertray::LitterTray;
+    use pretty_assertions::assert_eq;
+
+    use super::disk_space;
+
+    #[test]
+    fn nonexistent_path_reports_on_ancestor() {
+        LitterTray::try_with(|tray| {
+            let _ = tray.make_dir("d")?;
+            let here = disk_space("d")?.map(|s| s.total);
+            let there = disk_space("d/not/yet/created")?.map(|s| s.total);
+            assert_eq!(here, there);
+            assert_eq!(disk_space("")?.map(|s| s.total), here);
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn free_space_round_trip() -> Result<()> {
+        let (pipe1, mut pipe2) = new_test_plumbing();
+        let spec = CopyJobSpec::from_parts("local", "somehost:.", false, false).unwrap();
+        let params = Parameters::default();
+        let (mut sender, _) = crate::session::factory::client_sender(
+            pipe1,
+            &spec,
+            crate::session::factory::TransferPhase::FreeSpace,
+            Compatibility::Level(5),
+            &params,
+            None,
+            Configuration::system_default(),
+        );
+        let client_fut = sender.send(&spec, params);
+        tokio::pin!(client_fut);
+
+        let result = read_from_stream(&mut pipe2.recv, &mut client_fut).await;
+        let cmd = result.expect_left("client should not have completed early")?;
+        let Command::FreeSpace(ref args) = cmd else {
+            bail!("expected FreeSpace command");
+        };
+        assert_eq!(args.path, ".");
+
+        let (mut handler, _) = crate::session::factory::command_handler(
+            pipe2,
+            cmd,
+            Compatibility::Level(5),
+            Configuration::system_default(),
+        );
+        let (r1, r2) = tokio::join!(client_fut, handler.handle());
+        r2?;
+        // Available space may change under our feet, but the total should not
+        assert_eq!(
+            r1?.space.map(|s| s.total),
+            disk_space(".")?.map(|s| s.total)
+        );
+        Ok(())
+    }
+}
@@ -3,21 +3,26 @@
 
 use anyhow::{Context as _, Result};
 use async_trait::async_trait;
-use std::path::PathBuf;
+use std::{io::SeekFrom, path::PathBuf};
 use tokio::fs::File as TokioFile;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::io::{AsyncReadExt, AsyncSeekExt as _, AsyncWriteExt};
 use tokio::time::Instant;
-use tracing::trace;
+use tracing::{debug, trace, warn};
 
-use crate::Parameters;
 use crate::protocol::common::{ProtocolMessage, ReceivingStream, SendingStream};
 use crate::protocol::session::prelude::*;
-use crate::protocol::session::{
-    FileHeader, FileHeaderV2, FileTrailer, FileTrailerV2, Get2Args, GetArgs,
+use crate::protocol::session::{FileHeader, FileHeaderV2, FileTrailer, Get2Args, GetArgs};
+use crate::session::common::{
+    FindOption as _, compression_option, range_options, receive_payload, receive_trailer,
+    requested_compression, requested_range, resume_offset, resume_report, send_payload,
+    send_trailer, verify_digest,
 };
-use crate::session::common::FindOption as _;
 use crate::session::handler::{CommandHandler, SessionCommandInner};
 use crate::session::{CommandStats, RequestResult, error_and_return};
+use crate::util::IncomingFile;
+use crate::util::sparse::{ExtentReader, ExtentWriter, data_extents, extents_len};
+use crate::util::xattr::{XattrSelection, read_extended_attributes};
+use crate::{Parameters, PreserveSelection};
 
 // Extension trait!
 use crate::util::FileExt as _;
@@ -28,6 +33,7 @@ pub(crate) struct GetHandler;
 impl CommandHandler for GetHandler {
     type Args = Get2Args;
 
+    #[allow(clippy::too_many_lines)]
     async fn send_impl<'a, S: SendingStream, R: ReceivingStream>(
         &mut self,
         inner: &mut SessionCommandInner<'a, S, R>,
@@ -38,10 +44,26 @@ impl CommandHandler for GetHandler {
         let dest = &job.destination.filename;
 
         let real_start = Instant::now();
+        if job.range.is_some() {
+            anyhow::ensure!(
+                inner.compat.supports(Feature::RANGED_TRANSFER),
+                "Ranged transfers are not supported by remote"
+            );
+        }
+        let compression = compression_option(inner.config, inner.compat);
+        let compressed = compression.is_some();
         let cmd = if inner.compat.supports(Feature::GET2_PUT2) {
             let mut options = vec![];
-            if job.preserve {
-                options.push(CommandParam::PreserveMetadata.into());
+            options.extend(compression);
+            options.extend(job.preserve.to_options(inner.compat));
+            if inner.compat.supports(Feature::EXTENDED_ATTRIBUTES) {
+                options.extend(XattrSelection::from_params(&params).to_options());
+            }
+            if let Some(range) = &job.range {
+                options.extend(range_options(range));
+            } else if params.resume && inner.compat.supports(Feature::RESUME) {
+                options
+                    .extend(resume_report(&job.local_path(), inner.config.io_buffer_size).await?);
             }
             Command::Get2(Get2Args {
                 filename: filename.clone(),
@@ -65,7 +87,12 @@ impl CommandHandler for GetHandler {
         let header = FileHeader::from_reader_async_framed(&mut inner.stream.recv).await?;
         trace!("{header:?}");
         let header = FileHeaderV2::from(header);
-        let mut file = TokioFile::create_or_truncate(dest, &header).await?;
+        let extents = header.extents()?;
+        let payload_len = header.payload_len();
+        let whole_file = header.range().is_none() && payload_len == header.size.0;
+        // A transfer which may be resumed is written in place, so an interruption leaves something to resume from
+        let in_place = params.in_place || params.resume;
+        let (incoming, mut file) = IncomingFile::create(dest, &header, in_place).await?;
 
         // Now we know how much we're receiving, update the chrome.
         // File Trailers are currently 5-17 bytes on the wire; hardly material.
@@ -75,46 +102,74 @@ impl CommandHandler for GetHandler {
         // Therefore we incorporate time in flight so far to get the estimate closer to reality.
         let progress_bar = inner
             .ui
-            .progress_bar_for(job, header.size.0 + 17, params.quiet)?
+            .progress_bar_for(job, payload_len + 17, params.quiet)?
             .with_elapsed(Instant::now().duration_since(real_start));
 
         let mut meter = crate::client::meter::InstaMeterRunner::new(
             &progress_bar,
             Some(inner.spinner().clone()),
             inner.config.rx(),
+            inner.config.limit_rate_rx,
         );
         meter.start().await;
 
-        let inbound = progress_bar.wrap_async_read(&mut inner.stream.recv);
-
-        let mut inbound = inbound.take(header.size.0);
         trace!("payload");
-        let _ = crate::util::io::copy_large(&mut inbound, &mut file, inner.config.io_buffer_size)
-            .await?;
-        // Retrieve the stream from within the Take wrapper for further operations
-        let mut inbound = inbound.into_inner();
+        // The progress bar counts file data, so when compressing it has to watch the file rather than the stream.
+        let (counts, digest) = if compressed {
+            receive_payload(
+                &mut inner.stream.recv,
+                &mut progress_bar.wrap_async_write(ExtentWriter::new(&mut file, extents)),
+                payload_len,
+                inner.config.io_buffer_size,
+                inner.compat,
+                true,
+            )
+            .await?
+        } else {
+            receive_payload(
+                &mut progress_bar.wrap_async_read(&mut inner.stream.recv),
+                &mut ExtentWriter::new(&mut file, extents),
+                payload_len,
+                inner.config.io_buffer_size,
+                inner.compat,
+                false,
+            )
+            .await?
+        };
 
-        let trailer =
-            FileTrailerV2::from(FileTrailer::from_reader_async_framed(&mut inbound).await?);
+        let mut trailer = receive_trailer(&mut inner.stream.recv).await?;
         // Even if we only get the older V1 trailer, the server believes the file was sent correctly.
         trace!("{trailer:?}");
 
         // Note that the Quinn send stream automatically calls finish on drop.
         meter.stop().await;
         file.flush().await?;
+        verify_digest(&trailer, digest.as_ref())
+            .with_context(|| format!("GET {filename}: received data is corrupt"))?;
 
-        file = file.update_metadata(&trailer.metadata).await?;
+        // An older server may send more than we asked for
+        trailer.metadata.retain(|md| job.preserve.keeps(md));
+        let (file, warnings) = file
+            .update_metadata(&trailer.metadata, params.numeric_ids)
+            .await?;
+        for w in warnings {
+            warn!("{dest}: {w}");
+        }
         drop(file);
+        incoming.commit().await?;
 
         trace!("complete");
         progress_bar.finish_and_clear();
-        Ok(RequestResult::new(
-            CommandStats {
-                payload_bytes: header.size.0,
+        Ok(RequestResult {
+            stats: CommandStats {
+                payload_bytes: payload_len,
+                wire_bytes: counts.wire,
                 peak_transfer_rate: meter.peak(),
             },
-            None,
-        ))
+            // The digest only covers the whole file if we received all of it
+            digest: digest.filter(|_| whole_file),
+            ..Default::default()
+        })
     }
 
     async fn handle_impl<'a, S: SendingStream, R: ReceivingStream>(
@@ -135,6 +190,29 @@ impl CommandHandler for GetHandler {
         if file_original_meta.is_dir() {
             error_and_return!(stream, Status::ItIsADirectory);
         }
+        let mut range = requested_range(&args.options, file_original_meta.len());
+        if range.is_none() && args.options.find_option(CommandParam::ResumeFrom).is_some() {
+            let offset = match resume_offset(
+                &args.options,
+                &mut file,
+                file_original_meta.len(),
+                inner.config.io_buffer_size,
+            )
+            .await
+            {
+                Ok(o) => o,
+                Err(e) => error_and_return!(stream, e),
+            };
+            if offset > 0 {
+                debug!("resuming from offset {offset}");
+                range = Some(offset..file_original_meta.len());
+            }
+        }
+        if let Some(r) = &range
+            && let Err(e) = file.seek(SeekFrom::Start(r.start)).await
+        {
+            error_and_return!(stream, e);
+        }
 
         // We believe we can fulfil this request.
         trace!("responding OK");
@@ -142,28 +220,59 @@ impl CommandHandler for GetHandler {
 
         let protocol_filename = path.file_name().unwrap().to_str().unwrap(); // can't fail with the preceding checks
 
-        let hdr = FileHeader::for_file(compat, &file_original_meta, protocol_filename);
+        let (hdr, send_range) = if let Some(r) = &range {
+            (
+                FileHeader::for_file_range(&file_original_meta, protocol_filename, r),
+                r.clone(),
+            )
+        } else {
+            (
+                FileHeader::for_file(compat, &file_original_meta, protocol_filename),
+                0..file_original_meta.len(),
+            )
+        };
+        let extents = if compat.supports(Feature::SPARSE_FILES) {
+            data_extents(&path, &send_range).await
+        } else {
+            None
+        };
+        let payload_len = extents
+            .as_deref()
+            .map_or(send_range.end - send_range.start, extents_len);
+        let hdr = hdr.with_extents(extents.as_deref());
         trace!("{hdr:?}");
         hdr.to_writer_async_framed(&mut stream.send).await?;
 
-        trace!("sending file payload");
-        let result =
-            crate::util::io::copy_large(&mut file, &mut stream.send, inner.config.io_buffer_size)
-                .await;
-        anyhow::ensure!(result.is_ok(), "copy ended prematurely");
+        let compression = requested_compression(&args.options);
+        trace!("sending file payload (compression {compression:?})");
+        let mut file = ExtentReader::new(file, extents).take(payload_len);
+        let result = send_payload(
+            &mut file,
+            &mut stream.send,
+            inner.config.io_buffer_size,
+            compat,
+            compression,
+            inner.rate_limiter.as_deref(),
+        )
+        .await;
+        let Ok((sent, digest)) = result else {
+            anyhow::bail!("copy ended prematurely");
+        };
         anyhow::ensure!(
-            result.is_ok_and(|r| r == file_original_meta.len()),
+            sent.logical == payload_len,
             "logic error: file sent size doesn't match metadata"
         );
 
-        let preserve = args
-            .options
-            .find_option(CommandParam::PreserveMetadata)
-            .is_some();
+        let preserve = PreserveSelection::from_options(&args.options);
 
-        let trl = FileTrailer::for_file(compat, &file_original_meta, preserve);
+        let mut trl = FileTrailer::for_file(compat, &file_original_meta, preserve, digest).await;
+        if let FileTrailer::V2(t) = &mut trl {
+            t.metadata.extend(
+                read_extended_attributes(&path, XattrSelection::from_options(&args.options)).await,
+            );
+        }
         trace!("send trailer {trl:?}");
-        trl.to_writer_async_framed(&mut stream.send).await?;
+        send_trailer(&mut stream.send, trl).await?;
 
         stream.send.flush().await?;
         trace!("complete");
@@ -198,22 +307,40 @@ pub(crate) mod test_shared {
         server_level: u16,
         preserve: bool,
     ) -> Result<(Result<RequestResult>, Result<()>)> {
-        let (pipe1, mut pipe2) = new_test_plumbing();
         let spec = CopyJobSpec::from_parts(file1, file2, preserve, false).unwrap();
+        test_get_spec(
+            &spec,
+            Parameters::default(),
+            Configuration::system_default(),
+            client_level,
+            server_level,
+        )
+        .await
+    }
+
+    /// As [`test_getx_main`], but runs an arbitrary job spec
+    pub(crate) async fn test_get_spec(
+        spec: &CopyJobSpec,
+        params: Parameters,
+        config: &Configuration,
+        client_level: u16,
+        server_level: u16,
+    ) -> Result<(Result<RequestResult>, Result<()>)> {
+        let (pipe1, mut pipe2) = new_test_plumbing();
         let params = Parameters {
             quiet: true,
-            ..Default::default()
+            ..params
         };
         let (mut sender, _) = crate::session::factory::client_sender(
             pipe1,
-            &spec,
+            spec,
             crate::session::factory::TransferPhase::Transfer,
             Compatibility::Level(client_level),
             &params,
             None,
-            Configuration::system_default(),
+            config,
         );
-        let fut = sender.send(&spec, params);
+        let fut = sender.send(spec, params);
         tokio::pin!(fut);
 
         let result = read_from_stream(&mut pipe2.recv, &mut fut).await;
@@ -227,7 +354,7 @@ pub(crate) mod test_shared {
             pipe2,
             cmd,
             Compatibility::Level(server_level),
-            Configuration::system_default(),
+            config,
         );
 
         let (r1, r2) = tokio::join!(fut, handler.handle());
@@ -244,9 +371,10 @@ mod test {
     use littertray::LitterTray;
     use pretty_assertions::assert_eq;
 
-    use super::test_shared::test_getx_main;
+    use super::test_shared::{test_get_spec, test_getx_main};
     use crate::{
-        Configuration,
+        Configuration, Parameters, PreserveSelection,
+        client::CopyJobSpec,
         protocol::{control::Compatibility, session::Status, test_helpers::new_test_plumbing},
         session::{
             RequestResult, SessionCommandImpl as _,
@@ -285,6 +413,93 @@ mod test {
         .await
     }
 
+    #[tokio::test]
+    async fn get_ranges() -> Result<()> {
+        let contents = "0123456789";
+        LitterTray::try_with_async(async |tray| {
+            let _ = tray.create_text("file1", contents)?;
+            let _ = tray.create_text("file2", "this will be overwritten")?;
+            // Fetch the parts out of order, to show they are positioned correctly.
+            // The last range overruns the end of the file, so the server truncates it.
+            for (range, expected) in [(6..10, 4), (0..3, 3), (3..99, 7)] {
+                let mut spec = CopyJobSpec::from_parts("s:file1", "file2", false, false)?;
+                spec.range = Some(range);
+                let (r1, r2) = test_get_spec(
+                    &spec,
+                    Parameters::default(),
+                    Configuration::system_default(),
+                    5,
+                    5,
+                )
+                .await?;
+                assert_eq!(r1?.stats.payload_bytes, expected);
+                assert!(r2.is_ok());
+            }
+            let readback = std::fs::read_to_string("file2")?;
+            assert_eq!(readback, contents);
+            Ok(())
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn get_resume() -> Result<()> {
+        let contents = "0123456789";
+        let params = Parameters {
+            resume: true,
+            ..Default::default()
+        };
+        LitterTray::try_with_async(async |tray| {
+            let _ = tray.create_text("file1", contents)?;
+            // (destination held, bytes expected to be sent)
+            for (held, expected) in [("0123", 6), ("01xx", 10), ("", 10), (contents, 0)] {
+                let _ = tray.create_text("file2", held)?;
+                let spec = CopyJobSpec::from_parts("s:file1", "file2", false, false)?;
+                let (r1, r2) =
+                    test_get_spec(&spec, params, Configuration::system_default(), 5, 5).await?;
+                let r1 = r1?;
+                assert_eq!(r1.stats.payload_bytes, expected, "held {held:?}");
+                // Only a transfer of the whole file has its digest
+                let digest = ring::digest::digest(&ring::digest::SHA256, contents.as_bytes());
+                assert_eq!(
+                    r1.digest.as_deref(),
+                    (expected == 10).then_some(digest.as_ref()),
+                    "held {held:?}"
+                );
+                assert!(r2.is_ok());
+                let readback = std::fs::read_to_string("file2")?;
+                assert_eq!(readback, contents);
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn get_compressed() -> Result<()> {
+        let contents = "a,b,c,d,e\n".repeat(100_000);
+        let config = Configuration {
+            compression: 3,
+            ..Configuration::system_default().clone()
+        };
+        LitterTray::try_with_async(async |tray| {
+            let _ = tray.create_text("file1", &contents)?;
+            let spec = CopyJobSpec::from_parts("s:file1", "file2", false, false)?;
+            // (compatibility level, whether we expect compression)
+            for (level, compressed) in [(5, true), (4, false)] {
+                let (r1, r2) =
+                    test_get_spec(&spec, Parameters::default(), &config, level, level).await?;
+                let stats = r1?.stats;
+                assert_eq!(stats.payload_bytes, contents.len() as u64);
+                assert_eq!(stats.wire_bytes < stats.payload_bytes, compressed);
+                assert!(r2.is_ok());
+                assert_eq!(std::fs::read_to_string("file2")?, contents);
+            }
+            Ok(())
+        })
+        .await
+    }
+
     #[tokio::test]
     async fn file_not_found() -> Result<()> {
         LitterTray::try_with_async(async |_tray| {
@@ -412,6 +627,47 @@ mod test {
         .unwrap();
     }
 
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn get_preserve_times_only() {
+        use std::fs::{Permissions, set_permissions};
+
+        LitterTray::try_with_async(async |tray| {
+            let file = tray.create_text("hi", "hi")?;
+            file.set_times(FileTimes::new().set_modified(SystemTime::from_unix(654_321)))?;
+            drop(file);
+            set_permissions("hi", Permissions::from_mode(0o755))?;
+
+            let mut spec = CopyJobSpec::from_parts("remote:hi", "hi2", false, false)?;
+            spec.preserve = PreserveSelection {
+                times: true,
+                ..Default::default()
+            };
+            let params = Parameters {
+                in_place: true,
+                ..Default::default()
+            };
+            // An older server sends everything; we apply only what we asked for
+            for server in [5, 4] {
+                // Writing in place keeps the mode of the existing file, unless we change it
+                let _ = tray.create_text("hi2", "old")?;
+                set_permissions("hi2", Permissions::from_mode(0o600))?;
+
+                let (r1, r2) =
+                    test_get_spec(&spec, params, Configuration::system_default(), 5, server)
+                        .await?;
+                let _ = r1?;
+                r2?;
+                let meta = std::fs::metadata("hi2")?;
+                assert_eq!(meta.modified()?, SystemTime::from_unix(654_321));
+                assert_eq!(meta.permissions().mode() & 0o777, 0o600);
+            }
+            Ok(())
+        })
+        .await
+        .unwrap();
+    }
+
     async fn compat_get(client: u16, server: u16, preserve: bool) {
         LitterTray::try_with_async(async |tray| {
             let _ = tray.create_text("aa", "aa")?;
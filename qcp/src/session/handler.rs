@@ -1,6 +1,8 @@
 //! Generic command handler wrapper and trait
 // (c) 2025 Ross Younger
 
+use std::sync::Arc;
+
 use anyhow::Result;
 use async_trait::async_trait;
 use indicatif::{MultiProgress, ProgressBar};
@@ -8,6 +10,7 @@ use indicatif::{MultiProgress, ProgressBar};
 use crate::client::progress::style_for;
 use crate::protocol::common::{ReceivingStream, SendReceivePair, SendingStream};
 use crate::protocol::control::Compatibility;
+use crate::util::rate_limit::RateLimiter;
 use crate::{Parameters, client::CopyJobSpec, config::Configuration};
 
 use super::{RequestResult, SessionCommandImpl};
@@ -94,6 +97,10 @@ pub(crate) struct SessionCommandInner<'a, S: SendingStream, R: ReceivingStream>
     pub ui: UI,
     /// Negotiated configuration
     pub config: &'a Configuration,
+    /// Limits the rate of outbound file data, if there is a limit.
+    ///
+    /// This belongs to the session and is shared by all its connections and streams.
+    pub rate_limiter: Option<Arc<RateLimiter>>,
 }
 
 impl<'a, S: SendingStream, R: ReceivingStream> SessionCommandInner<'a, S, R> {
@@ -108,6 +115,7 @@ impl<'a, S: SendingStream, R: ReceivingStream> SessionCommandInner<'a, S, R> {
             compat,
             ui,
             config,
+            rate_limiter: None,
         }
     }
 
@@ -125,6 +133,7 @@ impl<'a, S: SendingStream, R: ReceivingStream> SessionCommandInner<'a, S, R> {
                 spinner: ProgressBar::hidden(),
             },
             config,
+            rate_limiter: None,
         }
     }
 
@@ -175,12 +184,17 @@ impl<S: SendingStream + 'static, R: ReceivingStream + 'static, H: CommandHandler
             .ok_or_else(|| anyhow::anyhow!("command handler missing args"))?;
         self.handler.handle_impl(&mut self.inner, args).await
     }
+
+    fn set_rate_limiter(&mut self, limiter: Option<Arc<RateLimiter>>) {
+        self.inner.rate_limiter = limiter;
+    }
 }
 
 // Re-export handler types for use in factory.rs and tests
 pub(crate) use super::{
-    get::GetHandler, ls::ListingHandler, mkdir::CreateDirectoryHandler, put::PutHandler,
-    set_meta::SetMetadataHandler,
+    free_space::FreeSpaceHandler, get::GetHandler, hardlink::CreateHardLinkHandler,
+    ls::ListingHandler, mkdir::CreateDirectoryHandler, probe::ProbeHandler, put::PutHandler,
+    set_meta::SetMetadataHandler, stat::StatHandler, symlink::CreateSymlinkHandler,
 };
 
 #[cfg(test)]
@@ -0,0 +1,194 @@
+//! Create Hard Link command
+// (c) 2025 Ross Younger
+
+use anyhow::Result;
+use async_trait::async_trait;
+use tokio::io::AsyncWriteExt;
+use tracing::{debug, trace};
+
+use crate::Parameters;
+use crate::protocol::common::{ProtocolMessage, ReceivingStream, SendingStream};
+use crate::protocol::compat::Feature;
+use crate::protocol::session::{Command, CreateHardLinkArgs, Response};
+use crate::session::common::send_ok;
+use crate::session::handler::SessionCommandInner;
+use crate::session::{RequestResult, error_and_return, handler::CommandHandler};
+
+/// Creates a hard link at `path` to the existing file `target`.
+///
+/// Any existing file or link at `path` is replaced; an existing directory is not.
+pub(crate) async fn create_hard_link(target: &str, path: &str) -> std::io::Result<()> {
+    if let Ok(meta) = tokio::fs::symlink_metadata(path).await
+        && !meta.is_dir()
+    {
+        tokio::fs::remove_file(path).await?;
+    }
+    tokio::fs::hard_link(target, path).await
+}
+
+pub(crate) struct CreateHardLinkHandler;
+
+#[async_trait]
+impl CommandHandler for CreateHardLinkHandler {
+    type Args = CreateHardLinkArgs;
+
+    async fn send_impl<'a, S: SendingStream, R: ReceivingStream>(
+        &mut self,
+        inner: &mut SessionCommandInner<'a, S, R>,
+        job: &crate::client::CopyJobSpec,
+        _params: Parameters,
+    ) -> Result<RequestResult> {
+        anyhow::ensure!(
+            inner.compat.supports(Feature::HARD_LINKS),
+            "Operation not supported by remote"
+        );
+        let Some(target) = &job.hard_link_target else {
+            anyhow::bail!("logic error: hard link job has no target");
+        };
+
+        trace!("sending command");
+        let mut outbound = &mut inner.stream.send;
+        let cmd = Command::CreateHardLink(CreateHardLinkArgs {
+            path: job.destination.filename.clone(),
+            target: target.clone(),
+            options: vec![],
+        });
+        cmd.to_writer_async_framed(&mut outbound).await?;
+        outbound.flush().await?;
+
+        trace!("await response");
+        let _ = Response::from_reader_async_framed(&mut inner.stream.recv)
+            .await?
+            .into_result()?;
+        Ok(RequestResult::default())
+    }
+
+    async fn handle_impl<'a, S: SendingStream, R: ReceivingStream>(
+        &mut self,
+        inner: &mut SessionCommandInner<'a, S, R>,
+        args: &CreateHardLinkArgs,
+    ) -> Result<()> {
+        let stream = &mut inner.stream;
+        if let Err(e) = create_hard_link(&args.target, &args.path).await {
+            debug!("Could not create hard link: {e}");
+            error_and_return!(stream, e);
+        }
+        send_ok(&mut stream.send).await
+    }
+}
+
+#[cfg(test)]
+#[cfg_attr(coverage_nightly, coverage(off))]
+mod test {
+    use crate::{
+        Configuration, Parameters,
+        client::CopyJobSpec,
+        protocol::{
+            control::Compatibility,
+            session::Command,
+            test_helpers::{new_test_plumbing, read_from_stream},
+        },
+        session::RequestResult,
+    };
+    use anyhow::{Result, bail};
+    use littertray::LitterTray;
+    use pretty_assertions::assert_eq;
+
+    async fn test_hard_link_main(
+        path: &str,
+        target: &str,
+        compat: u16,
+    ) -> Result<(Result<RequestResult>, Result<()>)> {
+        let (pipe1, mut pipe2) = new_test_plumbing();
+        let mut spec =
+            CopyJobSpec::from_parts(path, &format!("somehost:{path}"), false, false).unwrap();
+        spec.hard_link_target = Some(target.to_string());
+
+        let params = Parameters::default();
+        let (mut sender, _) = crate::session::factory::client_sender(
+            pipe1,
+            &spec,
+            crate::session::factory::TransferPhase::Transfer,
+            Compatibility::Level(compat),
+            &params,
+            None,
+            Configuration::system_default(),
+        );
+        let sender_fut = sender.send(&spec, params);
+        tokio::pin!(sender_fut);
+
+        let result = read_from_stream(&mut pipe2.recv, &mut sender_fut).await;
+        let cmd = result.expect_left("sender should not have completed early")?;
+        let Command::CreateHardLink(ref args) = cmd else {
+            bail!("expected CreateHardLink command");
+        };
+        assert_eq!(args.target, target);
+
+        let (mut handler, _) = crate::session::factory::command_handler(
+            pipe2,
+            cmd,
+            Compatibility::Level(compat),
+            Configuration::system_default(),
+        );
+        let (r1, r2) = tokio::join!(sender_fut, handler.handle());
+        Ok((r1, r2))
+    }
+
+    #[tokio::test]
+    async fn hard_link_created() -> Result<()> {
+        LitterTray::try_with_async(async |tray| {
+            let _ = tray.create_text("original", "contents")?;
+            let _ = tray.create_text("existing", "replace me")?;
+            for path in ["new", "existing"] {
+                let (r1, r2) = test_hard_link_main(path, "original", 5).await?;
+                let _ = r1?;
+                r2?;
+                assert_eq!(std::fs::read_to_string(path)?, "contents");
+            }
+            // They are all the same file
+            std::fs::write("original", "changed")?;
+            assert_eq!(std::fs::read_to_string("new")?, "changed");
+            assert_eq!(std::fs::read_to_string("existing")?, "changed");
+            Ok(())
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn missing_target() -> Result<()> {
+        LitterTray::try_with_async(async |_| {
+            let (r1, r2) = test_hard_link_main("new", "nonexistent", 5).await?;
+            let _ = r1.unwrap_err();
+            r2?;
+            assert!(std::fs::symlink_metadata("new").is_err());
+            Ok(())
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn falls_back_to_put() -> Result<()> {
+        LitterTray::try_with_async(async |tray| {
+            let _ = tray.create_text("l", "contents")?;
+            let (pipe1, mut pipe2) = new_test_plumbing();
+            let mut spec = CopyJobSpec::from_parts("l", "somehost:l", false, false).unwrap();
+            spec.hard_link_target = Some("t".to_string());
+            let (mut sender, _) = crate::session::factory::client_sender(
+                pipe1,
+                &spec,
+                crate::session::factory::TransferPhase::Transfer,
+                Compatibility::Level(4),
+                &Parameters::default(),
+                None,
+                Configuration::system_default(),
+            );
+            let sender_fut = sender.send(&spec, Parameters::default());
+            tokio::pin!(sender_fut);
+            let result = read_from_stream(&mut pipe2.recv, &mut sender_fut).await;
+            let cmd = result.expect_left("sender should not have completed early")?;
+            assert!(matches!(cmd, Command::Put2(_)), "{cmd:?}");
+            Ok(())
+        })
+        .await
+    }
+}
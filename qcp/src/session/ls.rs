@@ -14,6 +14,55 @@ use crate::protocol::session::{ResponseV1, prelude::*};
 use crate::session::common::{FindOption as _, send_ok};
 use crate::session::handler::{CommandHandler, SessionCommandInner};
 use crate::session::{CommandStats, RequestResult, error_and_return};
+use crate::util::FsMetadataExt as _;
+use crate::util::dirwalk::HardLinkTracker;
+use crate::util::xattr::{XattrSelection, read_blocking};
+
+/// Converts a directory entry for the listing.
+///
+/// If requested, this adds the times of directories (with `nanos`, to the nanosecond)
+/// and their selected extended attributes, and marks any further names for a file already listed.
+fn list_entry(
+    entry: walkdir::DirEntry,
+    times: bool,
+    nanos: bool,
+    xattrs: XattrSelection,
+    hard_links: Option<&mut HardLinkTracker>,
+) -> ListEntry {
+    let name = entry.path().to_string_lossy().to_string();
+    let mut extra = Vec::new();
+    if times
+        && entry.file_type().is_dir()
+        && let Ok(meta) = entry.metadata()
+    {
+        if let Ok(t) = meta.accessed() {
+            extra.push(MetadataAttr::new_atime(t));
+        }
+        if let Ok(t) = meta.modified() {
+            extra.push(MetadataAttr::new_mtime(t));
+        }
+        if nanos {
+            extra.extend(meta.time_nanos());
+        }
+    }
+    if xattrs.any() && entry.file_type().is_dir() {
+        extra.extend(read_blocking(entry.path(), xattrs));
+    }
+    // Files reached through a symbolic link are not hard links
+    if let Some(tracker) = hard_links
+        && entry.file_type().is_file()
+        && !entry.path_is_symlink()
+        && let Some(target) = entry
+            .metadata()
+            .ok()
+            .and_then(|meta| tracker.check(&meta, &name))
+    {
+        extra.push(MetadataAttr::HardLinkTarget.with_str(target));
+    }
+    let mut result = ListEntry::from(entry);
+    result.attributes.extend(extra);
+    result
+}
 
 pub(crate) struct ListingHandler;
 
@@ -40,6 +89,18 @@ impl CommandHandler for ListingHandler {
         if params.recurse {
             options.push(CommandParam::Recurse.into());
         }
+        if !params.links.follows() && inner.compat.supports(Feature::SYMLINKS) {
+            options.push(CommandParam::PreserveLinks.into());
+        }
+        if params.hard_links && inner.compat.supports(Feature::HARD_LINKS) {
+            options.push(CommandParam::HardLinks.into());
+        }
+        if params.preserve.times && inner.compat.supports(Feature::DIRECTORY_TIMES) {
+            options.push(CommandParam::PreserveMetadata.into());
+        }
+        if inner.compat.supports(Feature::EXTENDED_ATTRIBUTES) {
+            options.extend(XattrSelection::from_params(&params).to_options());
+        }
         let cmd = Command::List(ListArgs {
             path: path.clone(),
             options,
@@ -75,6 +136,21 @@ impl CommandHandler for ListingHandler {
     ) -> Result<()> {
         let path = &args.path;
         let recurse = args.options.find_option(CommandParam::Recurse).is_some();
+        let preserve_links = args
+            .options
+            .find_option(CommandParam::PreserveLinks)
+            .is_some();
+        let times = args
+            .options
+            .find_option(CommandParam::PreserveMetadata)
+            .is_some();
+        let nanos = inner.compat.supports(Feature::NANOSECOND_TIMES);
+        let xattrs = XattrSelection::from_options(&args.options);
+        let mut hard_links = args
+            .options
+            .find_option(CommandParam::HardLinks)
+            .is_some()
+            .then(HardLinkTracker::default);
         let stream = &mut inner.stream;
         // debug!("ls: path {path}, recurse={recurse}");
 
@@ -107,9 +183,9 @@ impl CommandHandler for ListingHandler {
         let entries: Result<Vec<_>, walkdir::Error> = WalkDir::new(path)
             // do NOT omit the root here, recursive transfer depends on it to mkdir the top-level dir
             .max_depth(if recurse { usize::MAX } else { 1 })
-            .follow_links(true)
+            .follow_links(!preserve_links)
             .into_iter()
-            .map(|e| e.map(ListEntry::from))
+            .map(|e| e.map(|e| list_entry(e, times, nanos, xattrs, hard_links.as_mut())))
             .collect();
 
         let list = match entries {
@@ -153,19 +229,33 @@ mod test {
     use pretty_assertions::assert_eq;
 
     async fn test_ls_main(path: &str, recurse: bool, expect_success: bool) -> Result<ListData> {
+        test_ls_with(
+            path,
+            Parameters {
+                recurse,
+                ..Default::default()
+            },
+            4,
+            expect_success,
+        )
+        .await
+    }
+
+    async fn test_ls_with(
+        path: &str,
+        params: Parameters,
+        compat: u16,
+        expect_success: bool,
+    ) -> Result<ListData> {
         let (pipe1, mut pipe2) = new_test_plumbing();
 
         let spec =
             CopyJobSpec::from_parts(path, &format!("desthost:{path}"), false, false).unwrap();
-        let params = Parameters {
-            recurse,
-            ..Default::default()
-        };
         let (mut sender, _) = crate::session::factory::client_sender(
             pipe1,
             &spec,
             crate::session::factory::TransferPhase::Pre,
-            Compatibility::Level(4),
+            Compatibility::Level(compat),
             &params,
             None,
             Configuration::system_default(),
@@ -185,7 +275,7 @@ mod test {
             let (mut handler, _) = crate::session::factory::command_handler(
                 pipe2,
                 cmd,
-                Compatibility::Level(4),
+                Compatibility::Level(compat),
                 Configuration::system_default(),
             );
             let (r1, r2) = tokio::join!(sender_fut, handler.handle());
@@ -315,4 +405,147 @@ mod test {
         .unwrap_err();
         assert!(result.to_string().contains("FileNotFound"));
     }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn preserve_links() {
+        let result = LitterTray::try_with_async(async |tray| {
+            let _ = tray.make_dir("d")?;
+            let _ = tray.make_dir("d/e")?;
+            let _ = tray.create_text("d/e/f", "hi")?;
+            std::os::unix::fs::symlink("e", "d/link")?;
+            test_ls_with(
+                "d",
+                Parameters {
+                    recurse: true,
+                    links: crate::LinkMode::Preserve,
+                    ..Default::default()
+                },
+                5,
+                true,
+            )
+            .await
+        })
+        .await
+        .unwrap();
+        let link = result
+            .entries
+            .iter()
+            .find(|e| e.name == "d/link")
+            .expect("link should be listed");
+        assert!(!link.directory);
+        assert_eq!(link.symlink_target(), Some("e"));
+        // The link was not followed
+        assert!(!result.entries.iter().any(|e| e.name.starts_with("d/link/")));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn hard_links() {
+        let result = LitterTray::try_with_async(async |tray| {
+            let _ = tray.make_dir("d")?;
+            let _ = tray.make_dir("d/e")?;
+            let _ = tray.create_text("d/e/f", "hi")?;
+            let _ = tray.create_text("d/solo", "alone")?;
+            std::fs::hard_link("d/e/f", "d/g")?;
+            test_ls_with(
+                "d",
+                Parameters {
+                    recurse: true,
+                    hard_links: true,
+                    ..Default::default()
+                },
+                5,
+                true,
+            )
+            .await
+        })
+        .await
+        .unwrap();
+        let links: Vec<_> = result
+            .entries
+            .iter()
+            .filter_map(|e| e.hard_link_target().map(|t| (e.name.as_str(), t)))
+            .collect();
+        // Whichever name the walk found first is the one that is transferred
+        assert!(
+            links == [("d/g", "d/e/f")] || links == [("d/e/f", "d/g")],
+            "{links:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn directory_times() {
+        let list = async |preserve: bool| {
+            LitterTray::try_with_async(async |tray| {
+                let _ = tray.make_dir("d")?;
+                let _ = tray.make_dir("d/e")?;
+                let _ = tray.create_text("d/f", "hi")?;
+                test_ls_with(
+                    "d",
+                    Parameters {
+                        recurse: true,
+                        preserve: preserve.into(),
+                        ..Default::default()
+                    },
+                    5,
+                    true,
+                )
+                .await
+            })
+            .await
+            .unwrap()
+        };
+        for entry in list(true).await.entries {
+            let has_mtime = entry
+                .attributes
+                .find_tag(MetadataAttr::ModificationTime)
+                .is_some();
+            // Only directories carry times
+            assert_eq!(has_mtime, entry.directory, "{entry}");
+        }
+        for entry in list(false).await.entries {
+            assert!(
+                entry
+                    .attributes
+                    .find_tag(MetadataAttr::ModificationTime)
+                    .is_none()
+            );
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn directory_extended_attributes() -> Result<()> {
+        use crate::os::{AbstractPlatform as _, Platform};
+
+        LitterTray::try_with_async(async |tray| {
+            let _ = tray.make_dir("d")?;
+            let _ = tray.create_text("d/f", "hi")?;
+            for path in ["d", "d/f"] {
+                let file = std::fs::File::open(path)?;
+                if Platform::set_extended_attribute(&file, "user.qcp.test", b"hello").is_err() {
+                    // Not every filesystem supports user attributes
+                    return Ok(());
+                }
+            }
+            for xattrs in [true, false] {
+                let params = Parameters {
+                    recurse: true,
+                    xattrs,
+                    ..Default::default()
+                };
+                for entry in test_ls_with("d", params, 5, true).await?.entries {
+                    let has_xattr = entry
+                        .attributes
+                        .find_tag(MetadataAttr::ExtendedAttribute)
+                        .is_some();
+                    // Only directories carry their attributes; files send theirs with the data
+                    assert_eq!(has_xattr, xattrs && entry.directory, "{entry}");
+                }
+            }
+            Ok(())
+        })
+        .await
+    }
 }
@@ -5,20 +5,30 @@ mod common;
 pub(crate) mod factory;
 pub(crate) mod handler;
 
+pub(crate) mod free_space;
 mod get;
+pub(crate) mod hardlink;
 mod ls;
 mod mkdir;
+pub(crate) mod probe;
 mod put;
 mod set_meta;
+mod stat;
+pub(crate) mod symlink;
 
 #[cfg(feature = "unstable-test-helpers")]
 #[allow(unused_imports)] // Selectively exported by qcp::test_helpers
 pub(crate) use get::test_shared;
 
+use std::sync::Arc;
+
 use anyhow::Result;
 use async_trait::async_trait;
 
-use crate::{Parameters, client::CopyJobSpec, protocol::session::ListData};
+use crate::{
+    Parameters, client::CopyJobSpec, os::DiskSpace, protocol::session::ListData,
+    util::rate_limit::RateLimiter,
+};
 
 /// Helper macro for making error returns
 ///
@@ -36,14 +46,26 @@ use error_and_return; // export within this crate
 /// Internal statistics for a completed command
 #[allow(unreachable_pub)] // Selectively exported by qcp::test_helpers
 pub struct CommandStats {
-    /// Total number of payload bytes sent
+    /// Total number of payload bytes sent (file data, before any compression)
     pub payload_bytes: u64,
+    /// Number of bytes the payload occupied on the wire.
+    /// This differs from `payload_bytes` when compression is in use.
+    pub wire_bytes: u64,
     /// Peak transfer rate observed (in bytes per second); this is not terribly accurate at the moment, particularly on PUT commands
     pub peak_transfer_rate: u64,
 }
 
+impl CommandStats {
+    /// Folds the statistics for another command into this one
+    pub(crate) fn accumulate(&mut self, other: &CommandStats) {
+        self.payload_bytes += other.payload_bytes;
+        self.wire_bytes += other.wire_bytes;
+        self.peak_transfer_rate = self.peak_transfer_rate.max(other.peak_transfer_rate);
+    }
+}
+
 /// Result of a successfully completed request
-#[derive(Debug, derive_more::Constructor)]
+#[derive(Debug)]
 pub struct RequestResult {
     /// Statistics for the command, if applicable (i.e. for file transfer commands)
     pub stats: CommandStats,
@@ -52,15 +74,29 @@ pub struct RequestResult {
     /// This is used for commands that return data which the client processes and may cause further commands,
     /// for example `List` returns directory entries which may cause further `Get` commands.
     pub list: Option<ListData>,
+    /// The capacity of the remote filesystem, if this was a `FreeSpace` command and the remote could tell us
+    pub space: Option<DiskSpace>,
+    /// The SHA-256 digest of the file, if this was a file transfer which sent the whole of it
+    /// and the remote supports checksums
+    pub digest: Option<Vec<u8>>,
+}
+
+impl RequestResult {
+    /// Constructor
+    pub(crate) fn new(stats: CommandStats, list: Option<ListData>) -> Self {
+        Self {
+            stats,
+            list,
+            space: None,
+            digest: None,
+        }
+    }
 }
 
 impl Default for RequestResult {
     /// A default successful request result with no stats or response data
     fn default() -> Self {
-        Self {
-            stats: CommandStats::default(),
-            list: None,
-        }
+        Self::new(CommandStats::default(), None)
     }
 }
 
@@ -79,4 +115,7 @@ pub(crate) trait SessionCommandImpl: Send {
     ///
     /// See also the [`crate::session::common::send_ok`] and [`crate::session::common::send_error`] helpers.
     async fn handle(&mut self) -> Result<()>;
+
+    /// Sets the limiter for outbound file data, which is shared by all the connections and streams of a session
+    fn set_rate_limiter(&mut self, limiter: Option<Arc<RateLimiter>>);
 }
@@ -0,0 +1,197 @@
+//! Probe command
+// (c) 2025 Ross Younger
+
+use std::time::Duration;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use serde_bare::Uint;
+use tokio::io::{AsyncRead, AsyncReadExt as _, AsyncWrite, AsyncWriteExt as _};
+use tokio::time::Instant;
+use tracing::trace;
+
+use crate::Parameters;
+use crate::protocol::common::{
+    ProtocolMessage as _, ReceivingStream, SendReceivePair, SendingStream,
+};
+use crate::protocol::session::{Command, ProbeArgs, ProbeReport, ProbeReportV1, Response};
+use crate::session::RequestResult;
+use crate::session::common::send_ok;
+use crate::session::handler::{CommandHandler, SessionCommandInner};
+
+/// The longest we will send probe data for, whatever the client asks
+const MAX_PROBE_DURATION: Duration = Duration::from_secs(5);
+
+/// Size of the writes we make when sending probe data
+const PROBE_BUFFER_SIZE: usize = 65536;
+
+/// Measured throughput of the link in each direction, in bytes per second
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct ProbeRates {
+    pub(crate) to_server: u64,
+    pub(crate) to_client: u64,
+}
+
+/// Sends filler data for the given duration, then closes the stream.
+async fn send_filler<W: AsyncWrite + Unpin>(send: &mut W, duration: Duration) -> Result<()> {
+    let deadline = Instant::now() + duration;
+    let buf = vec![0u8; PROBE_BUFFER_SIZE];
+    while Instant::now() < deadline {
+        send.write_all(&buf).await?;
+    }
+    send.flush().await?;
+    send.shutdown().await?;
+    Ok(())
+}
+
+/// Reads until end of stream.
+///
+/// Returns the number of bytes read, and the time between the arrival of the first and last of them.
+async fn drain<R: AsyncRead + Unpin>(recv: &mut R) -> Result<(u64, Duration)> {
+    let mut buf = vec![0u8; PROBE_BUFFER_SIZE];
+    let mut total = 0u64;
+    let mut first = None;
+    let mut last = Instant::now();
+    loop {
+        let n = recv.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        last = Instant::now();
+        let _ = first.get_or_insert(last);
+        total += n as u64;
+    }
+    let elapsed = first.map_or(Duration::ZERO, |f| last - f);
+    Ok((total, elapsed))
+}
+
+/// Computes a rate in bytes per second
+fn rate(bytes: u64, elapsed_us: u64) -> u64 {
+    if elapsed_us == 0 {
+        return 0;
+    }
+    u64::try_from(u128::from(bytes) * 1_000_000 / u128::from(elapsed_us)).unwrap_or(u64::MAX)
+}
+
+/// Client side: measures the throughput of the link in each direction, over a fresh stream.
+///
+/// The client sends data for `duration`, then the server does the same.
+/// Each direction is measured by its receiver.
+pub(crate) async fn probe<S: SendingStream, R: ReceivingStream>(
+    mut stream: SendReceivePair<S, R>,
+    duration: Duration,
+) -> Result<ProbeRates> {
+    trace!("sending command");
+    let cmd = Command::Probe(ProbeArgs {
+        duration_ms: Uint(u64::try_from(duration.as_millis()).unwrap_or(u64::MAX)),
+        options: vec![],
+    });
+    cmd.to_writer_async_framed(&mut stream.send).await?;
+    stream.send.flush().await?;
+
+    trace!("await response");
+    let _ = Response::from_reader_async_framed(&mut stream.recv)
+        .await?
+        .into_result()?;
+
+    trace!("sending probe data");
+    send_filler(&mut stream.send, duration).await?;
+    let report =
+        ProbeReportV1::from(ProbeReport::from_reader_async_framed(&mut stream.recv).await?);
+
+    trace!("receiving probe data");
+    let (received, elapsed) = drain(&mut stream.recv).await?;
+    let elapsed_us = u64::try_from(elapsed.as_micros()).unwrap_or(u64::MAX);
+    Ok(ProbeRates {
+        to_server: rate(report.received.0, report.elapsed_us.0),
+        to_client: rate(received, elapsed_us),
+    })
+}
+
+pub(crate) struct ProbeHandler;
+
+#[async_trait]
+impl CommandHandler for ProbeHandler {
+    type Args = ProbeArgs;
+
+    async fn send_impl<'a, S: SendingStream, R: ReceivingStream>(
+        &mut self,
+        _inner: &mut SessionCommandInner<'a, S, R>,
+        _job: &crate::client::CopyJobSpec,
+        _params: Parameters,
+    ) -> Result<RequestResult> {
+        anyhow::bail!("logic error: probe is not a copy job; use session::probe::probe()")
+    }
+
+    async fn handle_impl<'a, S: SendingStream, R: ReceivingStream>(
+        &mut self,
+        inner: &mut SessionCommandInner<'a, S, R>,
+        args: &ProbeArgs,
+    ) -> Result<()> {
+        let duration = Duration::from_millis(args.duration_ms.0).min(MAX_PROBE_DURATION);
+        let stream = &mut inner.stream;
+        send_ok(&mut stream.send).await?;
+
+        let (received, elapsed) = drain(&mut stream.recv).await?;
+        trace!("probe received {received} bytes in {elapsed:?}");
+        ProbeReport::V1(ProbeReportV1 {
+            received: Uint(received),
+            elapsed_us: Uint(u64::try_from(elapsed.as_micros()).unwrap_or(u64::MAX)),
+        })
+        .to_writer_async_framed(&mut stream.send)
+        .await?;
+
+        send_filler(&mut stream.send, duration).await
+    }
+}
+
+#[cfg(test)]
+#[cfg_attr(coverage_nightly, coverage(off))]
+mod test {
+    use std::time::Duration;
+
+    use crate::{
+        Configuration,
+        protocol::{
+            control::Compatibility,
+            session::Command,
+            test_helpers::{new_test_plumbing, read_from_stream},
+        },
+    };
+    use anyhow::{Result, bail};
+
+    use super::{probe, rate};
+
+    #[test]
+    fn rates() {
+        assert_eq!(rate(1000, 1_000_000), 1000);
+        assert_eq!(rate(1000, 500), 2_000_000);
+        assert_eq!(rate(1000, 0), 0);
+    }
+
+    #[tokio::test]
+    async fn probe_measures_both_directions() -> Result<()> {
+        let (pipe1, mut pipe2) = new_test_plumbing();
+        let mut probe_fut = Box::pin(probe(pipe1, Duration::from_millis(50)));
+
+        let result = read_from_stream(&mut pipe2.recv, &mut probe_fut).await;
+        let cmd = result.expect_left("probe should not have completed early")?;
+        let Command::Probe(ref args) = cmd else {
+            bail!("expected Probe command");
+        };
+        assert_eq!(args.duration_ms.0, 50);
+
+        let (mut handler, _) = crate::session::factory::command_handler(
+            pipe2,
+            cmd,
+            Compatibility::Level(5),
+            Configuration::system_default(),
+        );
+        let (r1, r2) = tokio::join!(probe_fut, handler.handle());
+        r2?;
+        let rates = r1?;
+        assert!(rates.to_server > 0);
+        assert!(rates.to_client > 0);
+        Ok(())
+    }
+}
@@ -2,23 +2,30 @@
 // (c) 2024-5 Ross Younger
 
 use anyhow::{Context as _, Result, anyhow};
-use std::path::PathBuf;
+use std::{io::SeekFrom, path::PathBuf};
 use tokio::fs::File as TokioFile;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tracing::{debug, error, trace};
+use tokio::io::{AsyncReadExt, AsyncSeekExt as _, AsyncWriteExt};
+use tracing::{debug, error, trace, warn};
 
-use crate::Parameters;
 use crate::protocol::common::{ProtocolMessage, ReceivingStream, SendingStream};
 use crate::protocol::compat::Feature;
 use crate::protocol::session::{
-    Command, CommandParam, FileHeader, FileHeaderV2, FileTrailer, FileTrailerV2, Put2Args, PutArgs,
-    Response, Status,
+    Command, CommandParam, FileHeader, FileHeaderV2, FileTrailer, Put2Args, PutArgs, Response,
+    ResumeReport, ResumeReportV1, Status,
+};
+use crate::session::common::{
+    FindOption as _, compression_option, range_options, receive_payload, receive_trailer,
+    requested_range, resume_offset, resume_report, send_payload, send_trailer, verify_digest,
 };
 use crate::session::handler::SessionCommandInner;
 use crate::session::{RequestResult, error_and_return, handler::CommandHandler};
+use crate::{Parameters, PreserveSelection};
 
 // Extension trait for TokioFile!
 use crate::util::FileExt as _;
+use crate::util::IncomingFile;
+use crate::util::sparse::{ExtentReader, ExtentWriter, data_extents, extents_len};
+use crate::util::xattr::{XattrSelection, read_extended_attributes};
 
 pub(crate) struct PutHandler;
 
@@ -26,6 +33,7 @@ pub(crate) struct PutHandler;
 impl CommandHandler for PutHandler {
     type Args = Put2Args;
 
+    #[allow(clippy::too_many_lines)]
     async fn send_impl<'a, S: SendingStream, R: ReceivingStream>(
         &mut self,
         inner: &mut SessionCommandInner<'a, S, R>,
@@ -41,7 +49,37 @@ impl CommandHandler for PutHandler {
             anyhow::bail!("PUT: Source is a directory");
         }
 
-        let payload_len = src_meta.len();
+        let range = job.range.clone().unwrap_or(0..src_meta.len());
+        if job.range.is_some() {
+            anyhow::ensure!(
+                inner.compat.supports(Feature::RANGED_TRANSFER),
+                "Ranged transfers are not supported by remote"
+            );
+            anyhow::ensure!(
+                range.end <= src_meta.len(),
+                "PUT: Range extends beyond the end of the file"
+            );
+            let _ = file.seek(SeekFrom::Start(range.start)).await?;
+        }
+        let mut payload_len = range.end - range.start;
+        let resume = params.resume
+            && job.range.is_none()
+            && inner.compat.supports(Feature::RESUME)
+            && inner.compat.supports(Feature::RANGED_TRANSFER);
+        let sparse = inner.compat.supports(Feature::SPARSE_FILES);
+        // When resuming, we only know what we're sending once we have the resume report
+        let mut extents = if sparse && !resume {
+            data_extents(&path, &range).await
+        } else {
+            None
+        };
+        if let Some(e) = &extents {
+            payload_len = extents_len(e);
+        }
+        let compression_param = compression_option(inner.config, inner.compat);
+        let compression = compression_param
+            .is_some()
+            .then(|| i32::from(inner.config.compression));
 
         // Now we can compute how much we're going to send, update the chrome.
         // Marshalled commands are currently 48 bytes + filename length
@@ -52,6 +90,7 @@ impl CommandHandler for PutHandler {
             &progress_bar,
             Some(inner.spinner().clone()),
             inner.config.tx(),
+            inner.config.limit_rate_tx,
         );
         let mut outbound = progress_bar.wrap_async_write(&mut inner.stream.send);
         meter.start().await;
@@ -60,9 +99,22 @@ impl CommandHandler for PutHandler {
 
         let cmd = if inner.compat.supports(Feature::GET2_PUT2) {
             let mut options = vec![];
-            if job.preserve {
-                options.push(CommandParam::PreserveMetadata.into());
+            if job.preserve.any() {
+                options.extend(job.preserve.to_options(inner.compat));
+                if params.numeric_ids && inner.compat.supports(Feature::OWNERSHIP) {
+                    options.push(CommandParam::NumericIds.into());
+                }
+            }
+            if let Some(range) = &job.range {
+                options.extend(range_options(range));
             }
+            if resume {
+                options.push(CommandParam::Resume.into());
+            }
+            if params.in_place && inner.compat.supports(Feature::ATOMIC_WRITE) {
+                options.push(CommandParam::InPlace.into());
+            }
+            options.extend(compression_param);
             Command::Put2(Put2Args {
                 filename: dest_filename.clone(),
                 options,
@@ -78,7 +130,12 @@ impl CommandHandler for PutHandler {
         // The filename in the protocol is the file part only of src_filename
         trace!("send header");
         let protocol_filename = path.file_name().unwrap().to_str().unwrap(); // can't fail with the preceding checks
-        let hdr = FileHeader::for_file(inner.compat, &src_meta, protocol_filename);
+        let hdr = if job.range.is_some() {
+            FileHeader::for_file_range(&src_meta, protocol_filename, &range)
+        } else {
+            FileHeader::for_file(inner.compat, &src_meta, protocol_filename)
+        }
+        .with_extents(extents.as_deref());
         trace!("{hdr:?}");
         hdr.to_writer_async_framed(&mut outbound).await?;
 
@@ -88,19 +145,69 @@ impl CommandHandler for PutHandler {
             .into_result()
             .with_context(|| format!("PUTx {src_filename} failed"))?;
 
+        if resume {
+            trace!("await resume report");
+            let report = ResumeReportV1::from(
+                ResumeReport::from_reader_async_framed(&mut inner.stream.recv).await?,
+            );
+            let offset = resume_offset(
+                &report.options,
+                &mut file,
+                src_meta.len(),
+                inner.config.io_buffer_size,
+            )
+            .await?;
+            let send_range = offset..src_meta.len();
+            if sparse {
+                extents = data_extents(&path, &send_range).await;
+            }
+            let new_len = extents
+                .as_deref()
+                .map_or(send_range.end - send_range.start, extents_len);
+            progress_bar.dec_length(payload_len - new_len);
+            payload_len = new_len;
+            let hdr = if offset > 0 {
+                debug!("resuming from offset {offset}");
+                FileHeader::for_file_range(&src_meta, protocol_filename, &send_range)
+            } else {
+                FileHeader::for_file(inner.compat, &src_meta, protocol_filename)
+            }
+            .with_extents(extents.as_deref());
+            trace!("resume header {hdr:?}");
+            hdr.to_writer_async_framed(&mut outbound).await?;
+        }
+
         // A server-side abort might happen part-way through a large transfer.
         trace!("send payload");
-        let result =
-            crate::util::io::copy_large(&mut file, &mut outbound, inner.config.io_buffer_size)
-                .await;
-
-        match result {
-            Ok(sent) if sent == src_meta.len() => (),
-            Ok(sent) => {
-                anyhow::bail!(
-                    "File sent size {sent} doesn't match its metadata {}",
-                    src_meta.len()
-                );
+        let mut file = ExtentReader::new(file, extents).take(payload_len);
+        // The progress bar counts file data, so when compressing it has to watch the file rather than the stream.
+        let result = if compression.is_some() {
+            send_payload(
+                &mut progress_bar.wrap_async_read(&mut file),
+                &mut inner.stream.send,
+                inner.config.io_buffer_size,
+                inner.compat,
+                compression,
+                inner.rate_limiter.as_deref(),
+            )
+            .await
+        } else {
+            send_payload(
+                &mut file,
+                &mut progress_bar.wrap_async_write(&mut inner.stream.send),
+                inner.config.io_buffer_size,
+                inner.compat,
+                None,
+                inner.rate_limiter.as_deref(),
+            )
+            .await
+        };
+
+        let (counts, digest) = match result {
+            Ok((sent, digest)) if sent.logical == payload_len => (sent, digest),
+            Ok((sent, _)) => {
+                let sent = sent.logical;
+                anyhow::bail!("File sent size {sent} doesn't match its metadata {payload_len}");
             }
             Err(e) => {
                 if e.kind() == tokio::io::ErrorKind::ConnectionReset {
@@ -119,11 +226,21 @@ impl CommandHandler for PutHandler {
                 }
                 return Err(anyhow!(e).context("I/O error during PUT"));
             }
-        }
+        };
 
-        let trl = FileTrailer::for_file(inner.compat, &src_meta, job.preserve);
+        // The digest only covers the whole file if we sent all of it
+        let file_digest = digest.clone().filter(|_| payload_len == src_meta.len());
+        let mut trl = FileTrailer::for_file(inner.compat, &src_meta, job.preserve, digest).await;
+        if let FileTrailer::V2(t) = &mut trl
+            && inner.compat.supports(Feature::EXTENDED_ATTRIBUTES)
+        {
+            t.metadata.extend(
+                read_extended_attributes(&path, XattrSelection::from_params(&params)).await,
+            );
+        }
         trace!("send trailer {trl:?}");
-        trl.to_writer_async_framed(&mut outbound).await?;
+        let mut outbound = progress_bar.wrap_async_write(&mut inner.stream.send);
+        send_trailer(&mut outbound, trl).await?;
         outbound.flush().await?;
         meter.stop().await;
 
@@ -137,6 +254,10 @@ impl CommandHandler for PutHandler {
                 "PUTx ({src_filename}) failed on completion check: {response}"
             ));
         }
+        if let Some(message) = response.message {
+            // Something went wrong with the metadata, but not badly enough to fail the transfer
+            warn!("{dest_filename}: {message}");
+        }
 
         // Note that the Quinn sendstream calls finish() on drop.
         trace!("complete");
@@ -144,12 +265,15 @@ impl CommandHandler for PutHandler {
         Ok(RequestResult {
             stats: crate::session::CommandStats {
                 payload_bytes: payload_len,
+                wire_bytes: counts.wire,
                 peak_transfer_rate: meter.peak(),
             },
+            digest: file_digest,
             ..Default::default()
         })
     }
 
+    #[allow(clippy::too_many_lines)]
     async fn handle_impl<'a, S: SendingStream, R: ReceivingStream>(
         &mut self,
         inner: &mut SessionCommandInner<'a, S, R>,
@@ -201,13 +325,56 @@ impl CommandHandler for PutHandler {
 
         let header = FileHeader::from_reader_async_framed(&mut stream.recv).await?;
         trace!("{header:?}");
-        let header = FileHeaderV2::from(header);
+        let mut header = FileHeaderV2::from(header);
+
+        // The header describes any partial content; a requested range must agree with it.
+        if let Some(requested) = requested_range(&args.options, u64::MAX) {
+            let described = header
+                .range()
+                .map(|(offset, _)| offset..offset + header.size.0);
+            if described != Some(requested) {
+                error_and_return!(
+                    stream,
+                    anyhow!("requested range does not match the file header")
+                );
+            }
+        }
 
         debug!("PUT {} -> {destination}", &header.filename);
         if append_filename {
             path.push(&header.filename);
         }
-        let mut file = match TokioFile::create_or_truncate(path, &header).await {
+
+        // When resuming, tell the client what we already hold. It then sends the header for what it will actually send.
+        let resuming = args.options.find_option(CommandParam::Resume).is_some();
+        if resuming {
+            // Once we have replied, the client will send the data; so make sure we can write it first.
+            if let Err(e) = IncomingFile::check(&path, &header).await {
+                debug!("Could not write to destination: {e}");
+                error_and_return!(stream, e);
+            }
+            let report = match resume_report(&path, inner.config.io_buffer_size).await {
+                Ok(r) => r,
+                Err(e) => error_and_return!(stream, e),
+            };
+            trace!("responding OK with resume report");
+            crate::session::common::send_ok(&mut stream.send).await?;
+            ResumeReport::V1(ResumeReportV1 { options: report })
+                .to_writer_async_framed(&mut stream.send)
+                .await?;
+            stream.send.flush().await?;
+            header =
+                FileHeaderV2::from(FileHeader::from_reader_async_framed(&mut stream.recv).await?);
+            trace!("{header:?}");
+        }
+
+        let extents = match header.extents() {
+            Ok(e) => e,
+            Err(e) => error_and_return!(stream, e),
+        };
+        // A transfer which may be resumed is written in place, so an interruption leaves something to resume from
+        let in_place = resuming || args.options.find_option(CommandParam::InPlace).is_some();
+        let (incoming, mut file) = match IncomingFile::create(path, &header, in_place).await {
             Ok(f) => f,
             Err(e) => {
                 let str = e.to_string();
@@ -218,64 +385,78 @@ impl CommandHandler for PutHandler {
 
         // So far as we can tell, we believe we will be able to fulfil this request.
         // We might still fail with an I/O error.
-        trace!("responding OK");
-        crate::session::common::send_ok(&mut stream.send).await?;
-        stream.send.flush().await?;
+        if !resuming {
+            trace!("responding OK");
+            crate::session::common::send_ok(&mut stream.send).await?;
+            stream.send.flush().await?;
+        }
 
-        trace!("receiving file payload");
-        let result = limited_copy(
+        let compressed = args
+            .options
+            .find_option(CommandParam::Compression)
+            .is_some();
+        trace!("receiving file payload (compressed: {compressed})");
+        let result = receive_payload(
             &mut stream.recv,
-            header.size.0,
-            &mut file,
+            &mut ExtentWriter::new(&mut file, extents),
+            header.payload_len(),
             inner.config.io_buffer_size,
+            inner.compat,
+            compressed,
         )
         .await;
-        if let Err(e) = result {
-            error!("Failed to write to destination: {e}");
-            error_and_return!(stream, e);
-        }
+        let digest = match result {
+            Ok((_, digest)) => digest,
+            Err(e) => {
+                error!("Failed to write to destination: {e}");
+                error_and_return!(stream, e);
+            }
+        };
 
         trace!("receiving trailer");
-        let trailer =
-            FileTrailerV2::from(FileTrailer::from_reader_async_framed(&mut stream.recv).await?);
+        let mut trailer = receive_trailer(&mut stream.recv).await?;
         // Even if we only get the older V1 trailer, the server believes the file was sent correctly.
         trace!("{trailer:?}");
 
         file.flush().await?;
-        file = file.update_metadata(&trailer.metadata).await?;
+        if let Err(e) = verify_digest(&trailer, digest.as_ref()) {
+            error!("Received data for {} is corrupt", header.filename);
+            error_and_return!(stream, e);
+        }
+        let preserve = PreserveSelection::from_options(&args.options);
+        trailer.metadata.retain(|md| preserve.keeps(md));
+        let numeric_ids = args.options.find_option(CommandParam::NumericIds).is_some();
+        let (file, warnings) = file.update_metadata(&trailer.metadata, numeric_ids).await?;
         drop(file);
+        if let Err(e) = incoming.commit().await {
+            error!("Failed to move received file into place: {e}");
+            error_and_return!(stream, e);
+        }
 
-        crate::session::common::send_ok(&mut stream.send).await?;
+        if warnings.is_empty() {
+            crate::session::common::send_ok(&mut stream.send).await?;
+        } else {
+            // Report what we could not apply, without failing the transfer
+            crate::session::common::send_ok_with_message(&mut stream.send, &warnings.join("; "))
+                .await?;
+        }
         stream.send.flush().await?;
         trace!("complete");
         Ok(())
     }
 }
 
-// this function exists because without it, the compiler complains that we're _moving_
-// Put's self.stream.recv; but _borrowing_ it and consuming it is OK.
-// This doesn't seem wholly comfortable, but it works.
-async fn limited_copy(
-    recv: &mut dyn ReceivingStream,
-    n: u64,
-    f: &mut TokioFile,
-    buffer_size: u64,
-) -> Result<u64, std::io::Error> {
-    let mut limited = recv.take(n);
-    crate::util::io::copy_large(&mut limited, f, buffer_size).await
-}
-
 #[cfg(test)]
 #[cfg_attr(coverage_nightly, coverage(off))]
 mod test {
     use std::{fs::FileTimes, time::SystemTime};
 
     use anyhow::{Result, bail};
-    use assertables::assert_contains;
+    use assertables::{assert_contains, assert_not_contains};
     use pretty_assertions::assert_eq;
 
     use crate::{
-        Configuration, Parameters,
+        Configuration, Parameters, PreserveSelection,
         client::CopyJobSpec,
         protocol::{
             control::Compatibility,
@@ -318,23 +499,43 @@ mod test {
         sender_bails: bool,
         preserve: bool,
     ) -> Result<(Result<RequestResult>, Result<()>)> {
-        let (pipe1, mut pipe2) = new_test_plumbing();
         let spec = CopyJobSpec::from_parts(file1, file2, preserve, false).unwrap();
+        test_put_spec(
+            &spec,
+            Parameters::default(),
+            Configuration::system_default(),
+            client_level,
+            server_level,
+            sender_bails,
+        )
+        .await
+    }
+
+    /// As [`test_putx_main`], but runs an arbitrary job spec
+    async fn test_put_spec(
+        spec: &CopyJobSpec,
+        params: Parameters,
+        config: &Configuration,
+        client_level: u16,
+        server_level: u16,
+        sender_bails: bool,
+    ) -> Result<(Result<RequestResult>, Result<()>)> {
+        let (pipe1, mut pipe2) = new_test_plumbing();
         let params = Parameters {
             quiet: true,
-            ..Default::default()
+            ..params
         };
 
         let (mut sender, _) = crate::session::factory::client_sender(
             pipe1,
-            &spec,
+            spec,
             crate::session::factory::TransferPhase::Transfer,
             Compatibility::Level(client_level),
             &params,
             None,
-            Configuration::system_default(),
+            config,
         );
-        let sender_fut = sender.send(&spec, params);
+        let sender_fut = sender.send(spec, params);
         tokio::pin!(sender_fut);
 
         // The first difference between Get and Put is that in the error cases for Put, the sending future
@@ -360,7 +561,7 @@ mod test {
             pipe2,
             cmd,
             Compatibility::Level(server_level),
-            Configuration::system_default(),
+            config,
         );
         let (r1, r2) = tokio::join!(sender_fut, handler.handle());
         Ok((r1, r2))
@@ -381,6 +582,219 @@ mod test {
         .await
     }
 
+    #[tokio::test]
+    async fn put_ranges() -> Result<()> {
+        let contents = "0123456789";
+        LitterTray::try_with_async(async |tray| {
+            let _ = tray.create_text("file1", contents)?;
+            let _ = tray.create_text("file2", "this will be overwritten")?;
+            // Send the parts out of order, to show they are positioned correctly
+            for range in [6..10, 0..3, 3..6] {
+                let mut spec = CopyJobSpec::from_parts("file1", "server:file2", false, false)?;
+                spec.range = Some(range.clone());
+                let (r1, r2) = test_put_spec(
+                    &spec,
+                    Parameters::default(),
+                    Configuration::system_default(),
+                    5,
+                    5,
+                    false,
+                )
+                .await?;
+                assert_eq!(r1?.stats.payload_bytes, range.end - range.start);
+                assert!(r2.is_ok());
+            }
+            let readback = std::fs::read_to_string("file2")?;
+            assert_eq!(readback, contents);
+            Ok(())
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn put_in_place() -> Result<()> {
+        let params = Parameters {
+            in_place: true,
+            ..Default::default()
+        };
+        LitterTray::try_with_async(async |tray| {
+            let _ = tray.create_text("file1", "wibble")?;
+            let _ = tray.create_text("file2", "this will be overwritten")?;
+            let spec = CopyJobSpec::from_parts("file1", "server:file2", false, false)?;
+            let (r1, r2) =
+                test_put_spec(&spec, params, Configuration::system_default(), 5, 5, false).await?;
+            assert_eq!(r1?.stats.payload_bytes, 6);
+            assert!(r2.is_ok());
+            assert_eq!(std::fs::read_to_string("file2")?, "wibble");
+            Ok(())
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn put_compressed() -> Result<()> {
+        let contents = "a,b,c,d,e\n".repeat(100_000);
+        let config = Configuration {
+            compression: 3,
+            ..Configuration::system_default().clone()
+        };
+        LitterTray::try_with_async(async |tray| {
+            let _ = tray.create_text("file1", &contents)?;
+            let spec = CopyJobSpec::from_parts("file1", "server:file2", false, false)?;
+            // (server compatibility level, whether we expect compression)
+            for (level, compressed) in [(5, true), (4, false)] {
+                let (r1, r2) =
+                    test_put_spec(&spec, Parameters::default(), &config, level, level, false)
+                        .await?;
+                let stats = r1?.stats;
+                assert_eq!(stats.payload_bytes, contents.len() as u64);
+                assert_eq!(stats.wire_bytes < stats.payload_bytes, compressed);
+                assert!(r2.is_ok());
+                assert_eq!(std::fs::read_to_string("file2")?, contents);
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn put_range_needs_support() -> Result<()> {
+        LitterTray::try_with_async(async |tray| {
+            let _ = tray.create_text("file1", "0123456789")?;
+            let mut spec = CopyJobSpec::from_parts("file1", "server:file2", false, false)?;
+            spec.range = Some(0..3);
+            let (r1, _) = test_put_spec(
+                &spec,
+                Parameters::default(),
+                Configuration::system_default(),
+                4,
+                4,
+                true,
+            )
+            .await?;
+            assert_contains!(r1.unwrap_err().to_string(), "not supported");
+            Ok(())
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn put_resume() -> Result<()> {
+        let contents = "0123456789";
+        let params = Parameters {
+            resume: true,
+            ..Default::default()
+        };
+        LitterTray::try_with_async(async |tray| {
+            let _ = tray.create_text("file1", contents)?;
+            // (destination held, bytes expected to be sent)
+            for (held, expected) in [
+                ("0123", 6),
+                ("01xx", 10),
+                ("0123456789abc", 10),
+                (contents, 0),
+            ] {
+                let _ = tray.create_text("file2", held)?;
+                let spec = CopyJobSpec::from_parts("file1", "server:file2", false, false)?;
+                let (r1, r2) =
+                    test_put_spec(&spec, params, Configuration::system_default(), 5, 5, false)
+                        .await?;
+                let r1 = r1?;
+                assert_eq!(r1.stats.payload_bytes, expected, "held {held:?}");
+                // Only a transfer of the whole file has its digest
+                let digest = ring::digest::digest(&ring::digest::SHA256, contents.as_bytes());
+                assert_eq!(
+                    r1.digest.as_deref(),
+                    (expected == 10).then_some(digest.as_ref()),
+                    "held {held:?}"
+                );
+                assert!(r2.is_ok());
+                let readback = std::fs::read_to_string("file2")?;
+                assert_eq!(readback, contents);
+            }
+            // Without a destination file, the whole thing is sent
+            std::fs::remove_file("file2")?;
+            let spec = CopyJobSpec::from_parts("file1", "server:file2", false, false)?;
+            let (r1, r2) =
+                test_put_spec(&spec, params, Configuration::system_default(), 5, 5, false).await?;
+            assert_eq!(r1?.stats.payload_bytes, 10);
+            assert!(r2.is_ok());
+            Ok(())
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn interrupted_resume_keeps_partial_file() -> Result<()> {
+        use crate::protocol::{
+            common::ProtocolMessage as _,
+            session::{CommandParam, FileHeader, FileHeaderV2, Put2Args, Response},
+        };
+        use tokio::io::AsyncWriteExt as _;
+
+        LitterTray::try_with_async(async |_| {
+            let (mut client, server) = new_test_plumbing();
+            let cmd = Command::Put2(Put2Args {
+                filename: "file".into(),
+                options: vec![CommandParam::Resume.into()],
+            });
+            let (mut handler, _) = crate::session::factory::command_handler(
+                server,
+                cmd,
+                Compatibility::Level(5),
+                Configuration::system_default(),
+            );
+            let client_fut = async move {
+                let header = FileHeader::V2(FileHeaderV2 {
+                    size: serde_bare::Uint(10),
+                    filename: "file".into(),
+                    metadata: vec![],
+                });
+                header.to_writer_async_framed(&mut client.send).await?;
+                let _ = Response::from_reader_async_framed(&mut client.recv)
+                    .await?
+                    .into_result()?;
+                let _ = crate::protocol::session::ResumeReport::from_reader_async_framed(
+                    &mut client.recv,
+                )
+                .await?;
+                header.to_writer_async_framed(&mut client.send).await?;
+                // Send part of the file, then give up
+                client.send.write_all(b"0123").await?;
+                client.send.shutdown().await?;
+                anyhow::Ok(())
+            };
+            let (r1, _) = tokio::join!(client_fut, handler.handle());
+            r1?;
+            assert_eq!(std::fs::read_to_string("file")?, "0123");
+            Ok(())
+        })
+        .await
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn put_resume_to_unwritable_destination() -> Result<()> {
+        let params = Parameters {
+            resume: true,
+            ..Default::default()
+        };
+        LitterTray::try_with_async(async |tray| {
+            let _ = tray.create_text("file1", "0123456789")?;
+            // Not a regular file, so we cannot receive into it
+            let spec = CopyJobSpec::from_parts("file1", "server:/dev/null", false, false)?;
+            let (r1, r2) =
+                test_put_spec(&spec, params, Configuration::system_default(), 5, 5, false).await?;
+            // The server refuses before the data is sent, not once it has all arrived
+            let msg = format!("{:#}", r1.unwrap_err());
+            assert_contains!(msg, "not a regular file");
+            assert_not_contains!(msg, "completion");
+            assert!(r2.is_ok());
+            Ok(())
+        })
+        .await
+    }
+
     #[tokio::test]
     async fn put_to_login_dir() -> Result<()> {
         let contents = "wibble";
@@ -523,6 +937,125 @@ mod test {
         );
     }
 
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn put_sparse_file() -> Result<()> {
+        use std::io::{Seek as _, SeekFrom, Write as _};
+        use std::os::unix::fs::MetadataExt as _;
+        const MB: u64 = 1 << 20;
+
+        LitterTray::try_with_async(async |_| {
+            let mut file = std::fs::File::create("file1")?;
+            file.set_len(8 * MB)?;
+            let _ = file.seek(SeekFrom::Start(3 * MB))?;
+            file.write_all(&[42u8; 10_000])?;
+            file.sync_all()?;
+            drop(file);
+            let sparse = std::fs::metadata("file1")?.blocks() * 512 < 8 * MB;
+
+            let spec = CopyJobSpec::from_parts("file1", "server:file2", false, false)?;
+            // (compatibility level, whether we expect the holes to be preserved)
+            for (level, holes) in [(5, sparse), (4, false)] {
+                let (r1, r2) = test_put_spec(
+                    &spec,
+                    Parameters::default(),
+                    Configuration::system_default(),
+                    level,
+                    level,
+                    false,
+                )
+                .await?;
+                let stats = r1?.stats;
+                r2?;
+                assert_eq!(std::fs::read("file1")?, std::fs::read("file2")?);
+                assert_eq!(stats.payload_bytes < 8 * MB, holes);
+                assert_eq!(std::fs::metadata("file2")?.blocks() * 512 < 8 * MB, holes);
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn put_sparse_stripes_over_existing_file() -> Result<()> {
+        use std::io::{Seek as _, SeekFrom, Write as _};
+        const MB: u64 = 1 << 20;
+
+        LitterTray::try_with_async(async |tray| {
+            let mut file = std::fs::File::create("file1")?;
+            file.set_len(8 * MB)?;
+            let _ = file.seek(SeekFrom::Start(3 * MB))?;
+            file.write_all(&[42u8; 10_000])?;
+            file.sync_all()?;
+            drop(file);
+            // The destination already exists, is the same size, and holds no zeros
+            #[allow(clippy::cast_possible_truncation)]
+            let _ = tray.create_binary("file2", &vec![0xffu8; (8 * MB) as usize])?;
+
+            let mut spec = CopyJobSpec::from_parts("file1", "server:file2", false, false)?;
+            for range in [4 * MB..8 * MB, 0..4 * MB] {
+                spec.range = Some(range);
+                let (r1, r2) = test_put_spec(
+                    &spec,
+                    Parameters::default(),
+                    Configuration::system_default(),
+                    5,
+                    5,
+                    false,
+                )
+                .await?;
+                let _ = r1?;
+                r2?;
+            }
+            assert!(std::fs::read("file1")? == std::fs::read("file2")?);
+            Ok(())
+        })
+        .await
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn put_extended_attributes() -> Result<()> {
+        use crate::os::{AbstractPlatform as _, Platform};
+
+        LitterTray::try_with_async(async |tray| {
+            let _ = tray.create_text("file1", "wibble")?;
+            let file = std::fs::File::options().write(true).open("file1")?;
+            if Platform::set_extended_attribute(&file, "user.qcp.test", b"hello").is_err() {
+                // Not every filesystem supports user attributes
+                return Ok(());
+            }
+            let spec = CopyJobSpec::from_parts("file1", "server:file2", false, false)?;
+            // (whether requested, compatibility level, whether we expect the attribute)
+            for (xattrs, level, expected) in [(true, 5, true), (false, 5, false), (true, 4, false)]
+            {
+                let _ = std::fs::remove_file("file2");
+                let params = Parameters {
+                    xattrs,
+                    ..Default::default()
+                };
+                let (r1, r2) = test_put_spec(
+                    &spec,
+                    params,
+                    Configuration::system_default(),
+                    level,
+                    level,
+                    false,
+                )
+                .await?;
+                let _ = r1?;
+                r2?;
+                let attrs = Platform::extended_attributes("file2".as_ref())?;
+                assert_eq!(
+                    attrs.contains(&("user.qcp.test".to_string(), b"hello".to_vec())),
+                    expected
+                );
+            }
+            Ok(())
+        })
+        .await
+    }
+
     #[cfg(unix)]
     #[tokio::test]
     async fn put_preserves_execute_bit() {
@@ -589,6 +1122,72 @@ mod test {
         .unwrap();
     }
 
+    #[tokio::test]
+    async fn put_preserve_nanosecond_times() {
+        LitterTray::try_with_async(async |tray| {
+            let atime = SystemTime::from_unix_nanos(12345, 678_900);
+            let mtime = SystemTime::from_unix_nanos(654_321, 123_456_789);
+            let file = tray.create_text("hi", "hi")?;
+            file.set_times(FileTimes::new().set_accessed(atime).set_modified(mtime))?;
+            drop(file);
+
+            let (r1, r2) = test_putx_main("hi", "remote:hi2", 5, 5, false, true).await?;
+            assert!(r1.is_ok());
+            assert!(r2.is_ok());
+
+            let meta = std::fs::metadata("hi2")?;
+            assert_eq!(meta.modified()?, mtime);
+            assert_eq!(meta.accessed()?, atime);
+
+            // An older peer only gets whole seconds
+            let (r1, r2) = test_putx_main("hi", "remote:hi3", 4, 4, false, true).await?;
+            assert!(r1.is_ok());
+            assert!(r2.is_ok());
+            let meta = std::fs::metadata("hi3")?;
+            assert_eq!(meta.modified()?, SystemTime::from_unix(654_321));
+            Ok(())
+        })
+        .await
+        .unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn put_preserve_times_only() {
+        use std::fs::{Permissions, set_permissions};
+        use std::os::unix::fs::PermissionsExt as _;
+
+        LitterTray::try_with_async(async |tray| {
+            let file = tray.create_text("hi", "hi")?;
+            file.set_times(FileTimes::new().set_modified(SystemTime::from_unix(654_321)))?;
+            drop(file);
+            set_permissions("hi", Permissions::from_mode(0o755))?;
+            // Writing in place keeps the mode of the existing file, unless we change it
+            let _ = tray.create_text("hi2", "old")?;
+            set_permissions("hi2", Permissions::from_mode(0o600))?;
+
+            let mut spec = CopyJobSpec::from_parts("hi", "remote:hi2", false, false)?;
+            spec.preserve = PreserveSelection {
+                times: true,
+                ..Default::default()
+            };
+            let params = Parameters {
+                in_place: true,
+                ..Default::default()
+            };
+            let (r1, r2) =
+                test_put_spec(&spec, params, Configuration::system_default(), 5, 5, false).await?;
+            let _ = r1?;
+            r2?;
+            let meta = std::fs::metadata("hi2")?;
+            assert_eq!(meta.modified()?, SystemTime::from_unix(654_321));
+            assert_eq!(meta.permissions().mode() & 0o777, 0o600);
+            Ok(())
+        })
+        .await
+        .unwrap();
+    }
+
     async fn compat_put(client: u16, server: u16, preserve: bool) {
         LitterTray::try_with_async(async |tray| {
             let _ = tray.create_text("aa", "aa")?;
@@ -5,9 +5,13 @@ use anyhow::Result;
 use async_trait::async_trait;
 use cfg_if::cfg_if;
 use tokio::io::AsyncWriteExt;
-use tracing::trace;
+use tracing::{trace, warn};
+
+use std::fs::FileTimes;
+use std::path::Path;
 
 use crate::Parameters;
+use crate::os::{AbstractPlatform as _, Platform};
 use crate::protocol::common::{ProtocolMessage, ReceivingStream, SendingStream};
 use crate::protocol::compat::Feature;
 use crate::protocol::session::{Command, MetadataAttr, Response, SetMetadataArgs, Status};
@@ -15,6 +19,7 @@ use crate::session::handler::SessionCommandInner;
 use crate::session::{RequestResult, error_and_return, handler::CommandHandler};
 // Extension trait for std::fs::Metadata
 use crate::util::FsMetadataExt as _;
+use crate::util::xattr::{XattrSelection, apply_to_directory, collect, read_extended_attributes};
 
 #[cfg(unix)]
 use std::os::unix::fs::PermissionsExt as _;
@@ -29,7 +34,7 @@ impl CommandHandler for SetMetadataHandler {
         &mut self,
         inner: &mut SessionCommandInner<'a, S, R>,
         job: &crate::CopyJobSpec,
-        _params: Parameters,
+        params: Parameters,
     ) -> Result<RequestResult> {
         anyhow::ensure!(
             inner.compat.supports(Feature::MKDIR_SETMETA_LS),
@@ -46,18 +51,33 @@ impl CommandHandler for SetMetadataHandler {
 
         trace!("sending command");
         let mut outbound = &mut inner.stream.send;
+        let mut metadata = localmeta.tagged_data_for_dir(inner.compat);
+        metadata.retain(|md| job.preserve.keeps(md));
+        if inner.compat.supports(Feature::EXTENDED_ATTRIBUTES) {
+            metadata.extend(
+                read_extended_attributes(
+                    Path::new(&job.source.filename),
+                    XattrSelection::from_params(&params),
+                )
+                .await,
+            );
+        }
         let cmd = Command::SetMetadata(SetMetadataArgs {
             path: job.destination.filename.clone(),
-            metadata: localmeta.tagged_data_for_dir(inner.compat),
+            metadata,
             options: vec![],
         });
         cmd.to_writer_async_framed(&mut outbound).await?;
         outbound.flush().await?;
 
         trace!("await response");
-        let _ = Response::from_reader_async_framed(&mut inner.stream.recv)
+        let Response::V1(response) = Response::from_reader_async_framed(&mut inner.stream.recv)
             .await?
             .into_result()?;
+        if let Some(message) = response.message {
+            // Something went wrong with the metadata, but not badly enough to fail the request
+            warn!("{}: {message}", job.destination.filename);
+        }
         Ok(RequestResult::default())
     }
 
@@ -77,9 +97,21 @@ impl CommandHandler for SetMetadataHandler {
             error_and_return!(stream, Status::ItIsAFile);
         }
 
+        let mut new_perms = None;
+        let mut times = FileTimes::new();
+        let mut times_changed = false;
         for md in &args.metadata {
             match md.tag() {
-                None | Some(MetadataAttr::Invalid) => (),
+                // Times are gathered below, to full precision; extended attributes are applied below
+                None
+                | Some(
+                    MetadataAttr::Invalid
+                    | MetadataAttr::AccessTime
+                    | MetadataAttr::ModificationTime
+                    | MetadataAttr::AccessTimeNanos
+                    | MetadataAttr::ModificationTimeNanos
+                    | MetadataAttr::ExtendedAttribute,
+                ) => (),
                 Some(MetadataAttr::ModeBits) => {
                     static_assertions::assert_cfg!(
                         any(unix, windows),
@@ -91,9 +123,7 @@ impl CommandHandler for SetMetadataHandler {
                                 let mode = (mode & 0o777) as u32;
                                 let mut perms = localmeta.permissions();
                                 perms.set_mode(mode);
-                                if let Err(e)= tokio::fs::set_permissions(&path, perms).await {
-                                    error_and_return!(stream, e);
-                                }
+                                new_perms = Some(perms);
                             }
                         } else if #[cfg(windows)] {
                             // The Windows 'read only' attribute is not useful on a directory. Ignore for now.
@@ -104,7 +134,38 @@ impl CommandHandler for SetMetadataHandler {
                 Some(t) => anyhow::bail!("unknown metadata tag {t}"),
             }
         }
-        crate::session::common::send_ok(&mut stream.send).await
+        if let Some(t) = MetadataAttr::find_atime(&args.metadata) {
+            times = times.set_accessed(t);
+            times_changed = true;
+        }
+        if let Some(t) = MetadataAttr::find_mtime(&args.metadata) {
+            times = times.set_modified(t);
+            times_changed = true;
+        }
+        // Extended attributes and times first, in case the new permissions prevent us from opening the directory.
+        // (Setting the mode afterwards leaves any access ACL as it was, as the source mode agrees with it.)
+        let warnings = apply_to_directory(Path::new(path), collect(&args.metadata)).await;
+        if times_changed {
+            let owned = std::path::PathBuf::from(path);
+            let result =
+                tokio::task::spawn_blocking(move || Platform::set_directory_times(&owned, times))
+                    .await?;
+            if let Err(e) = result {
+                error_and_return!(stream, e);
+            }
+        }
+        if let Some(perms) = new_perms
+            && let Err(e) = tokio::fs::set_permissions(&path, perms).await
+        {
+            error_and_return!(stream, e);
+        }
+        if warnings.is_empty() {
+            crate::session::common::send_ok(&mut stream.send).await
+        } else {
+            // Report what we could not apply, without failing the request
+            crate::session::common::send_ok_with_message(&mut stream.send, &warnings.join("; "))
+                .await
+        }
     }
 }
 
@@ -129,18 +190,28 @@ mod test {
     async fn test_setmeta_main(
         local_path: &str,
         remote_path: &str,
+        compat: u16,
+    ) -> Result<(Result<RequestResult>, Result<()>)> {
+        test_setmeta_with(local_path, remote_path, compat, Parameters::default()).await
+    }
+
+    async fn test_setmeta_with(
+        local_path: &str,
+        remote_path: &str,
+        compat: u16,
+        params: Parameters,
     ) -> Result<(Result<RequestResult>, Result<()>)> {
         let (pipe1, mut pipe2) = new_test_plumbing();
+        // Directory metadata is only set when preserving
         let spec =
-            CopyJobSpec::from_parts(local_path, &format!("somehost:{remote_path}"), false, false)
+            CopyJobSpec::from_parts(local_path, &format!("somehost:{remote_path}"), true, false)
                 .unwrap();
-        let params = Parameters::default();
 
         let (mut sender, _) = crate::session::factory::client_sender(
             pipe1,
             &spec,
             crate::session::factory::TransferPhase::Post,
-            Compatibility::Level(4),
+            Compatibility::Level(compat),
             &params,
             None,
             Configuration::system_default(),
@@ -156,7 +227,7 @@ mod test {
         let (mut handler, _) = crate::session::factory::command_handler(
             pipe2,
             cmd,
-            Compatibility::Level(4),
+            Compatibility::Level(compat),
             Configuration::system_default(),
         );
 
@@ -192,7 +263,7 @@ mod test {
                     .expect("failed to set remote/testdir permissions");
             }
 
-            let (r1, r2) = test_setmeta_main("testdir", "remote/testdir").await?;
+            let (r1, r2) = test_setmeta_main("testdir", "remote/testdir", 4).await?;
             assert!(r1.is_ok());
             assert!(r2.is_ok());
             #[cfg(unix)]
@@ -212,7 +283,7 @@ mod test {
         LitterTray::try_with_async(async |tray| {
             let _ = tray.make_dir("local")?;
             let _ = tray.make_dir("remote")?;
-            let (r1, r2) = test_setmeta_main("local", "remote/xyzy").await?;
+            let (r1, r2) = test_setmeta_main("local", "remote/xyzy", 4).await?;
             let msg = r1.unwrap_err().to_string();
             assert_contains!(msg, "FileNotFound");
             assert!(r2.is_ok());
@@ -220,4 +291,70 @@ mod test {
         })
         .await
     }
+
+    #[tokio::test]
+    async fn setmeta_times() -> Result<()> {
+        use crate::os::{AbstractPlatform as _, Platform};
+        use std::time::{Duration, SystemTime};
+        LitterTray::try_with_async(async |tray| {
+            let _ = tray.make_dir("testdir")?;
+            let _ = tray.make_dir("remote")?;
+            let _ = tray.make_dir("remote/testdir")?;
+            let when = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+            Platform::set_directory_times(
+                std::path::Path::new("testdir"),
+                std::fs::FileTimes::new().set_modified(when),
+            )?;
+
+            // Older servers do not take times
+            let (r1, r2) = test_setmeta_main("testdir", "remote/testdir", 4).await?;
+            let _ = r1?;
+            r2?;
+            assert_ne!(std::fs::metadata("remote/testdir")?.modified()?, when);
+
+            let (r1, r2) = test_setmeta_main("testdir", "remote/testdir", 5).await?;
+            let _ = r1?;
+            r2?;
+            assert_eq!(std::fs::metadata("remote/testdir")?.modified()?, when);
+            Ok(())
+        })
+        .await
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn setmeta_extended_attributes() -> Result<()> {
+        use crate::os::{AbstractPlatform as _, Platform};
+
+        LitterTray::try_with_async(async |tray| {
+            let _ = tray.make_dir("testdir")?;
+            let _ = tray.make_dir("remote")?;
+            let dir = std::fs::File::open("testdir")?;
+            if Platform::set_extended_attribute(&dir, "user.qcp.test", b"hello").is_err() {
+                // Not every filesystem supports user attributes
+                return Ok(());
+            }
+            // (whether requested, compatibility level, whether we expect the attribute)
+            for (xattrs, level, expected) in [(true, 5, true), (false, 5, false), (true, 4, false)]
+            {
+                let _ = std::fs::remove_dir("remote/testdir");
+                let _ = tray.make_dir("remote/testdir")?;
+                let params = Parameters {
+                    xattrs,
+                    ..Default::default()
+                };
+                let (r1, r2) =
+                    test_setmeta_with("testdir", "remote/testdir", level, params).await?;
+                let _ = r1?;
+                r2?;
+                let attrs = Platform::extended_attributes("remote/testdir".as_ref())?;
+                assert_eq!(
+                    attrs.contains(&("user.qcp.test".to_string(), b"hello".to_vec())),
+                    expected
+                );
+            }
+            Ok(())
+        })
+        .await
+    }
 }
@@ -0,0 +1,201 @@
+//! Stat command (remote file or directory metadata)
+// (c) 2025 Ross Younger
+
+use anyhow::Result;
+use async_trait::async_trait;
+use serde_bare::Uint;
+use tokio::io::AsyncWriteExt;
+use tracing::{error, trace};
+
+use crate::Parameters;
+use crate::protocol::common::{ProtocolMessage, ReceivingStream, SendingStream};
+use crate::protocol::compat::Feature;
+use crate::protocol::session::{Command, ListData, ListEntry, Response, StatArgs, Status};
+use crate::session::common::send_ok;
+use crate::session::handler::{CommandHandler, SessionCommandInner};
+use crate::session::{CommandStats, RequestResult, error_and_return};
+use crate::util::FsMetadataExt as _;
+
+pub(crate) struct StatHandler;
+
+#[async_trait]
+impl CommandHandler for StatHandler {
+    type Args = StatArgs;
+
+    async fn send_impl<'a, S: SendingStream, R: ReceivingStream>(
+        &mut self,
+        inner: &mut SessionCommandInner<'a, S, R>,
+        job: &crate::client::CopyJobSpec,
+        _params: Parameters,
+    ) -> Result<RequestResult> {
+        anyhow::ensure!(
+            inner.compat.supports(Feature::STAT),
+            "Operation not supported by remote"
+        );
+        // Whichever side is remote
+        let path = if job.source.user_at_host.is_some() {
+            &job.source.filename
+        } else {
+            &job.destination.filename
+        };
+
+        trace!("sending command");
+        let mut outbound = &mut inner.stream.send;
+        let cmd = Command::Stat(StatArgs {
+            path: path.clone(),
+            options: vec![],
+        });
+        cmd.to_writer_async_framed(&mut outbound).await?;
+        outbound.flush().await?;
+
+        trace!("await response");
+        let result = Response::from_reader_async_framed(&mut inner.stream.recv).await?;
+        if result.status() != Status::Ok {
+            error!("Stat failed: {:?}", result);
+            return Err(anyhow::Error::new(result));
+        }
+        let entry = ListEntry::from_reader_async_framed(&mut inner.stream.recv)
+            .await
+            .map_err(|r| anyhow::anyhow!("failed to parse Stat response: {r}"))?;
+        Ok(RequestResult::new(
+            CommandStats::default(),
+            Some(ListData::new(vec![entry], false)),
+        ))
+    }
+
+    async fn handle_impl<'a, S: SendingStream, R: ReceivingStream>(
+        &mut self,
+        inner: &mut SessionCommandInner<'a, S, R>,
+        args: &StatArgs,
+    ) -> Result<()> {
+        let path = &args.path;
+        let stream = &mut inner.stream;
+
+        // Like List, this follows symbolic links
+        let meta = match tokio::fs::metadata(path).await {
+            Ok(meta) => meta,
+            Err(e) => {
+                error_and_return!(stream, e);
+            }
+        };
+        let entry = ListEntry {
+            name: path.clone(),
+            directory: meta.is_dir(),
+            size: Uint(if meta.is_dir() { 0 } else { meta.len() }),
+            attributes: meta.to_tagged_data(true),
+        };
+        send_ok(&mut stream.send).await?;
+        entry.to_writer_async_framed(&mut stream.send).await?;
+        stream.send.flush().await?;
+        trace!("complete");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+#[cfg_attr(coverage_nightly, coverage(off))]
+mod test {
+    use crate::protocol::session::{ListEntry, prelude::*};
+    use crate::{
+        Configuration, Parameters,
+        client::CopyJobSpec,
+        protocol::test_helpers::{new_test_plumbing, read_from_stream},
+    };
+    use anyhow::{Result, bail};
+    use littertray::LitterTray;
+    use pretty_assertions::assert_eq;
+
+    async fn test_stat_main(path: &str, compat: u16) -> Result<ListEntry> {
+        let (pipe1, mut pipe2) = new_test_plumbing();
+        let spec =
+            CopyJobSpec::from_parts(&format!("somehost:{path}"), "local", false, false).unwrap();
+        let params = Parameters::default();
+        let (mut sender, _) = crate::session::factory::client_sender(
+            pipe1,
+            &spec,
+            crate::session::factory::TransferPhase::Stat,
+            Compatibility::Level(compat),
+            &params,
+            None,
+            Configuration::system_default(),
+        );
+
+        let sender_fut = sender.send(&spec, params);
+        tokio::pin!(sender_fut);
+
+        let result = read_from_stream(&mut pipe2.recv, &mut sender_fut).await;
+        let cmd = result.expect_left("sender should not have completed early")?;
+        let Command::Stat(_) = cmd else {
+            bail!("expected Stat command");
+        };
+
+        let (mut handler, _) = crate::session::factory::command_handler(
+            pipe2,
+            cmd,
+            Compatibility::Level(compat),
+            Configuration::system_default(),
+        );
+        let (r1, r2) = tokio::join!(sender_fut, handler.handle());
+        r2.expect("handler should not have failed");
+        let mut list = r1?.list.expect("expected ListData in result");
+        assert_eq!(list.entries.len(), 1);
+        Ok(list.entries.remove(0))
+    }
+
+    #[tokio::test]
+    async fn stat_file() {
+        let entry = LitterTray::try_with_async(async |tray| {
+            let _ = tray.create_text("f", "hello")?;
+            test_stat_main("f", 5).await
+        })
+        .await
+        .unwrap();
+        assert_eq!(entry.name, "f");
+        assert!(!entry.directory);
+        assert_eq!(entry.size.0, 5);
+        assert!(entry.attributes.find_tag(MetadataAttr::ModeBits).is_some());
+        assert!(
+            entry
+                .attributes
+                .find_tag(MetadataAttr::ModificationTime)
+                .is_some()
+        );
+    }
+
+    #[tokio::test]
+    async fn stat_directory() {
+        let entry = LitterTray::try_with_async(async |tray| {
+            let _ = tray.make_dir("d")?;
+            test_stat_main("d", 5).await
+        })
+        .await
+        .unwrap();
+        assert!(entry.directory);
+        assert_eq!(entry.size.0, 0);
+    }
+
+    #[tokio::test]
+    async fn not_found() {
+        let err = LitterTray::try_with_async(async |_| test_stat_main("xyzy", 5).await)
+            .await
+            .unwrap_err();
+        assert_eq!(Status::from(err), Status::FileNotFound);
+    }
+
+    #[tokio::test]
+    async fn not_supported() {
+        let (pipe1, _pipe2) = new_test_plumbing();
+        let spec = CopyJobSpec::from_parts("somehost:f", "local", false, false).unwrap();
+        let (mut sender, _) = crate::session::factory::client_sender(
+            pipe1,
+            &spec,
+            crate::session::factory::TransferPhase::Stat,
+            Compatibility::Level(4),
+            &Parameters::default(),
+            None,
+            Configuration::system_default(),
+        );
+        let err = sender.send(&spec, Parameters::default()).await.unwrap_err();
+        assert_eq!(err.to_string(), "Operation not supported by remote");
+    }
+}
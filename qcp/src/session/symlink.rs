@@ -0,0 +1,218 @@
+//! Create Symlink command
+// (c) 2025 Ross Younger
+
+use anyhow::Result;
+use async_trait::async_trait;
+use tokio::io::AsyncWriteExt;
+use tracing::{debug, trace};
+
+use crate::Parameters;
+use crate::protocol::common::{ProtocolMessage, ReceivingStream, SendingStream};
+use crate::protocol::compat::Feature;
+use crate::protocol::session::{Command, CreateSymlinkArgs, Response};
+use crate::session::common::send_ok;
+use crate::session::handler::SessionCommandInner;
+use crate::session::{RequestResult, error_and_return, handler::CommandHandler};
+
+/// Creates a symbolic link at `path` pointing to `target`.
+///
+/// Any existing file or link at `path` is replaced; an existing directory is not.
+pub(crate) async fn create_symlink(target: &str, path: &str) -> std::io::Result<()> {
+    if let Ok(meta) = tokio::fs::symlink_metadata(path).await
+        && !meta.is_dir()
+    {
+        tokio::fs::remove_file(path).await?;
+    }
+    #[cfg(windows)]
+    return tokio::fs::symlink_file(target, path).await;
+    #[cfg(not(windows))]
+    tokio::fs::symlink(target, path).await
+}
+
+pub(crate) struct CreateSymlinkHandler;
+
+#[async_trait]
+impl CommandHandler for CreateSymlinkHandler {
+    type Args = CreateSymlinkArgs;
+
+    async fn send_impl<'a, S: SendingStream, R: ReceivingStream>(
+        &mut self,
+        inner: &mut SessionCommandInner<'a, S, R>,
+        job: &crate::client::CopyJobSpec,
+        _params: Parameters,
+    ) -> Result<RequestResult> {
+        anyhow::ensure!(
+            inner.compat.supports(Feature::SYMLINKS),
+            "Operation not supported by remote"
+        );
+        let Some(target) = &job.link_target else {
+            anyhow::bail!("logic error: symlink job has no target");
+        };
+
+        trace!("sending command");
+        let mut outbound = &mut inner.stream.send;
+        let cmd = Command::CreateSymlink(CreateSymlinkArgs {
+            path: job.destination.filename.clone(),
+            target: target.clone(),
+            options: vec![],
+        });
+        cmd.to_writer_async_framed(&mut outbound).await?;
+        outbound.flush().await?;
+
+        trace!("await response");
+        let _ = Response::from_reader_async_framed(&mut inner.stream.recv)
+            .await?
+            .into_result()?;
+        Ok(RequestResult::default())
+    }
+
+    async fn handle_impl<'a, S: SendingStream, R: ReceivingStream>(
+        &mut self,
+        inner: &mut SessionCommandInner<'a, S, R>,
+        args: &CreateSymlinkArgs,
+    ) -> Result<()> {
+        let stream = &mut inner.stream;
+        if let Err(e) = create_symlink(&args.target, &args.path).await {
+            debug!("Could not create symlink: {e}");
+            error_and_return!(stream, e);
+        }
+        send_ok(&mut stream.send).await
+    }
+}
+
+#[cfg(test)]
+#[cfg_attr(coverage_nightly, coverage(off))]
+mod test {
+    use crate::{
+        Configuration, Parameters,
+        client::CopyJobSpec,
+        protocol::{
+            control::Compatibility,
+            session::Command,
+            test_helpers::{new_test_plumbing, read_from_stream},
+        },
+        session::RequestResult,
+    };
+    use anyhow::{Result, bail};
+    use littertray::LitterTray;
+    use pretty_assertions::assert_eq;
+
+    async fn test_symlink_main(
+        path: &str,
+        target: &str,
+        compat: u16,
+    ) -> Result<(Result<RequestResult>, Result<()>)> {
+        let (pipe1, mut pipe2) = new_test_plumbing();
+        let mut spec =
+            CopyJobSpec::from_parts(path, &format!("somehost:{path}"), false, false).unwrap();
+        spec.link_target = Some(target.to_string());
+
+        let params = Parameters::default();
+        let (mut sender, _) = crate::session::factory::client_sender(
+            pipe1,
+            &spec,
+            crate::session::factory::TransferPhase::Transfer,
+            Compatibility::Level(compat),
+            &params,
+            None,
+            Configuration::system_default(),
+        );
+
+        let sender_fut = sender.send(&spec, params);
+        tokio::pin!(sender_fut);
+
+        let result = read_from_stream(&mut pipe2.recv, &mut sender_fut).await;
+        let cmd = result.expect_left("sender should not have completed early")?;
+        let Command::CreateSymlink(ref args) = cmd else {
+            bail!("expected CreateSymlink command");
+        };
+        assert_eq!(args.target, target);
+
+        let (mut handler, _) = crate::session::factory::command_handler(
+            pipe2,
+            cmd,
+            Compatibility::Level(compat),
+            Configuration::system_default(),
+        );
+        let (r1, r2) = tokio::join!(sender_fut, handler.handle());
+        Ok((r1, r2))
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn symlink_created() -> Result<()> {
+        LitterTray::try_with_async(async |tray| {
+            let _ = tray.create_text("existing", "replace me")?;
+            for path in ["new", "existing"] {
+                let (r1, r2) = test_symlink_main(path, "../elsewhere", 5).await?;
+                let _ = r1?;
+                r2?;
+                assert_eq!(std::fs::read_link(path)?.to_str(), Some("../elsewhere"));
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn symlink_does_not_replace_directory() -> Result<()> {
+        LitterTray::try_with_async(async |tray| {
+            let _ = tray.make_dir("d")?;
+            let (r1, r2) = test_symlink_main("d", "target", 5).await?;
+            let _ = r1.unwrap_err();
+            r2?;
+            assert!(std::fs::symlink_metadata("d")?.is_dir());
+            Ok(())
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn not_supported() {
+        use crate::session::{
+            SessionCommandImpl as _,
+            handler::{CreateSymlinkHandler, SessionCommand},
+        };
+        let (pipe1, _pipe2) = new_test_plumbing();
+        let mut spec = CopyJobSpec::from_parts("l", "somehost:l", false, false).unwrap();
+        spec.link_target = Some("t".to_string());
+        let mut sender = SessionCommand::boxed(
+            pipe1,
+            CreateSymlinkHandler,
+            None,
+            Compatibility::Level(4),
+            Configuration::system_default(),
+            None,
+        );
+        let err = sender.send(&spec, Parameters::default()).await.unwrap_err();
+        assert_eq!(err.to_string(), "Operation not supported by remote");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn falls_back_to_put() -> Result<()> {
+        LitterTray::try_with_async(async |tray| {
+            let _ = tray.create_text("t", "contents")?;
+            let _ = tray.make_symlink("t", "l")?;
+            let (pipe1, mut pipe2) = new_test_plumbing();
+            let mut spec = CopyJobSpec::from_parts("l", "somehost:l", false, false).unwrap();
+            spec.link_target = Some("t".to_string());
+            let (mut sender, _) = crate::session::factory::client_sender(
+                pipe1,
+                &spec,
+                crate::session::factory::TransferPhase::Transfer,
+                Compatibility::Level(4),
+                &Parameters::default(),
+                None,
+                Configuration::system_default(),
+            );
+            let sender_fut = sender.send(&spec, Parameters::default());
+            tokio::pin!(sender_fut);
+            let result = read_from_stream(&mut pipe2.recv, &mut sender_fut).await;
+            let cmd = result.expect_left("sender should not have completed early")?;
+            assert!(matches!(cmd, Command::Put2(_)), "{cmd:?}");
+            Ok(())
+        })
+        .await
+    }
+}
@@ -23,16 +23,26 @@ use crate::{
     util::PortRange,
 };
 
+mod fixed_rate;
+pub use fixed_rate::FixedRateConfig;
+mod ledbat;
+pub use ledbat::LedbatConfig;
+
 /// A wrapping trait for congestion controller factories. Needed to be able to
 /// debug-print them as `TransportConfig` does not.
 pub trait DebugControllerFactory: quinn::congestion::ControllerFactory + std::fmt::Debug {}
 impl DebugControllerFactory for BbrConfig {}
 impl DebugControllerFactory for CubicConfig {}
 impl DebugControllerFactory for NewRenoConfig {}
+impl DebugControllerFactory for FixedRateConfig {}
+impl DebugControllerFactory for LedbatConfig {}
 
 /// Keepalive interval for the QUIC connection
 pub(crate) const PROTOCOL_KEEPALIVE: Duration = Duration::from_secs(5);
 
+/// How often each endpoint checks whether the network path of the connection has changed
+pub(crate) const PATH_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
 const META_CLIENT: &str = "requested by client";
 const META_NEGOTIATED: &str = "config resolution logic";
 
@@ -49,6 +59,74 @@ pub enum ThroughputMode {
     Both,
 }
 
+/// Selects and configures the congestion controller
+fn congestion_controller(
+    params: &Configuration,
+    compat: Compatibility,
+    config: &mut TransportConfig,
+) -> Result<Arc<dyn DebugControllerFactory>> {
+    let window = params.initial_congestion_window;
+    let congestion: Arc<dyn DebugControllerFactory> = match params.congestion {
+        CongestionController::Cubic => {
+            let mut cubic = CubicConfig::default();
+            if window != 0 {
+                let _ = cubic.initial_window(window);
+            }
+            let factory = Arc::new(cubic);
+            let _ = config.congestion_controller_factory(factory.clone());
+            factory
+        }
+        CongestionController::Bbr => {
+            let mut bbr = BbrConfig::default();
+            if window != 0 {
+                let _ = bbr.initial_window(window);
+            }
+            let factory = Arc::new(bbr);
+            let _ = config.congestion_controller_factory(factory.clone());
+            factory
+        }
+        CongestionController::NewReno => {
+            anyhow::ensure!(
+                compat.supports(Feature::NEW_RENO),
+                "Remote host does not support NewReno"
+            );
+            let mut newreno = NewRenoConfig::default();
+            if window != 0 {
+                let _ = newreno.initial_window(window);
+            }
+            let factory = Arc::new(newreno);
+            let _ = config.congestion_controller_factory(factory.clone());
+            factory
+        }
+        CongestionController::FixedRate => {
+            anyhow::ensure!(
+                compat.supports(Feature::FIXED_RATE),
+                "Remote host does not support FixedRate"
+            );
+            // The initial window is derived from the rate, so initial_congestion_window does not apply.
+            // Each connection sends its share of the rate.
+            let rate = params.tx() / u64::from(params.connections.max(1));
+            let factory = Arc::new(FixedRateConfig::new(rate, params.rtt_duration()));
+            let _ = config.congestion_controller_factory(factory.clone());
+            factory
+        }
+        CongestionController::Ledbat => {
+            anyhow::ensure!(
+                compat.supports(Feature::LEDBAT),
+                "Remote host does not support Ledbat"
+            );
+            let mut ledbat = LedbatConfig::default();
+            if window != 0 {
+                let _ = ledbat.initial_window(window);
+            }
+            let factory = Arc::new(ledbat);
+            let _ = config.congestion_controller_factory(factory.clone());
+            factory
+        }
+    };
+    Ok(congestion)
+}
+
 /// Creates a `quinn::TransportConfig` for the endpoint setup.
 /// Also returns the `quinn_proto::congestion::ControllerFactory` for testing.
 pub fn create_config(
@@ -61,7 +139,7 @@ pub fn create_config(
 
     let mut config = TransportConfig::default();
     let _ = config
-        .max_concurrent_bidi_streams(1u8.into())
+        .max_concurrent_bidi_streams(params.parallel_streams.into())
         .max_concurrent_uni_streams(0u8.into())
         .keep_alive_interval(Some(PROTOCOL_KEEPALIVE))
         .allow_spin(true)
@@ -87,51 +165,19 @@ pub fn create_config(
     }
 
     match mode {
-        // TODO: If we later support multiple streams at once, will need to consider receive_window and stream_receive_window.
         ThroughputMode::Rx | ThroughputMode::Both => {
+            // The BDP is shared between all the streams that may be open at once.
             let rwnd: VarInt = params.recv_window().try_into()?;
+            let stream_rwnd: VarInt = params.stream_recv_window().try_into()?;
             let _ = config
                 .receive_window(rwnd) // Not strictly essential as quinn defaults to unlimited
-                .stream_receive_window(rwnd) // essential; quinn defaults to 100Mbits x 100ms
+                .stream_receive_window(stream_rwnd) // essential; quinn defaults to 100Mbits x 100ms
                 .datagram_receive_buffer_size(Some(udp_buf));
         }
         ThroughputMode::Tx => (),
     }
 
-    let window = params.initial_congestion_window;
-    let congestion: Arc<dyn DebugControllerFactory> = match params.congestion {
-        CongestionController::Cubic => {
-            let mut cubic = CubicConfig::default();
-            if window != 0 {
-                let _ = cubic.initial_window(window);
-            }
-            let factory = Arc::new(cubic);
-            let _ = config.congestion_controller_factory(factory.clone());
-            factory
-        }
-        CongestionController::Bbr => {
-            let mut bbr = BbrConfig::default();
-            if window != 0 {
-                let _ = bbr.initial_window(window);
-            }
-            let factory = Arc::new(bbr);
-            let _ = config.congestion_controller_factory(factory.clone());
-            factory
-        }
-        CongestionController::NewReno => {
-            anyhow::ensure!(
-                compat.supports(Feature::NEW_RENO),
-                "Remote host does not support NewReno"
-            );
-            let mut newreno = NewRenoConfig::default();
-            if window != 0 {
-                let _ = newreno.initial_window(window);
-            }
-            let factory = Arc::new(newreno);
-            let _ = config.congestion_controller_factory(factory.clone());
-            factory
-        }
-    };
+    let congestion = congestion_controller(params, compat, &mut config)?;
 
     debug!(
         "Final network configuration: {}",
@@ -152,8 +198,9 @@ pub fn create_config(
         ""
     } else {
         &format!(
-            "; recv window {}, recv buffer {}",
+            "; recv window {} ({} per stream), recv buffer {}",
             params.recv_window().human_count_bytes(),
+            params.stream_recv_window().human_count_bytes(),
             udp_buf.human_count_bytes()
         )
     };
@@ -221,6 +268,20 @@ where
     Ok(())
 }
 
+/// If either side asks for the low-priority controller, use it; otherwise the two must agree.
+fn resolve_congestion(
+    cli: CongestionController,
+    srv: CongestionController,
+) -> CombinationResponse<CongestionController> {
+    match (cli, srv) {
+        (CongestionController::Ledbat, _) => CombinationResponse::Client,
+        (_, CongestionController::Ledbat) => CombinationResponse::Server,
+        _ => CombinationResponse::Failure(anyhow::anyhow!(
+            "server and client have incompatible congestion algorithm requirements"
+        )),
+    }
+}
+
 fn min_ignoring_zero(cli: u64, srv: u64) -> CombinationResponse<u64> {
     match (cli, srv) {
         (0, _) => CombinationResponse::Server,
@@ -249,9 +310,14 @@ use crate::protocol::control::ClientMessageV1;
 /// | Client [`rx`](Configuration#structfield.rx) / Server [`tx`](Configuration#structfield.tx) | [`bandwidth_to_client`](ClientMessageV1#structfield.bandwidth_to_client) | Use the smaller of the two (ignoring zeroes) |
 /// | Client [`tx`](Configuration#structfield.tx) / Server [`rx`](Configuration#structfield.rx) | [`bandwidth_to_server`](ClientMessageV1#structfield.bandwidth_to_server) | Use the smaller of the two (ignoring zeroes) |
 /// | [`rtt`](Configuration#structfield.rtt) |  [`rtt`](ClientMessageV1#structfield.rtt) | Client preference wins |
-/// | [`congestion`](Configuration#structfield.congestion) | [`congestion`](ClientMessageV1#structfield.congestion) | If the two prefs match, use that; if not, error |
+/// | [`congestion`](Configuration#structfield.congestion) | [`congestion`](ClientMessageV1#structfield.congestion) | If the two prefs match, use that. If either is [`ledbat`](CongestionController::Ledbat), use that. Otherwise, error |
 /// | [`initial_congestion_window`](Configuration#structfield.initial_congestion_window) | [`initial_congestion_window`](ClientMessageV1#structfield.initial_congestion_window) | Client preference wins |
 /// | [`timeout`](Configuration#structfield.timeout) | [`timeout`](ClientMessageV1#structfield.timeout) | Client preference wins |
+/// | [`parallel_streams`](Configuration#structfield.parallel_streams) | [`ParallelStreams`](ClientMessage2Attributes::ParallelStreams) attribute | Use the smaller of the two |
+/// | Client [`limit_rate_tx`](Configuration#structfield.limit_rate_tx) / Server [`limit_rate_rx`](Configuration#structfield.limit_rate_rx) | [`RateLimitToServer`](ClientMessage2Attributes::RateLimitToServer) attribute | Use the smaller of the two (ignoring zeroes) |
+/// | Client [`limit_rate_rx`](Configuration#structfield.limit_rate_rx) / Server [`limit_rate_tx`](Configuration#structfield.limit_rate_tx) | [`RateLimitToClient`](ClientMessage2Attributes::RateLimitToClient) attribute | Use the smaller of the two (ignoring zeroes) |
+/// | [`max_udp_payload_size`](Configuration#structfield.max_udp_payload_size) | [`MaxUdpPayloadSize`](ClientMessage2Attributes::MaxUdpPayloadSize) attribute | Use the smaller of the two |
+/// | [`connections`](Configuration#structfield.connections) | [`Connections`](ClientMessage2Attributes::Connections) attribute | Use the smaller of the two |
 /// | Client [`remote_port`](Configuration#structfield.remote_port) / Server [`port`](ClientMessageV1#structfield.port) | [`port`](ClientMessageV1#structfield.port) | Treat port `0` as "no preference". Compute the intersection of the two ranges. If they do not intersect, error. |
 ///
 /// # Outputs
@@ -264,6 +330,7 @@ use crate::protocol::control::ClientMessageV1;
 /// * If the resultant [`Configuration`] fails validation checks
 /// * If the two configurations cannot be satisfactorily combined
 ///
+#[allow(clippy::too_many_lines)]
 pub fn combine_bandwidth_configurations(
     manager: &mut Manager,
     client: &ClientMessageV2,
@@ -316,14 +383,7 @@ pub fn combine_bandwidth_configurations(
         .find_tag(ClientMessage2Attributes::CongestionControllerType)
         .map(Variant::coerce_unsigned)
         .and_then(|v| CongestionController::from_repr(v.as_()));
-    negotiate!(
-        cctrl,
-        server.congestion,
-        |_: CongestionController, _| CombinationResponse::Failure(anyhow::anyhow!(
-            "server and client have incompatible congestion algorithm requirements"
-        )),
-        "congestion"
-    )?;
+    negotiate!(cctrl, server.congestion, resolve_congestion, "congestion")?;
     negotiate!(
         ca.find_tag(ClientMessage2Attributes::InitialCongestionWindow)
             .map(Variant::coerce_unsigned),
@@ -357,6 +417,41 @@ pub fn combine_bandwidth_configurations(
         |_: u16, _| CombinationResponse::Client,
         "timeout"
     )?;
+    negotiate!(
+        ca.find_tag(ClientMessage2Attributes::ParallelStreams)
+            .map(|v| (v.coerce_unsigned() & 0xffff) as u16),
+        server.parallel_streams,
+        |cc: u16, ss| CombinationResponse::Combined(std::cmp::min(cc, ss)),
+        "parallel_streams"
+    )?;
+    negotiate!(
+        ca.find_tag(ClientMessage2Attributes::RateLimitToServer)
+            .map(Variant::coerce_unsigned),
+        server.limit_rate_rx,
+        min_ignoring_zero,
+        "limit_rate_rx"
+    )?;
+    negotiate!(
+        ca.find_tag(ClientMessage2Attributes::RateLimitToClient)
+            .map(Variant::coerce_unsigned),
+        server.limit_rate_tx,
+        min_ignoring_zero,
+        "limit_rate_tx"
+    )?;
+    negotiate!(
+        ca.find_tag(ClientMessage2Attributes::MaxUdpPayloadSize)
+            .map(|v| (v.coerce_unsigned() & 0xffff) as u16),
+        server.max_udp_payload_size,
+        |cc: u16, ss| CombinationResponse::Combined(std::cmp::min(cc, ss)),
+        "max_udp_payload_size"
+    )?;
+    negotiate!(
+        ca.find_tag(ClientMessage2Attributes::Connections)
+            .map(|v| (v.coerce_unsigned() & 0xffff) as u16),
+        server.connections,
+        |cc: u16, ss| CombinationResponse::Combined(std::cmp::min(cc, ss)),
+        "connections"
+    )?;
 
     // Convert selected fields to human-friendly representations
     make_dict_human_friendly(client_picks.borrow());
@@ -387,6 +482,8 @@ fn make_entry_human_friendly(
 fn make_dict_human_friendly(dict: &mut figment::value::Dict) {
     make_entry_human_friendly(dict.entry("rx".into()));
     make_entry_human_friendly(dict.entry("tx".into()));
+    make_entry_human_friendly(dict.entry("limit_rate_rx".into()));
+    make_entry_human_friendly(dict.entry("limit_rate_tx".into()));
 }
 
 #[cfg(test)]
@@ -434,6 +531,19 @@ mod tests {
         assert_contains!(str, "send_window: 3648000"); //
     }
 
+    #[test]
+    fn parallel_streams_share_receive_window() {
+        let mut cfg = Configuration::system_default().clone();
+        cfg.rx = 1_000_000;
+        cfg.rtt = 456;
+        cfg.parallel_streams = 4;
+
+        let (str, _) = process_config(&cfg, ThroughputMode::Rx);
+        assert_contains!(str, "max_concurrent_bidi_streams: 4");
+        assert_contains!(str, "stream_receive_window: 114000");
+        assert_contains!(str, "receive_window: 456000");
+    }
+
     #[test]
     fn congestion_config() {
         use crate::protocol::control::CongestionController::*;
@@ -454,6 +564,27 @@ mod tests {
         let (_, str) = process_config(&cfg, ThroughputMode::Both);
         assert_contains!(str, "NewRenoConfig");
         assert_contains!(str, "initial_window: 1000");
+
+        cfg.congestion = FixedRate;
+        cfg.tx = 5_000_000;
+        let (_, congestion) =
+            create_config(&cfg, ThroughputMode::Both, Compatibility::Level(5)).unwrap();
+        let str = format!("{congestion:#?}");
+        assert_contains!(str, "FixedRateConfig");
+        assert_contains!(str, "rate: 5000000");
+
+        cfg.connections = 4;
+        let (_, congestion) =
+            create_config(&cfg, ThroughputMode::Both, Compatibility::Level(5)).unwrap();
+        assert_contains!(format!("{congestion:#?}"), "rate: 1250000");
+        cfg.connections = 1;
+
+        cfg.congestion = Ledbat;
+        let (_, congestion) =
+            create_config(&cfg, ThroughputMode::Both, Compatibility::Level(5)).unwrap();
+        let str = format!("{congestion:#?}");
+        assert_contains!(str, "LedbatConfig");
+        assert_contains!(str, "initial_window: Some(\n        1000,\n    )");
     }
 
     #[test]
@@ -464,6 +595,14 @@ mod tests {
         let e = create_config(&cfg, ThroughputMode::Both, Compatibility::Level(1)).unwrap_err();
         eprintln!("{e}");
         assert_contains!(e.to_string(), "Remote host does not support NewReno");
+
+        cfg.congestion = crate::protocol::control::CongestionController::FixedRate;
+        let e = create_config(&cfg, ThroughputMode::Both, Compatibility::Level(4)).unwrap_err();
+        assert_contains!(e.to_string(), "Remote host does not support FixedRate");
+
+        cfg.congestion = crate::protocol::control::CongestionController::Ledbat;
+        let e = create_config(&cfg, ThroughputMode::Both, Compatibility::Level(4)).unwrap_err();
+        assert_contains!(e.to_string(), "Remote host does not support Ledbat");
     }
     #[test]
     fn congestion_config_incompat() {
@@ -492,6 +631,27 @@ mod tests {
         );
     }
 
+    #[test]
+    fn congestion_ledbat_wins() {
+        use crate::protocol::control::CongestionController::{Cubic, Ledbat};
+        for (client, server) in [(Ledbat, Cubic), (Cubic, Ledbat)] {
+            let server_cfg = Configuration_Optional {
+                congestion: Some(server),
+                ..Default::default()
+            };
+            let mut mgr = Manager::without_files(None);
+            mgr.merge_provider(server_cfg);
+            let mp = crate::protocol::control::ClientMessageV2 {
+                attributes: vec![
+                    ClientMessage2Attributes::CongestionControllerType.with_unsigned(client as u64),
+                ],
+                ..Default::default()
+            };
+            let result = combine_bandwidth_configurations(&mut mgr, &mp).unwrap();
+            assert_eq!(result.congestion, Ledbat);
+        }
+    }
+
     #[test]
     fn negotiation() {
         // server config
@@ -513,6 +673,7 @@ mod tests {
             ClientMessage2Attributes::PortRangeEnd.with_unsigned(1500u64),
             ClientMessage2Attributes::InitialCongestionWindow.with_unsigned(500u64),
             ClientMessage2Attributes::RoundTripTime.with_unsigned(1234u64),
+            ClientMessage2Attributes::ParallelStreams.with_unsigned(8u64),
         ];
         let mp = crate::protocol::control::ClientMessageV2 {
             attributes,
@@ -529,6 +690,7 @@ mod tests {
                 },
                 rtt: 1234,
                 initial_congestion_window: 1000,
+                parallel_streams: 8,
                 ..
             }
         );
@@ -593,7 +755,7 @@ mod tests {
         let smsg = ServerMessage::new(
             OUR_COMPATIBILITY_LEVEL,
             &cfg,
-            1234,
+            &[1234],
             cert,
             "test".into(),
             String::new(),
@@ -687,6 +849,10 @@ mod tests {
         let server_cfg = Configuration_Optional {
             rx: Some(222_111),
             tx: Some(333_444),
+            parallel_streams: Some(3),
+            limit_rate_rx: Some(50_000),
+            max_udp_payload_size: Some(8972),
+            connections: Some(2),
             ..Default::default()
         };
         let mut mgr = Manager::new(None, false, false);
@@ -695,6 +861,11 @@ mod tests {
         let attributes = vec![
             ClientMessage2Attributes::BandwidthToClient.with_unsigned(987_654u32), // greater than the other, so that one wins
             ClientMessage2Attributes::BandwidthToServer.with_unsigned(123_456u32), // lower than the other, so this one wins
+            ClientMessage2Attributes::ParallelStreams.with_unsigned(16u32), // the server limit wins
+            ClientMessage2Attributes::RateLimitToServer.with_unsigned(80_000u32), // the server limit wins
+            ClientMessage2Attributes::RateLimitToClient.with_unsigned(70_000u32), // the server has no limit, so this one wins
+            ClientMessage2Attributes::MaxUdpPayloadSize.with_unsigned(4000u32), // the smaller wins
+            ClientMessage2Attributes::Connections.with_unsigned(4u32), // the server limit wins
         ];
         let cmsg = crate::protocol::control::ClientMessageV2 {
             attributes,
@@ -705,5 +876,10 @@ mod tests {
         // this is a server-oriented configuration
         assert_eq!(c.tx, 333_444);
         assert_eq!(c.rx, 123_456);
+        assert_eq!(c.parallel_streams, 3);
+        assert_eq!(c.limit_rate_rx, 50_000);
+        assert_eq!(c.limit_rate_tx, 70_000);
+        assert_eq!(c.max_udp_payload_size, 4000);
+        assert_eq!(c.connections, 2);
     }
 }
@@ -0,0 +1,151 @@
+//! Fixed-rate congestion controller
+// (c) 2025 Ross Younger
+//!
+//! This controller does not probe for capacity, and does not back off when packets are lost.
+//! It keeps enough data in flight to send at a constant rate, given the measured round-trip time.
+//! (The Quinn pacer then spreads those packets evenly over each round trip.)
+//!
+//! This is only appropriate for dedicated circuits with guaranteed capacity.
+//! On a shared or congested network, it will cause heavy packet loss, for us and for everybody else.
+
+use std::{any::Any, sync::Arc, time::Duration, time::Instant};
+
+use quinn::congestion::{Controller, ControllerFactory};
+use quinn_proto::RttEstimator;
+
+/// The smallest window we use, in packets
+const MINIMUM_WINDOW_PACKETS: u64 = 2;
+
+/// Configuration for the fixed-rate congestion controller
+#[derive(Debug, Clone, Copy)]
+pub struct FixedRateConfig {
+    /// Target rate, in bytes per second
+    rate: u64,
+    /// Round-trip time to assume until we have measured one
+    initial_rtt: Duration,
+}
+
+impl FixedRateConfig {
+    /// Constructor.
+    ///
+    /// * `rate` is the rate to send at, in bytes per second
+    /// * `initial_rtt` is the round-trip time to assume until one has been measured
+    #[must_use]
+    pub fn new(rate: u64, initial_rtt: Duration) -> Self {
+        Self { rate, initial_rtt }
+    }
+
+    /// The congestion window needed to send at our rate, for a given round-trip time
+    fn window(&self, rtt: Duration, mtu: u64) -> u64 {
+        let bytes = u128::from(self.rate) * rtt.as_micros() / 1_000_000;
+        u64::try_from(bytes)
+            .unwrap_or(u64::MAX)
+            .max(MINIMUM_WINDOW_PACKETS * mtu)
+    }
+}
+
+impl ControllerFactory for FixedRateConfig {
+    fn build(self: Arc<Self>, _now: Instant, current_mtu: u16) -> Box<dyn Controller> {
+        Box::new(FixedRate::new(self, current_mtu))
+    }
+}
+
+/// The fixed-rate congestion controller
+#[derive(Debug, Clone)]
+struct FixedRate {
+    config: Arc<FixedRateConfig>,
+    current_mtu: u64,
+    window: u64,
+}
+
+impl FixedRate {
+    fn new(config: Arc<FixedRateConfig>, current_mtu: u16) -> Self {
+        let current_mtu = u64::from(current_mtu);
+        Self {
+            window: config.window(config.initial_rtt, current_mtu),
+            current_mtu,
+            config,
+        }
+    }
+}
+
+impl Controller for FixedRate {
+    fn on_ack(
+        &mut self,
+        _now: Instant,
+        _sent: Instant,
+        _bytes: u64,
+        _app_limited: bool,
+        rtt: &RttEstimator,
+    ) {
+        self.window = self.config.window(rtt.get(), self.current_mtu);
+    }
+
+    fn on_congestion_event(
+        &mut self,
+        _now: Instant,
+        _sent: Instant,
+        _is_persistent_congestion: bool,
+        _lost_bytes: u64,
+    ) {
+        // This is the point: we do not back off.
+    }
+
+    fn on_mtu_update(&mut self, new_mtu: u16) {
+        self.current_mtu = new_mtu.into();
+        self.window = self.window.max(MINIMUM_WINDOW_PACKETS * self.current_mtu);
+    }
+
+    fn window(&self) -> u64 {
+        self.window
+    }
+
+    fn clone_box(&self) -> Box<dyn Controller> {
+        Box::new(self.clone())
+    }
+
+    fn initial_window(&self) -> u64 {
+        self.config
+            .window(self.config.initial_rtt, self.current_mtu)
+    }
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
+
+#[cfg(test)]
+#[cfg_attr(coverage_nightly, coverage(off))]
+mod test {
+    use std::{
+        sync::Arc,
+        time::{Duration, Instant},
+    };
+
+    use pretty_assertions::assert_eq;
+    use quinn::congestion::ControllerFactory as _;
+
+    use super::FixedRateConfig;
+
+    #[test]
+    fn window_follows_rate_not_loss() {
+        // 10MB/s at 100ms is 1MB in flight
+        let config = Arc::new(FixedRateConfig::new(10_000_000, Duration::from_millis(100)));
+        let now = Instant::now();
+        let mut uut = config.build(now, 1200);
+        assert_eq!(uut.initial_window(), 1_000_000);
+        assert_eq!(uut.window(), 1_000_000);
+
+        uut.on_congestion_event(now, now, true, 100_000);
+        assert_eq!(uut.window(), 1_000_000);
+    }
+
+    #[test]
+    fn minimum_window() {
+        let config = Arc::new(FixedRateConfig::new(1000, Duration::from_millis(10)));
+        let mut uut = config.build(Instant::now(), 1200);
+        assert_eq!(uut.window(), 2400);
+        uut.on_mtu_update(1500);
+        assert_eq!(uut.window(), 3000);
+    }
+}
@@ -0,0 +1,227 @@
+//! Low-priority (LEDBAT-style) congestion controller
+// (c) 2025 Ross Younger
+//!
+//! This is a "scavenger" or less-than-best-effort controller, after RFC 6817.
+//! Rather than waiting for packet loss, it watches the queueing delay: the amount by which the
+//! round-trip time exceeds the smallest round-trip time seen on the connection.
+//! While the queueing delay is below a small target, the window grows; as soon as it rises above the
+//! target (i.e. somebody else is using the link and queues are building), the window shrinks.
+//!
+//! The effect is that a transfer using this controller makes use of spare capacity, but yields
+//! to other traffic on the link, including standard TCP flows.
+
+use std::{
+    any::Any,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use quinn::congestion::{Controller, ControllerFactory};
+use quinn_proto::RttEstimator;
+
+/// The queueing delay we aim for.
+///
+/// RFC 6817 allows up to 100ms; we use the smaller LEDBAT++ target, so that we yield earlier.
+const TARGET_DELAY: Duration = Duration::from_millis(60);
+
+/// The smallest window we use, in packets
+const MINIMUM_WINDOW_PACKETS: u64 = 2;
+
+/// The default initial window, in packets
+const DEFAULT_INITIAL_WINDOW_PACKETS: u64 = 10;
+
+/// Configuration for the LEDBAT congestion controller
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LedbatConfig {
+    /// Initial congestion window, in bytes. If not set, we use a small number of packets.
+    initial_window: Option<u64>,
+}
+
+impl LedbatConfig {
+    /// Sets the initial congestion window, in bytes
+    pub fn initial_window(&mut self, value: u64) -> &mut Self {
+        self.initial_window = Some(value);
+        self
+    }
+
+    fn initial_window_for(&self, mtu: u64) -> u64 {
+        self.initial_window
+            .unwrap_or(DEFAULT_INITIAL_WINDOW_PACKETS * mtu)
+            .max(MINIMUM_WINDOW_PACKETS * mtu)
+    }
+}
+
+impl ControllerFactory for LedbatConfig {
+    fn build(self: Arc<Self>, now: Instant, current_mtu: u16) -> Box<dyn Controller> {
+        Box::new(Ledbat::new(self, now, current_mtu))
+    }
+}
+
+/// The LEDBAT congestion controller
+#[derive(Debug, Clone)]
+struct Ledbat {
+    config: Arc<LedbatConfig>,
+    current_mtu: u64,
+    window: u64,
+    /// Packets sent before this time do not cause another reduction on loss
+    recovery_start_time: Instant,
+}
+
+impl Ledbat {
+    fn new(config: Arc<LedbatConfig>, now: Instant, current_mtu: u16) -> Self {
+        let current_mtu = u64::from(current_mtu);
+        Self {
+            window: config.initial_window_for(current_mtu),
+            current_mtu,
+            config,
+            recovery_start_time: now,
+        }
+    }
+
+    fn minimum_window(&self) -> u64 {
+        MINIMUM_WINDOW_PACKETS * self.current_mtu
+    }
+
+    /// Adjusts the window in response to `bytes` being acknowledged, given the current
+    /// round-trip time and the smallest seen (which we take to be the delay without queueing).
+    #[allow(
+        clippy::cast_precision_loss,
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss
+    )]
+    fn on_delay_sample(&mut self, bytes: u64, base: Duration, current: Duration) {
+        let queueing = current.saturating_sub(base);
+        // Positive while under target, negative over it. Clamped so that we never fall faster than
+        // we would have risen with no queueing at all.
+        let off_target = ((TARGET_DELAY.as_secs_f64() - queueing.as_secs_f64())
+            / TARGET_DELAY.as_secs_f64())
+        .max(-1.0);
+        // RFC 6817 s2.4.2: cwnd += GAIN * off_target * bytes_newly_acked * MSS / cwnd (GAIN = 1)
+        let delta = off_target * bytes as f64 * self.current_mtu as f64 / self.window.max(1) as f64;
+        // The increase may not outpace slow start.
+        let delta = delta.min(bytes as f64);
+        let window = (self.window as f64 + delta).max(0.0) as u64;
+        self.window = window.max(self.minimum_window());
+    }
+}
+
+impl Controller for Ledbat {
+    fn on_ack(
+        &mut self,
+        _now: Instant,
+        sent: Instant,
+        bytes: u64,
+        app_limited: bool,
+        rtt: &RttEstimator,
+    ) {
+        if app_limited || sent <= self.recovery_start_time {
+            return;
+        }
+        self.on_delay_sample(bytes, rtt.min(), rtt.get());
+    }
+
+    fn on_congestion_event(
+        &mut self,
+        now: Instant,
+        sent: Instant,
+        is_persistent_congestion: bool,
+        _lost_bytes: u64,
+    ) {
+        if sent <= self.recovery_start_time {
+            return;
+        }
+        self.recovery_start_time = now;
+        self.window = if is_persistent_congestion {
+            self.minimum_window()
+        } else {
+            (self.window / 2).max(self.minimum_window())
+        };
+    }
+
+    fn on_mtu_update(&mut self, new_mtu: u16) {
+        self.current_mtu = new_mtu.into();
+        self.window = self.window.max(self.minimum_window());
+    }
+
+    fn window(&self) -> u64 {
+        self.window
+    }
+
+    fn clone_box(&self) -> Box<dyn Controller> {
+        Box::new(self.clone())
+    }
+
+    fn initial_window(&self) -> u64 {
+        self.config.initial_window_for(self.current_mtu)
+    }
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
+
+#[cfg(test)]
+#[cfg_attr(coverage_nightly, coverage(off))]
+mod test {
+    use std::{
+        sync::Arc,
+        time::{Duration, Instant},
+    };
+
+    use pretty_assertions::assert_eq;
+    use quinn::congestion::Controller as _;
+
+    use super::{Ledbat, LedbatConfig};
+
+    const MS: Duration = Duration::from_millis(1);
+
+    fn uut(initial: u64) -> (Ledbat, Instant) {
+        let mut config = LedbatConfig::default();
+        let _ = config.initial_window(initial);
+        let now = Instant::now();
+        (Ledbat::new(Arc::new(config), now, 1000), now)
+    }
+
+    #[test]
+    fn grows_without_queueing() {
+        let (mut uut, _) = uut(100_000);
+        uut.on_delay_sample(10_000, 50 * MS, 50 * MS);
+        // 10000 * 1000 / 100000
+        assert_eq!(uut.window(), 100_100);
+    }
+
+    #[test]
+    fn yields_to_queueing() {
+        let (mut uut, _) = uut(100_000);
+        // At target: no change
+        uut.on_delay_sample(10_000, 50 * MS, 110 * MS);
+        assert_eq!(uut.window(), 100_000);
+        // Over target: shrinks
+        uut.on_delay_sample(10_000, 50 * MS, 170 * MS);
+        assert!(uut.window() < 100_000);
+        // Never below the minimum
+        for _ in 0..10_000 {
+            uut.on_delay_sample(10_000, 50 * MS, 500 * MS);
+        }
+        assert_eq!(uut.window(), 2000);
+    }
+
+    #[test]
+    fn halves_on_loss_once_per_round_trip() {
+        let (mut uut, start) = uut(100_000);
+        let later = start + 10 * MS;
+        uut.on_congestion_event(later, later, false, 1000);
+        assert_eq!(uut.window(), 50_000);
+        // Another loss from before recovery started does not reduce further
+        uut.on_congestion_event(later + MS, later, false, 1000);
+        assert_eq!(uut.window(), 50_000);
+        uut.on_congestion_event(later + 2 * MS, later + MS, true, 1000);
+        assert_eq!(uut.window(), 2000);
+    }
+
+    #[test]
+    fn default_initial_window() {
+        let uut = Ledbat::new(Arc::new(LedbatConfig::default()), Instant::now(), 1200);
+        assert_eq!(uut.initial_window(), 12_000);
+    }
+}
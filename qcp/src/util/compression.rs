@@ -0,0 +1,234 @@
+//! Compressed encoding of file payloads
+// (c) 2025 Ross Younger
+//!
+//! A compressed payload is a sequence of chunks, each of which is compressed independently with zstd.
+//! Each chunk is preceded by two little-endian `u32`s: its decoded length, then its encoded length.
+//! If the two are equal, the chunk was not compressed; its data is sent as-is.
+//!
+//! The receiver knows the decoded length of the whole payload (from the file header), so there is
+//! no end marker.
+//!
+//! If the first few chunks of a file do not shrink usefully, the sender stops trying to compress it,
+//! so data that is already compressed costs little more than the chunk headers.
+
+use std::io::{Error, ErrorKind};
+
+use ring::digest::Context as Digest;
+use tokio::io::{AsyncRead, AsyncReadExt as _, AsyncWrite, AsyncWriteExt as _};
+
+/// Amount of data compressed as a single chunk
+const CHUNK_SIZE: usize = 256 * 1024;
+
+/// Largest chunk we are prepared to receive.
+/// This is larger than we send, to allow for future changes.
+const MAX_CHUNK_SIZE: usize = 16 * 1024 * 1024;
+
+/// Number of chunks we try before deciding whether a file is compressible
+const PROBE_CHUNKS: u64 = 4;
+
+/// Byte counts for a payload
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct PayloadCounts {
+    /// Bytes of file data
+    pub(crate) logical: u64,
+    /// Bytes sent on the wire
+    pub(crate) wire: u64,
+}
+
+/// Fills `buf` from `reader`, stopping early only at end of file.
+async fn read_chunk<R>(reader: &mut R, buf: &mut [u8]) -> std::io::Result<usize>
+where
+    R: AsyncRead + Unpin + ?Sized,
+{
+    let mut filled = 0;
+    while filled < buf.len() {
+        let n = reader.read(&mut buf[filled..]).await?;
+        if n == 0 {
+            break;
+        }
+        filled += n;
+    }
+    Ok(filled)
+}
+
+/// Reads all of `reader` and writes it to `writer` as compressed chunks, at the given zstd level.
+///
+/// If `digest` is given, it is updated with the uncompressed data.
+pub(crate) async fn send_compressed<R, W>(
+    reader: &mut R,
+    writer: &mut W,
+    level: i32,
+    mut digest: Option<&mut Digest>,
+) -> std::io::Result<PayloadCounts>
+where
+    R: AsyncRead + Unpin + ?Sized,
+    W: AsyncWrite + Unpin + ?Sized,
+{
+    let mut compressor = Some(zstd::bulk::Compressor::new(level)?);
+    let mut buf = vec![0u8; CHUNK_SIZE];
+    let mut counts = PayloadCounts::default();
+    let mut chunks = 0u64;
+    loop {
+        let n = read_chunk(reader, &mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        let data = &buf[..n];
+        if let Some(d) = digest.as_mut() {
+            d.update(data);
+        }
+        let compressed = compressor
+            .as_mut()
+            .map(|c| c.compress(data))
+            .transpose()?
+            .filter(|c| c.len() < n);
+        let encoded = compressed.as_deref().unwrap_or(data);
+
+        #[allow(clippy::cast_possible_truncation)] // chunks are much smaller than 4GB
+        let header = [
+            (n as u32).to_le_bytes(),
+            (encoded.len() as u32).to_le_bytes(),
+        ];
+        writer.write_all(header.as_flattened()).await?;
+        writer.write_all(encoded).await?;
+
+        counts.logical += n as u64;
+        counts.wire += (header.as_flattened().len() + encoded.len()) as u64;
+        chunks += 1;
+        // Is this file worth compressing? Give up unless we are saving at least 10%.
+        if chunks == PROBE_CHUNKS && compressor.is_some() && counts.wire * 10 > counts.logical * 9 {
+            tracing::debug!("data does not compress well; sending the rest uncompressed");
+            compressor = None;
+        }
+    }
+    writer.flush().await?;
+    Ok(counts)
+}
+
+/// Reads `len` bytes of file data from `reader`, encoded as compressed chunks, and writes them to `writer`.
+///
+/// If `digest` is given, it is updated with the uncompressed data.
+pub(crate) async fn receive_compressed<R, W>(
+    reader: &mut R,
+    writer: &mut W,
+    len: u64,
+    mut digest: Option<&mut Digest>,
+) -> std::io::Result<PayloadCounts>
+where
+    R: AsyncRead + Unpin + ?Sized,
+    W: AsyncWrite + Unpin + ?Sized,
+{
+    let mut decompressor = zstd::bulk::Decompressor::new()?;
+    let mut counts = PayloadCounts::default();
+    let mut buf = Vec::new();
+    while counts.logical < len {
+        let mut header = [0u8; 8];
+        let _ = reader.read_exact(&mut header).await?;
+        let (decoded_len, encoded_len) = header.split_at(4);
+        let decoded_len = u32::from_le_bytes(decoded_len.try_into().unwrap_or_default()) as usize;
+        let encoded_len = u32::from_le_bytes(encoded_len.try_into().unwrap_or_default()) as usize;
+        if decoded_len == 0
+            || decoded_len > MAX_CHUNK_SIZE
+            || encoded_len > decoded_len
+            || counts.logical + decoded_len as u64 > len
+        {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "invalid compressed chunk header",
+            ));
+        }
+        buf.resize(encoded_len, 0);
+        let _ = reader.read_exact(&mut buf).await?;
+        let decoded = if encoded_len == decoded_len {
+            std::borrow::Cow::Borrowed(&buf[..])
+        } else {
+            let out = decompressor.decompress(&buf, decoded_len)?;
+            if out.len() != decoded_len {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "compressed chunk has the wrong length",
+                ));
+            }
+            std::borrow::Cow::Owned(out)
+        };
+        if let Some(d) = digest.as_mut() {
+            d.update(&decoded);
+        }
+        writer.write_all(&decoded).await?;
+        counts.logical += decoded_len as u64;
+        counts.wire += (header.len() + encoded_len) as u64;
+    }
+    writer.flush().await?;
+    Ok(counts)
+}
+
+#[cfg(test)]
+#[cfg_attr(coverage_nightly, coverage(off))]
+mod test {
+    use pretty_assertions::assert_eq;
+
+    use super::{CHUNK_SIZE, PayloadCounts, receive_compressed, send_compressed};
+
+    async fn round_trip(data: &[u8]) -> (PayloadCounts, Vec<u8>) {
+        let mut wire = Vec::new();
+        let sent = send_compressed(&mut &data[..], &mut wire, 3, None)
+            .await
+            .unwrap();
+        assert_eq!(sent.wire, wire.len() as u64);
+        let mut output = Vec::new();
+        let received = receive_compressed(&mut &wire[..], &mut output, data.len() as u64, None)
+            .await
+            .unwrap();
+        assert_eq!(sent, received);
+        (sent, output)
+    }
+
+    #[tokio::test]
+    async fn compressible() {
+        let data = "the quick brown fox jumps over the lazy dog\n".repeat(20_000);
+        let (counts, output) = round_trip(data.as_bytes()).await;
+        assert_eq!(output, data.as_bytes());
+        assert_eq!(counts.logical, data.len() as u64);
+        assert!(counts.wire * 10 < counts.logical);
+    }
+
+    #[tokio::test]
+    async fn incompressible() {
+        // A simple PRNG is enough to defeat zstd
+        let mut x = 0x1234_5678_u32;
+        let data: Vec<u8> = (0..CHUNK_SIZE * 6)
+            .map(|_| {
+                x ^= x << 13;
+                x ^= x >> 17;
+                x ^= x << 5;
+                x.to_le_bytes()[0]
+            })
+            .collect();
+        let (counts, output) = round_trip(&data).await;
+        assert_eq!(output, data);
+        // Only the chunk headers are added
+        assert_eq!(counts.wire, counts.logical + 6 * 8);
+    }
+
+    #[tokio::test]
+    async fn empty() {
+        let (counts, output) = round_trip(&[]).await;
+        assert_eq!(counts, PayloadCounts::default());
+        assert!(output.is_empty());
+    }
+
+    #[tokio::test]
+    async fn rejects_oversized_chunk() {
+        let data = b"hello world";
+        let mut wire = Vec::new();
+        let _ = send_compressed(&mut &data[..], &mut wire, 3, None)
+            .await
+            .unwrap();
+        // The receiver was expecting less data than this chunk contains
+        let mut output = Vec::new();
+        let err = receive_compressed(&mut &wire[..], &mut output, 5, None)
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    }
+}
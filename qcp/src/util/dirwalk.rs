@@ -2,14 +2,18 @@
 // (c) 2025 Ross Younger
 
 use std::{
+    collections::{HashMap, hash_map::Entry},
     ffi::{OsStr, OsString},
     io::ErrorKind,
     path::MAIN_SEPARATOR,
 };
 
-use crate::{CopyJobSpec, FileSpec, util::path};
+use crate::{
+    CopyJobSpec, FileSpec, LinkMode, PreserveSelection,
+    util::{FsMetadataExt as _, path},
+};
 
-use tracing::error;
+use tracing::{debug, error, warn};
 use walkdir::WalkDir;
 
 #[derive(thiserror::Error, Debug)]
@@ -66,8 +70,33 @@ where
     }
 }
 
+/// Remembers the files seen so far which have more than one name (hard links),
+/// so that later names for them can be recreated as links instead of being transferred again.
+#[derive(Debug, Default)]
+pub(crate) struct HardLinkTracker {
+    seen: HashMap<(u64, u64), String>,
+}
+
+impl HardLinkTracker {
+    /// Records a file with the given metadata, which is known to the destination as `name`.
+    ///
+    /// If the same file was recorded before, returns the name it was given then.
+    pub(crate) fn check(&mut self, meta: &std::fs::Metadata, name: &str) -> Option<String> {
+        match self.seen.entry(meta.hard_link_id()?) {
+            Entry::Occupied(e) => Some(e.get().clone()),
+            Entry::Vacant(e) => {
+                let _ = e.insert(name.to_string());
+                None
+            }
+        }
+    }
+}
+
 /// Resolves a single `FileSpec`
 ///
+/// If `hard_links` is given, files which have already been seen under another name
+/// are marked as hard links to that name.
+///
 /// Returns:
 /// - Ok(true) on success
 /// - Ok(false) on partial success
@@ -75,7 +104,9 @@ where
 pub(crate) fn recurse_local_source(
     source: &FileSpec,
     destination: &FileSpec,
-    preserve: bool,
+    preserve: PreserveSelection,
+    links: LinkMode,
+    mut hard_links: Option<&mut HardLinkTracker>,
     output: &mut Vec<CopyJobSpec>,
 ) -> Result<bool, Error> {
     if destination.user_at_host.is_none() {
@@ -113,7 +144,12 @@ pub(crate) fn recurse_local_source(
         destination.filename.clone()
     };
 
-    let (success1, listing) = contents_of(&source.filename, bare_host, &dest_separator_str)?;
+    let (success1, listing) = contents_of(
+        &source.filename,
+        bare_host,
+        &dest_separator_str,
+        links.follows(),
+    )?;
     success &= success1;
     for (entry, leaf_str) in listing {
         let file_type = entry.file_type();
@@ -127,6 +163,38 @@ pub(crate) fn recurse_local_source(
             continue;
         };
 
+        // We only see links here if the walk is not following them
+        let link_target = if file_type.is_symlink() {
+            if links == LinkMode::Skip {
+                debug!("Skipping symbolic link {src_str}");
+                continue;
+            }
+            let target = match std::fs::read_link(path) {
+                Ok(t) => t,
+                Err(e) => {
+                    error!("Could not read symbolic link {src_str}: {e}");
+                    success = false;
+                    continue;
+                }
+            };
+            let Some(target) = target.to_str() else {
+                error!(
+                    "Target of symbolic link {src_str} could not be converted into Unicode string"
+                );
+                success = false;
+                continue;
+            };
+            if links == LinkMode::Safe && !path::symlink_stays_within(&leaf_str, target) {
+                warn!(
+                    "Skipping symbolic link {src_str} -> {target}, which points outside the tree"
+                );
+                continue;
+            }
+            Some(target.to_string())
+        } else {
+            None
+        };
+
         let src_fs = FileSpec {
             user_at_host: source.user_at_host.clone(),
             filename: src_str.to_string(),
@@ -135,10 +203,18 @@ pub(crate) fn recurse_local_source(
             user_at_host: destination.user_at_host.clone(),
             filename: path::join_remote(&dest_stem, &leaf_str),
         };
-        output.push(
-            CopyJobSpec::try_new(src_fs, dest_fs, preserve, file_type.is_dir())
-                .map_err(Error::from)?,
-        );
+        // Files reached through a symbolic link are not hard links
+        let hard_link_target = match &mut hard_links {
+            Some(tracker) if file_type.is_file() && !entry.path_is_symlink() => {
+                tracker.check(&entry.metadata()?, &dest_fs.filename)
+            }
+            _ => None,
+        };
+        let mut job = CopyJobSpec::try_new(src_fs, dest_fs, preserve, file_type.is_dir())
+            .map_err(Error::from)?;
+        job.link_target = link_target;
+        job.hard_link_target = hard_link_target;
+        output.push(job);
     }
     Ok(success)
 }
@@ -151,13 +227,14 @@ fn contents_of(
     path: &str,
     skip_root: bool,
     separator: &str,
+    follow_links: bool,
 ) -> Result<(bool, Vec<(walkdir::DirEntry, String)>), Error> {
     let mut output = vec![];
     let mut success = true;
     for entry in WalkDir::new(path)
         // skip_root true => min_depth 1; false => min_depth 0
         .min_depth(usize::from(skip_root))
-        .follow_links(true)
+        .follow_links(follow_links)
     {
         match entry {
             Ok(entry) => {
@@ -203,7 +280,9 @@ mod test {
     use core::iter::Iterator;
     use std::{path::PathBuf, str::FromStr};
 
-    use crate::{CopyJobSpec, FileSpec, util::dirwalk::LocalToString as _};
+    use crate::{
+        CopyJobSpec, FileSpec, LinkMode, PreserveSelection, util::dirwalk::LocalToString as _,
+    };
 
     use anyhow::Result;
     use littertray::LitterTray;
@@ -295,7 +374,14 @@ mod test {
         let res = LitterTray::try_with(|tray| {
             setup(tray)?;
             let mut out = Vec::new();
-            let ok = super::recurse_local_source(&source_fs, &destination, false, &mut out)?;
+            let ok = super::recurse_local_source(
+                &source_fs,
+                &destination,
+                PreserveSelection::default(),
+                LinkMode::default(),
+                None,
+                &mut out,
+            )?;
             assert_eq!(expected_success, ok);
             Ok(out)
         })
@@ -466,4 +552,95 @@ mod test {
             false,
         );
     }
+
+    #[cfg(unix)]
+    #[test]
+    fn recurse_links() {
+        use std::os::unix::fs::symlink;
+
+        let run = |links| {
+            LitterTray::try_with(|tray| {
+                setup_fs(tray)?;
+                symlink("a/f", "dir1/inside")?;
+                symlink("../../file1", "dir1/a/outside")?;
+                symlink("a", "dir1/dirlink")?;
+                let mut out = Vec::new();
+                let ok = super::recurse_local_source(
+                    &filespec_local("dir1"),
+                    &FileSpec::from_str("host:destdir").unwrap(),
+                    PreserveSelection::default(),
+                    links,
+                    None,
+                    &mut out,
+                )?;
+                assert!(ok);
+                let mut links = out
+                    .into_iter()
+                    .filter_map(|j| j.link_target.map(|t| (j.destination.filename, t)))
+                    .collect::<Vec<_>>();
+                links.sort();
+                Ok(links)
+            })
+            .unwrap()
+        };
+        assert_eq!(run(LinkMode::Follow), vec![]);
+        assert_eq!(run(LinkMode::Skip), vec![]);
+        assert_eq!(
+            run(LinkMode::Preserve),
+            vec![
+                ("destdir/a/outside".into(), "../../file1".into()),
+                ("destdir/dirlink".into(), "a".into()),
+                ("destdir/inside".into(), "a/f".into()),
+            ]
+        );
+        assert_eq!(
+            run(LinkMode::Safe),
+            vec![
+                ("destdir/dirlink".into(), "a".into()),
+                ("destdir/inside".into(), "a/f".into()),
+            ]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn recurse_hard_links() {
+        let run = |track: bool| {
+            LitterTray::try_with(|tray| {
+                setup_fs(tray)?;
+                std::fs::hard_link("dir1/a/f", "dir1/b/g")?;
+                std::fs::hard_link("dir1/a/f", "dir1/h")?;
+                // A symbolic link to a hard-linked file is followed, and copied as a file
+                std::os::unix::fs::symlink("a/f", "dir1/s")?;
+                let mut tracker = super::HardLinkTracker::default();
+                let mut out = Vec::new();
+                let ok = super::recurse_local_source(
+                    &filespec_local("dir1"),
+                    &FileSpec::from_str("host:destdir").unwrap(),
+                    PreserveSelection::default(),
+                    LinkMode::Follow,
+                    track.then_some(&mut tracker),
+                    &mut out,
+                )?;
+                assert!(ok);
+                Ok(out
+                    .into_iter()
+                    .filter_map(|j| j.hard_link_target.map(|t| (j.destination.filename, t)))
+                    .collect::<Vec<_>>())
+            })
+            .unwrap()
+        };
+        assert_eq!(run(false), vec![]);
+        let links = run(true);
+        // Whichever name the walk found first is the one that is transferred
+        assert_eq!(links.len(), 2);
+        let names = ["destdir/a/f", "destdir/b/g", "destdir/h"];
+        let first = &links[0].1;
+        assert!(names.contains(&first.as_str()));
+        for (name, target) in &links {
+            assert!(names.contains(&name.as_str()));
+            assert_eq!(target, first);
+            assert_ne!(name, target);
+        }
+    }
 }
@@ -1,19 +1,19 @@
 //! Extension traits for tokio::fs::File and related structures
 // (c) 2025 Ross Younger
 
-use crate::protocol::TaggedData;
 use crate::protocol::session::{FileHeaderV2, MetadataAttr};
-use crate::util::time::SystemTimeExt as _;
+use crate::protocol::{TaggedData, Variant};
 
-use std::time::SystemTime;
 use std::{
     fs::FileTimes,
+    io::SeekFrom,
     path::{Path, PathBuf},
 };
 
 use async_trait::async_trait;
 use cfg_if::cfg_if;
 use tokio::fs::File as TokioFile;
+use tokio::io::AsyncSeekExt as _;
 
 /// Extension trait for tokio::fs::OpenOptions
 trait OpenOptionsExt {
@@ -59,6 +59,144 @@ impl OpenOptionsExt for tokio::fs::OpenOptions {
     }
 }
 
+/// Determines the file to write, given the destination path of an incoming file.
+///
+/// If the destination is a directory, the filename from the header is appended.
+pub(crate) async fn resolve_destination(
+    path: &Path,
+    header: &FileHeaderV2,
+) -> std::io::Result<PathBuf> {
+    let mut dest_path = PathBuf::from(path);
+    let dest_meta = tokio::fs::metadata(&dest_path).await;
+    if let Ok(meta) = dest_meta {
+        // if it's a file, proceed (overwriting)
+        if meta.is_dir() {
+            dest_path.push(header.filename.clone());
+        } else if !meta.is_file() {
+            // Disallow writing to pre-existing non-regular files (sockets, device nodes)
+            return Err(std::io::Error::other(
+                "Destination path exists but is not a regular file",
+            ));
+        }
+    } // error ignored; file doesn't exist is perfectly OK with us :-)
+    Ok(dest_path)
+}
+
+/// File ownership as described by incoming metadata
+#[derive(Debug, Default)]
+struct Ownership {
+    uid: Option<u32>,
+    gid: Option<u32>,
+    user: Option<String>,
+    group: Option<String>,
+}
+
+impl Ownership {
+    fn from_metadata(metadata: &[TaggedData<MetadataAttr>]) -> Self {
+        let find = |tag| {
+            metadata
+                .iter()
+                .find(|md| md.tag() == Some(tag))
+                .map(|md| &md.data)
+        };
+        let id = |tag| {
+            find(tag)
+                .and_then(Variant::as_unsigned_ref)
+                .and_then(|u| u32::try_from(*u).ok())
+        };
+        let name = |tag| find(tag).and_then(Variant::as_str).map(ToString::to_string);
+        Self {
+            uid: id(MetadataAttr::OwnerId),
+            gid: id(MetadataAttr::GroupId),
+            user: name(MetadataAttr::OwnerName),
+            group: name(MetadataAttr::GroupName),
+        }
+    }
+
+    /// Does the metadata say anything about ownership?
+    fn is_set(&self) -> bool {
+        self.uid.is_some() || self.gid.is_some() || self.user.is_some() || self.group.is_some()
+    }
+
+    /// Works out the local user and group ids to apply, if any.
+    ///
+    /// Names take precedence over numbers, unless `numeric_ids` is set or the name is not known here.
+    /// Without privilege, we can only change the group of a file, and only to one we belong to.
+    /// Returns `None` if there is nothing we can do.
+    ///
+    /// Looking up names may block.
+    #[cfg(unix)]
+    fn resolve(self, numeric_ids: bool) -> Option<(Option<u32>, Option<u32>)> {
+        use crate::util::ids::{group_id, user_id};
+
+        if !self.is_set() {
+            return None;
+        }
+        let (mut uid, mut gid) = (self.uid, self.gid);
+        if !numeric_ids {
+            if let Some(id) = self.user.as_deref().and_then(user_id) {
+                uid = Some(id);
+            }
+            if let Some(id) = self.group.as_deref().and_then(group_id) {
+                gid = Some(id);
+            }
+        }
+        if !rustix::process::geteuid().is_root() {
+            uid = None;
+            gid = gid.filter(|&g| is_own_group(g));
+            if gid.is_none() {
+                tracing::debug!("not privileged to change file ownership; ignoring {self:?}");
+                return None;
+            }
+        }
+        Some((uid, gid))
+    }
+
+    #[cfg(windows)]
+    #[allow(clippy::unused_self)]
+    fn resolve(self, _numeric_ids: bool) -> Option<(Option<u32>, Option<u32>)> {
+        // Unix users and groups do not map onto Windows file ownership
+        None
+    }
+}
+
+/// Is this one of the groups the current process belongs to?
+#[cfg(unix)]
+fn is_own_group(gid: u32) -> bool {
+    use rustix::process::{getegid, getgroups};
+    getegid().as_raw() == gid
+        || getgroups().is_ok_and(|groups| groups.iter().any(|g| g.as_raw() == gid))
+}
+
+/// Applies incoming file ownership, as far as we can. Returns a warning if we could not.
+fn apply_ownership(
+    file: &std::fs::File,
+    ownership: Ownership,
+    numeric_ids: bool,
+) -> Option<String> {
+    let (uid, gid) = ownership.resolve(numeric_ids)?;
+    set_ownership(file, uid, gid)
+        .err()
+        .map(|e| format!("could not change ownership: {e}"))
+}
+
+#[cfg(unix)]
+fn set_ownership(file: &std::fs::File, uid: Option<u32>, gid: Option<u32>) -> std::io::Result<()> {
+    use rustix::fs::{Gid, Uid, fchown};
+    fchown(file, uid.map(Uid::from_raw), gid.map(Gid::from_raw))?;
+    Ok(())
+}
+
+#[cfg(windows)]
+#[allow(clippy::unnecessary_wraps)]
+fn set_ownership(
+    _file: &std::fs::File,
+    _uid: Option<u32>,
+    _gid: Option<u32>,
+) -> std::io::Result<()> {
+    Ok(())
+}
+
 #[async_trait]
 /// Extension trait for `tokio::fs::File`
 pub(crate) trait FileExt {
@@ -68,19 +206,39 @@ pub(crate) trait FileExt {
     ) -> anyhow::Result<(TokioFile, std::fs::Metadata), tokio::io::Error>;
 
     /// Opens a local file for writing, from an incoming `FileHeader`
+    ///
+    /// If the header describes partial content, the file is not truncated; instead it is
+    /// sized to the full length of the file, and the cursor is positioned at the start of the range.
+    /// This allows several streams to write their parts of the same file at once.
     async fn create_or_truncate<P: AsRef<Path> + Send>(
         path: P,
         header: &FileHeaderV2,
     ) -> anyhow::Result<TokioFile>;
 
+    /// Creates a new, hidden, temporary file in the same directory as `destination`,
+    /// from an incoming `FileHeader`.
+    ///
+    /// Returns the file and its path.
+    async fn create_temporary(
+        destination: &Path,
+        header: &FileHeaderV2,
+    ) -> anyhow::Result<(TokioFile, PathBuf)>;
+
     /// Update file metadata to match the passed-in set.
     ///
+    /// File ownership is only applied as far as we are privileged to do so.
+    /// Users and groups are mapped by name where possible, unless `numeric_ids` is set.
+    ///
+    /// Ownership and extended attributes which could not be applied do not cause an error;
+    /// they are described in the returned list of warnings.
+    ///
     /// NOTE: This function necessarily consumes and re-wraps the given File.
     /// This works around a tokio limitation; see commentary within.
     async fn update_metadata(
         self,
         metadata: &[TaggedData<MetadataAttr>],
-    ) -> anyhow::Result<TokioFile>;
+        numeric_ids: bool,
+    ) -> anyhow::Result<(TokioFile, Vec<String>)>;
 }
 
 #[async_trait]
@@ -112,30 +270,73 @@ impl FileExt for TokioFile {
     ) -> anyhow::Result<TokioFile> {
         use OpenOptionsExt as _;
 
-        let mut dest_path = PathBuf::from(path.as_ref());
-        let dest_meta = tokio::fs::metadata(&dest_path).await;
-        if let Ok(meta) = dest_meta {
-            // if it's a file, proceed (overwriting)
-            if meta.is_dir() {
-                dest_path.push(header.filename.clone());
-            } else if !meta.is_file() {
-                // Disallow writing to pre-existing non-regular files (sockets, device nodes)
-                return Err(std::io::Error::other(
-                    "Destination path exists but is not a regular file",
-                )
-                .into());
+        let dest_path = resolve_destination(path.as_ref(), header).await?;
+        let range = header.range();
+        let mut options = tokio::fs::OpenOptions::new();
+        let _ = options.create(true).truncate(range.is_none());
+        options.apply_qcp_meta(&header.metadata);
+        let mut file = options.write(true).open(&dest_path).await?;
+        if range.is_none() && header.extents()?.is_some() {
+            // A sparse file starts out as all hole; the data is written into it
+            file.set_len(header.size.0).await?;
+        }
+        if let Some((offset, total)) = range {
+            anyhow::ensure!(
+                offset.saturating_add(header.size.0) <= total,
+                "Partial content does not fit within the file"
+            );
+            // Every part sets the same length, so the order in which the parts arrive does not matter.
+            let existing = file.metadata().await?.len();
+            if existing != total {
+                file.set_len(total).await?;
+            }
+            // The holes in sparse partial content must not keep what the file held before.
+            // Anything beyond the previous end of the file already reads as zeros.
+            let region = offset..(offset + header.size.0).min(existing);
+            if let Some(extents) = header.extents()?
+                && !region.is_empty()
+            {
+                crate::util::sparse::clear_gaps(&mut file, &region, &extents).await?;
             }
-        } // error ignored; file doesn't exist is perfectly OK with us :-)
+            let _ = file.seek(SeekFrom::Start(offset)).await?;
+        }
+        Ok(file)
+    }
+
+    async fn create_temporary(
+        destination: &Path,
+        header: &FileHeaderV2,
+    ) -> anyhow::Result<(TokioFile, PathBuf)> {
+        use OpenOptionsExt as _;
+        use ring::rand::SecureRandom as _;
+
+        let name = destination
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy();
+        // The name is unpredictable, and we insist on creating a new file,
+        // so we cannot be tricked into following a symlink someone else has planted.
+        let mut nonce = [0u8; 8];
+        ring::rand::SystemRandom::new()
+            .fill(&mut nonce)
+            .map_err(|_| anyhow::anyhow!("random number generator failed"))?;
+        let temp_path = destination.with_file_name(format!(".{name}.qcp-{}", hex::encode(nonce)));
+
         let mut options = tokio::fs::OpenOptions::new();
-        let _ = options.create(true).truncate(true);
+        let _ = options.create_new(true).write(true);
         options.apply_qcp_meta(&header.metadata);
-        Ok(options.write(true).open(&dest_path).await?)
+        let file = options.open(&temp_path).await?;
+        if header.range().is_none() && header.extents()?.is_some() {
+            file.set_len(header.size.0).await?;
+        }
+        Ok((file, temp_path))
     }
 
     async fn update_metadata(
         self,
         metadata: &[TaggedData<MetadataAttr>],
-    ) -> anyhow::Result<TokioFile> {
+        numeric_ids: bool,
+    ) -> anyhow::Result<(TokioFile, Vec<String>)> {
         #[cfg(unix)]
         use std::os::unix::fs::PermissionsExt as _;
 
@@ -143,13 +344,36 @@ impl FileExt for TokioFile {
         let mut times = FileTimes::default();
         let mut changed = false;
         let mut new_perms = None;
+        let mut xattrs = Vec::new();
         for md in metadata {
             let tag = match md.tag() {
                 None | Some(MetadataAttr::Invalid) => continue,
                 Some(v) => v,
             };
             match tag {
-                MetadataAttr::Invalid => (),
+                // The range attributes describe the payload, not the file.
+                // A symlink target only appears in listings.
+                // Times are gathered below, to full precision, and ownership likewise.
+                MetadataAttr::Invalid
+                | MetadataAttr::RangeOffset
+                | MetadataAttr::FileSize
+                | MetadataAttr::Sha256Digest
+                | MetadataAttr::SymlinkTarget
+                | MetadataAttr::MoreTrailers
+                | MetadataAttr::DataExtent
+                | MetadataAttr::HardLinkTarget
+                | MetadataAttr::AccessTime
+                | MetadataAttr::ModificationTime
+                | MetadataAttr::AccessTimeNanos
+                | MetadataAttr::ModificationTimeNanos
+                | MetadataAttr::OwnerId
+                | MetadataAttr::GroupId
+                | MetadataAttr::OwnerName
+                | MetadataAttr::GroupName => (),
+                MetadataAttr::ExtendedAttribute => {
+                    changed = true;
+                    xattrs.push(md.clone());
+                }
                 MetadataAttr::ModeBits => {
                     let mut perms = meta.permissions();
                     if let Some(mode) = md.data.as_unsigned_ref() {
@@ -173,20 +397,19 @@ impl FileExt for TokioFile {
                         changed = true;
                     }
                 }
-                MetadataAttr::AccessTime => {
-                    if let Some(t) = md.data.as_unsigned_ref() {
-                        changed = true;
-                        times = times.set_accessed(SystemTime::from_unix(*t));
-                    }
-                }
-                MetadataAttr::ModificationTime => {
-                    if let Some(t) = md.data.as_unsigned_ref() {
-                        changed = true;
-                        times = times.set_modified(SystemTime::from_unix(*t));
-                    }
-                }
             }
         }
+        if let Some(t) = MetadataAttr::find_atime(metadata) {
+            changed = true;
+            times = times.set_accessed(t);
+        }
+        if let Some(t) = MetadataAttr::find_mtime(metadata) {
+            changed = true;
+            times = times.set_modified(t);
+        }
+        let ownership = Ownership::from_metadata(metadata);
+        let owner = ownership.is_set().then_some(ownership);
+        changed |= owner.is_some();
 
         if changed {
             /* Unfortunately, tokio doesn't currently provide an analogue to `std::fs::set_times()`.
@@ -197,17 +420,26 @@ impl FileExt for TokioFile {
              * writing to an NFS filesystem that might block indeterminately). */
             let std_file = self.into_std().await;
             let file = tokio::task::spawn_blocking(move || {
+                // Changing ownership may clear some attributes (e.g. `security.capability`),
+                // so it must come first.
+                let mut warnings: Vec<_> = owner
+                    .and_then(|o| apply_ownership(&std_file, o, numeric_ids))
+                    .into_iter()
+                    .collect();
+                warnings.extend(crate::util::xattr::apply_extended_attributes(
+                    &std_file, &xattrs,
+                ));
                 std_file.set_times(times)?;
-                Ok::<TokioFile, std::io::Error>(TokioFile::from_std(std_file))
+                Ok::<_, std::io::Error>((TokioFile::from_std(std_file), warnings))
             })
             .await??;
             // Caution: Ordering appears to be critical on Windows. Setting the modification time appears to clear the readonly attribute.
             if let Some(p) = new_perms {
-                file.set_permissions(p).await?;
+                file.0.set_permissions(p).await?;
             }
             return Ok(file);
         }
-        Ok(self)
+        Ok((self, Vec::new()))
     }
 }
 
@@ -301,6 +533,40 @@ mod tests {
         .unwrap();
     }
 
+    #[tokio::test]
+    async fn partial_content_preserves_other_ranges() {
+        use crate::protocol::{DataTag as _, session::MetadataAttr};
+        use tokio::io::AsyncWriteExt as _;
+
+        LitterTray::try_with_async(async |tray| {
+            let _ = tray.create_text(FILE, "0123456789abcdef")?;
+            let header = FileHeaderV2 {
+                size: serde_bare::Uint(3),
+                filename: FILENAME_IN_HEADER.to_string(),
+                metadata: vec![
+                    MetadataAttr::RangeOffset.with_unsigned(4u64),
+                    MetadataAttr::FileSize.with_unsigned(10u64),
+                ],
+            };
+            let mut f = TokioFile::create_or_truncate(FILE, &header).await?;
+            f.write_all(b"xyz").await?;
+            f.flush().await?;
+            drop(f);
+            // The file was resized to the full length, and only the range was overwritten
+            assert_eq!(std::fs::read_to_string(FILE)?, "0123xyz789");
+
+            // A range that overruns the stated file size is rejected
+            let header = FileHeaderV2 {
+                size: serde_bare::Uint(7),
+                ..header
+            };
+            assert!(TokioFile::create_or_truncate(FILE, &header).await.is_err());
+            Ok(())
+        })
+        .await
+        .unwrap();
+    }
+
     #[cfg(unix)]
     #[tokio::test]
     async fn dest_is_broken_link() {
@@ -315,4 +581,49 @@ mod tests {
         .await
         .unwrap();
     }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn update_ownership() {
+        use crate::protocol::{DataTag as _, session::MetadataAttr};
+        use std::os::unix::fs::MetadataExt as _;
+
+        LitterTray::try_with_async(async |tray| {
+            let _ = tray.create_text(FILE, "12345")?;
+            let before = std::fs::metadata(FILE)?;
+            // We can always give a file to its existing owner, whether or not we are privileged.
+            // An unknown name does not prevent the numeric id from being used.
+            let metadata = vec![
+                MetadataAttr::OwnerId.with_unsigned(before.uid()),
+                MetadataAttr::GroupId.with_unsigned(before.gid()),
+                MetadataAttr::OwnerName.with_str("no-such-user-qcp"),
+            ];
+            for numeric in [false, true] {
+                let f = tokio::fs::OpenOptions::new().write(true).open(FILE).await?;
+                let (_f, warnings) = f.update_metadata(&metadata, numeric).await?;
+                assert!(warnings.is_empty());
+                let after = std::fs::metadata(FILE)?;
+                assert_eq!((after.uid(), after.gid()), (before.uid(), before.gid()));
+            }
+            Ok(())
+        })
+        .await
+        .unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn own_groups() {
+        let gid = rustix::process::getegid().as_raw();
+        assert!(super::is_own_group(gid));
+        if !rustix::process::geteuid().is_root() {
+            // We can change the group of our files to our own group, but not the owner
+            let ownership = super::Ownership {
+                uid: Some(0),
+                gid: Some(gid),
+                ..Default::default()
+            };
+            assert_eq!(ownership.resolve(true), Some((None, Some(gid))));
+        }
+    }
 }
@@ -0,0 +1,95 @@
+//! Mapping between user and group ids and names, with caching
+// (c) 2025 Ross Younger
+//!
+//! Looking up a name may involve the system's name services (e.g. LDAP), so can be slow;
+//! and a tree of files usually has only a handful of owners.
+//! Lookups may block, so these functions should be called from outside the async runtime.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::{LazyLock, Mutex};
+
+use nix::unistd::{Gid, Group, Uid, User};
+
+/// A cache of lookups, including those that found nothing
+struct Cache<K, V>(LazyLock<Mutex<HashMap<K, Option<V>>>>);
+
+impl<K: Eq + Hash + Clone, V: Clone> Cache<K, V> {
+    const fn new() -> Self {
+        Self(LazyLock::new(Mutex::default))
+    }
+
+    fn get(&self, key: &K, lookup: impl FnOnce(&K) -> Option<V>) -> Option<V> {
+        if let Some(value) = self.0.lock().unwrap().get(key) {
+            return value.clone();
+        }
+        // Don't hold the lock during the lookup
+        let value = lookup(key);
+        let _ = self.0.lock().unwrap().insert(key.clone(), value.clone());
+        value
+    }
+}
+
+static USER_NAMES: Cache<u32, String> = Cache::new();
+static GROUP_NAMES: Cache<u32, String> = Cache::new();
+static USER_IDS: Cache<String, u32> = Cache::new();
+static GROUP_IDS: Cache<String, u32> = Cache::new();
+
+/// The name of a user, if known
+pub(crate) fn user_name(uid: u32) -> Option<String> {
+    USER_NAMES.get(&uid, |&uid| {
+        User::from_uid(Uid::from_raw(uid))
+            .ok()
+            .flatten()
+            .map(|u| u.name)
+    })
+}
+
+/// The name of a group, if known
+pub(crate) fn group_name(gid: u32) -> Option<String> {
+    GROUP_NAMES.get(&gid, |&gid| {
+        Group::from_gid(Gid::from_raw(gid))
+            .ok()
+            .flatten()
+            .map(|g| g.name)
+    })
+}
+
+/// The id of a named user, if known
+pub(crate) fn user_id(name: &str) -> Option<u32> {
+    USER_IDS.get(&name.to_string(), |name| {
+        User::from_name(name).ok().flatten().map(|u| u.uid.as_raw())
+    })
+}
+
+/// The id of a named group, if known
+pub(crate) fn group_id(name: &str) -> Option<u32> {
+    GROUP_IDS.get(&name.to_string(), |name| {
+        Group::from_name(name)
+            .ok()
+            .flatten()
+            .map(|g| g.gid.as_raw())
+    })
+}
+
+#[cfg(test)]
+#[cfg_attr(coverage_nightly, coverage(off))]
+mod test {
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn root_round_trip() {
+        let name = super::user_name(0).unwrap();
+        assert_eq!(super::user_id(&name), Some(0));
+        // Cached answers are the same
+        assert_eq!(super::user_name(0), Some(name));
+        let group = super::group_name(0).unwrap();
+        assert_eq!(super::group_id(&group), Some(0));
+    }
+
+    #[test]
+    fn unknown_name() {
+        assert_eq!(super::user_id("no-such-user-qcp-test"), None);
+        assert_eq!(super::user_id("no-such-user-qcp-test"), None);
+    }
+}
@@ -0,0 +1,256 @@
+//! Safe handling of files being received
+// (c) 2025 Ross Younger
+
+use std::path::{Path, PathBuf};
+
+use tokio::fs::File as TokioFile;
+use tracing::{debug, warn};
+
+use super::FileExt as _;
+use super::file_ext::resolve_destination;
+use crate::protocol::session::FileHeaderV2;
+
+/// A file being received.
+///
+/// Unless we are writing in place, the data goes to a hidden temporary file alongside the destination,
+/// which [`IncomingFile::commit`] renames over the destination.
+///
+/// If an `IncomingFile` is dropped without being committed (because the transfer failed, or was cancelled),
+/// the temporary file is deleted.
+#[derive(Debug)]
+pub(crate) struct IncomingFile {
+    destination: PathBuf,
+    temporary: Option<PathBuf>,
+}
+
+impl IncomingFile {
+    /// Opens a file to receive into, from an incoming `FileHeader`.
+    ///
+    /// Partial content (see [`FileHeaderV2::range`]) is always written in place,
+    /// as it updates part of an existing file.
+    pub(crate) async fn create<P: AsRef<Path> + Send>(
+        path: P,
+        header: &FileHeaderV2,
+        in_place: bool,
+    ) -> anyhow::Result<(Self, TokioFile)> {
+        let mut destination = resolve_destination(path.as_ref(), header).await?;
+        let mut in_place = in_place || header.range().is_some();
+
+        // Write through symlinks, as we would in place.
+        if !in_place
+            && tokio::fs::symlink_metadata(&destination)
+                .await
+                .is_ok_and(|m| m.is_symlink())
+        {
+            if let Ok(target) = tokio::fs::canonicalize(&destination).await {
+                destination = target;
+            } else {
+                // The link is broken; let the OS follow it
+                in_place = true;
+            }
+        }
+
+        if in_place {
+            let file = TokioFile::create_or_truncate(path, header).await?;
+            return Ok((
+                Self {
+                    destination,
+                    temporary: None,
+                },
+                file,
+            ));
+        }
+
+        // Don't replace a file that we would not be allowed to overwrite in place.
+        match tokio::fs::OpenOptions::new()
+            .write(true)
+            .open(&destination)
+            .await
+        {
+            Ok(_) => (),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => (),
+            Err(e) => return Err(e.into()),
+        }
+
+        let (file, temporary) = TokioFile::create_temporary(&destination, header).await?;
+        debug!("receiving into {temporary:?}");
+        let result = Self {
+            destination,
+            temporary: Some(temporary),
+        };
+        // Overwriting an existing file keeps its permissions, as it would in place.
+        #[cfg(unix)]
+        if let Ok(meta) = tokio::fs::metadata(&result.destination).await {
+            file.set_permissions(meta.permissions()).await?;
+        }
+        Ok((result, file))
+    }
+
+    /// Checks that we could receive a file described by `header` at `path`, without changing any existing file.
+    ///
+    /// This lets us refuse a transfer before the sender starts on it.
+    pub(crate) async fn check<P: AsRef<Path> + Send>(
+        path: P,
+        header: &FileHeaderV2,
+    ) -> anyhow::Result<()> {
+        let destination = resolve_destination(path.as_ref(), header).await?;
+        match tokio::fs::OpenOptions::new()
+            .write(true)
+            .open(&destination)
+            .await
+        {
+            Ok(_) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                // We need to be able to create files in its directory
+                let (file, temporary) = TokioFile::create_temporary(&destination, header).await?;
+                drop(file);
+                tokio::fs::remove_file(temporary).await?;
+                Ok(())
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Moves the received file into place.
+    ///
+    /// The caller must have finished writing to it.
+    pub(crate) async fn commit(mut self) -> std::io::Result<()> {
+        if let Some(temporary) = self.temporary.take() {
+            if let Err(e) = tokio::fs::rename(&temporary, &self.destination).await {
+                // Put it back so we clean up on drop
+                self.temporary = Some(temporary);
+                return Err(e);
+            }
+            debug!("renamed into place: {:?}", self.destination);
+        }
+        Ok(())
+    }
+}
+
+impl Drop for IncomingFile {
+    fn drop(&mut self) {
+        if let Some(temporary) = self.temporary.take()
+            && let Err(e) = std::fs::remove_file(&temporary)
+        {
+            warn!("Failed to remove temporary file {temporary:?}: {e}");
+        }
+    }
+}
+
+#[cfg(test)]
+#[cfg_attr(coverage_nightly, coverage(off))]
+mod test {
+    use littertray::LitterTray;
+    use pretty_assertions::assert_eq;
+    use serde_bare::Uint;
+    use tokio::io::AsyncWriteExt as _;
+
+    use super::IncomingFile;
+    use crate::protocol::session::FileHeaderV2;
+
+    fn header() -> FileHeaderV2 {
+        FileHeaderV2 {
+            size: Uint(5),
+            filename: "file".into(),
+            metadata: vec![],
+        }
+    }
+
+    fn dir_entries() -> Vec<String> {
+        let mut v: Vec<_> = std::fs::read_dir(".")
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        v.sort();
+        v
+    }
+
+    #[tokio::test]
+    async fn commit_replaces_destination() {
+        LitterTray::try_with_async(async |tray| {
+            let _ = tray.create_text("file", "old contents")?;
+            let (incoming, mut file) = IncomingFile::create("file", &header(), false).await?;
+            file.write_all(b"hello").await?;
+            file.flush().await?;
+            // The destination is untouched until we commit
+            assert_eq!(std::fs::read_to_string("file")?, "old contents");
+            assert_eq!(dir_entries().len(), 2);
+            drop(file);
+            incoming.commit().await?;
+            assert_eq!(std::fs::read_to_string("file")?, "hello");
+            assert_eq!(dir_entries(), vec!["file"]);
+            Ok(())
+        })
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn drop_cleans_up() {
+        LitterTray::try_with_async(async |_| {
+            let (incoming, mut file) = IncomingFile::create(".", &header(), false).await?;
+            file.write_all(b"hel").await?;
+            drop(file);
+            assert_eq!(dir_entries().len(), 1);
+            drop(incoming);
+            assert!(dir_entries().is_empty());
+            Ok(())
+        })
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn in_place() {
+        LitterTray::try_with_async(async |tray| {
+            let _ = tray.create_text("file", "old contents")?;
+            let (incoming, mut file) = IncomingFile::create("file", &header(), true).await?;
+            file.write_all(b"hel").await?;
+            file.flush().await?;
+            assert_eq!(std::fs::read_to_string("file")?, "hel");
+            drop(incoming);
+            assert_eq!(dir_entries(), vec!["file"]);
+            Ok(())
+        })
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn check_leaves_no_trace() {
+        LitterTray::try_with_async(async |tray| {
+            IncomingFile::check("file", &header()).await?;
+            assert!(dir_entries().is_empty());
+            let _ = tray.create_text("file", "old contents")?;
+            IncomingFile::check("file", &header()).await?;
+            assert_eq!(std::fs::read_to_string("file")?, "old contents");
+            assert_eq!(dir_entries(), vec!["file"]);
+            assert!(
+                IncomingFile::check("no-such-dir/file", &header())
+                    .await
+                    .is_err()
+            );
+            Ok(())
+        })
+        .await
+        .unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn writes_through_symlink() {
+        LitterTray::try_with_async(async |tray| {
+            let _ = tray.create_text("target", "old contents")?;
+            let _ = tray.make_symlink("target", "link")?;
+            let (incoming, mut file) = IncomingFile::create("link", &header(), false).await?;
+            file.write_all(b"hello").await?;
+            drop(file);
+            incoming.commit().await?;
+            assert!(std::fs::symlink_metadata("link")?.is_symlink());
+            assert_eq!(std::fs::read_to_string("target")?, "hello");
+            Ok(())
+        })
+        .await
+        .unwrap();
+    }
+}
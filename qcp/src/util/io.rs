@@ -2,7 +2,9 @@
 // (c) 2024-5 Ross Younger
 
 use std::pin::Pin;
-use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::io::{
+    AsyncBufReadExt as _, AsyncRead, AsyncReadExt as _, AsyncWrite, AsyncWriteExt as _,
+};
 
 pub(crate) async fn read_available_non_blocking<R: AsyncRead + Unpin>(
     mut reader: R,
@@ -40,6 +42,84 @@ where
     tokio::io::copy_buf(&mut reader, writer).await
 }
 
+/// As [`copy_large`], but also computes the SHA-256 hash of the data copied.
+///
+/// Returns the number of bytes copied, and the hash.
+pub(crate) async fn copy_large_hashed<R, W, Z>(
+    reader: &mut R,
+    writer: &mut W,
+    buffer_size: Z,
+) -> Result<(u64, Vec<u8>), std::io::Error>
+where
+    R: AsyncRead + Unpin + ?Sized,
+    W: AsyncWrite + Unpin + ?Sized,
+    Z: num_traits::cast::AsPrimitive<usize>,
+{
+    let mut context = ring::digest::Context::new(&ring::digest::SHA256);
+    let mut reader = tokio::io::BufReader::with_capacity(buffer_size.as_(), reader);
+    let mut total = 0u64;
+    loop {
+        let buf = reader.fill_buf().await?;
+        if buf.is_empty() {
+            break;
+        }
+        context.update(buf);
+        writer.write_all(buf).await?;
+        let n = buf.len();
+        reader.consume(n);
+        total += n as u64;
+    }
+    writer.flush().await?;
+    Ok((total, context.finish().as_ref().to_vec()))
+}
+
+/// Computes the SHA-256 hash of (up to) the first `len` bytes from `reader`.
+pub(crate) async fn hash_prefix<R, Z>(
+    reader: &mut R,
+    len: u64,
+    buffer_size: Z,
+) -> Result<Vec<u8>, std::io::Error>
+where
+    R: AsyncRead + Unpin + ?Sized,
+    Z: num_traits::cast::AsPrimitive<usize>,
+{
+    copy_large_hashed(&mut reader.take(len), &mut tokio::io::sink(), buffer_size)
+        .await
+        .map(|(_, hash)| hash)
+}
+
+#[cfg(test)]
+#[cfg_attr(coverage_nightly, coverage(off))]
+mod test {
+    use pretty_assertions::assert_eq;
+
+    #[tokio::test]
+    async fn hash_prefix() {
+        let data = b"hello world";
+        let hash = super::hash_prefix(&mut &data[..], 5, 2).await.unwrap();
+        // sha256("hello")
+        assert_eq!(
+            hex::encode(hash),
+            "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824"
+        );
+    }
+
+    #[tokio::test]
+    async fn copy_large_hashed() {
+        let data = b"hello";
+        let mut out = Vec::new();
+        let (n, hash) = super::copy_large_hashed(&mut &data[..], &mut out, 2)
+            .await
+            .unwrap();
+        assert_eq!(n, 5);
+        assert_eq!(out, data);
+        assert_eq!(
+            hex::encode(hash),
+            "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824"
+        );
+    }
+}
+
 #[cfg(test)]
 #[cfg_attr(coverage_nightly, coverage(off))]
 mod microbench {
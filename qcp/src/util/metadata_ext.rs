@@ -1,7 +1,9 @@
 //! Extension trait for std::fs::Metadata
 // (c) 2025 Ross Younger
 
-use crate::protocol::{TaggedData, control::Compatibility, session::MetadataAttr};
+use crate::protocol::{
+    DataTag as _, TaggedData, compat::Feature, control::Compatibility, session::MetadataAttr,
+};
 
 /// Extension trait for `std::fs::Metadata`
 pub(crate) trait FsMetadataExt: std::marker::Sized {
@@ -10,8 +12,25 @@ pub(crate) trait FsMetadataExt: std::marker::Sized {
     /// Convert filesystem metadata to QCP protocol metadata
     fn to_tagged_data(&self, times: bool) -> Vec<TaggedData<MetadataAttr>>;
 
-    /// Convert filesystem metadata to QCP protocol metadata for a directory
+    /// Convert the sub-second parts of the access and modification times to QCP protocol metadata.
+    ///
+    /// These accompany the whole seconds sent by `to_tagged_data`.
+    fn time_nanos(&self) -> Vec<TaggedData<MetadataAttr>>;
+
+    /// Convert filesystem metadata to QCP protocol metadata for a directory.
+    ///
+    /// This includes the access and modification times, if the remote supports them.
     fn tagged_data_for_dir(&self, compat: Compatibility) -> Vec<TaggedData<MetadataAttr>>;
+
+    /// Convert file ownership to QCP protocol metadata.
+    ///
+    /// This includes the user and group names where they are known.
+    /// Looking up the names may block.
+    fn ownership(&self) -> Vec<TaggedData<MetadataAttr>>;
+
+    /// If this is a file with more than one name (hard link), returns something which
+    /// uniquely identifies it within the system.
+    fn hard_link_id(&self) -> Option<(u64, u64)>;
 }
 
 impl FsMetadataExt for std::fs::Metadata {
@@ -78,8 +97,65 @@ impl FsMetadataExt for std::fs::Metadata {
         vec
     }
 
-    fn tagged_data_for_dir(&self, _compat: Compatibility) -> Vec<TaggedData<MetadataAttr>> {
+    fn time_nanos(&self) -> Vec<TaggedData<MetadataAttr>> {
+        let mut vec = Vec::new();
+        if let Ok(t) = self.accessed() {
+            vec.push(MetadataAttr::new_atime_nanos(t));
+        }
+        if let Ok(t) = self.modified() {
+            vec.push(MetadataAttr::new_mtime_nanos(t));
+        }
+        vec
+    }
+
+    fn tagged_data_for_dir(&self, compat: Compatibility) -> Vec<TaggedData<MetadataAttr>> {
         static_assertions::assert_cfg!(any(unix, windows), "This OS is not currently supported");
-        self.to_tagged_data(false)
+        let mut vec = self.to_tagged_data(false);
+        if compat.supports(Feature::DIRECTORY_TIMES) {
+            if let Ok(t) = self.accessed() {
+                vec.push(MetadataAttr::new_atime(t));
+            }
+            if let Ok(t) = self.modified() {
+                vec.push(MetadataAttr::new_mtime(t));
+            }
+            if compat.supports(Feature::NANOSECOND_TIMES) {
+                vec.extend(self.time_nanos());
+            }
+        }
+        vec
+    }
+
+    #[cfg(unix)]
+    fn ownership(&self) -> Vec<TaggedData<MetadataAttr>> {
+        use crate::util::ids::{group_name, user_name};
+        use std::os::unix::fs::MetadataExt as _;
+
+        let mut vec = vec![
+            MetadataAttr::OwnerId.with_unsigned(self.uid()),
+            MetadataAttr::GroupId.with_unsigned(self.gid()),
+        ];
+        if let Some(user) = user_name(self.uid()) {
+            vec.push(MetadataAttr::OwnerName.with_str(user));
+        }
+        if let Some(group) = group_name(self.gid()) {
+            vec.push(MetadataAttr::GroupName.with_str(group));
+        }
+        vec
+    }
+    #[cfg(windows)]
+    fn ownership(&self) -> Vec<TaggedData<MetadataAttr>> {
+        // Windows file ownership does not map onto Unix users and groups
+        vec![]
+    }
+
+    #[cfg(unix)]
+    fn hard_link_id(&self) -> Option<(u64, u64)> {
+        use std::os::unix::fs::MetadataExt as _;
+        (self.is_file() && self.nlink() > 1).then(|| (self.dev(), self.ino()))
+    }
+    #[cfg(windows)]
+    fn hard_link_id(&self) -> Option<(u64, u64)> {
+        // The file index is not yet available in stable Rust
+        None
     }
 }
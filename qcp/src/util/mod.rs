@@ -14,17 +14,25 @@ pub use cert::Credentials;
 
 mod file_ext;
 pub(crate) use file_ext::FileExt;
+mod incoming;
+pub(crate) use incoming::IncomingFile;
 mod metadata_ext;
 pub(crate) use metadata_ext::FsMetadataExt;
 
+pub(crate) mod compression;
 pub(crate) mod dirwalk;
+#[cfg(unix)]
+pub(crate) mod ids;
 
 pub(crate) mod io;
 pub(crate) mod path;
 pub(crate) mod process;
+pub(crate) mod rate_limit;
 pub(crate) mod socket;
+pub(crate) mod sparse;
 pub(crate) mod stats;
 pub(crate) mod time;
+pub(crate) mod xattr;
 
 pub(crate) mod serialization;
 pub use serialization::{SerializeAsString, SerializeEnumAsString, ToStringForFigment};
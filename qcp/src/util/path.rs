@@ -1,6 +1,6 @@
 //! Path-related
 
-use std::path::{MAIN_SEPARATOR, Path, PathBuf};
+use std::path::{Component, MAIN_SEPARATOR, Path, PathBuf};
 
 pub(crate) fn basename_of(path: &str) -> anyhow::Result<String> {
     let path = Path::new(path);
@@ -43,3 +43,48 @@ pub(crate) fn add_pathsep_if_needed(path: &mut String, local: bool) {
         path.push(sep);
     }
 }
+
+/// Determines whether a symbolic link points somewhere within the tree being copied.
+///
+/// `leaf` is the path of the link, relative to the root of the tree; `target` is what the link contains.
+/// Absolute targets, and relative targets that climb above the root, are outside the tree.
+pub(crate) fn symlink_stays_within(leaf: &str, target: &str) -> bool {
+    // A relative target is resolved from the directory containing the link
+    let mut depth = Path::new(leaf)
+        .components()
+        .filter(|c| matches!(c, Component::Normal(_)))
+        .count()
+        .saturating_sub(1);
+    for component in Path::new(target).components() {
+        match component {
+            Component::Prefix(_) | Component::RootDir => return false,
+            Component::CurDir => (),
+            Component::ParentDir => {
+                if depth == 0 {
+                    return false;
+                }
+                depth -= 1;
+            }
+            Component::Normal(_) => depth += 1,
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+#[cfg_attr(coverage_nightly, coverage(off))]
+mod test {
+    use super::symlink_stays_within;
+
+    #[test]
+    fn symlink_safety() {
+        assert!(symlink_stays_within("a/link", "file"));
+        assert!(symlink_stays_within("a/link", "../b/file"));
+        assert!(symlink_stays_within("a/link", "./c/../file"));
+        assert!(!symlink_stays_within("link", "../file"));
+        assert!(!symlink_stays_within("a/link", "../../file"));
+        assert!(!symlink_stays_within("a/link", "/etc/passwd"));
+        // Climbing out and back in again is still climbing out
+        assert!(!symlink_stays_within("a/link", "../../tree/file"));
+    }
+}
@@ -0,0 +1,166 @@
+//! Hard limits on the rate of outbound file data
+// (c) 2025 Ross Younger
+
+use std::{
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, ready},
+    time::Duration,
+};
+
+use tokio::{
+    io::AsyncWrite,
+    time::{Instant, Sleep},
+};
+
+/// How far a sender may get behind its schedule, and then catch up in a burst
+const MAX_BURST: Duration = Duration::from_millis(50);
+
+/// Writes are split so each takes roughly this long at the limited rate
+const WRITE_SLICE: Duration = Duration::from_millis(20);
+
+/// Smallest slice we will write
+const MIN_WRITE_SLICE: usize = 1500;
+
+/// A pacing schedule, which may be shared between several streams.
+///
+/// Each session has its own limiter, shared by all its connections and streams, so the limit applies to the session as a whole.
+///
+/// This is a simple virtual scheduling algorithm: every byte sent moves the schedule forwards,
+/// and senders wait until the schedule catches up with real time.
+#[derive(Debug)]
+pub(crate) struct RateLimiter {
+    /// Bytes per second
+    rate: u64,
+    /// When the data sent so far is due to have been sent
+    schedule: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(rate: u64) -> Self {
+        Self {
+            rate: rate.max(1),
+            schedule: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Returns a limiter to be shared by the connections and streams of a session, if there is a limit
+    pub(crate) fn shared(rate: u64) -> Option<Arc<Self>> {
+        (rate > 0).then(|| Arc::new(Self::new(rate)))
+    }
+
+    fn rate(&self) -> u64 {
+        self.rate
+    }
+
+    /// Accounts for `n` bytes sent, and returns how long the sender should wait before sending any more.
+    pub(crate) fn consume(&self, n: usize) -> Duration {
+        #[allow(clippy::cast_precision_loss)]
+        let cost = Duration::from_secs_f64(n as f64 / self.rate() as f64);
+        let now = Instant::now();
+        let mut schedule = self.schedule.lock().unwrap();
+        // Time when nothing was being sent is not saved up, beyond a short burst
+        let earliest = now.checked_sub(MAX_BURST).unwrap_or(now);
+        *schedule = (*schedule).max(earliest) + cost;
+        schedule.saturating_duration_since(now)
+    }
+
+    /// The largest amount of data to write at once
+    fn slice_size(&self) -> usize {
+        let bytes = u128::from(self.rate()) * WRITE_SLICE.as_millis() / 1000;
+        usize::try_from(bytes)
+            .unwrap_or(usize::MAX)
+            .max(MIN_WRITE_SLICE)
+    }
+}
+
+/// A writer which paces the data written through it, according to a [`RateLimiter`]
+pub(crate) struct Paced<'a, W> {
+    inner: W,
+    limiter: &'a RateLimiter,
+    delay: Option<Pin<Box<Sleep>>>,
+}
+
+impl<'a, W> Paced<'a, W> {
+    pub(crate) fn new(inner: W, limiter: &'a RateLimiter) -> Self {
+        Self {
+            inner,
+            limiter,
+            delay: None,
+        }
+    }
+
+    fn poll_delay(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        if let Some(delay) = self.delay.as_mut() {
+            ready!(delay.as_mut().poll(cx));
+            self.delay = None;
+        }
+        Poll::Ready(())
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for Paced<'_, W> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        ready!(self.poll_delay(cx));
+        let len = buf.len().min(self.limiter.slice_size());
+        let n = ready!(Pin::new(&mut self.inner).poll_write(cx, &buf[..len]))?;
+        let wait = self.limiter.consume(n);
+        if !wait.is_zero() {
+            self.delay = Some(Box::pin(tokio::time::sleep(wait)));
+        }
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+#[cfg_attr(coverage_nightly, coverage(off))]
+mod test {
+    use std::time::Duration;
+
+    use tokio::{io::AsyncWriteExt as _, time::Instant};
+
+    use super::{Paced, RateLimiter};
+
+    #[tokio::test]
+    async fn paces_writes() {
+        let limiter = RateLimiter::new(100_000);
+        let mut output = Vec::new();
+        let start = Instant::now();
+        {
+            let mut writer = Paced::new(&mut output, &limiter);
+            writer.write_all(&vec![0u8; 20_000]).await.unwrap();
+            writer.flush().await.unwrap();
+        }
+        assert_eq!(output.len(), 20_000);
+        // 20kB at 100kB/s is 200ms, less the permitted burst
+        assert!(start.elapsed() >= Duration::from_millis(140));
+    }
+
+    #[tokio::test]
+    async fn shared_schedule() {
+        let limiter = RateLimiter::new(10_000);
+        // Two senders of 1000 bytes each use up 200ms between them
+        let _ = limiter.consume(1000);
+        let wait = limiter.consume(1000);
+        assert!(wait > Duration::from_millis(100), "{wait:?}");
+        assert!(wait <= Duration::from_millis(200), "{wait:?}");
+    }
+
+    #[test]
+    fn shared_only_when_limited() {
+        assert!(RateLimiter::shared(0).is_none());
+        assert_eq!(RateLimiter::shared(1000).unwrap().rate(), 1000);
+    }
+}
@@ -119,6 +119,19 @@ pub(crate) fn bind_range_for_family(
     bind_range_for_address(addr, range)
 }
 
+/// Determines which local address the OS would use to send to `peer`.
+///
+/// No packets are sent. Returns `None` if there is no route to the peer.
+pub(crate) fn local_address_for(peer: SocketAddr) -> Option<IpAddr> {
+    let unspecified = match peer {
+        SocketAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        SocketAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+    };
+    let socket = UdpSocket::bind(SocketAddr::new(unspecified, 0)).ok()?;
+    socket.connect(peer).ok()?;
+    socket.local_addr().ok().map(|a| a.ip())
+}
+
 #[cfg(test)]
 #[cfg_attr(coverage_nightly, coverage(off))]
 mod test {
@@ -126,7 +139,7 @@ mod test {
     use rusty_fork::rusty_fork_test;
     use std::net::{IpAddr, Ipv4Addr, UdpSocket};
 
-    use super::{bind_range_for_address, bind_range_for_family};
+    use super::{bind_range_for_address, bind_range_for_family, local_address_for};
 
     const UNSPECIFIED: IpAddr = IpAddr::V4(Ipv4Addr::UNSPECIFIED);
 
@@ -170,6 +183,15 @@ mod test {
         let _ = r.unwrap_err();
     }
 
+    #[test]
+    fn local_address_for_loopback() {
+        let peer = (Ipv4Addr::LOCALHOST, 1234).into();
+        assert_eq!(
+            local_address_for(peer),
+            Some(IpAddr::V4(Ipv4Addr::LOCALHOST))
+        );
+    }
+
     #[test]
     fn bind_ipv6() {
         let range = PortRange::default();
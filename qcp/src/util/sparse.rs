@@ -0,0 +1,379 @@
+//! Sparse file support
+// (c) 2025 Ross Younger
+//!
+//! A sparse file is sent as its data extents only; see [`MetadataAttr::DataExtent`].
+//! The payload is the data from each extent in turn, so compression and checksums work unchanged.
+//! The receiver writes each piece of data at its offset, leaving holes in between.
+//!
+//! [`MetadataAttr::DataExtent`]: crate::protocol::session::MetadataAttr::DataExtent
+
+use std::collections::VecDeque;
+use std::io::SeekFrom;
+use std::ops::Range;
+use std::path::Path;
+use std::pin::Pin;
+use std::task::{Context, Poll, ready};
+
+use tokio::io::{AsyncRead, AsyncSeek, AsyncSeekExt as _, AsyncWrite, AsyncWriteExt as _, ReadBuf};
+use tracing::debug;
+
+use crate::os::{AbstractPlatform as _, Platform};
+
+/// The most extents we describe in a single file header.
+/// This keeps the header well within its wire encoding limit.
+const MAX_EXTENTS: usize = 2048;
+
+/// Finds the extents of the file at `path` within `range` which hold data.
+///
+/// Returns `None` if the range holds no holes, or if we cannot tell.
+pub(crate) async fn data_extents(path: &Path, range: &Range<u64>) -> Option<Vec<Range<u64>>> {
+    let owned = path.to_owned();
+    let extents = match tokio::task::spawn_blocking(move || Platform::data_extents(&owned)).await {
+        Ok(Ok(extents)) => extents?,
+        Ok(Err(e)) => {
+            debug!("{}: could not find data extents: {e}", path.display());
+            return None;
+        }
+        Err(e) => {
+            debug!("{}: could not find data extents: {e}", path.display());
+            return None;
+        }
+    };
+    let clipped: Vec<_> = extents
+        .into_iter()
+        .filter_map(|e| {
+            let start = e.start.max(range.start);
+            let end = e.end.min(range.end);
+            (start < end).then_some(start..end)
+        })
+        .collect();
+    let extents = limit_extents(clipped, MAX_EXTENTS);
+    if extents.len() == 1 && extents[0] == *range {
+        return None;
+    }
+    debug!(
+        "{}: sparse; {} data extents in {range:?}",
+        path.display(),
+        extents.len()
+    );
+    Some(extents)
+}
+
+/// Reduces a list of extents to no more than `max`, by filling in the smallest holes.
+fn limit_extents(extents: Vec<Range<u64>>, max: usize) -> Vec<Range<u64>> {
+    if extents.len() <= max || max == 0 {
+        return extents;
+    }
+    let mut holes: Vec<u64> = extents.windows(2).map(|w| w[1].start - w[0].end).collect();
+    holes.sort_unstable_by(|a, b| b.cmp(a));
+    // Keep only holes larger than this
+    let threshold = holes[max - 1];
+    let mut result: Vec<Range<u64>> = Vec::with_capacity(max);
+    for extent in extents {
+        match result.last_mut() {
+            Some(last) if extent.start - last.end <= threshold => last.end = extent.end,
+            _ => result.push(extent),
+        }
+    }
+    result
+}
+
+/// The parts of `region` which lie outside all of the given extents
+fn gaps(region: &Range<u64>, extents: &[Range<u64>]) -> Vec<Range<u64>> {
+    let mut result = Vec::new();
+    let mut pos = region.start;
+    for e in extents {
+        if e.start > pos {
+            result.push(pos..e.start.min(region.end));
+        }
+        pos = pos.max(e.end);
+    }
+    if pos < region.end {
+        result.push(pos..region.end);
+    }
+    result
+}
+
+/// Ensures that the parts of `region` which lie outside all of the given extents read as zeros.
+///
+/// When sparse partial content is written into an existing file, the holes would otherwise keep
+/// whatever the file held before. Holes are punched where the platform supports it; elsewhere,
+/// zeros are written.
+/// The file position is left undefined.
+pub(crate) async fn clear_gaps(
+    file: &mut tokio::fs::File,
+    region: &Range<u64>,
+    extents: &[Range<u64>],
+) -> std::io::Result<()> {
+    let gaps = gaps(region, extents);
+    if gaps.is_empty() {
+        return Ok(());
+    }
+    let std_file = file.try_clone().await?.into_std().await;
+    let punch_gaps = gaps.clone();
+    let punched = tokio::task::spawn_blocking(move || {
+        punch_gaps
+            .into_iter()
+            .try_for_each(|gap| Platform::punch_hole(&std_file, gap))
+    })
+    .await?;
+    let Err(e) = punched else {
+        return Ok(());
+    };
+    debug!("could not punch holes ({e}); writing zeros instead");
+    let zeros = vec![0u8; 65_536];
+    for gap in gaps {
+        let _ = file.seek(SeekFrom::Start(gap.start)).await?;
+        let mut remaining = gap.end - gap.start;
+        while remaining > 0 {
+            let n = usize::try_from(remaining)
+                .unwrap_or(usize::MAX)
+                .min(zeros.len());
+            file.write_all(&zeros[..n]).await?;
+            remaining -= n as u64;
+        }
+    }
+    file.flush().await
+}
+
+/// Total length of a list of extents
+pub(crate) fn extents_len(extents: &[Range<u64>]) -> u64 {
+    extents.iter().map(|e| e.end - e.start).sum()
+}
+
+/// Reads only the given extents of a file, one after the other.
+///
+/// Without any extents, this reads straight through.
+#[derive(Debug)]
+pub(crate) struct ExtentReader<R> {
+    inner: R,
+    extents: Option<VecDeque<Range<u64>>>,
+    remaining: u64,
+    seeking: bool,
+}
+
+impl<R> ExtentReader<R> {
+    pub(crate) fn new(inner: R, extents: Option<Vec<Range<u64>>>) -> Self {
+        Self {
+            inner,
+            extents: extents.map(VecDeque::from),
+            remaining: 0,
+            seeking: false,
+        }
+    }
+}
+
+impl<R: AsyncRead + AsyncSeek + Unpin> AsyncRead for ExtentReader<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        let Some(extents) = &mut this.extents else {
+            return Pin::new(&mut this.inner).poll_read(cx, buf);
+        };
+        loop {
+            if this.seeking {
+                let _ = ready!(Pin::new(&mut this.inner).poll_complete(cx))?;
+                this.seeking = false;
+            }
+            if this.remaining > 0 {
+                break;
+            }
+            let Some(next) = extents.pop_front() else {
+                return Poll::Ready(Ok(()));
+            };
+            Pin::new(&mut this.inner).start_seek(SeekFrom::Start(next.start))?;
+            this.seeking = true;
+            this.remaining = next.end - next.start;
+        }
+        let max = usize::try_from(this.remaining)
+            .unwrap_or(usize::MAX)
+            .min(buf.remaining());
+        let mut part = ReadBuf::new(buf.initialize_unfilled_to(max));
+        ready!(Pin::new(&mut this.inner).poll_read(cx, &mut part))?;
+        let n = part.filled().len();
+        if n == 0 && max > 0 {
+            return Poll::Ready(Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "file is shorter than its data extents",
+            )));
+        }
+        buf.advance(n);
+        this.remaining -= n as u64;
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Writes data into the given extents of a file, one after the other, leaving holes in between.
+///
+/// Without any extents, this writes straight through.
+#[derive(Debug)]
+pub(crate) struct ExtentWriter<W> {
+    inner: W,
+    extents: Option<VecDeque<Range<u64>>>,
+    remaining: u64,
+    seeking: bool,
+}
+
+impl<W> ExtentWriter<W> {
+    pub(crate) fn new(inner: W, extents: Option<Vec<Range<u64>>>) -> Self {
+        Self {
+            inner,
+            extents: extents.map(VecDeque::from),
+            remaining: 0,
+            seeking: false,
+        }
+    }
+}
+
+impl<W: AsyncWrite + AsyncSeek + Unpin> AsyncWrite for ExtentWriter<W> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        data: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let this = self.get_mut();
+        let Some(extents) = &mut this.extents else {
+            return Pin::new(&mut this.inner).poll_write(cx, data);
+        };
+        loop {
+            if this.seeking {
+                let _ = ready!(Pin::new(&mut this.inner).poll_complete(cx))?;
+                this.seeking = false;
+            }
+            if this.remaining > 0 || data.is_empty() {
+                break;
+            }
+            if extents.is_empty() {
+                return Poll::Ready(Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "received more data than the data extents describe",
+                )));
+            }
+            // Any write in flight must complete before we can seek
+            ready!(Pin::new(&mut this.inner).poll_flush(cx))?;
+            let next = extents.pop_front().unwrap_or_default();
+            Pin::new(&mut this.inner).start_seek(SeekFrom::Start(next.start))?;
+            this.seeking = true;
+            this.remaining = next.end - next.start;
+        }
+        let max = usize::try_from(this.remaining)
+            .unwrap_or(usize::MAX)
+            .min(data.len());
+        let n = ready!(Pin::new(&mut this.inner).poll_write(cx, &data[..max]))?;
+        this.remaining -= n as u64;
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        if this.seeking {
+            let _ = ready!(Pin::new(&mut this.inner).poll_complete(cx))?;
+            this.seeking = false;
+        }
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+#[cfg_attr(coverage_nightly, coverage(off))]
+mod test {
+    use std::io::Cursor;
+
+    use pretty_assertions::assert_eq;
+    use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
+
+    use super::{ExtentReader, ExtentWriter, clear_gaps, extents_len, gaps, limit_extents};
+
+    #[test]
+    fn limit() {
+        let extents = vec![0..10, 12..20, 100..110, 111..120, 200..210];
+        assert_eq!(limit_extents(extents.clone(), 5), extents);
+        // The smallest holes are filled first
+        assert_eq!(
+            limit_extents(extents.clone(), 3),
+            vec![0..20, 100..120, 200..210]
+        );
+        assert_eq!(limit_extents(extents.clone(), 1), vec![0..210]);
+        assert_eq!(extents_len(&extents), 47);
+    }
+
+    #[test]
+    #[allow(clippy::single_range_in_vec_init)]
+    fn find_gaps() {
+        assert_eq!(
+            gaps(&(0..100), &[10..20, 50..60]),
+            vec![0..10, 20..50, 60..100]
+        );
+        assert_eq!(gaps(&(10..60), &[10..20, 50..60]), vec![20..50]);
+        assert_eq!(gaps(&(0..100), &[0..100]), vec![]);
+        assert_eq!(gaps(&(0..100), &[]), vec![0..100]);
+    }
+
+    #[tokio::test]
+    #[allow(clippy::single_range_in_vec_init)]
+    async fn clear_gaps_in_existing_file() {
+        littertray::LitterTray::try_with_async(async |tray| {
+            let _ = tray.create_binary("f", &vec![0xffu8; 200_000])?;
+            let mut file = tokio::fs::OpenOptions::new().write(true).open("f").await?;
+            clear_gaps(&mut file, &(1000..150_000), &[2000..3000]).await?;
+            drop(file);
+            let data = std::fs::read("f")?;
+            assert_eq!(data.len(), 200_000);
+            for (i, byte) in data.iter().enumerate() {
+                let kept = !(1000..150_000).contains(&i) || (2000..3000).contains(&i);
+                assert_eq!(*byte, if kept { 0xff } else { 0 }, "byte {i}");
+            }
+            Ok(())
+        })
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn read_and_write_extents() {
+        let source: Vec<u8> = (0..100).collect();
+        let extents = vec![5..10, 20..22, 90..100];
+
+        let mut reader = ExtentReader::new(Cursor::new(source.clone()), Some(extents.clone()));
+        let mut payload = Vec::new();
+        let _ = reader.read_to_end(&mut payload).await.unwrap();
+        assert_eq!(payload.len() as u64, extents_len(&extents));
+        assert_eq!(&payload[..5], &source[5..10]);
+
+        let mut dest = Cursor::new(vec![0u8; 100]);
+        let mut writer = ExtentWriter::new(&mut dest, Some(extents.clone()));
+        writer.write_all(&payload).await.unwrap();
+        writer.flush().await.unwrap();
+        // Too much data
+        let _ = writer.write_all(b"x").await.unwrap_err();
+
+        let dest = dest.into_inner();
+        for (i, byte) in dest.iter().enumerate() {
+            let expected = if extents.iter().any(|e| e.contains(&(i as u64))) {
+                source[i]
+            } else {
+                0
+            };
+            assert_eq!(*byte, expected, "byte {i}");
+        }
+    }
+
+    #[tokio::test]
+    async fn without_extents() {
+        let mut reader = ExtentReader::new(Cursor::new(b"hello".to_vec()), None);
+        let mut payload = Vec::new();
+        let _ = reader.read_to_end(&mut payload).await.unwrap();
+        assert_eq!(payload, b"hello");
+
+        let mut writer = ExtentWriter::new(Cursor::new(Vec::new()), None);
+        writer.write_all(b"hello").await.unwrap();
+        assert_eq!(writer.inner.into_inner(), b"hello");
+    }
+}
@@ -75,6 +75,36 @@ pub(crate) fn format_rate(size: u64, time: Option<Duration>, measured_peak: u64)
     )
 }
 
+/// Combines the statistics of several connections into one.
+///
+/// Counters are summed, and so is the congestion window. The round-trip time is the longest seen,
+/// and the MTU is the smallest.
+pub(crate) fn merge_connection_stats(all: &[ConnectionStats]) -> ConnectionStats {
+    let mut total = ConnectionStats::default();
+    let mut first = true;
+    for stats in all {
+        macro_rules! sum {
+            ($group:ident: $($field:ident),+) => {
+                $( total.$group.$field += stats.$group.$field; )+
+            };
+        }
+        sum!(udp_tx: datagrams, bytes, ios);
+        sum!(udp_rx: datagrams, bytes, ios);
+        sum!(frame_tx: data_blocked, stream_data_blocked, stream, acks);
+        sum!(frame_rx: data_blocked, stream_data_blocked, stream, acks);
+        sum!(path: cwnd, congestion_events, lost_packets, lost_bytes, sent_packets,
+            sent_plpmtud_probes, lost_plpmtud_probes, black_holes_detected);
+        total.path.rtt = total.path.rtt.max(stats.path.rtt);
+        total.path.current_mtu = if first {
+            stats.path.current_mtu
+        } else {
+            total.path.current_mtu.min(stats.path.current_mtu)
+        };
+        first = false;
+    }
+    total
+}
+
 /// Output the end-of-game statistics
 #[cfg_attr(coverage_nightly, coverage(off))]
 // this is a cosmetic function, it is not practical to test in its current form
@@ -159,6 +189,7 @@ fn advanced_statistics(
     let sender_sent_bytes = cmp::max(stats.udp_tx.bytes, remote_stats.sent_bytes.0);
     let locale = &num_format::Locale::en;
     let payload_bytes = command_stats.payload_bytes;
+    let wire_bytes = command_stats.wire_bytes;
 
     {
         use crate::protocol::FindTag as _;
@@ -194,14 +225,30 @@ fn advanced_statistics(
             rx = stats.udp_rx.datagrams.human_count_bare(),
             black_holes = black_holes.to_formatted_string(locale),
         );
-        if payload_bytes != 0 {
+        if let Some(Variant::Unsigned(Uint(migrations))) = remote_stats
+            .extension
+            .find_tag(ClosedownReportExtension::Migrations)
+        {
+            info!("Connection migrated to a new client address {migrations} time(s)");
+        }
+        if payload_bytes != wire_bytes && payload_bytes != 0 {
+            #[allow(clippy::cast_precision_loss)]
+            let ratio_pct = 100. * wire_bytes as f64 / payload_bytes as f64;
+            info!(
+                "Compression: {} bytes of file data sent as {} bytes ({:.2}%)",
+                payload_bytes.to_formatted_string(locale),
+                wire_bytes.to_formatted_string(locale),
+                ratio_pct
+            );
+        }
+        if wire_bytes != 0 {
             #[allow(clippy::cast_precision_loss)]
             let overhead_pct =
-                100. * (sender_sent_bytes - payload_bytes) as f64 / payload_bytes as f64;
+                100. * sender_sent_bytes.saturating_sub(wire_bytes) as f64 / wire_bytes as f64;
             info!(
                 "{} total bytes sent for {} bytes payload  ({:.2}% overhead/loss)",
                 sender_sent_bytes.to_formatted_string(locale),
-                payload_bytes.to_formatted_string(locale),
+                wire_bytes.to_formatted_string(locale),
                 overhead_pct
             );
         }
@@ -236,7 +283,8 @@ fn suggest_bandwidth_tuning(
     config: &Configuration,
     direction: Direction,
 ) {
-    let payload_bytes = command_stats.payload_bytes;
+    // What matters here is what went over the network
+    let payload_bytes = command_stats.wire_bytes;
     if payload_bytes == 0 {
         return;
     }
@@ -333,8 +381,9 @@ fn suggest_bandwidth_tuning(
 #[cfg_attr(coverage_nightly, coverage(off))]
 #[allow(clippy::float_cmp)]
 mod tests {
-    use super::DataRate;
+    use super::{DataRate, merge_connection_stats};
     use pretty_assertions::assert_eq;
+    use quinn::ConnectionStats;
     use std::time::Duration;
 
     #[test]
@@ -364,6 +413,32 @@ mod tests {
         test_case(10_000_000_000, 500, 20_000_000, "20MB/s");
         test_case(1_000_000_000_000_000, 1234, 810_372_771_474, "810.37GB/s");
     }
+    #[test]
+    fn merge_stats() {
+        let mut a = ConnectionStats::default();
+        a.udp_tx.bytes = 100;
+        a.path.sent_packets = 10;
+        a.path.cwnd = 1000;
+        a.path.rtt = Duration::from_millis(20);
+        a.path.current_mtu = 1452;
+        a.frame_tx.data_blocked = 1;
+        let mut b = a;
+        b.udp_tx.bytes = 50;
+        b.path.rtt = Duration::from_millis(30);
+        b.path.current_mtu = 1200;
+
+        let m = merge_connection_stats(&[a, b]);
+        assert_eq!(m.udp_tx.bytes, 150);
+        assert_eq!(m.path.sent_packets, 20);
+        assert_eq!(m.path.cwnd, 2000);
+        assert_eq!(m.path.rtt, Duration::from_millis(30));
+        assert_eq!(m.path.current_mtu, 1200);
+        assert_eq!(m.frame_tx.data_blocked, 2);
+
+        let m = merge_connection_stats(&[a]);
+        assert_eq!(m.path.current_mtu, 1452);
+    }
+
     #[test]
     fn format_rate_() {
         assert_eq!(
@@ -11,17 +11,29 @@ use human_repr::HumanDuration;
 /// Extension trait for std::time::SystemTime
 pub(crate) trait SystemTimeExt {
     fn from_unix(t: u64) -> Self;
+    /// As `from_unix`, with a number of nanoseconds added. Out of range nanoseconds are ignored.
+    fn from_unix_nanos(t: u64, nanos: u64) -> Self;
     fn to_unix(self) -> u64;
+    /// The sub-second part of the time, in nanoseconds
+    fn unix_subsec_nanos(self) -> u32;
 }
 
 impl SystemTimeExt for SystemTime {
     fn from_unix(t: u64) -> Self {
         UNIX_EPOCH + Duration::from_secs(t)
     }
+    fn from_unix_nanos(t: u64, nanos: u64) -> Self {
+        let nanos = if nanos < 1_000_000_000 { nanos } else { 0 };
+        Self::from_unix(t) + Duration::from_nanos(nanos)
+    }
     fn to_unix(self) -> u64 {
         self.duration_since(UNIX_EPOCH)
             .map_or_else(|_| 0, |d| d.as_secs())
     }
+    fn unix_subsec_nanos(self) -> u32 {
+        self.duration_since(UNIX_EPOCH)
+            .map_or_else(|_| 0, |d| d.subsec_nanos())
+    }
 }
 
 #[derive(Debug, Default, Clone)]
@@ -124,6 +136,16 @@ impl StopwatchChain {
     pub(crate) fn find(&self, name: &str) -> Option<&Stopwatch> {
         self.watches.iter().find(|&sw| sw.name == name)
     }
+
+    /// Totals the elapsed time of every finished stopwatch with the given name, if there were any
+    #[must_use]
+    pub(crate) fn total(&self, name: &str) -> Option<Duration> {
+        self.watches
+            .iter()
+            .filter(|sw| sw.name == name)
+            .filter_map(Stopwatch::elapsed)
+            .reduce(|a, b| a + b)
+    }
 }
 
 /// Simple display formatting
@@ -173,6 +195,18 @@ mod tests {
         let _ = a.stop();
     }
 
+    #[test]
+    fn unix_nanos() {
+        let t = SystemTime::from_unix_nanos(1_000_000_000, 123_456_789);
+        assert_eq!(t.to_unix(), 1_000_000_000);
+        assert_eq!(t.unix_subsec_nanos(), 123_456_789);
+        assert_eq!(
+            SystemTime::from_unix_nanos(42, 1_000_000_000),
+            SystemTime::from_unix(42)
+        );
+        assert_eq!(SystemTime::UNIX_EPOCH.unix_subsec_nanos(), 0);
+    }
+
     #[test]
     fn empty_chain() {
         let c = StopwatchChain::default();
@@ -196,6 +230,19 @@ mod tests {
         println!("{c}");
     }
     #[test]
+    fn chain_total() {
+        let mut c = StopwatchChain::default();
+        c.next("a");
+        c.next("b");
+        c.next("a");
+        assert!(c.total("b").is_some());
+        // the second "a" is still running
+        assert_eq!(c.total("a"), c.find("a").and_then(Stopwatch::elapsed));
+        c.stop();
+        assert!(c.total("a") >= c.find("a").and_then(Stopwatch::elapsed));
+        assert!(c.total("c").is_none());
+    }
+    #[test]
     #[should_panic(expected = "Stopwatch already stopped")]
     fn cannot_restart_stopped_chain() {
         let mut c = StopwatchChain::default();
@@ -0,0 +1,249 @@
+//! Extended attributes and POSIX access control lists
+// (c) 2025 Ross Younger
+
+use std::path::Path;
+
+use tracing::{debug, warn};
+
+use crate::os::{AbstractPlatform as _, Platform};
+use crate::protocol::{
+    DataTag as _, TaggedData, Variant,
+    session::{CommandParam, MetadataAttr},
+};
+
+/// The largest part of an attribute value we carry in a single metadata entry
+const CHUNK_SIZE: usize = 16_384;
+
+/// The most attribute data we are prepared to send for a single file.
+/// This keeps the number of file trailers needed within reasonable bounds.
+const MAX_TOTAL_SIZE: usize = 1_048_576;
+
+/// Linux stores POSIX ACLs as extended attributes with these names
+fn is_acl(name: &str) -> bool {
+    name.starts_with("system.posix_acl_")
+}
+
+/// Which extended attributes to transfer
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct XattrSelection {
+    /// Extended attributes, other than ACLs
+    pub(crate) xattrs: bool,
+    /// POSIX ACLs
+    pub(crate) acls: bool,
+}
+
+impl XattrSelection {
+    /// Is anything selected?
+    pub(crate) fn any(self) -> bool {
+        self.xattrs || self.acls
+    }
+
+    fn wants(self, name: &str) -> bool {
+        if is_acl(name) { self.acls } else { self.xattrs }
+    }
+
+    /// Reads the selection from the client parameters
+    pub(crate) fn from_params(params: &crate::Parameters) -> Self {
+        Self {
+            xattrs: params.xattrs || params.preserve.xattrs,
+            acls: params.acls || params.preserve.acls,
+        }
+    }
+
+    /// Reads the selection from command options
+    pub(crate) fn from_options(options: &[TaggedData<CommandParam>]) -> Self {
+        let has = |param| options.iter().any(|o| o.tag() == Some(param));
+        Self {
+            xattrs: has(CommandParam::ExtendedAttributes),
+            acls: has(CommandParam::Acls),
+        }
+    }
+
+    /// Converts the selection to command options
+    pub(crate) fn to_options(self) -> Vec<TaggedData<CommandParam>> {
+        let mut options = Vec::new();
+        if self.xattrs {
+            options.push(CommandParam::ExtendedAttributes.into());
+        }
+        if self.acls {
+            options.push(CommandParam::Acls.into());
+        }
+        options
+    }
+}
+
+/// Reads the selected extended attributes of a file, as metadata ready to send.
+///
+/// Problems are reported as warnings; they do not prevent the file from being sent.
+pub(crate) async fn read_extended_attributes(
+    path: &Path,
+    selection: XattrSelection,
+) -> Vec<TaggedData<MetadataAttr>> {
+    if !selection.any() {
+        return Vec::new();
+    }
+    let owned = path.to_owned();
+    tokio::task::spawn_blocking(move || read_blocking(&owned, selection))
+        .await
+        .unwrap_or_else(|e| {
+            warn!(
+                "{}: could not read extended attributes: {e}",
+                path.display()
+            );
+            Vec::new()
+        })
+}
+
+/// Reads the selected extended attributes of a file, as metadata ready to send.
+///
+/// This is the blocking form of [`read_extended_attributes`], for callers which are not async.
+pub(crate) fn read_blocking(
+    path: &Path,
+    selection: XattrSelection,
+) -> Vec<TaggedData<MetadataAttr>> {
+    let attrs = match Platform::extended_attributes(path) {
+        Ok(a) => a,
+        Err(e) => {
+            warn!(
+                "{}: could not read extended attributes: {e}",
+                path.display()
+            );
+            return Vec::new();
+        }
+    };
+    let mut result = Vec::new();
+    let mut total = 0;
+    for (name, value) in attrs {
+        if !selection.wants(&name) {
+            continue;
+        }
+        total += name.len() + value.len();
+        if total > MAX_TOTAL_SIZE {
+            warn!(
+                "{}: extended attributes are too large; not sending {name} and any that follow",
+                path.display()
+            );
+            break;
+        }
+        if value.is_empty() {
+            result.push(entry(&name, &[]));
+        }
+        for chunk in value.chunks(CHUNK_SIZE) {
+            result.push(entry(&name, chunk));
+        }
+    }
+    debug!(
+        "{}: {} extended attribute entries",
+        path.display(),
+        result.len()
+    );
+    result
+}
+
+fn entry(name: &str, value: &[u8]) -> TaggedData<MetadataAttr> {
+    MetadataAttr::ExtendedAttribute.with_variant(Variant::List(vec![
+        Variant::String(name.to_string()),
+        Variant::Bytes(value.to_vec()),
+    ]))
+}
+
+/// Gathers the extended attributes from incoming metadata, joining values which were split.
+pub(crate) fn collect(metadata: &[TaggedData<MetadataAttr>]) -> Vec<(String, Vec<u8>)> {
+    let mut result: Vec<(String, Vec<u8>)> = Vec::new();
+    for md in metadata {
+        if md.tag() != Some(MetadataAttr::ExtendedAttribute) {
+            continue;
+        }
+        let Some([Variant::String(name), Variant::Bytes(value)]) = md.data.as_slice_variant()
+        else {
+            debug!("ignoring malformed extended attribute {md:?}");
+            continue;
+        };
+        match result.last_mut() {
+            Some((last, data)) if last == name => data.extend_from_slice(value),
+            _ => result.push((name.clone(), value.clone())),
+        }
+    }
+    result
+}
+
+/// Applies extended attributes from incoming metadata to a file.
+///
+/// Returns a description of each attribute that could not be applied.
+pub(crate) fn apply_extended_attributes(
+    file: &std::fs::File,
+    metadata: &[TaggedData<MetadataAttr>],
+) -> Vec<String> {
+    apply(file, collect(metadata))
+}
+
+fn apply(file: &std::fs::File, attrs: Vec<(String, Vec<u8>)>) -> Vec<String> {
+    attrs
+        .into_iter()
+        .filter_map(|(name, value)| {
+            Platform::set_extended_attribute(file, &name, &value)
+                .err()
+                .map(|e| format!("could not set extended attribute {name}: {e}"))
+        })
+        .collect()
+}
+
+/// Applies extended attributes (as gathered by [`collect`]) to a directory.
+///
+/// Returns a description of each attribute that could not be applied.
+pub(crate) async fn apply_to_directory(path: &Path, attrs: Vec<(String, Vec<u8>)>) -> Vec<String> {
+    if attrs.is_empty() {
+        return Vec::new();
+    }
+    let owned = path.to_owned();
+    tokio::task::spawn_blocking(move || match std::fs::File::open(&owned) {
+        Ok(dir) => apply(&dir, attrs),
+        Err(e) => vec![format!("could not set extended attributes: {e}")],
+    })
+    .await
+    .unwrap_or_else(|e| vec![format!("could not set extended attributes: {e}")])
+}
+
+#[cfg(test)]
+#[cfg_attr(coverage_nightly, coverage(off))]
+mod test {
+    use pretty_assertions::assert_eq;
+
+    use super::{CHUNK_SIZE, XattrSelection, collect, entry};
+    use crate::protocol::{DataTag as _, session::MetadataAttr};
+
+    #[test]
+    fn selection() {
+        let both = XattrSelection {
+            xattrs: true,
+            acls: true,
+        };
+        assert_eq!(XattrSelection::from_options(&both.to_options()), both);
+        assert!(!XattrSelection::default().any());
+
+        let acls = XattrSelection {
+            xattrs: false,
+            acls: true,
+        };
+        assert!(acls.wants("system.posix_acl_access"));
+        assert!(!acls.wants("user.provenance"));
+        assert!(!acls.wants("security.selinux"));
+    }
+
+    #[test]
+    fn split_values_are_joined() {
+        let big = vec![7u8; CHUNK_SIZE * 2 + 1];
+        let mut metadata = vec![MetadataAttr::ModeBits.with_unsigned(0o644u32)];
+        metadata.extend(big.chunks(CHUNK_SIZE).map(|c| entry("user.big", c)));
+        metadata.push(entry("user.small", b"x"));
+        metadata.push(entry("user.empty", b""));
+        assert_eq!(
+            collect(&metadata),
+            vec![
+                ("user.big".to_string(), big),
+                ("user.small".to_string(), b"x".to_vec()),
+                ("user.empty".to_string(), vec![]),
+            ]
+        );
+    }
+}